@@ -16,23 +16,29 @@
  * limitations under the License.
  */
 
-use cadence_vm::runtime::bbq::Function;
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::Constant;
 use cadence_vm::runtime::opcodes::{
-    Argument, Call, GlobalFuncLoad, IntAdd, IntConstantLoad, IntLess, IntMove, IntSubtract, Jump,
-    JumpIfFalse, ReturnValue,
+    Call, GlobalFuncLoad, IntAdd, IntConstantLoad, IntEqual, IntLess, IntMove, IntSubtract, Jump,
+    JumpIfFalse, OpCode, ReturnValue, Switch, SwitchTable,
 };
 use cadence_vm::runtime::registers;
-use cadence_vm::runtime::values::{FunctionValue, IntValue};
+use cadence_vm::runtime::types::StaticType;
+use cadence_vm::runtime::values::{IntValue, Value};
 use cadence_vm::runtime::vm::VM;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
-fn bench_cadence_recursive_fib(c: &mut Criterion) {
+fn recursive_fib_program() -> Program {
     let func = Function {
+        name: "fib".to_string(),
+        parameters: vec![Parameter::unlabeled("n", StaticType::Int)],
         local_count: registers::RegisterCounts {
-            ints: 9,
+            ints: 10,
             bools: 1,
             funcs: 2,
+            values: 0,
         },
+        return_type: StaticType::Int,
         code: vec![
             // if n < 2
             Box::new(IntConstantLoad {
@@ -58,7 +64,7 @@ fn bench_cadence_recursive_fib(c: &mut Criterion) {
             Box::new(IntSubtract {
                 left_operand: 0,
                 right_operand: 2,
-                result: 3,
+                result: 9,
             }),
             Box::new(GlobalFuncLoad {
                 index: 0,
@@ -66,10 +72,7 @@ fn bench_cadence_recursive_fib(c: &mut Criterion) {
             }),
             Box::new(Call {
                 func_index: 0,
-                arguments: &[Argument {
-                    typ: registers::RegisterType::Int,
-                    index: 3,
-                }],
+                arguments: vec![registers::RegisterType::Int],
                 result: 4,
             }),
             // fib(n - 2)
@@ -80,7 +83,7 @@ fn bench_cadence_recursive_fib(c: &mut Criterion) {
             Box::new(IntSubtract {
                 left_operand: 0,
                 right_operand: 5,
-                result: 6,
+                result: 9,
             }),
             Box::new(GlobalFuncLoad {
                 index: 0,
@@ -88,10 +91,7 @@ fn bench_cadence_recursive_fib(c: &mut Criterion) {
             }),
             Box::new(Call {
                 func_index: 1,
-                arguments: &[Argument {
-                    typ: registers::RegisterType::Int,
-                    index: 6,
-                }],
+                arguments: vec![registers::RegisterType::Int],
                 result: 7,
             }),
             // return sum
@@ -102,34 +102,51 @@ fn bench_cadence_recursive_fib(c: &mut Criterion) {
             }),
             Box::new(ReturnValue { index: 8 }),
         ],
+        ..Default::default()
     };
 
-    let mut vm = VM {
-        constants: vec![
-            IntValue { value: 2 },
-            IntValue { value: 1 },
-            IntValue { value: 2 },
-        ],
-        call_stack: vec![],
-        globals: vec![FunctionValue { function: &func }],
-        current_index: 0,
-        return_value: IntValue { value: 0 },
-    };
+    Program {
+        functions: vec![func],
+        constants: vec![Constant::int(2), Constant::int(1), Constant::int(2)],
+        ..Default::default()
+    }
+}
+
+fn bench_cadence_recursive_fib(c: &mut Criterion) {
+    let program = recursive_fib_program();
+    let mut vm = VM::new(&program);
 
-    let n = IntValue { value: 7 };
+    let arguments = [Value::Int(IntValue { value: 7 })];
 
     c.bench_function("cadence recursive fib 7", |b| {
-        b.iter(|| vm.invoke(&func, black_box(n)))
+        b.iter(|| vm.invoke("fib", black_box(&arguments)))
+    });
+}
+
+/// Dominated by calls, which reuse the registers of the register stack
+/// once it has grown to the deepest call.
+fn bench_cadence_recursive_fib_calls(c: &mut Criterion) {
+    let program = recursive_fib_program();
+    let mut vm = VM::new(&program);
+
+    let arguments = [Value::Int(IntValue { value: 15 })];
+
+    c.bench_function("cadence recursive fib 15", |b| {
+        b.iter(|| vm.invoke("fib", black_box(&arguments)))
     });
 }
 
 fn bench_cadence_imperative_fib(c: &mut Criterion) {
     let func = Function {
+        name: "fib".to_string(),
+        parameters: vec![Parameter::unlabeled("n", StaticType::Int)],
         local_count: registers::RegisterCounts {
             ints: 11,
             bools: 1,
             funcs: 0,
+            values: 0,
         },
+        return_type: StaticType::Int,
         code: vec![
             // var fib1 = 1
             Box::new(IntConstantLoad {
@@ -188,28 +205,128 @@ fn bench_cadence_imperative_fib(c: &mut Criterion) {
             // return fibonacci
             Box::new(ReturnValue { index: 5 }),
         ],
+        ..Default::default()
     };
 
-    let mut vm = VM {
+    let program = Program {
+        functions: vec![func],
         constants: vec![
-            IntValue { value: 1 },
-            IntValue { value: 1 },
-            IntValue { value: 2 },
-            IntValue { value: 1 },
+            Constant::int(1),
+            Constant::int(1),
+            Constant::int(2),
+            Constant::int(1),
         ],
-        call_stack: vec![],
-        globals: vec![FunctionValue { function: &func }],
-        current_index: 0,
-        return_value: IntValue { value: 0 },
+        ..Default::default()
     };
 
-    let n = IntValue { value: 7 };
+    let mut vm = VM::new(&program);
+
+    let arguments = [Value::Int(IntValue { value: 7 })];
 
     c.bench_function("cadence imperative fib 7", |b| {
-        b.iter(|| vm.invoke(&func, black_box(n)))
+        b.iter(|| vm.invoke("fib", black_box(&arguments)))
+    });
+}
+
+const SWITCH_CASES: usize = 16;
+
+/// `switch n { case 0: return 0 ... case 15: return 15 default: return -1 }`,
+/// with constant `i` holding `i` for each case, and the last constant holding `-1`.
+fn switch_program(code: Vec<Box<dyn OpCode>>) -> Program {
+    Program {
+        functions: vec![Function {
+            name: "switch".to_string(),
+            parameters: vec![Parameter::unlabeled("n", StaticType::Int)],
+            return_type: StaticType::Int,
+            local_count: registers::RegisterCounts {
+                ints: 2,
+                bools: 1,
+                funcs: 0,
+                values: 0,
+            },
+            code,
+            ..Default::default()
+        }],
+        constants: (0..SWITCH_CASES as i128)
+            .chain(std::iter::once(-1))
+            .map(Constant::int)
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn bench_switch(c: &mut Criterion, name: &str, program: &Program) {
+    let mut vm = VM::new(program);
+
+    let arguments = [Value::Int(IntValue {
+        value: SWITCH_CASES as isize - 1,
+    })];
+
+    c.bench_function(name, |b| {
+        b.iter(|| vm.invoke("switch", black_box(&arguments)))
     });
 }
 
+fn bench_switch_comparison_chain(c: &mut Criterion) {
+    let mut code: Vec<Box<dyn OpCode>> = vec![];
+    for case in 0..SWITCH_CASES {
+        let next_case = code.len() + 4;
+        code.push(Box::new(IntConstantLoad {
+            index: case,
+            target: 1,
+        }));
+        code.push(Box::new(IntEqual {
+            left_operand: 0,
+            right_operand: 1,
+            result: 0,
+        }));
+        code.push(Box::new(JumpIfFalse {
+            condition: 0,
+            target: next_case,
+        }));
+        // The case's constant is also its result
+        code.push(Box::new(ReturnValue { index: 1 }));
+    }
+    code.push(Box::new(IntConstantLoad {
+        index: SWITCH_CASES,
+        target: 1,
+    }));
+    code.push(Box::new(ReturnValue { index: 1 }));
+
+    let program = switch_program(code);
+    bench_switch(c, "cadence switch comparison chain 16", &program);
+}
+
+fn bench_switch_table(c: &mut Criterion) {
+    let cases: Vec<(isize, usize)> = (0..SWITCH_CASES)
+        .map(|case| (case as isize, 3 + 2 * case))
+        .collect();
+
+    let mut code: Vec<Box<dyn OpCode>> = vec![
+        Box::new(Switch {
+            value: 0,
+            typ: registers::RegisterType::Int,
+            table: SwitchTable::new(&cases),
+            default: 1,
+        }),
+        Box::new(IntConstantLoad {
+            index: SWITCH_CASES,
+            target: 1,
+        }),
+        Box::new(ReturnValue { index: 1 }),
+    ];
+    for case in 0..SWITCH_CASES {
+        code.push(Box::new(IntConstantLoad {
+            index: case,
+            target: 1,
+        }));
+        code.push(Box::new(ReturnValue { index: 1 }));
+    }
+
+    let program = switch_program(code);
+    bench_switch(c, "cadence switch table 16", &program);
+}
+
 fn bench_rust_fib(c: &mut Criterion) {
     c.bench_function("rust fib 7", |b| b.iter(|| fibonacci(black_box(7))));
 }
@@ -219,13 +336,16 @@ fn fibonacci(n: u32) -> u32 {
         return n;
     }
 
-    return fibonacci(n - 1) + fibonacci(n - 2);
+    fibonacci(n - 1) + fibonacci(n - 2)
 }
 
 criterion_group!(
     benches,
     bench_cadence_recursive_fib,
+    bench_cadence_recursive_fib_calls,
     bench_cadence_imperative_fib,
+    bench_switch_comparison_chain,
+    bench_switch_table,
     bench_rust_fib,
 );
 
@@ -0,0 +1,83 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Prints the values stored in a storage file, per account and key.
+//! When all accounts are printed, the state commitment to the storage follows,
+//! so the results of runs can be compared.
+//!
+//! Usage: `dump_storage <file> [address]`
+
+use std::io::Write;
+use std::path::Path;
+use std::process::ExitCode;
+
+use cadence_vm::runtime::commitment::StateCommitment;
+use cadence_vm::runtime::file_storage::{self, FileStorage};
+use cadence_vm::runtime::values::AddressValue;
+
+fn main() -> ExitCode {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let (file, address) = match arguments.as_slice() {
+        [file] => (file, None),
+        [file, address] => match parse_address(address) {
+            Some(address) => (file, Some(address)),
+            None => {
+                eprintln!("invalid address: {}", address);
+                return ExitCode::FAILURE;
+            }
+        },
+        _ => {
+            eprintln!("usage: dump_storage <file> [address]");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Opening a storage file creates it if it does not exist.
+    if !Path::new(file).is_file() {
+        eprintln!("no such file: {}", file);
+        return ExitCode::FAILURE;
+    }
+
+    let result = FileStorage::open(file)
+        .map_err(|err| err.to_string())
+        .and_then(|storage| {
+            let mut out = std::io::stdout().lock();
+            file_storage::dump(&storage, address, &mut out).map_err(|err| err.to_string())?;
+            if address.is_none() {
+                let commitment = StateCommitment::from_entries(storage.entries())
+                    .map_err(|err| err.to_string())?;
+                writeln!(out, "commitment: {}", commitment.root())
+                    .map_err(|err| err.to_string())?;
+            }
+            Ok(())
+        });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}: {}", file, err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Parses a hexadecimal address, with or without the `0x` prefix.
+fn parse_address(address: &str) -> Option<AddressValue> {
+    let digits = address.strip_prefix("0x").unwrap_or(address);
+    let value = u64::from_str_radix(digits, 16).ok()?;
+    Some(AddressValue { value })
+}
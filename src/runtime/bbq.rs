@@ -16,9 +16,94 @@
  * limitations under the License.
  */
 
+use crate::runtime::constants::Constant;
+use crate::runtime::types::{Access, CompositeType, InterfaceType, Purity, StaticType};
 use crate::runtime::{opcodes, registers};
 
+/// A compiled program.
+///
+/// The globals of a program are its functions,
+/// followed by its native functions and its variables.
+#[derive(Default)]
+pub struct Program {
+    pub functions: Vec<Function>,
+    /// The constants of the program, decoded by the VM when first loaded.
+    pub constants: Vec<Constant>,
+    pub composite_types: Vec<CompositeType>,
+    pub interface_types: Vec<InterfaceType>,
+    /// The names of the methods invoked by the program, referred to by index.
+    pub method_names: Vec<String>,
+    /// The names of the native functions used by the program, registered by the host.
+    pub native_functions: Vec<String>,
+    /// The global variables of the program, e.g. contract fields,
+    /// top-level declarations and imported contracts.
+    pub variables: Vec<Variable>,
+    /// The index of the function initializing the global variables, if any.
+    /// It is run once, before the first function of the program is invoked.
+    pub initializer: Option<usize>,
+}
+
+pub struct Variable {
+    pub name: String,
+    pub typ: StaticType,
+}
+
+#[derive(Default)]
 pub struct Function {
+    pub name: String,
+    /// The parameters of the function, excluding the receiver of methods.
+    pub parameters: Vec<Parameter>,
+    pub return_type: StaticType,
+    pub purity: Purity,
+    pub access: Access,
     pub code: Vec<Box<dyn opcodes::OpCode>>,
     pub local_count: registers::RegisterCounts,
+    /// The source positions of the instructions, ordered by instruction index.
+    pub positions: Vec<Position>,
+}
+
+impl Function {
+    /// Returns the class of the register holding the return value.
+    pub fn return_register_type(&self) -> registers::RegisterType {
+        registers::RegisterType::of(&self.return_type)
+    }
+
+    /// Returns the source position of the instruction at the given index.
+    pub fn position(&self, instruction: usize) -> Option<&Position> {
+        let end = self
+            .positions
+            .partition_point(|position| position.instruction <= instruction);
+        end.checked_sub(1).map(|index| &self.positions[index])
+    }
+}
+
+pub struct Parameter {
+    /// The argument label, or `None` if the argument is passed without one.
+    pub label: Option<String>,
+    pub identifier: String,
+    pub typ: StaticType,
+}
+
+impl Parameter {
+    /// Returns a parameter without an argument label, i.e. `_ identifier: typ`.
+    pub fn unlabeled(identifier: &str, typ: StaticType) -> Self {
+        Parameter {
+            label: None,
+            identifier: identifier.to_string(),
+            typ,
+        }
+    }
+
+    /// Returns the class of the register the argument is passed in.
+    pub fn register_type(&self) -> registers::RegisterType {
+        registers::RegisterType::of(&self.typ)
+    }
+}
+
+/// The source position of the instructions starting at the given index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub instruction: usize,
+    pub line: usize,
+    pub column: usize,
 }
@@ -0,0 +1,302 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt;
+use std::mem;
+
+use sha3::{Digest, Sha3_256};
+
+use crate::runtime::encoding::{encode_value, EncodeError, Encoder};
+use crate::runtime::storage::StorageKey;
+use crate::runtime::values::{AddressValue, Value};
+
+/*
+*  Hash
+*/
+
+/// A SHA3-256 hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Hash(pub [u8; 32]);
+
+impl Hash {
+    /// The hash of an empty subtree.
+    pub const EMPTY: Hash = Hash([0; 32]);
+
+    fn of(parts: &[&[u8]]) -> Hash {
+        let mut hasher = Sha3_256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        Hash(hasher.finalize().into())
+    }
+
+    /// Returns the bit at the index, from the most significant bit of the first byte.
+    fn bit(&self, index: usize) -> bool {
+        self.0[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+const LEAF_PREFIX: u8 = 0;
+const BRANCH_PREFIX: u8 = 1;
+
+/// Returns the position of the register in the trie: the hash of its address and key.
+fn register_path(address: AddressValue, key: &StorageKey) -> Hash {
+    let mut encoder = Encoder::new();
+    encoder.write_unsigned(address.value.into());
+    encoder.write_storage_key(key);
+    Hash::of(&[&encoder.into_bytes()])
+}
+
+fn leaf_hash(path: &Hash, value: &Value) -> Result<Hash, EncodeError> {
+    Ok(Hash::of(&[&[LEAF_PREFIX], &path.0, &encode_value(value)?]))
+}
+
+fn branch_hash(left: &Hash, right: &Hash) -> Hash {
+    Hash::of(&[&[BRANCH_PREFIX], &left.0, &right.0])
+}
+
+/*
+*  StateCommitment
+*/
+
+/// A commitment to the contents of account storage: the root of a sparse Merkle trie
+/// over all registers, i.e. the values stored at each address and key.
+///
+/// Registers are placed at the hash of their address and key. Subtrees with a single register
+/// are represented by its leaf, so the root only depends on the registers,
+/// and not on the order in which they were written.
+#[derive(Default)]
+pub struct StateCommitment {
+    root: Node,
+}
+
+#[derive(Default)]
+enum Node {
+    #[default]
+    Empty,
+    Leaf {
+        path: Hash,
+        hash: Hash,
+    },
+    Branch {
+        left: Box<Node>,
+        right: Box<Node>,
+        hash: Hash,
+    },
+}
+
+impl Node {
+    fn hash(&self) -> Hash {
+        match self {
+            Node::Empty => Hash::EMPTY,
+            Node::Leaf { hash, .. } | Node::Branch { hash, .. } => *hash,
+        }
+    }
+
+    fn branch(left: Node, right: Node) -> Node {
+        Node::Branch {
+            hash: branch_hash(&left.hash(), &right.hash()),
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    fn insert(&mut self, depth: usize, path: Hash, hash: Hash) {
+        match mem::take(self) {
+            Node::Empty => *self = Node::Leaf { path, hash },
+            Node::Leaf {
+                path: existing,
+                hash: existing_hash,
+            } => {
+                if existing == path {
+                    *self = Node::Leaf { path, hash };
+                    return;
+                }
+                // Move the existing leaf one level down, and insert next to it.
+                let leaf = Node::Leaf {
+                    path: existing,
+                    hash: existing_hash,
+                };
+                *self = if existing.bit(depth) {
+                    Node::branch(Node::Empty, leaf)
+                } else {
+                    Node::branch(leaf, Node::Empty)
+                };
+                self.insert(depth, path, hash);
+            }
+            Node::Branch {
+                mut left,
+                mut right,
+                ..
+            } => {
+                if path.bit(depth) {
+                    right.insert(depth + 1, path, hash);
+                } else {
+                    left.insert(depth + 1, path, hash);
+                }
+                *self = Node::branch(*left, *right);
+            }
+        }
+    }
+
+    fn remove(&mut self, depth: usize, path: &Hash) {
+        match mem::take(self) {
+            Node::Leaf { path: existing, .. } if existing == *path => {}
+            Node::Branch {
+                mut left,
+                mut right,
+                ..
+            } => {
+                if path.bit(depth) {
+                    right.remove(depth + 1, path);
+                } else {
+                    left.remove(depth + 1, path);
+                }
+                // A branch left with a single leaf, or none, is replaced by it.
+                *self = match (*left, *right) {
+                    (Node::Empty, Node::Empty) => Node::Empty,
+                    (leaf @ Node::Leaf { .. }, Node::Empty)
+                    | (Node::Empty, leaf @ Node::Leaf { .. }) => leaf,
+                    (left, right) => Node::branch(left, right),
+                };
+            }
+            node => *self = node,
+        }
+    }
+}
+
+impl StateCommitment {
+    /// Returns the commitment to empty storage.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the commitment to the registers, e.g. the entries of a storage.
+    pub fn from_entries<'a, 'b>(
+        entries: impl IntoIterator<Item = (AddressValue, &'b StorageKey, &'b Value<'a>)>,
+    ) -> Result<Self, EncodeError>
+    where
+        'a: 'b,
+    {
+        let mut commitment = StateCommitment::new();
+        for (address, key, value) in entries {
+            commitment.update(address, key, Some(value))?;
+        }
+        Ok(commitment)
+    }
+
+    /// Returns the root hash. The hash of empty storage is all zeros.
+    pub fn root(&self) -> Hash {
+        self.root.hash()
+    }
+
+    /// Updates the register to the value, or removes it if the value is `None`.
+    /// Fails if the value cannot be encoded, leaving the commitment unchanged.
+    pub fn update(
+        &mut self,
+        address: AddressValue,
+        key: &StorageKey,
+        value: Option<&Value>,
+    ) -> Result<(), EncodeError> {
+        let path = register_path(address, key);
+        match value {
+            Some(value) => {
+                let hash = leaf_hash(&path, value)?;
+                self.root.insert(0, path, hash);
+            }
+            None => self.root.remove(0, &path),
+        }
+        Ok(())
+    }
+
+    /// Returns a proof that the register is included in the commitment,
+    /// or `None` if the register is not stored.
+    pub fn prove(&self, address: AddressValue, key: &StorageKey) -> Option<InclusionProof> {
+        let path = register_path(address, key);
+        let mut siblings = vec![];
+        let mut node = &self.root;
+        loop {
+            match node {
+                Node::Empty => return None,
+                Node::Leaf { path: leaf, .. } => {
+                    return (*leaf == path).then_some(InclusionProof { siblings });
+                }
+                Node::Branch { left, right, .. } => {
+                    let (next, sibling) = if path.bit(siblings.len()) {
+                        (right, left)
+                    } else {
+                        (left, right)
+                    };
+                    siblings.push(sibling.hash());
+                    node = next;
+                }
+            }
+        }
+    }
+}
+
+/*
+*  InclusionProof
+*/
+
+/// A proof that a register is included in a state commitment:
+/// the hashes of the siblings on the path from the root to the register's leaf.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InclusionProof {
+    pub siblings: Vec<Hash>,
+}
+
+impl InclusionProof {
+    /// Reports whether the proof shows that the register stores the value
+    /// in the storage with the root hash.
+    pub fn verify(
+        &self,
+        root: &Hash,
+        address: AddressValue,
+        key: &StorageKey,
+        value: &Value,
+    ) -> bool {
+        let path = register_path(address, key);
+        let hash = match leaf_hash(&path, value) {
+            Ok(hash) => hash,
+            Err(_) => return false,
+        };
+        let hash = self
+            .siblings
+            .iter()
+            .enumerate()
+            .rev()
+            .fold(hash, |hash, (depth, sibling)| {
+                if path.bit(depth) {
+                    branch_hash(sibling, &hash)
+                } else {
+                    branch_hash(&hash, sibling)
+                }
+            });
+        hash == *root
+    }
+}
@@ -0,0 +1,224 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::runtime::encoding::{
+    decode_signed, decode_unsigned, encode_signed, encode_unsigned, DecodeError, Decoder, Encoder,
+};
+use crate::runtime::types::StaticType;
+use crate::runtime::values::{
+    AddressValue, Fix64Value, FixedSizeIntValue, IntValue, PathValue, StringValue, TypeValue,
+    UFix64Value, Value,
+};
+
+/*
+*  Constant
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConstantKind {
+    Int,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    Int128,
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    UInt128,
+    Word8,
+    Word16,
+    Word32,
+    Word64,
+    Fix64,
+    UFix64,
+    String,
+    Address,
+    Path,
+    Type,
+}
+
+/// A constant of a program, kept in its encoded form until it is first used.
+///
+/// Numbers are encoded as big-endian bytes of any length:
+/// two's complement for signed types, the magnitude for unsigned types.
+/// Strings are encoded as UTF-8, paths as their domain followed by the identifier,
+/// and type literals as encoded static types.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Constant {
+    pub kind: ConstantKind,
+    pub data: Vec<u8>,
+}
+
+impl Constant {
+    pub fn int(value: i128) -> Self {
+        Constant {
+            kind: ConstantKind::Int,
+            data: encode_signed(value),
+        }
+    }
+
+    pub fn fixed_size_int(value: FixedSizeIntValue) -> Self {
+        let (kind, data) = match value {
+            FixedSizeIntValue::Int8(value) => (ConstantKind::Int8, encode_signed(value.into())),
+            FixedSizeIntValue::Int16(value) => (ConstantKind::Int16, encode_signed(value.into())),
+            FixedSizeIntValue::Int32(value) => (ConstantKind::Int32, encode_signed(value.into())),
+            FixedSizeIntValue::Int64(value) => (ConstantKind::Int64, encode_signed(value.into())),
+            FixedSizeIntValue::Int128(value) => (ConstantKind::Int128, encode_signed(value)),
+            FixedSizeIntValue::UInt8(value) => (ConstantKind::UInt8, encode_unsigned(value.into())),
+            FixedSizeIntValue::UInt16(value) => {
+                (ConstantKind::UInt16, encode_unsigned(value.into()))
+            }
+            FixedSizeIntValue::UInt32(value) => {
+                (ConstantKind::UInt32, encode_unsigned(value.into()))
+            }
+            FixedSizeIntValue::UInt64(value) => {
+                (ConstantKind::UInt64, encode_unsigned(value.into()))
+            }
+            FixedSizeIntValue::UInt128(value) => (ConstantKind::UInt128, encode_unsigned(value)),
+            FixedSizeIntValue::Word8(value) => (ConstantKind::Word8, encode_unsigned(value.into())),
+            FixedSizeIntValue::Word16(value) => {
+                (ConstantKind::Word16, encode_unsigned(value.into()))
+            }
+            FixedSizeIntValue::Word32(value) => {
+                (ConstantKind::Word32, encode_unsigned(value.into()))
+            }
+            FixedSizeIntValue::Word64(value) => {
+                (ConstantKind::Word64, encode_unsigned(value.into()))
+            }
+        };
+        Constant { kind, data }
+    }
+
+    pub fn fix64(value: Fix64Value) -> Self {
+        Constant {
+            kind: ConstantKind::Fix64,
+            data: encode_signed(value.value.into()),
+        }
+    }
+
+    pub fn ufix64(value: UFix64Value) -> Self {
+        Constant {
+            kind: ConstantKind::UFix64,
+            data: encode_unsigned(value.value.into()),
+        }
+    }
+
+    pub fn string(value: &str) -> Self {
+        Constant {
+            kind: ConstantKind::String,
+            data: value.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn address(value: AddressValue) -> Self {
+        Constant {
+            kind: ConstantKind::Address,
+            data: encode_unsigned(value.value.into()),
+        }
+    }
+
+    pub fn path(value: &PathValue) -> Self {
+        let mut encoder = Encoder::new();
+        encoder.write_path(value);
+        Constant {
+            kind: ConstantKind::Path,
+            data: encoder.into_bytes(),
+        }
+    }
+
+    pub fn type_literal(typ: &StaticType) -> Self {
+        let mut encoder = Encoder::new();
+        encoder.write_static_type(typ);
+        Constant {
+            kind: ConstantKind::Type,
+            data: encoder.into_bytes(),
+        }
+    }
+
+    /// Decodes the constant into a runtime value.
+    pub fn decode<'a>(&self) -> Result<Value<'a>, DecodeError> {
+        let data = &self.data;
+        let value = match self.kind {
+            ConstantKind::Int => Value::Int(IntValue {
+                value: narrow(decode_signed(data)?)?,
+            }),
+            ConstantKind::Int8 => fixed(FixedSizeIntValue::Int8(narrow(decode_signed(data)?)?)),
+            ConstantKind::Int16 => fixed(FixedSizeIntValue::Int16(narrow(decode_signed(data)?)?)),
+            ConstantKind::Int32 => fixed(FixedSizeIntValue::Int32(narrow(decode_signed(data)?)?)),
+            ConstantKind::Int64 => fixed(FixedSizeIntValue::Int64(narrow(decode_signed(data)?)?)),
+            ConstantKind::Int128 => fixed(FixedSizeIntValue::Int128(decode_signed(data)?)),
+            ConstantKind::UInt8 => fixed(FixedSizeIntValue::UInt8(narrow(decode_unsigned(data)?)?)),
+            ConstantKind::UInt16 => {
+                fixed(FixedSizeIntValue::UInt16(narrow(decode_unsigned(data)?)?))
+            }
+            ConstantKind::UInt32 => {
+                fixed(FixedSizeIntValue::UInt32(narrow(decode_unsigned(data)?)?))
+            }
+            ConstantKind::UInt64 => {
+                fixed(FixedSizeIntValue::UInt64(narrow(decode_unsigned(data)?)?))
+            }
+            ConstantKind::UInt128 => fixed(FixedSizeIntValue::UInt128(decode_unsigned(data)?)),
+            ConstantKind::Word8 => fixed(FixedSizeIntValue::Word8(narrow(decode_unsigned(data)?)?)),
+            ConstantKind::Word16 => {
+                fixed(FixedSizeIntValue::Word16(narrow(decode_unsigned(data)?)?))
+            }
+            ConstantKind::Word32 => {
+                fixed(FixedSizeIntValue::Word32(narrow(decode_unsigned(data)?)?))
+            }
+            ConstantKind::Word64 => {
+                fixed(FixedSizeIntValue::Word64(narrow(decode_unsigned(data)?)?))
+            }
+            ConstantKind::Fix64 => Value::Fix64(Fix64Value {
+                value: narrow(decode_signed(data)?)?,
+            }),
+            ConstantKind::UFix64 => Value::UFix64(UFix64Value {
+                value: narrow(decode_unsigned(data)?)?,
+            }),
+            ConstantKind::String => {
+                let value = std::str::from_utf8(data).map_err(|_| DecodeError::InvalidUtf8)?;
+                Value::String(StringValue::new(value))
+            }
+            ConstantKind::Address => Value::Address(AddressValue {
+                value: narrow(decode_unsigned(data)?)?,
+            }),
+            ConstantKind::Path => {
+                let mut decoder = Decoder::new(data);
+                let path = decoder.read_path()?;
+                decoder.finish()?;
+                Value::Path(path)
+            }
+            ConstantKind::Type => {
+                let mut decoder = Decoder::new(data);
+                let typ = decoder.read_static_type()?;
+                decoder.finish()?;
+                Value::Type(TypeValue { typ })
+            }
+        };
+        Ok(value)
+    }
+}
+
+fn fixed<'a>(value: FixedSizeIntValue) -> Value<'a> {
+    Value::FixedSizeInt(value)
+}
+
+fn narrow<T, U: TryFrom<T>>(value: T) -> Result<U, DecodeError> {
+    U::try_from(value).map_err(|_| DecodeError::OutOfRange)
+}
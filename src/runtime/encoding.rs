@@ -0,0 +1,944 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::runtime::slabs::{
+    ArrayChild, Slab, SlabValue, StoredArrayValue, StoredDictionaryValue, DICTIONARY_BRANCH_WIDTH,
+};
+use crate::runtime::storage::StorageKey;
+use crate::runtime::types::{Authorization, CompositeKind, StaticType};
+use crate::runtime::values::{
+    AddressValue, ArrayValue, BoolValue, CapabilityControllerValue, CapabilityValue,
+    CharacterValue, CompositeValue, DictionaryValue, Fix64Value, FixedSizeIntValue,
+    InclusiveRangeValue, IntValue, PathDomain, PathValue, StringValue, TypeValue, UFix64Value,
+    Value,
+};
+
+/*
+*  DecodeError
+*/
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    TrailingData,
+    InvalidTag(u8),
+    InvalidUtf8,
+    InvalidIdentifier,
+    /// A number does not fit the type it is decoded as.
+    OutOfRange,
+    /// The data was written by a newer format version.
+    UnsupportedVersion(u8),
+    /// The data is not the only encoding of the value,
+    /// e.g. a dictionary with duplicate keys.
+    NonCanonical,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            DecodeError::TrailingData => write!(f, "unexpected data after the end"),
+            DecodeError::InvalidTag(tag) => write!(f, "invalid tag {}", tag),
+            DecodeError::InvalidUtf8 => write!(f, "invalid UTF-8 string"),
+            DecodeError::InvalidIdentifier => write!(f, "invalid identifier"),
+            DecodeError::OutOfRange => write!(f, "number out of range"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            DecodeError::NonCanonical => write!(f, "non-canonical encoding"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/*
+*  EncodeError
+*/
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The value cannot be stored, e.g. a function or a reference.
+    NotStorable(StaticType),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::NotStorable(typ) => write!(f, "cannot encode value of type `{}`", typ),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+/*
+*  Encoder
+*/
+
+/// Writes the primitives of the VM's binary formats.
+/// Lengths and tags are written as unsigned LEB128 varints.
+#[derive(Default)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_uvarint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    /// Writes the bytes, prefixed with their length.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_uvarint(bytes.len() as u64);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn write_str(&mut self, value: &str) {
+        self.write_bytes(value.as_bytes());
+    }
+
+    /// Writes the number as length-prefixed big-endian two's complement.
+    pub fn write_signed(&mut self, value: i128) {
+        self.write_bytes(&encode_signed(value));
+    }
+
+    /// Writes the number as length-prefixed big-endian bytes.
+    pub fn write_unsigned(&mut self, value: u128) {
+        self.write_bytes(&encode_unsigned(value));
+    }
+
+    pub fn write_path(&mut self, path: &PathValue) {
+        self.write_u8(path.domain as u8);
+        self.write_str(&path.identifier);
+    }
+
+    /// Writes the key of a value in account storage, as a tag followed by its contents.
+    pub fn write_storage_key(&mut self, key: &StorageKey) {
+        match key {
+            StorageKey::Path(path) => {
+                self.write_u8(PATH_KEY_TAG);
+                self.write_path(path);
+            }
+            StorageKey::CapabilityController(id) => {
+                self.write_u8(CAPABILITY_CONTROLLER_KEY_TAG);
+                self.write_uvarint(*id);
+            }
+            StorageKey::LastCapabilityId => self.write_u8(LAST_CAPABILITY_ID_KEY_TAG),
+            StorageKey::Slab(id) => {
+                self.write_u8(SLAB_KEY_TAG);
+                self.write_uvarint(*id);
+            }
+            StorageKey::LastSlabId => self.write_u8(LAST_SLAB_ID_KEY_TAG),
+        }
+    }
+
+    /// Writes a storable value, i.e. a value that is not a function, a reference,
+    /// an account, or a value only used during execution, like an iterator.
+    /// Values are written as a tag, followed by their contents.
+    pub fn write_value(&mut self, value: &Value) -> Result<(), EncodeError> {
+        match value {
+            Value::Void => self.write_u8(VOID_VALUE_TAG),
+            Value::Nil => self.write_u8(NIL_VALUE_TAG),
+            Value::Some(value) => {
+                self.write_u8(SOME_VALUE_TAG);
+                self.write_value(value)?;
+            }
+            Value::Bool(value) => {
+                self.write_u8(BOOL_VALUE_TAG);
+                self.write_u8(value.value as u8);
+            }
+            Value::Int(value) => {
+                self.write_u8(INT_VALUE_TAG);
+                self.write_signed(value.value as i128);
+            }
+            Value::FixedSizeInt(value) => {
+                self.write_u8(FIXED_SIZE_INT_VALUE_TAG);
+                self.write_static_type(&value.static_type());
+                match value {
+                    FixedSizeIntValue::UInt128(value) => self.write_unsigned(*value),
+                    value => self.write_signed(value.to_i128().unwrap()),
+                }
+            }
+            Value::Fix64(value) => {
+                self.write_u8(FIX64_VALUE_TAG);
+                self.write_signed(value.value.into());
+            }
+            Value::UFix64(value) => {
+                self.write_u8(UFIX64_VALUE_TAG);
+                self.write_unsigned(value.value.into());
+            }
+            Value::String(value) => {
+                self.write_u8(STRING_VALUE_TAG);
+                self.write_str(&value.value);
+            }
+            Value::Character(value) => {
+                self.write_u8(CHARACTER_VALUE_TAG);
+                self.write_str(&value.value);
+            }
+            Value::Address(value) => {
+                self.write_u8(ADDRESS_VALUE_TAG);
+                self.write_unsigned(value.value.into());
+            }
+            Value::Path(path) => {
+                self.write_u8(PATH_VALUE_TAG);
+                self.write_path(path);
+            }
+            Value::Capability(capability) => {
+                self.write_u8(CAPABILITY_VALUE_TAG);
+                self.write_unsigned(capability.address.value.into());
+                self.write_uvarint(capability.id);
+                self.write_static_type(&capability.borrow_type);
+            }
+            Value::CapabilityController(controller) => {
+                self.write_u8(CAPABILITY_CONTROLLER_VALUE_TAG);
+                self.write_unsigned(controller.address.value.into());
+                self.write_uvarint(controller.id);
+                self.write_static_type(&controller.borrow_type);
+                self.write_path(&controller.target);
+            }
+            Value::Array(array) => {
+                self.write_u8(ARRAY_VALUE_TAG);
+                self.write_static_type(&array.element_type());
+                self.write_uvarint(array.len() as u64);
+                for element in array.elements() {
+                    self.write_value(&element)?;
+                }
+            }
+            Value::Dictionary(dictionary) => {
+                self.write_u8(DICTIONARY_VALUE_TAG);
+                let (key_type, value_type) = dictionary.types();
+                self.write_static_type(&key_type);
+                self.write_static_type(&value_type);
+                self.write_uvarint(dictionary.len() as u64);
+                for key in dictionary.keys() {
+                    self.write_value(&key)?;
+                    self.write_value(&dictionary.get(&key).unwrap())?;
+                }
+            }
+            Value::InclusiveRange(range) => {
+                self.write_u8(INCLUSIVE_RANGE_VALUE_TAG);
+                self.write_value(&range.start())?;
+                self.write_value(&range.end())?;
+                self.write_value(&range.step())?;
+            }
+            Value::Composite(composite) => {
+                self.write_u8(COMPOSITE_VALUE_TAG);
+                self.write_str(&composite.identifier());
+                self.write_u8(match composite.kind() {
+                    CompositeKind::Structure => 0,
+                    CompositeKind::Resource => 1,
+                    CompositeKind::Contract => 2,
+                    CompositeKind::Enum => 3,
+                });
+                let fields = composite.fields();
+                self.write_uvarint(fields.len() as u64);
+                for (name, value) in &fields {
+                    self.write_str(name);
+                    self.write_value(value)?;
+                }
+            }
+            Value::Type(value) => {
+                self.write_u8(TYPE_VALUE_TAG);
+                self.write_static_type(&value.typ);
+            }
+            Value::StoredArray(array) => {
+                self.write_u8(STORED_ARRAY_VALUE_TAG);
+                self.write_unsigned(array.address.value.into());
+                self.write_uvarint(array.root);
+                self.write_static_type(&array.element_type);
+            }
+            Value::StoredDictionary(dictionary) => {
+                self.write_u8(STORED_DICTIONARY_VALUE_TAG);
+                self.write_unsigned(dictionary.address.value.into());
+                self.write_uvarint(dictionary.root);
+                self.write_static_type(&dictionary.key_type);
+                self.write_static_type(&dictionary.value_type);
+            }
+            Value::Slab(slab) => {
+                self.write_u8(SLAB_VALUE_TAG);
+                self.write_uvarint(slab.mutations);
+                self.write_slab(&slab.slab)?;
+            }
+            Value::Account(_)
+            | Value::Function(_)
+            | Value::Reference(_)
+            | Value::Upvalue(_)
+            | Value::Iterator(_) => return Err(EncodeError::NotStorable(value.static_type())),
+        }
+        Ok(())
+    }
+
+    /// Writes the node of a stored collection. Only the present children of dictionary branches
+    /// are written, as their slot and slab ID.
+    fn write_slab(&mut self, slab: &Slab) -> Result<(), EncodeError> {
+        match slab {
+            Slab::ArrayLeaf(elements) => {
+                self.write_u8(ARRAY_LEAF_SLAB_TAG);
+                self.write_uvarint(elements.len() as u64);
+                for element in elements {
+                    self.write_value(element)?;
+                }
+            }
+            Slab::ArrayBranch(children) => {
+                self.write_u8(ARRAY_BRANCH_SLAB_TAG);
+                self.write_uvarint(children.len() as u64);
+                for child in children {
+                    self.write_uvarint(child.id);
+                    self.write_uvarint(child.count);
+                }
+            }
+            Slab::DictionaryLeaf(entries) => {
+                self.write_u8(DICTIONARY_LEAF_SLAB_TAG);
+                self.write_uvarint(entries.len() as u64);
+                for (key, value) in entries {
+                    self.write_value(key)?;
+                    self.write_value(value)?;
+                }
+            }
+            Slab::DictionaryBranch { count, children } => {
+                self.write_u8(DICTIONARY_BRANCH_SLAB_TAG);
+                self.write_uvarint(*count);
+                let children: Vec<(usize, u64)> = children
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(slot, id)| id.map(|id| (slot, id)))
+                    .collect();
+                self.write_uvarint(children.len() as u64);
+                for (slot, id) in children {
+                    self.write_u8(slot as u8);
+                    self.write_uvarint(id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_static_type(&mut self, typ: &StaticType) {
+        match typ {
+            StaticType::Optional(typ) => {
+                self.write_u8(OPTIONAL_TAG);
+                self.write_static_type(typ);
+            }
+            StaticType::VariableSizedArray(typ) => {
+                self.write_u8(ARRAY_TAG);
+                self.write_static_type(typ);
+            }
+            StaticType::Dictionary {
+                key_type,
+                value_type,
+            } => {
+                self.write_u8(DICTIONARY_TAG);
+                self.write_static_type(key_type);
+                self.write_static_type(value_type);
+            }
+            StaticType::Capability(typ) => {
+                self.write_u8(CAPABILITY_TAG);
+                self.write_static_type(typ);
+            }
+            StaticType::InclusiveRange(typ) => {
+                self.write_u8(INCLUSIVE_RANGE_TAG);
+                self.write_static_type(typ);
+            }
+            StaticType::Composite(identifier) => {
+                self.write_u8(COMPOSITE_TAG);
+                self.write_str(identifier);
+            }
+            StaticType::Intersection(interfaces) => {
+                self.write_u8(INTERSECTION_TAG);
+                self.write_strings(interfaces);
+            }
+            StaticType::Reference {
+                authorization,
+                referenced_type,
+            } => {
+                self.write_u8(REFERENCE_TAG);
+                match authorization {
+                    Authorization::Unauthorized => self.write_u8(UNAUTHORIZED_TAG),
+                    Authorization::Conjunction(entitlements) => {
+                        self.write_u8(CONJUNCTION_TAG);
+                        self.write_strings(entitlements);
+                    }
+                    Authorization::Disjunction(entitlements) => {
+                        self.write_u8(DISJUNCTION_TAG);
+                        self.write_strings(entitlements);
+                    }
+                }
+                self.write_static_type(referenced_type);
+            }
+            _ => {
+                let tag = SIMPLE_TYPES
+                    .iter()
+                    .position(|simple_type| simple_type == typ)
+                    .unwrap();
+                self.write_u8(tag as u8);
+            }
+        }
+    }
+
+    fn write_strings(&mut self, strings: &BTreeSet<String>) {
+        self.write_uvarint(strings.len() as u64);
+        for string in strings {
+            self.write_str(string);
+        }
+    }
+}
+
+/*
+*  Decoder
+*/
+
+/// Reads the data written by an `Encoder`.
+pub struct Decoder<'b> {
+    bytes: &'b [u8],
+    offset: usize,
+}
+
+impl<'b> Decoder<'b> {
+    pub fn new(bytes: &'b [u8]) -> Self {
+        Decoder { bytes, offset: 0 }
+    }
+
+    /// Returns the number of bytes read so far.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Checks that all data has been read.
+    pub fn finish(&self) -> Result<(), DecodeError> {
+        if self.offset != self.bytes.len() {
+            return Err(DecodeError::TrailingData);
+        }
+        Ok(())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self
+            .bytes
+            .get(self.offset)
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    pub fn read_uvarint(&mut self) -> Result<u64, DecodeError> {
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if shift == 63 && byte > 1 {
+                return Err(DecodeError::OutOfRange);
+            }
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'b [u8], DecodeError> {
+        let length = self.read_uvarint()? as usize;
+        let end = self
+            .offset
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    pub fn read_str(&mut self) -> Result<&'b str, DecodeError> {
+        std::str::from_utf8(self.read_bytes()?).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    pub fn read_signed(&mut self) -> Result<i128, DecodeError> {
+        decode_signed(self.read_bytes()?)
+    }
+
+    pub fn read_unsigned(&mut self) -> Result<u128, DecodeError> {
+        decode_unsigned(self.read_bytes()?)
+    }
+
+    pub fn read_path(&mut self) -> Result<PathValue, DecodeError> {
+        let tag = self.read_u8()?;
+        let domain = PathDomain::from_u8(tag).ok_or(DecodeError::InvalidTag(tag))?;
+        PathValue::new(domain, self.read_str()?).ok_or(DecodeError::InvalidIdentifier)
+    }
+
+    pub fn read_storage_key(&mut self) -> Result<StorageKey, DecodeError> {
+        let tag = self.read_u8()?;
+        let key = match tag {
+            PATH_KEY_TAG => StorageKey::Path(self.read_path()?),
+            CAPABILITY_CONTROLLER_KEY_TAG => StorageKey::CapabilityController(self.read_uvarint()?),
+            LAST_CAPABILITY_ID_KEY_TAG => StorageKey::LastCapabilityId,
+            SLAB_KEY_TAG => StorageKey::Slab(self.read_uvarint()?),
+            LAST_SLAB_ID_KEY_TAG => StorageKey::LastSlabId,
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        };
+        Ok(key)
+    }
+
+    pub fn read_value<'a>(&mut self) -> Result<Value<'a>, DecodeError> {
+        let tag = self.read_u8()?;
+        let value = match tag {
+            VOID_VALUE_TAG => Value::Void,
+            NIL_VALUE_TAG => Value::Nil,
+            SOME_VALUE_TAG => Value::some(self.read_value()?),
+            BOOL_VALUE_TAG => match self.read_u8()? {
+                0 => Value::Bool(BoolValue { value: false }),
+                1 => Value::Bool(BoolValue { value: true }),
+                tag => return Err(DecodeError::InvalidTag(tag)),
+            },
+            INT_VALUE_TAG => Value::Int(IntValue {
+                value: narrow(self.read_signed()?)?,
+            }),
+            FIXED_SIZE_INT_VALUE_TAG => {
+                let typ = self.read_static_type()?;
+                match typ {
+                    StaticType::UInt128 => {
+                        Value::FixedSizeInt(FixedSizeIntValue::UInt128(self.read_unsigned()?))
+                    }
+                    StaticType::Int => return Err(DecodeError::InvalidTag(tag)),
+                    typ => {
+                        Value::integer(&typ, self.read_signed()?).ok_or(DecodeError::OutOfRange)?
+                    }
+                }
+            }
+            FIX64_VALUE_TAG => Value::Fix64(Fix64Value {
+                value: narrow(self.read_signed()?)?,
+            }),
+            UFIX64_VALUE_TAG => Value::UFix64(UFix64Value {
+                value: narrow(self.read_unsigned()?)?,
+            }),
+            STRING_VALUE_TAG => Value::String(StringValue::new(self.read_str()?)),
+            CHARACTER_VALUE_TAG => Value::Character(CharacterValue::new(self.read_str()?)),
+            ADDRESS_VALUE_TAG => Value::Address(self.read_address()?),
+            PATH_VALUE_TAG => Value::Path(self.read_path()?),
+            CAPABILITY_VALUE_TAG => Value::Capability(CapabilityValue {
+                address: self.read_address()?,
+                id: self.read_uvarint()?,
+                borrow_type: self.read_static_type()?,
+            }),
+            CAPABILITY_CONTROLLER_VALUE_TAG => {
+                Value::CapabilityController(CapabilityControllerValue {
+                    address: self.read_address()?,
+                    id: self.read_uvarint()?,
+                    borrow_type: self.read_static_type()?,
+                    target: self.read_path()?,
+                })
+            }
+            ARRAY_VALUE_TAG => {
+                let element_type = self.read_static_type()?;
+                let count = self.read_uvarint()?;
+                let elements = (0..count)
+                    .map(|_| self.read_value())
+                    .collect::<Result<_, _>>()?;
+                Value::Array(ArrayValue::new(element_type, elements))
+            }
+            DICTIONARY_VALUE_TAG => {
+                let key_type = self.read_static_type()?;
+                let value_type = self.read_static_type()?;
+                let count = self.read_uvarint()?;
+                let entries = (0..count)
+                    .map(|_| Ok((self.read_value()?, self.read_value()?)))
+                    .collect::<Result<_, _>>()?;
+                let dictionary = DictionaryValue::new(key_type, value_type, entries);
+                if dictionary.len() as u64 != count {
+                    return Err(DecodeError::NonCanonical);
+                }
+                Value::Dictionary(dictionary)
+            }
+            INCLUSIVE_RANGE_VALUE_TAG => {
+                let start = self.read_value()?;
+                let end = self.read_value()?;
+                let step = self.read_value()?;
+                let range = InclusiveRangeValue::new(&start, &end, Some(&step))
+                    .map_err(|_| DecodeError::OutOfRange)?;
+                Value::InclusiveRange(range)
+            }
+            COMPOSITE_VALUE_TAG => {
+                let identifier = self.read_str()?;
+                let kind = match self.read_u8()? {
+                    0 => CompositeKind::Structure,
+                    1 => CompositeKind::Resource,
+                    2 => CompositeKind::Contract,
+                    3 => CompositeKind::Enum,
+                    tag => return Err(DecodeError::InvalidTag(tag)),
+                };
+                let count = self.read_uvarint()?;
+                let fields: Vec<(&str, Value<'a>)> = (0..count)
+                    .map(|_| Ok((self.read_str()?, self.read_value()?)))
+                    .collect::<Result<_, _>>()?;
+                // Fields are written ordered by name.
+                if fields.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+                    return Err(DecodeError::NonCanonical);
+                }
+                Value::Composite(CompositeValue::new(identifier, kind, fields))
+            }
+            TYPE_VALUE_TAG => Value::Type(TypeValue {
+                typ: self.read_static_type()?,
+            }),
+            STORED_ARRAY_VALUE_TAG => Value::StoredArray(StoredArrayValue {
+                address: self.read_address()?,
+                root: self.read_uvarint()?,
+                element_type: self.read_static_type()?,
+            }),
+            STORED_DICTIONARY_VALUE_TAG => Value::StoredDictionary(StoredDictionaryValue {
+                address: self.read_address()?,
+                root: self.read_uvarint()?,
+                key_type: self.read_static_type()?,
+                value_type: self.read_static_type()?,
+            }),
+            SLAB_VALUE_TAG => Value::Slab(SlabValue {
+                mutations: self.read_uvarint()?,
+                slab: Rc::new(self.read_slab()?),
+            }),
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        };
+        Ok(value)
+    }
+
+    fn read_slab<'a>(&mut self) -> Result<Slab<'a>, DecodeError> {
+        let tag = self.read_u8()?;
+        let slab = match tag {
+            ARRAY_LEAF_SLAB_TAG => {
+                let count = self.read_uvarint()?;
+                let elements = (0..count)
+                    .map(|_| self.read_value())
+                    .collect::<Result<_, _>>()?;
+                Slab::ArrayLeaf(elements)
+            }
+            ARRAY_BRANCH_SLAB_TAG => {
+                let count = self.read_uvarint()?;
+                let children = (0..count)
+                    .map(|_| {
+                        Ok(ArrayChild {
+                            id: self.read_uvarint()?,
+                            count: self.read_uvarint()?,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                Slab::ArrayBranch(children)
+            }
+            DICTIONARY_LEAF_SLAB_TAG => {
+                let count = self.read_uvarint()?;
+                let entries: Vec<(Value<'a>, Value<'a>)> = (0..count)
+                    .map(|_| Ok((self.read_value()?, self.read_value()?)))
+                    .collect::<Result<_, _>>()?;
+                // Like dictionaries, leaves may not have duplicate keys.
+                let unique =
+                    DictionaryValue::new(StaticType::Never, StaticType::Never, entries.clone());
+                if unique.len() != entries.len() {
+                    return Err(DecodeError::NonCanonical);
+                }
+                Slab::DictionaryLeaf(entries)
+            }
+            DICTIONARY_BRANCH_SLAB_TAG => {
+                let count = self.read_uvarint()?;
+                let mut children = vec![None; DICTIONARY_BRANCH_WIDTH];
+                let present = self.read_uvarint()?;
+                let mut previous = None;
+                for _ in 0..present {
+                    let slot = self.read_u8()?;
+                    // Children are written in order of their slots.
+                    if previous.is_some_and(|previous| slot <= previous) {
+                        return Err(DecodeError::NonCanonical);
+                    }
+                    *children
+                        .get_mut(slot as usize)
+                        .ok_or(DecodeError::OutOfRange)? = Some(self.read_uvarint()?);
+                    previous = Some(slot);
+                }
+                Slab::DictionaryBranch { count, children }
+            }
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        };
+        Ok(slab)
+    }
+
+    fn read_address(&mut self) -> Result<AddressValue, DecodeError> {
+        Ok(AddressValue {
+            value: narrow(self.read_unsigned()?)?,
+        })
+    }
+
+    pub fn read_static_type(&mut self) -> Result<StaticType, DecodeError> {
+        let tag = self.read_u8()?;
+        match tag {
+            OPTIONAL_TAG => Ok(StaticType::optional(self.read_static_type()?)),
+            COMPOSITE_TAG => Ok(StaticType::Composite(self.read_str()?.to_string())),
+            INTERSECTION_TAG => Ok(StaticType::Intersection(self.read_strings()?)),
+            ARRAY_TAG => Ok(StaticType::array(self.read_static_type()?)),
+            DICTIONARY_TAG => {
+                let key_type = self.read_static_type()?;
+                Ok(StaticType::dictionary(key_type, self.read_static_type()?))
+            }
+            CAPABILITY_TAG => Ok(StaticType::capability(self.read_static_type()?)),
+            INCLUSIVE_RANGE_TAG => Ok(StaticType::inclusive_range(self.read_static_type()?)),
+            REFERENCE_TAG => {
+                let authorization = match self.read_u8()? {
+                    UNAUTHORIZED_TAG => Authorization::Unauthorized,
+                    CONJUNCTION_TAG => Authorization::Conjunction(self.read_strings()?),
+                    DISJUNCTION_TAG => Authorization::Disjunction(self.read_strings()?),
+                    tag => return Err(DecodeError::InvalidTag(tag)),
+                };
+                Ok(StaticType::reference(
+                    authorization,
+                    self.read_static_type()?,
+                ))
+            }
+            _ => SIMPLE_TYPES
+                .get(tag as usize)
+                .cloned()
+                .ok_or(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    fn read_strings(&mut self) -> Result<BTreeSet<String>, DecodeError> {
+        let count = self.read_uvarint()?;
+        (0..count)
+            .map(|_| self.read_str().map(str::to_string))
+            .collect()
+    }
+}
+
+/*
+*  Static type tags
+*/
+
+/// The types without type arguments, encoded as their index.
+/// New types must only be appended, as the tags are part of the format.
+const SIMPLE_TYPES: &[StaticType] = &[
+    StaticType::Never,
+    StaticType::Void,
+    StaticType::AnyStruct,
+    StaticType::AnyResource,
+    StaticType::Bool,
+    StaticType::String,
+    StaticType::Address,
+    StaticType::Number,
+    StaticType::SignedNumber,
+    StaticType::Integer,
+    StaticType::SignedInteger,
+    StaticType::Int,
+    StaticType::Int8,
+    StaticType::Int16,
+    StaticType::Int32,
+    StaticType::Int64,
+    StaticType::Int128,
+    StaticType::UInt8,
+    StaticType::UInt16,
+    StaticType::UInt32,
+    StaticType::UInt64,
+    StaticType::UInt128,
+    StaticType::Word8,
+    StaticType::Word16,
+    StaticType::Word32,
+    StaticType::Word64,
+    StaticType::FixedPoint,
+    StaticType::SignedFixedPoint,
+    StaticType::Fix64,
+    StaticType::UFix64,
+    StaticType::MetaType,
+    StaticType::Function,
+    StaticType::Character,
+    StaticType::Path,
+    StaticType::StoragePath,
+    StaticType::CapabilityPath,
+    StaticType::PublicPath,
+    StaticType::PrivatePath,
+    StaticType::Account,
+    StaticType::StorageCapabilityController,
+];
+
+const OPTIONAL_TAG: u8 = 0x80;
+const COMPOSITE_TAG: u8 = 0x81;
+const INTERSECTION_TAG: u8 = 0x82;
+const REFERENCE_TAG: u8 = 0x83;
+const ARRAY_TAG: u8 = 0x84;
+const DICTIONARY_TAG: u8 = 0x85;
+const INCLUSIVE_RANGE_TAG: u8 = 0x86;
+const CAPABILITY_TAG: u8 = 0x87;
+
+const UNAUTHORIZED_TAG: u8 = 0;
+const CONJUNCTION_TAG: u8 = 1;
+const DISJUNCTION_TAG: u8 = 2;
+
+/*
+*  Value format
+*/
+
+/// The version of the value format, written before each value encoded by `encode_value`.
+/// Values written by earlier versions must remain decodable: new kinds of values
+/// may be added with new tags, but other changes to the format must increment the version.
+pub const VALUE_FORMAT_VERSION: u8 = 1;
+
+/// Encodes the storable value, prefixed with the format version.
+///
+/// The encoding is deterministic: dictionaries are written in iteration order,
+/// and composite fields ordered by name.
+pub fn encode_value(value: &Value) -> Result<Vec<u8>, EncodeError> {
+    let mut encoder = Encoder::new();
+    encoder.write_u8(VALUE_FORMAT_VERSION);
+    encoder.write_value(value)?;
+    Ok(encoder.into_bytes())
+}
+
+/// Decodes a value encoded by `encode_value`, by this or an earlier version of the format.
+pub fn decode_value<'a>(bytes: &[u8]) -> Result<Value<'a>, DecodeError> {
+    let mut decoder = Decoder::new(bytes);
+    let version = decoder.read_u8()?;
+    if version == 0 || version > VALUE_FORMAT_VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let value = decoder.read_value()?;
+    decoder.finish()?;
+    Ok(value)
+}
+
+// The tags are part of the format: new tags must only be appended, and never reused.
+
+const VOID_VALUE_TAG: u8 = 0;
+const NIL_VALUE_TAG: u8 = 1;
+const SOME_VALUE_TAG: u8 = 2;
+const BOOL_VALUE_TAG: u8 = 3;
+const INT_VALUE_TAG: u8 = 4;
+const FIXED_SIZE_INT_VALUE_TAG: u8 = 5;
+const FIX64_VALUE_TAG: u8 = 6;
+const UFIX64_VALUE_TAG: u8 = 7;
+const STRING_VALUE_TAG: u8 = 8;
+const CHARACTER_VALUE_TAG: u8 = 9;
+const ADDRESS_VALUE_TAG: u8 = 10;
+const PATH_VALUE_TAG: u8 = 11;
+const CAPABILITY_VALUE_TAG: u8 = 12;
+const CAPABILITY_CONTROLLER_VALUE_TAG: u8 = 13;
+const ARRAY_VALUE_TAG: u8 = 14;
+const DICTIONARY_VALUE_TAG: u8 = 15;
+const INCLUSIVE_RANGE_VALUE_TAG: u8 = 16;
+const COMPOSITE_VALUE_TAG: u8 = 17;
+const TYPE_VALUE_TAG: u8 = 18;
+const STORED_ARRAY_VALUE_TAG: u8 = 19;
+const STORED_DICTIONARY_VALUE_TAG: u8 = 20;
+const SLAB_VALUE_TAG: u8 = 21;
+
+const PATH_KEY_TAG: u8 = 0;
+const CAPABILITY_CONTROLLER_KEY_TAG: u8 = 1;
+const LAST_CAPABILITY_ID_KEY_TAG: u8 = 2;
+const SLAB_KEY_TAG: u8 = 3;
+const LAST_SLAB_ID_KEY_TAG: u8 = 4;
+
+const ARRAY_LEAF_SLAB_TAG: u8 = 0;
+const ARRAY_BRANCH_SLAB_TAG: u8 = 1;
+const DICTIONARY_LEAF_SLAB_TAG: u8 = 2;
+const DICTIONARY_BRANCH_SLAB_TAG: u8 = 3;
+
+fn narrow<T, U: TryFrom<T>>(value: T) -> Result<U, DecodeError> {
+    U::try_from(value).map_err(|_| DecodeError::OutOfRange)
+}
+
+/*
+*  Number encoding
+*/
+
+/// Encodes the value as the shortest big-endian two's complement representation.
+pub(crate) fn encode_signed(value: i128) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let sign = if value < 0 { 0xff } else { 0x00 };
+    let mut start = 0;
+    while start < bytes.len() - 1
+        && bytes[start] == sign
+        && (bytes[start + 1] & 0x80) == (sign & 0x80)
+    {
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+/// Encodes the value as the shortest big-endian representation.
+pub(crate) fn encode_unsigned(value: u128) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let start = bytes
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(bytes.len() - 1);
+    bytes[start..].to_vec()
+}
+
+pub(crate) fn decode_signed(data: &[u8]) -> Result<i128, DecodeError> {
+    let first = *data.first().ok_or(DecodeError::UnexpectedEnd)?;
+    let sign = if first & 0x80 != 0 { 0xff } else { 0x00 };
+    let mut bytes = [sign; 16];
+    copy_right_aligned(data, &mut bytes, sign)?;
+    let value = i128::from_be_bytes(bytes);
+    if (value < 0) != (sign == 0xff) {
+        return Err(DecodeError::OutOfRange);
+    }
+    Ok(value)
+}
+
+pub(crate) fn decode_unsigned(data: &[u8]) -> Result<u128, DecodeError> {
+    if data.is_empty() {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let mut bytes = [0; 16];
+    copy_right_aligned(data, &mut bytes, 0)?;
+    Ok(u128::from_be_bytes(bytes))
+}
+
+/// Copies the big-endian data into the end of the buffer.
+/// Data longer than the buffer may only be padded with the given byte.
+fn copy_right_aligned(data: &[u8], buffer: &mut [u8; 16], padding: u8) -> Result<(), DecodeError> {
+    let data = match data.len().checked_sub(buffer.len()) {
+        Some(excess) => {
+            let (prefix, data) = data.split_at(excess);
+            if prefix.iter().any(|byte| *byte != padding) {
+                return Err(DecodeError::OutOfRange);
+            }
+            data
+        }
+        None => data,
+    };
+    buffer[16 - data.len()..].copy_from_slice(data);
+    Ok(())
+}
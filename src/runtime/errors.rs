@@ -0,0 +1,310 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt;
+
+use crate::runtime::encoding::DecodeError;
+use crate::runtime::types::StaticType;
+use crate::runtime::values::{AddressValue, PathValue};
+
+/// An error that aborts the execution of a program.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VMError {
+    ForceCastTypeMismatch {
+        expected_type: StaticType,
+        actual_type: StaticType,
+    },
+    MethodNotFound {
+        type_identifier: String,
+        method_name: String,
+    },
+    UndefinedFunction {
+        name: String,
+    },
+    ArgumentCountMismatch {
+        function: String,
+        expected: usize,
+        actual: usize,
+    },
+    ArgumentTypeMismatch {
+        parameter: String,
+        expected_type: StaticType,
+        actual_type: StaticType,
+        /// The frame of the call that passed the argument,
+        /// or `None` if the function was invoked by the host.
+        call_site: Option<Box<StackFrame>>,
+    },
+    UndefinedVariable {
+        name: String,
+    },
+    UninitializedVariable {
+        name: String,
+    },
+    VariableTypeMismatch {
+        name: String,
+        expected_type: StaticType,
+        actual_type: StaticType,
+    },
+    /// A global that is not a variable, e.g. a function, was assigned.
+    NotAVariable {
+        name: String,
+    },
+    /// An instruction was executed on an operand of a type it does not support.
+    OperandTypeMismatch {
+        instruction: &'static str,
+        actual_type: StaticType,
+    },
+    InvalidConstant {
+        index: usize,
+        error: DecodeError,
+    },
+    ContainerMutatedDuringIteration,
+    InclusiveRangeConstruction {
+        message: String,
+    },
+    StorageOverwrite {
+        address: AddressValue,
+        path: PathValue,
+    },
+    StorageMutatedDuringIteration,
+    /// `copy` was called on a path that stores a resource.
+    StoredResourceCopy {
+        address: AddressValue,
+        path: PathValue,
+    },
+    /// `borrow` was called with a type argument that is not a reference type.
+    NonReferenceBorrowType {
+        borrow_type: StaticType,
+    },
+    /// A reference to a stored value was used after the value was moved out of its path,
+    /// or replaced by a value that does not have the referenced type.
+    DereferenceFailure {
+        address: AddressValue,
+        path: PathValue,
+    },
+    CapabilityOverwrite {
+        address: AddressValue,
+        path: PathValue,
+    },
+    /// A capability controller was used after it was deleted.
+    CapabilityControllerDeleted {
+        address: AddressValue,
+        id: u64,
+    },
+    /// The host storage failed to persist the writes.
+    StorageFailure {
+        message: String,
+    },
+    /// The storage of the account does not hold what the VM wrote to it,
+    /// e.g. a slab of a stored array or dictionary is missing or malformed.
+    CorruptedStorage {
+        address: AddressValue,
+        message: String,
+    },
+    Panic {
+        message: String,
+    },
+    AssertionFailure {
+        message: String,
+    },
+    /// The invocation used more computation than the limit of the computation meter.
+    ComputationLimitExceeded {
+        limit: u64,
+    },
+    /// The invocation allocated more memory than the limit of the memory meter.
+    MemoryLimitExceeded {
+        limit: u64,
+    },
+    /// A call exceeded the maximum call depth.
+    /// The frames are the innermost frames of the call stack, innermost first.
+    CallStackOverflow {
+        max_depth: usize,
+        frames: Vec<StackFrame>,
+    },
+}
+
+/// A call frame in the call stack, reported in errors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StackFrame {
+    pub function: String,
+    /// The index of the instruction being executed.
+    pub instruction: usize,
+    /// The source line and column of the instruction, if known.
+    pub position: Option<(usize, usize)>,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.function, self.instruction)?;
+        if let Some((line, column)) = self.position {
+            write!(f, " ({}:{})", line, column)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for VMError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VMError::ForceCastTypeMismatch {
+                expected_type,
+                actual_type,
+            } => write!(
+                f,
+                "failed to force-cast value: expected type `{}`, got `{}`",
+                expected_type, actual_type
+            ),
+            VMError::MethodNotFound {
+                type_identifier,
+                method_name,
+            } => write!(
+                f,
+                "value of type `{}` has no member `{}`",
+                type_identifier, method_name
+            ),
+            VMError::UndefinedFunction { name } => {
+                write!(f, "cannot find function in this scope: `{}`", name)
+            }
+            VMError::ArgumentCountMismatch {
+                function,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "incorrect number of arguments for function `{}`: expected {}, got {}",
+                function, expected, actual
+            ),
+            VMError::ArgumentTypeMismatch {
+                parameter,
+                expected_type,
+                actual_type,
+                call_site,
+            } => {
+                write!(
+                    f,
+                    "mismatched types for parameter `{}`: expected `{}`, got `{}`",
+                    parameter, expected_type, actual_type
+                )?;
+                if let Some(call_site) = call_site {
+                    write!(f, ", at {}", call_site)?;
+                }
+                Ok(())
+            }
+            VMError::UndefinedVariable { name } => {
+                write!(f, "cannot find variable in this scope: `{}`", name)
+            }
+            VMError::UninitializedVariable { name } => {
+                write!(f, "variable `{}` is used before being initialized", name)
+            }
+            VMError::VariableTypeMismatch {
+                name,
+                expected_type,
+                actual_type,
+            } => write!(
+                f,
+                "mismatched types for variable `{}`: expected `{}`, got `{}`",
+                name, expected_type, actual_type
+            ),
+            VMError::NotAVariable { name } => {
+                write!(f, "cannot assign to `{}`: not a variable", name)
+            }
+            VMError::OperandTypeMismatch {
+                instruction,
+                actual_type,
+            } => write!(
+                f,
+                "`{}` cannot operate on a value of type `{}`",
+                instruction, actual_type
+            ),
+            VMError::InvalidConstant { index, error } => {
+                write!(f, "invalid constant {}: {}", index, error)
+            }
+            VMError::ContainerMutatedDuringIteration => {
+                write!(f, "resource container modified during iteration")
+            }
+            VMError::InclusiveRangeConstruction { message } => {
+                write!(f, "InclusiveRange construction failed: {}", message)
+            }
+            VMError::StorageOverwrite { address, path } => write!(
+                f,
+                "failed to save object: path {} in account {} already stores an object",
+                path, address
+            ),
+            VMError::StorageMutatedDuringIteration => {
+                write!(f, "storage iteration continued after modifying storage")
+            }
+            VMError::StoredResourceCopy { address, path } => write!(
+                f,
+                "cannot copy resource: path {} in account {} stores a resource",
+                path, address
+            ),
+            VMError::NonReferenceBorrowType { borrow_type } => {
+                write!(f, "cannot borrow as non-reference type `{}`", borrow_type)
+            }
+            VMError::DereferenceFailure { address, path } => write!(
+                f,
+                "cannot dereference: path {} in account {} does not store the referenced value",
+                path, address
+            ),
+            VMError::CapabilityOverwrite { address, path } => write!(
+                f,
+                "cannot publish capability: path {} in account {} already stores a value",
+                path, address
+            ),
+            VMError::CapabilityControllerDeleted { address, id } => write!(
+                f,
+                "capability controller {} in account {} was deleted",
+                id, address
+            ),
+            VMError::StorageFailure { message } => {
+                write!(f, "failed to persist storage: {}", message)
+            }
+            VMError::CorruptedStorage { address, message } => {
+                write!(f, "corrupted storage in account {}: {}", address, message)
+            }
+            VMError::Panic { message } => write!(f, "panic: {}", message),
+            VMError::AssertionFailure { message } => {
+                if message.is_empty() {
+                    write!(f, "assertion failed")
+                } else {
+                    write!(f, "assertion failed: {}", message)
+                }
+            }
+            VMError::ComputationLimitExceeded { limit } => {
+                write!(f, "computation limit exceeded: limit {}", limit)
+            }
+            VMError::MemoryLimitExceeded { limit } => {
+                write!(f, "memory limit exceeded: limit {}", limit)
+            }
+            VMError::CallStackOverflow { max_depth, frames } => {
+                write!(
+                    f,
+                    "call stack overflow: maximum depth {} exceeded",
+                    max_depth
+                )?;
+                for (index, frame) in frames.iter().enumerate() {
+                    let separator = if index == 0 { ", at " } else { ", " };
+                    write!(f, "{}{}", separator, frame)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for VMError {}
@@ -0,0 +1,329 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::runtime::encoding::{
+    decode_value, encode_value, DecodeError, Decoder, EncodeError, Encoder,
+};
+use crate::runtime::errors::VMError;
+use crate::runtime::storage::{InMemoryStorage, Storage, StorageKey};
+use crate::runtime::values::{AddressValue, PathDomain, PathValue, Value};
+
+/*
+*  FileStorageError
+*/
+
+#[derive(Debug)]
+pub enum FileStorageError {
+    Io(io::Error),
+    /// The file is not a storage file, or has an unsupported format version.
+    InvalidHeader,
+    Decode(DecodeError),
+    Encode(EncodeError),
+}
+
+impl fmt::Display for FileStorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileStorageError::Io(err) => write!(f, "{}", err),
+            FileStorageError::InvalidHeader => write!(f, "not a storage file"),
+            FileStorageError::Decode(err) => write!(f, "invalid storage file: {}", err),
+            FileStorageError::Encode(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for FileStorageError {}
+
+impl From<io::Error> for FileStorageError {
+    fn from(err: io::Error) -> Self {
+        FileStorageError::Io(err)
+    }
+}
+
+impl From<DecodeError> for FileStorageError {
+    fn from(err: DecodeError) -> Self {
+        FileStorageError::Decode(err)
+    }
+}
+
+/*
+*  FileStorage
+*/
+
+/// Storage persisted to a single file, which can be reopened in a later session.
+///
+/// The file is a header followed by a log of writes, each a length-prefixed record
+/// of the address, the key, and the value encoded by `encode_value`, or a marker for removals.
+/// Opening the file replays the log into memory, dropping a final record that was
+/// only partially written, e.g. when the process was killed; writes are appended to it,
+/// and persisted by `flush`. `compact` rewrites the log to only the stored values.
+/// Values read or written can also be mutated in place, e.g. through references:
+/// `flush` appends a record for each of them that changed since it was last logged.
+pub struct FileStorage<'a> {
+    path: PathBuf,
+    values: InMemoryStorage<'a>,
+    log: BufWriter<File>,
+    /// The last logged record of each value read or written that can be mutated in place.
+    /// Values are watched by `read`, so it is shared.
+    watched: RefCell<BTreeMap<(AddressValue, StorageKey), Vec<u8>>>,
+    /// The first error writing to the log since the last flush.
+    error: Option<FileStorageError>,
+}
+
+const MAGIC: &[u8] = b"CDCSTORE";
+const FORMAT_VERSION: u8 = 1;
+
+impl<'a> FileStorage<'a> {
+    /// Opens the storage file at the path, creating it if it does not exist.
+    /// An incomplete final record is truncated, so later records are appended after
+    /// the last complete one.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, FileStorageError> {
+        let path = path.as_ref().to_path_buf();
+
+        let mut values = InMemoryStorage::new();
+        match fs::read(&path) {
+            Ok(data) => {
+                let length = read_log(&data, &mut values)?;
+                if length < data.len() {
+                    let file = OpenOptions::new().write(true).open(&path)?;
+                    file.set_len(length as u64)?;
+                    file.sync_data()?;
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                fs::write(&path, header())?;
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        let log = BufWriter::new(OpenOptions::new().append(true).open(&path)?);
+        Ok(FileStorage {
+            path,
+            values,
+            log,
+            watched: RefCell::new(BTreeMap::new()),
+            error: None,
+        })
+    }
+
+    /// Returns the stored values, ordered by address and key.
+    pub fn entries(&self) -> impl Iterator<Item = (AddressValue, &StorageKey, &Value<'a>)> {
+        self.values.entries()
+    }
+
+    /// Rewrites the file to only contain the stored values, dropping overwritten values and removals.
+    pub fn compact(&mut self) -> Result<(), FileStorageError> {
+        self.flush_log()?;
+
+        let mut data = header();
+        for (address, key, value) in self.values.entries() {
+            let record =
+                encode_record(address, key, Some(value)).map_err(FileStorageError::Encode)?;
+            data.extend_from_slice(&record);
+        }
+
+        // Replace the file atomically, so a failure leaves either the old or the new file.
+        let temporary = self.path.with_extension("compacting");
+        fs::write(&temporary, data)?;
+        fs::rename(&temporary, &self.path)?;
+
+        self.log = BufWriter::new(OpenOptions::new().append(true).open(&self.path)?);
+        Ok(())
+    }
+
+    /// Writes the appended records to the file,
+    /// after appending the records of the watched values that were mutated in place.
+    pub fn flush_log(&mut self) -> Result<(), FileStorageError> {
+        self.log_mutations();
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.log.flush()?;
+        self.log.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    fn log_mutations(&mut self) {
+        for ((address, key), logged) in self.watched.get_mut() {
+            let Some(value) = self.values.read(*address, key) else {
+                continue;
+            };
+            let result = encode_record(*address, key, Some(&value))
+                .map_err(FileStorageError::Encode)
+                .and_then(|record| {
+                    if record != *logged {
+                        self.log.write_all(&record)?;
+                        *logged = record;
+                    }
+                    Ok(())
+                });
+            if let Err(err) = result {
+                self.error.get_or_insert(err);
+            }
+        }
+    }
+
+    fn watch(&self, address: AddressValue, key: &StorageKey, record: Vec<u8>) {
+        self.watched
+            .borrow_mut()
+            .insert((address, key.clone()), record);
+    }
+}
+
+impl<'a> Storage<'a> for FileStorage<'a> {
+    fn read(&self, address: AddressValue, key: &StorageKey) -> Option<Value<'a>> {
+        let value = self.values.read(address, key)?;
+        if value.is_mutable_in_place() {
+            let watched = self.watched.borrow().contains_key(&(address, key.clone()));
+            // Values that cannot be encoded were not logged, and already fail the next flush.
+            if let (false, Ok(record)) = (watched, encode_record(address, key, Some(&value))) {
+                self.watch(address, key, record);
+            }
+        }
+        Some(value)
+    }
+
+    /// Values that cannot be encoded are stored in memory only,
+    /// and fail the next flush.
+    fn write(&mut self, address: AddressValue, key: &StorageKey, value: Option<Value<'a>>) {
+        self.watched.get_mut().remove(&(address, key.clone()));
+
+        let record = encode_record(address, key, value.as_ref());
+        let result = record.map_err(FileStorageError::Encode).and_then(|record| {
+            self.log.write_all(&record)?;
+            if value.as_ref().is_some_and(Value::is_mutable_in_place) {
+                self.watch(address, key, record);
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            self.error.get_or_insert(err);
+        }
+
+        self.values.write(address, key, value);
+    }
+
+    fn paths(&self, address: AddressValue, domain: PathDomain) -> Vec<PathValue> {
+        self.values.paths(address, domain)
+    }
+
+    fn flush(&mut self) -> Result<(), VMError> {
+        self.flush_log().map_err(|err| VMError::StorageFailure {
+            message: err.to_string(),
+        })
+    }
+}
+
+/// Writes the stored values of the accounts, or of the given account only,
+/// one line per address and one line per key, e.g. `  /storage/r: R = R(id: 1)`.
+pub fn dump(
+    storage: &FileStorage,
+    address: Option<AddressValue>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut current_address = None;
+    for (entry_address, key, value) in storage.entries() {
+        if address.is_some_and(|address| address != entry_address) {
+            continue;
+        }
+        if current_address != Some(entry_address) {
+            writeln!(out, "{}:", entry_address)?;
+            current_address = Some(entry_address);
+        }
+        writeln!(out, "  {}: {} = {}", key, value.static_type(), value)?;
+    }
+    Ok(())
+}
+
+/*
+*  Log records
+*/
+
+const REMOVED_TAG: u8 = 0;
+const STORED_TAG: u8 = 1;
+
+fn header() -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.push(FORMAT_VERSION);
+    header
+}
+
+fn encode_record(
+    address: AddressValue,
+    key: &StorageKey,
+    value: Option<&Value>,
+) -> Result<Vec<u8>, EncodeError> {
+    let mut encoder = Encoder::new();
+    encoder.write_unsigned(address.value.into());
+    encoder.write_storage_key(key);
+    match value {
+        Some(value) => {
+            encoder.write_u8(STORED_TAG);
+            encoder.write_bytes(&encode_value(value)?);
+        }
+        None => encoder.write_u8(REMOVED_TAG),
+    }
+
+    let mut record = Encoder::new();
+    record.write_bytes(&encoder.into_bytes());
+    Ok(record.into_bytes())
+}
+
+/// Replays the records of the file into the storage, and returns the length of the data
+/// up to the end of the last complete record. Only the final record can be incomplete.
+fn read_log<'a>(data: &[u8], values: &mut InMemoryStorage<'a>) -> Result<usize, FileStorageError> {
+    let records = data
+        .strip_prefix(MAGIC)
+        .and_then(|data| data.strip_prefix(&[FORMAT_VERSION]))
+        .ok_or(FileStorageError::InvalidHeader)?;
+    let header_length = data.len() - records.len();
+
+    let mut decoder = Decoder::new(records);
+    while decoder.finish().is_err() {
+        let end = decoder.offset();
+        let mut record = match decoder.read_bytes() {
+            Ok(record) => Decoder::new(record),
+            Err(DecodeError::UnexpectedEnd) => return Ok(header_length + end),
+            Err(err) => return Err(err.into()),
+        };
+
+        let address = AddressValue {
+            value: record
+                .read_unsigned()?
+                .try_into()
+                .map_err(|_| DecodeError::OutOfRange)?,
+        };
+        let key = record.read_storage_key()?;
+        let value = match record.read_u8()? {
+            STORED_TAG => Some(decode_value(record.read_bytes()?)?),
+            REMOVED_TAG => None,
+            tag => return Err(DecodeError::InvalidTag(tag).into()),
+        };
+        record.finish()?;
+
+        values.write(address, &key, value);
+    }
+    Ok(data.len())
+}
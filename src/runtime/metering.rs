@@ -0,0 +1,391 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::runtime::errors::VMError;
+use crate::runtime::types::CompositeKind;
+use crate::runtime::values::{FixedSizeIntValue, Value};
+
+/*
+*  ComputationKind
+*/
+
+/// A unit of work charged to the computation meter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ComputationKind {
+    /// The execution of an instruction with the opcode of the given name.
+    Instruction(&'static str),
+    /// A jump back to an earlier instruction, i.e. an iteration of a loop.
+    LoopIteration,
+    /// A call of a function of the program, including invocations by the host.
+    FunctionCall,
+    /// The creation of a value, e.g. an array, a dictionary or a composite.
+    Allocation,
+    /// A call of a function implemented by the host.
+    NativeCall,
+}
+
+/*
+*  ComputationWeights
+*/
+
+/// The computation charged for each kind of work, supplied by the host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComputationWeights {
+    /// The weight of instructions whose opcode has no weight in `opcodes`.
+    pub instruction: u64,
+    /// The weights of instructions, by opcode name, e.g. `"InvokeMethod"`.
+    pub opcodes: HashMap<&'static str, u64>,
+    pub loop_iteration: u64,
+    pub function_call: u64,
+    pub allocation: u64,
+    pub native_call: u64,
+}
+
+impl Default for ComputationWeights {
+    /// Charges 1 for each instruction, and nothing else.
+    fn default() -> Self {
+        ComputationWeights {
+            instruction: 1,
+            opcodes: HashMap::new(),
+            loop_iteration: 0,
+            function_call: 0,
+            allocation: 0,
+            native_call: 0,
+        }
+    }
+}
+
+impl ComputationWeights {
+    /// Returns the weight of the kind of work.
+    pub fn weight(&self, kind: ComputationKind) -> u64 {
+        match kind {
+            ComputationKind::Instruction(name) => {
+                self.opcodes.get(name).copied().unwrap_or(self.instruction)
+            }
+            ComputationKind::LoopIteration => self.loop_iteration,
+            ComputationKind::FunctionCall => self.function_call,
+            ComputationKind::Allocation => self.allocation,
+            ComputationKind::NativeCall => self.native_call,
+        }
+    }
+}
+
+/*
+*  ComputationMeter
+*/
+
+/// Meters the computation of an invocation, failing once it exceeds the limit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComputationMeter {
+    weights: ComputationWeights,
+    limit: u64,
+    used: u64,
+}
+
+impl Default for ComputationMeter {
+    /// A meter with the default weights and no limit.
+    fn default() -> Self {
+        ComputationMeter::new(ComputationWeights::default(), u64::MAX)
+    }
+}
+
+impl ComputationMeter {
+    pub fn new(weights: ComputationWeights, limit: u64) -> Self {
+        ComputationMeter {
+            weights,
+            limit,
+            used: 0,
+        }
+    }
+
+    pub fn weights(&self) -> &ComputationWeights {
+        &self.weights
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Returns the computation used since the meter was last reset.
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    pub fn reset(&mut self) {
+        self.used = 0;
+    }
+
+    /// Charges the weight of the kind of work.
+    /// Fails if the used computation exceeds the limit.
+    pub fn charge(&mut self, kind: ComputationKind) -> Result<(), VMError> {
+        self.used = self.used.saturating_add(self.weights.weight(kind));
+        if self.used > self.limit {
+            return Err(VMError::ComputationLimitExceeded { limit: self.limit });
+        }
+        Ok(())
+    }
+}
+
+/*
+*  MemoryKind
+*/
+
+/// A kind of memory allocated by the VM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryKind {
+    /// The registers of a call frame, charged per register.
+    Registers,
+    /// Strings, charged per byte.
+    String,
+    /// Arrays, charged per array and per element.
+    Array,
+    /// Dictionaries, charged per dictionary and per entry.
+    Dictionary,
+    /// Composites, charged per composite and per field.
+    Composite,
+    /// Integers wider than 64 bits, charged per integer.
+    BigInteger,
+}
+
+impl MemoryKind {
+    pub const ALL: [MemoryKind; 6] = [
+        MemoryKind::Registers,
+        MemoryKind::String,
+        MemoryKind::Array,
+        MemoryKind::Dictionary,
+        MemoryKind::Composite,
+        MemoryKind::BigInteger,
+    ];
+}
+
+impl fmt::Display for MemoryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MemoryKind::Registers => "registers",
+            MemoryKind::String => "strings",
+            MemoryKind::Array => "arrays",
+            MemoryKind::Dictionary => "dictionaries",
+            MemoryKind::Composite => "composites",
+            MemoryKind::BigInteger => "big integers",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/*
+*  MemoryWeights
+*/
+
+/// The memory charged per unit of each kind of allocation, supplied by the host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryWeights {
+    pub registers: u64,
+    pub string: u64,
+    pub array: u64,
+    pub dictionary: u64,
+    pub composite: u64,
+    pub big_integer: u64,
+}
+
+impl Default for MemoryWeights {
+    /// Charges 1 per unit of each kind.
+    fn default() -> Self {
+        MemoryWeights {
+            registers: 1,
+            string: 1,
+            array: 1,
+            dictionary: 1,
+            composite: 1,
+            big_integer: 1,
+        }
+    }
+}
+
+impl MemoryWeights {
+    /// Returns the weight of a unit of the kind of memory.
+    pub fn weight(&self, kind: MemoryKind) -> u64 {
+        match kind {
+            MemoryKind::Registers => self.registers,
+            MemoryKind::String => self.string,
+            MemoryKind::Array => self.array,
+            MemoryKind::Dictionary => self.dictionary,
+            MemoryKind::Composite => self.composite,
+            MemoryKind::BigInteger => self.big_integer,
+        }
+    }
+}
+
+/*
+*  MemoryUsage
+*/
+
+/// The memory used by an invocation, by kind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    used: [u64; MemoryKind::ALL.len()],
+}
+
+impl MemoryUsage {
+    pub fn get(&self, kind: MemoryKind) -> u64 {
+        self.used[kind as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.used
+            .iter()
+            .fold(0, |total, used| total.saturating_add(*used))
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    /// Lists the memory used by each kind, e.g. `registers: 12, strings: 5, ..., total: 17`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for kind in MemoryKind::ALL {
+            write!(f, "{}: {}, ", kind, self.get(kind))?;
+        }
+        write!(f, "total: {}", self.total())
+    }
+}
+
+/*
+*  MemoryMeter
+*/
+
+/// Meters the memory allocated by an invocation, failing once it exceeds the limit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryMeter {
+    weights: MemoryWeights,
+    limit: u64,
+    usage: MemoryUsage,
+}
+
+impl Default for MemoryMeter {
+    /// A meter with the default weights and no limit.
+    fn default() -> Self {
+        MemoryMeter::new(MemoryWeights::default(), u64::MAX)
+    }
+}
+
+impl MemoryMeter {
+    pub fn new(weights: MemoryWeights, limit: u64) -> Self {
+        MemoryMeter {
+            weights,
+            limit,
+            usage: MemoryUsage::default(),
+        }
+    }
+
+    pub fn weights(&self) -> &MemoryWeights {
+        &self.weights
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Returns the memory used since the meter was last reset.
+    pub fn usage(&self) -> MemoryUsage {
+        self.usage
+    }
+
+    pub fn reset(&mut self) {
+        self.usage = MemoryUsage::default();
+    }
+
+    /// Charges the units of the kind of memory.
+    /// Fails if the used memory exceeds the limit.
+    pub fn charge(&mut self, kind: MemoryKind, units: u64) -> Result<(), VMError> {
+        let used = &mut self.usage.used[kind as usize];
+        *used = used.saturating_add(units.saturating_mul(self.weights.weight(kind)));
+        if self.usage.total() > self.limit {
+            return Err(VMError::MemoryLimitExceeded { limit: self.limit });
+        }
+        Ok(())
+    }
+
+    /// Charges the memory of a newly created value, e.g. a decoded constant or a value loaded from storage,
+    /// including the values it contains. The identifiers of paths are charged as strings.
+    pub fn charge_value(&mut self, value: &Value) -> Result<(), VMError> {
+        match value {
+            Value::String(string) => self.charge(MemoryKind::String, string.value.len() as u64),
+            Value::Character(character) => {
+                self.charge(MemoryKind::String, character.value.len() as u64)
+            }
+            Value::Path(path) => self.charge(MemoryKind::String, path.identifier.len() as u64),
+            Value::FixedSizeInt(FixedSizeIntValue::Int128(_) | FixedSizeIntValue::UInt128(_)) => {
+                self.charge(MemoryKind::BigInteger, 1)
+            }
+            Value::Some(value) => self.charge_value(value),
+            Value::Array(array) => {
+                self.charge(MemoryKind::Array, 1 + array.len() as u64)?;
+                array
+                    .elements()
+                    .iter()
+                    .try_for_each(|element| self.charge_value(element))
+            }
+            Value::Dictionary(dictionary) => {
+                self.charge(MemoryKind::Dictionary, 1 + dictionary.len() as u64)?;
+                dictionary.keys().iter().try_for_each(|key| {
+                    self.charge_value(key)?;
+                    self.charge_value(&dictionary.get(key).unwrap())
+                })
+            }
+            Value::Composite(composite) => {
+                let fields = composite.fields();
+                self.charge(MemoryKind::Composite, 1 + fields.len() as u64)?;
+                fields
+                    .iter()
+                    .try_for_each(|(_, value)| self.charge_value(value))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Charges the memory of a copy of the value, i.e. of the structs and containers it copies.
+    /// The copy shares all other values, e.g. strings, with the original.
+    pub fn charge_copy(&mut self, value: &Value) -> Result<(), VMError> {
+        match value {
+            Value::Some(value) => self.charge_copy(value),
+            Value::Array(array) => {
+                self.charge(MemoryKind::Array, 1 + array.len() as u64)?;
+                array
+                    .elements()
+                    .iter()
+                    .try_for_each(|element| self.charge_copy(element))
+            }
+            Value::Dictionary(dictionary) => {
+                self.charge(MemoryKind::Dictionary, 1 + dictionary.len() as u64)?;
+                dictionary.keys().iter().try_for_each(|key| {
+                    self.charge_copy(key)?;
+                    self.charge_copy(&dictionary.get(key).unwrap())
+                })
+            }
+            Value::Composite(composite) if composite.kind() == CompositeKind::Structure => {
+                let fields = composite.fields();
+                self.charge(MemoryKind::Composite, 1 + fields.len() as u64)?;
+                fields
+                    .iter()
+                    .try_for_each(|(_, value)| self.charge_copy(value))
+            }
+            _ => Ok(()),
+        }
+    }
+}
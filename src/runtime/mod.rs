@@ -17,7 +17,17 @@
  */
 
 pub mod bbq;
+pub mod commitment;
+pub mod constants;
+pub mod encoding;
+pub mod errors;
+pub mod file_storage;
+pub mod metering;
 pub mod opcodes;
+pub mod registers;
+pub mod slabs;
+pub mod stdlib;
+pub mod storage;
+pub mod types;
 pub mod values;
 pub mod vm;
-pub mod registers;
@@ -16,17 +16,35 @@
  * limitations under the License.
  */
 
-use crate::runtime::{registers, values, vm};
+use std::cell::RefCell;
+
+use crate::runtime::errors::VMError;
+use crate::runtime::metering::{ComputationKind, MemoryKind};
+use crate::runtime::registers::RegisterType;
+use crate::runtime::types::{CompositeKind, CompositeType, StaticType, TypeRegistry};
+use crate::runtime::values::{
+    ArrayValue, BoolValue, CompositeValue, DictionaryValue, FunctionValue, InclusiveRangeValue,
+    IteratorValue, UpvalueValue, Value,
+};
+use crate::runtime::{bbq, registers, values, vm};
 
 pub trait OpCode {
-    fn execute(&self, vm: &mut vm::VM);
+    /// The name of the opcode, e.g. to look up its computation weight.
+    fn name(&self) -> &'static str;
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError>;
 }
 
 pub struct Return {}
 
 impl OpCode for Return {
-    fn execute(&self, _: &mut vm::VM) {
-        panic!("not implemented!")
+    fn name(&self) -> &'static str {
+        "Return"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        vm.pop_call_frame(None);
+        Ok(())
     }
 }
 
@@ -35,8 +53,13 @@ pub struct ReturnValue {
 }
 
 impl OpCode for ReturnValue {
-    fn execute(&self, vm: &mut vm::VM) {
-        vm.pop_call_frame(self.index);
+    fn name(&self) -> &'static str {
+        "ReturnValue"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        vm.pop_call_frame(Some(self.index));
+        Ok(())
     }
 }
 
@@ -45,8 +68,12 @@ pub struct Jump {
 }
 
 impl OpCode for Jump {
-    fn execute(&self, vm: &mut vm::VM) {
-        vm.call_frame().ip = self.target;
+    fn name(&self) -> &'static str {
+        "Jump"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        vm.jump(self.target)
     }
 }
 
@@ -56,13 +83,108 @@ pub struct JumpIfFalse {
 }
 
 impl OpCode for JumpIfFalse {
-    fn execute(&self, vm: &mut vm::VM) {
-        let call_frame = vm.call_frame();
-        let bools = &call_frame.locals.bools;
+    fn name(&self) -> &'static str {
+        "JumpIfFalse"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        if !vm.bools()[self.condition].value {
+            vm.jump(self.target)?;
+        }
+        Ok(())
+    }
+}
+
+/// Jumps to the target of the case matching the value of a register,
+/// or to the default target if no case matches, e.g. for `switch` statements.
+/// Int registers are switched over directly. Value registers may hold
+/// fixed-size integers, or enum cases, which are switched over by their raw values.
+pub struct Switch {
+    pub value: usize,
+    pub typ: RegisterType,
+    pub table: SwitchTable,
+    pub default: usize,
+}
+
+impl OpCode for Switch {
+    fn name(&self) -> &'static str {
+        "Switch"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let value = match self.typ {
+            RegisterType::Int => Some(vm.ints()[self.value].value),
+            typ => {
+                let value = vm.locals().get(typ, self.value);
+                let integer = match &value {
+                    Value::Composite(composite) if composite.kind() == CompositeKind::Enum => {
+                        composite.get_field(ENUM_RAW_VALUE_FIELD).unwrap().to_i128()
+                    }
+                    value => value.to_i128(),
+                };
+                let integer = integer.ok_or_else(|| VMError::OperandTypeMismatch {
+                    instruction: self.name(),
+                    actual_type: value.static_type(),
+                })?;
+                // Values outside of the range of the table match no case.
+                isize::try_from(integer).ok()
+            }
+        };
+        let target = value.and_then(|value| self.table.target(value));
+        vm.jump(target.unwrap_or(self.default))
+    }
+}
+
+/// The jump targets of the cases of a `Switch`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SwitchTable {
+    /// The targets of consecutive values, starting at `first`.
+    /// Cases without a target jump to the default target.
+    Dense {
+        first: isize,
+        targets: Vec<Option<usize>>,
+    },
+    /// The cases as `(value, target)` pairs, ordered by value.
+    Sparse(Vec<(isize, usize)>),
+}
+
+impl SwitchTable {
+    /// Builds the table for the `(value, target)` pairs of the cases.
+    /// If several cases have the same value, the first one is used.
+    /// Dense tables are used if at least half of the range of the values has cases.
+    pub fn new(cases: &[(isize, usize)]) -> Self {
+        let mut cases = cases.to_vec();
+        cases.sort_by_key(|(value, _)| *value);
+        cases.dedup_by_key(|(value, _)| *value);
+
+        let (first, last) = match (cases.first(), cases.last()) {
+            (Some((first, _)), Some((last, _))) => (*first, *last),
+            _ => return SwitchTable::Sparse(cases),
+        };
 
-        let condition = &bools[self.condition];
-        if !condition.value {
-            call_frame.ip = self.target;
+        let span = (last as i128 - first as i128 + 1) as u128;
+        if span > 2 * cases.len() as u128 {
+            return SwitchTable::Sparse(cases);
+        }
+
+        let mut targets = vec![None; span as usize];
+        for (value, target) in cases {
+            targets[(value - first) as usize] = Some(target);
+        }
+        SwitchTable::Dense { first, targets }
+    }
+
+    pub fn target(&self, value: isize) -> Option<usize> {
+        match self {
+            SwitchTable::Dense { first, targets } => {
+                let index = value.checked_sub(*first)?;
+                let index = usize::try_from(index).ok()?;
+                *targets.get(index)?
+            }
+            SwitchTable::Sparse(cases) => cases
+                .binary_search_by_key(&value, |(value, _)| *value)
+                .ok()
+                .map(|index| cases[index].1),
         }
     }
 }
@@ -74,11 +196,16 @@ pub struct IntAdd {
 }
 
 impl OpCode for IntAdd {
-    fn execute(&self, vm: &mut vm::VM) {
-        let int_reg = &mut vm.call_frame().locals.ints;
+    fn name(&self) -> &'static str {
+        "IntAdd"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let int_reg = vm.ints();
         let left_number = &int_reg[self.left_operand];
         let right_number = &int_reg[self.right_operand];
         int_reg[self.result] = left_number.add(right_number);
+        Ok(())
     }
 }
 
@@ -89,11 +216,16 @@ pub struct IntSubtract {
 }
 
 impl OpCode for IntSubtract {
-    fn execute(&self, vm: &mut vm::VM) {
-        let int_reg = &mut vm.call_frame().locals.ints;
+    fn name(&self) -> &'static str {
+        "IntSubtract"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let int_reg = vm.ints();
         let left_number = &int_reg[self.left_operand];
         let right_number = &int_reg[self.right_operand];
         int_reg[self.result] = left_number.subtract(right_number);
+        Ok(())
     }
 }
 
@@ -104,11 +236,17 @@ pub struct IntEqual {
 }
 
 impl OpCode for IntEqual {
-    fn execute(&self, vm: &mut vm::VM) {
-        let int_reg = &mut vm.call_frame().locals.ints;
+    fn name(&self) -> &'static str {
+        "IntEqual"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let int_reg = vm.ints();
         let left_number = &int_reg[self.left_operand];
         let right_number = &int_reg[self.right_operand];
-        int_reg[self.result] = left_number.add(right_number);
+
+        vm.bools()[self.result] = left_number.equal(right_number);
+        Ok(())
     }
 }
 
@@ -119,8 +257,19 @@ pub struct IntNotEqual {
 }
 
 impl OpCode for IntNotEqual {
-    fn execute(&self, _: &mut vm::VM) {
-        panic!("not implemented!")
+    fn name(&self) -> &'static str {
+        "IntNotEqual"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let int_reg = vm.ints();
+        let left_number = &int_reg[self.left_operand];
+        let right_number = &int_reg[self.right_operand];
+
+        vm.bools()[self.result] = BoolValue {
+            value: !left_number.equal(right_number).value,
+        };
+        Ok(())
     }
 }
 
@@ -131,13 +280,17 @@ pub struct IntLess {
 }
 
 impl OpCode for IntLess {
-    fn execute(&self, vm: &mut vm::VM) {
-        let call_frame = vm.call_frame();
-        let int_reg = &mut call_frame.locals.ints;
+    fn name(&self) -> &'static str {
+        "IntLess"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let int_reg = vm.ints();
         let left_number = &int_reg[self.left_operand];
         let right_number = &int_reg[self.right_operand];
 
-        call_frame.locals.bools[self.result] = left_number.less(right_number);
+        vm.bools()[self.result] = left_number.less(right_number);
+        Ok(())
     }
 }
 
@@ -148,13 +301,17 @@ pub struct IntGreater {
 }
 
 impl OpCode for IntGreater {
-    fn execute(&self, vm: &mut vm::VM) {
-        let call_frame = vm.call_frame();
-        let int_reg = &mut call_frame.locals.ints;
+    fn name(&self) -> &'static str {
+        "IntGreater"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let int_reg = vm.ints();
         let left_number = &int_reg[self.left_operand];
         let right_number = &int_reg[self.right_operand];
 
-        call_frame.locals.bools[self.result] = left_number.greater(right_number);
+        vm.bools()[self.result] = left_number.greater(right_number);
+        Ok(())
     }
 }
 
@@ -165,7 +322,11 @@ pub struct IntLessOrEqual {
 }
 
 impl OpCode for IntLessOrEqual {
-    fn execute(&self, _: &mut vm::VM) {
+    fn name(&self) -> &'static str {
+        "IntLessOrEqual"
+    }
+
+    fn execute(&self, _: &mut vm::VM) -> Result<(), VMError> {
         panic!("not implemented!")
     }
 }
@@ -177,7 +338,11 @@ pub struct IntGreaterOrEqual {
 }
 
 impl OpCode for IntGreaterOrEqual {
-    fn execute(&self, _: &mut vm::VM) {
+    fn name(&self) -> &'static str {
+        "IntGreaterOrEqual"
+    }
+
+    fn execute(&self, _: &mut vm::VM) -> Result<(), VMError> {
         panic!("not implemented!")
     }
 }
@@ -188,10 +353,37 @@ pub struct IntConstantLoad {
 }
 
 impl OpCode for IntConstantLoad {
-    fn execute(&self, vm: &mut vm::VM) {
-        let constant = vm.constants[self.index];
-        let int_reg = &mut vm.call_frame().locals.ints;
+    fn name(&self) -> &'static str {
+        "IntConstantLoad"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let constant = match vm.constant(self.index)? {
+            Value::Int(value) => value,
+            value => panic!("cannot load constant {} into an Int register", value),
+        };
+        let int_reg = vm.ints();
         int_reg[self.target] = constant;
+        Ok(())
+    }
+}
+
+/// Loads a constant of any type into a register of its type's class.
+pub struct ConstantLoad {
+    pub index: usize,
+    pub result: usize,
+}
+
+impl OpCode for ConstantLoad {
+    fn name(&self) -> &'static str {
+        "ConstantLoad"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let constant = vm.constant(self.index)?;
+        let typ = RegisterType::of(&constant.static_type());
+        vm.locals().set(typ, self.result, constant);
+        Ok(())
     }
 }
 
@@ -200,8 +392,13 @@ pub struct True {
 }
 
 impl OpCode for True {
-    fn execute(&self, vm: &mut vm::VM) {
-        vm.call_frame().locals.bools[self.index] = values::TRUE_VALUE;
+    fn name(&self) -> &'static str {
+        "True"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        vm.bools()[self.index] = values::TRUE_VALUE;
+        Ok(())
     }
 }
 
@@ -209,8 +406,13 @@ pub struct False {
     pub index: usize,
 }
 impl OpCode for False {
-    fn execute(&self, vm: &mut vm::VM) {
-        vm.call_frame().locals.bools[self.index] = values::FALSE_VALUE;
+    fn name(&self) -> &'static str {
+        "False"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        vm.bools()[self.index] = values::FALSE_VALUE;
+        Ok(())
     }
 }
 pub struct IntMove {
@@ -219,9 +421,36 @@ pub struct IntMove {
 }
 
 impl OpCode for IntMove {
-    fn execute(&self, vm: &mut vm::VM) {
-        let int_reg = &mut vm.call_frame().locals.ints;
+    fn name(&self) -> &'static str {
+        "IntMove"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let int_reg = vm.ints();
         int_reg[self.to] = int_reg[self.from];
+        Ok(())
+    }
+}
+
+/// Copies a register of any class to another register of the same class,
+/// e.g. to place an argument in the argument registers of a `Call`.
+/// Resources are moved out of the source register.
+pub struct Move {
+    pub typ: RegisterType,
+    pub from: usize,
+    pub to: usize,
+}
+
+impl OpCode for Move {
+    fn name(&self) -> &'static str {
+        "Move"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let locals = &mut vm.registers.frame(vm.call_stack.last().unwrap());
+        let value = locals.read_moving_resources(self.typ, self.from, &vm.types);
+        locals.set(self.typ, self.to, value);
+        Ok(())
     }
 }
 
@@ -230,24 +459,830 @@ pub struct GlobalFuncLoad {
     pub result: usize,
 }
 
+/// Loads a global function, i.e. a `GlobalLoad` into a function register.
 impl OpCode for GlobalFuncLoad {
-    fn execute(&self, vm: &mut vm::VM) {
-        let value = vm.globals[self.index];
-        vm.call_frame().locals.funcs[self.result] = Option::Some(value);
+    fn name(&self) -> &'static str {
+        "GlobalFuncLoad"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        load_global(vm, self.index, RegisterType::Func, self.result)
     }
 }
 
-pub struct Call<'a> {
+/// Loads the value of a global into a register of the given class.
+pub struct GlobalLoad {
+    pub index: usize,
+    pub typ: RegisterType,
+    pub result: usize,
+}
+
+impl OpCode for GlobalLoad {
+    fn name(&self) -> &'static str {
+        "GlobalLoad"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        load_global(vm, self.index, self.typ, self.result)
+    }
+}
+
+fn load_global(
+    vm: &mut vm::VM,
+    index: usize,
+    typ: RegisterType,
+    result: usize,
+) -> Result<(), VMError> {
+    let value = vm.load_global(index)?;
+    vm.locals().set(typ, result, value);
+    Ok(())
+}
+
+/// Stores the value of a register into a global variable,
+/// which must have the variable's type. Resources are moved out of the register.
+pub struct GlobalStore {
+    pub index: usize,
+    pub typ: RegisterType,
+    pub value: usize,
+}
+
+impl OpCode for GlobalStore {
+    fn name(&self) -> &'static str {
+        "GlobalStore"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let value = vm
+            .registers
+            .frame(vm.call_stack.last().unwrap())
+            .read_moving_resources(self.typ, self.value, &vm.types);
+        vm.store_global(self.index, value)
+    }
+}
+
+/// Calls the function in the function register.
+/// The arguments are the top registers of each class of the current call frame, in order,
+/// which become the parameter registers of the callee.
+pub struct Call {
     pub func_index: usize,
-    pub arguments: &'a [Argument],
+    /// The register classes of the arguments.
+    pub arguments: Vec<RegisterType>,
+    pub result: usize,
+}
+
+impl OpCode for Call {
+    fn name(&self) -> &'static str {
+        "Call"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let func = vm.locals().funcs[self.func_index].clone().unwrap();
+        vm.call(&func, None, &self.arguments, self.result)
+    }
+}
+
+/// Creates a closure of the global function, capturing the given registers.
+/// Structs are captured by value. Variables that are mutated by either the closure
+/// or the enclosing function must be captured as upvalues (see `NewUpvalue`).
+pub struct NewClosure {
+    pub function_index: usize,
+    pub captures: Vec<Argument>,
+    pub result: usize,
+}
+
+impl OpCode for NewClosure {
+    fn name(&self) -> &'static str {
+        "NewClosure"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        vm.meter(ComputationKind::Allocation)?;
+        let program = vm.program;
+        let function = &program.functions[self.function_index];
+        let locals = &mut vm.locals();
+
+        let captures = self
+            .captures
+            .iter()
+            .map(|capture| locals.get(capture.typ, capture.index).copy())
+            .collect();
+
+        locals.funcs[self.result] = Some(FunctionValue::Compiled {
+            function,
+            captures: Some(captures),
+        });
+        Ok(())
+    }
+}
+
+/// Loads a value captured by the currently executing closure.
+pub struct CaptureLoad {
+    pub index: usize,
+    pub typ: RegisterType,
+    pub result: usize,
+}
+
+impl OpCode for CaptureLoad {
+    fn name(&self) -> &'static str {
+        "CaptureLoad"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let value = vm.call_frame().captures.as_ref().unwrap()[self.index].clone();
+        vm.locals().set(self.typ, self.result, value);
+        Ok(())
+    }
+}
+
+/// Creates an upvalue holding a variable that is shared with closures,
+/// initialized with the value of the given register.
+pub struct NewUpvalue {
+    pub typ: RegisterType,
+    pub value: usize,
+    pub result: usize,
+}
+
+impl OpCode for NewUpvalue {
+    fn name(&self) -> &'static str {
+        "NewUpvalue"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        vm.meter(ComputationKind::Allocation)?;
+        let locals = &mut vm.locals();
+        let upvalue = UpvalueValue::new(locals.get(self.typ, self.value));
+        locals.values[self.result] = Value::Upvalue(upvalue);
+        Ok(())
+    }
+}
+
+pub struct UpvalueLoad {
+    pub upvalue: usize,
+    pub typ: RegisterType,
+    pub result: usize,
+}
+
+impl OpCode for UpvalueLoad {
+    fn name(&self) -> &'static str {
+        "UpvalueLoad"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let locals = &mut vm.locals();
+        let value = match &locals.values[self.upvalue] {
+            Value::Upvalue(upvalue) => upvalue.get(),
+            value => {
+                return Err(VMError::OperandTypeMismatch {
+                    instruction: self.name(),
+                    actual_type: value.static_type(),
+                })
+            }
+        };
+        locals.set(self.typ, self.result, value);
+        Ok(())
+    }
+}
+
+pub struct UpvalueStore {
+    pub upvalue: usize,
+    pub typ: RegisterType,
+    pub value: usize,
+}
+
+impl OpCode for UpvalueStore {
+    fn name(&self) -> &'static str {
+        "UpvalueStore"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let locals = &vm.locals();
+        match &locals.values[self.upvalue] {
+            Value::Upvalue(upvalue) => {
+                upvalue.set(locals.get(self.typ, self.value));
+                Ok(())
+            }
+            value => Err(VMError::OperandTypeMismatch {
+                instruction: self.name(),
+                actual_type: value.static_type(),
+            }),
+        }
+    }
+}
+
+pub struct NewComposite {
+    /// The index of the type in the program's composite types.
+    pub type_index: usize,
+    pub result: usize,
+}
+
+impl OpCode for NewComposite {
+    fn name(&self) -> &'static str {
+        "NewComposite"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        vm.meter(ComputationKind::Allocation)?;
+        let typ = &vm.program.composite_types[self.type_index];
+        let value = CompositeValue::new(&typ.identifier, typ.kind, vec![]);
+        vm.memory.charge(MemoryKind::Composite, 1)?;
+        vm.locals().values[self.result] = Value::Composite(value);
+        Ok(())
+    }
+}
+
+/// Compares the values of two value registers, e.g. enum cases.
+pub struct Equal {
+    pub left_operand: usize,
+    pub right_operand: usize,
+    pub result: usize,
+}
+
+impl OpCode for Equal {
+    fn name(&self) -> &'static str {
+        "Equal"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let locals = &mut vm.locals();
+        let value = locals.values[self.left_operand] == locals.values[self.right_operand];
+        locals.bools[self.result] = BoolValue { value };
+        Ok(())
+    }
+}
+
+pub struct NotEqual {
+    pub left_operand: usize,
+    pub right_operand: usize,
+    pub result: usize,
+}
+
+impl OpCode for NotEqual {
+    fn name(&self) -> &'static str {
+        "NotEqual"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let locals = &mut vm.locals();
+        let value = locals.values[self.left_operand] != locals.values[self.right_operand];
+        locals.bools[self.result] = BoolValue { value };
+        Ok(())
+    }
+}
+
+/*
+*  Arrays and dictionaries
+*/
+
+/// Creates an array of the values of the element registers, e.g. `[a, b]`.
+pub struct NewArray {
+    pub element_type: StaticType,
+    pub elements: Vec<Argument>,
+    pub result: usize,
+}
+
+impl OpCode for NewArray {
+    fn name(&self) -> &'static str {
+        "NewArray"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        vm.meter(ComputationKind::Allocation)?;
+        let locals = &mut vm.locals();
+        let elements = self
+            .elements
+            .iter()
+            .map(|element| locals.get(element.typ, element.index).copy())
+            .collect();
+        let array = Value::Array(ArrayValue::new(self.element_type.clone(), elements));
+        vm.memory.charge_copy(&array)?;
+        vm.locals().values[self.result] = array;
+        Ok(())
+    }
+}
+
+/// `array.append(value)`, also through a reference.
+/// Appending to a stored array writes only the slabs on the path to its last element.
+pub struct ArrayAppend {
+    pub array: usize,
+    pub value: Argument,
+}
+
+impl OpCode for ArrayAppend {
+    fn name(&self) -> &'static str {
+        "ArrayAppend"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let locals = &vm.locals();
+        let value = locals.get(self.value.typ, self.value.index).copy();
+        let array = locals.values[self.array].clone();
+        vm.memory.charge_copy(&value)?;
+        match vm.dereference(&array)? {
+            Value::Array(array) => {
+                vm.memory.charge(MemoryKind::Array, 1)?;
+                array.append(value);
+            }
+            Value::StoredArray(array) => {
+                vm.memory.charge(MemoryKind::Array, 1)?;
+                array.append(&mut vm.storage, value)?;
+            }
+            value => {
+                return Err(VMError::OperandTypeMismatch {
+                    instruction: self.name(),
+                    actual_type: value.static_type(),
+                })
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Creates a dictionary of the values of the key and value registers, e.g. `{k: v}`.
+pub struct NewDictionary {
+    pub key_type: StaticType,
+    pub value_type: StaticType,
+    pub entries: Vec<(Argument, Argument)>,
+    pub result: usize,
+}
+
+impl OpCode for NewDictionary {
+    fn name(&self) -> &'static str {
+        "NewDictionary"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        vm.meter(ComputationKind::Allocation)?;
+        let locals = &mut vm.locals();
+        let entries = self
+            .entries
+            .iter()
+            .map(|(key, value)| {
+                (
+                    locals.get(key.typ, key.index).copy(),
+                    locals.get(value.typ, value.index).copy(),
+                )
+            })
+            .collect();
+        let dictionary = Value::Dictionary(DictionaryValue::new(
+            self.key_type.clone(),
+            self.value_type.clone(),
+            entries,
+        ));
+        vm.memory.charge_copy(&dictionary)?;
+        vm.locals().values[self.result] = dictionary;
+        Ok(())
+    }
+}
+
+/// `dictionary.insert(key: k, v)`, also through a reference:
+/// results in the previous value as an optional.
+pub struct DictionaryInsert {
+    pub dictionary: usize,
+    pub key: Argument,
+    pub value: Argument,
+    pub result: usize,
+}
+
+impl OpCode for DictionaryInsert {
+    fn name(&self) -> &'static str {
+        "DictionaryInsert"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let locals = &vm.locals();
+        let key = locals.get(self.key.typ, self.key.index).copy();
+        let value = locals.get(self.value.typ, self.value.index).copy();
+        let dictionary = locals.values[self.dictionary].clone();
+        vm.memory.charge_copy(&key)?;
+        vm.memory.charge_copy(&value)?;
+        let previous = match vm.dereference(&dictionary)? {
+            Value::Dictionary(dictionary) => {
+                let previous = dictionary.insert(key, value);
+                if previous.is_none() {
+                    vm.memory.charge(MemoryKind::Dictionary, 1)?;
+                }
+                previous
+            }
+            Value::StoredDictionary(dictionary) => {
+                let previous = dictionary.insert(&mut vm.storage, key, value)?;
+                if previous.is_none() {
+                    vm.memory.charge(MemoryKind::Dictionary, 1)?;
+                }
+                previous
+            }
+            value => {
+                return Err(VMError::OperandTypeMismatch {
+                    instruction: self.name(),
+                    actual_type: value.static_type(),
+                })
+            }
+        };
+        vm.locals().values[self.result] = previous.map_or(Value::Nil, Value::some);
+        Ok(())
+    }
+}
+
+/*
+*  Ranges
+*/
+
+/// `InclusiveRange(start, end)` or `InclusiveRange(start, end, step: step)`.
+/// Fails if the step is zero or moves away from the end.
+pub struct NewInclusiveRange {
+    pub start: Argument,
+    pub end: Argument,
+    pub step: Option<Argument>,
+    pub result: usize,
+}
+
+impl OpCode for NewInclusiveRange {
+    fn name(&self) -> &'static str {
+        "NewInclusiveRange"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        vm.meter(ComputationKind::Allocation)?;
+        let locals = &mut vm.locals();
+        let start = locals.get(self.start.typ, self.start.index);
+        let end = locals.get(self.end.typ, self.end.index);
+        let step = self
+            .step
+            .as_ref()
+            .map(|step| locals.get(step.typ, step.index));
+        let range = InclusiveRangeValue::new(&start, &end, step.as_ref())?;
+        locals.values[self.result] = Value::InclusiveRange(range);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InclusiveRangeMember {
+    Start,
+    End,
+    Step,
+}
+
+/// Loads `range.start`, `range.end` or `range.step` into a register of the given class.
+pub struct InclusiveRangeMemberLoad {
+    pub range: usize,
+    pub member: InclusiveRangeMember,
+    pub typ: RegisterType,
+    pub result: usize,
+}
+
+impl OpCode for InclusiveRangeMemberLoad {
+    fn name(&self) -> &'static str {
+        "InclusiveRangeMemberLoad"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let locals = &mut vm.locals();
+        let value = match &locals.values[self.range] {
+            Value::InclusiveRange(range) => match self.member {
+                InclusiveRangeMember::Start => range.start(),
+                InclusiveRangeMember::End => range.end(),
+                InclusiveRangeMember::Step => range.step(),
+            },
+            value => {
+                return Err(VMError::OperandTypeMismatch {
+                    instruction: self.name(),
+                    actual_type: value.static_type(),
+                })
+            }
+        };
+        locals.set(self.typ, self.result, value);
+        Ok(())
+    }
+}
+
+/// `range.contains(value)`
+pub struct InclusiveRangeContains {
+    pub range: usize,
+    pub value: Argument,
+    pub result: usize,
+}
+
+impl OpCode for InclusiveRangeContains {
+    fn name(&self) -> &'static str {
+        "InclusiveRangeContains"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let locals = &mut vm.locals();
+        let value = locals.get(self.value.typ, self.value.index);
+        let contains = match &locals.values[self.range] {
+            Value::InclusiveRange(range) => range.contains(&value),
+            value => {
+                return Err(VMError::OperandTypeMismatch {
+                    instruction: self.name(),
+                    actual_type: value.static_type(),
+                })
+            }
+        };
+        locals.bools[self.result] = BoolValue { value: contains };
+        Ok(())
+    }
+}
+
+/*
+*  Iteration
+*/
+
+// A `for` loop is compiled to:
+//
+//     IterNew { iterable, result: iterator }
+//   loop:
+//     IterHasNext { iterator, result: condition }
+//     JumpIfFalse { condition, target: end }
+//     IterNext { iterator, typ, result: element }
+//     ...body...
+//     Jump { target: loop }
+//   end:
+//
+// so each iteration executes exactly one `IterNext`.
+
+/// Creates an iterator over an array, the keys of a dictionary, the elements of a range,
+/// or the characters of a string, or a reference to one of them.
+pub struct IterNew {
+    pub iterable: usize,
+    pub result: usize,
+}
+
+impl OpCode for IterNew {
+    fn name(&self) -> &'static str {
+        "IterNew"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let iterable = vm.locals().values[self.iterable].clone();
+        let iterable = vm.dereference(&iterable)?;
+        let iterator = IteratorValue::new(&iterable, &vm.storage)?.ok_or_else(|| {
+            VMError::OperandTypeMismatch {
+                instruction: self.name(),
+                actual_type: iterable.static_type(),
+            }
+        })?;
+        vm.locals().values[self.result] = Value::Iterator(iterator);
+        Ok(())
+    }
+}
+
+/// Reports whether the iterator has more elements.
+/// Fails if the iterated container has been mutated.
+pub struct IterHasNext {
+    pub iterator: usize,
+    pub result: usize,
+}
+
+impl OpCode for IterHasNext {
+    fn name(&self) -> &'static str {
+        "IterHasNext"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let locals = vm.registers.frame(vm.call_stack.last().unwrap());
+        let value = match &locals.values[self.iterator] {
+            Value::Iterator(iterator) => iterator.has_next(&vm.storage)?,
+            value => {
+                return Err(VMError::OperandTypeMismatch {
+                    instruction: self.name(),
+                    actual_type: value.static_type(),
+                })
+            }
+        };
+        vm.bools()[self.result] = BoolValue { value };
+        Ok(())
+    }
+}
+
+/// Advances the iterator and loads the next element into a register of the given class.
+/// Fails if the iterated container has been mutated.
+pub struct IterNext {
+    pub iterator: usize,
+    pub typ: RegisterType,
     pub result: usize,
 }
 
-impl<'a> OpCode for Call<'a> {
-    fn execute(&self, vm: &mut vm::VM) {
-        let value = vm.call_frame().locals.funcs[self.func_index].as_ref();
-        let func = value.unwrap().function;
-        vm.push_call_frame(func, self.arguments, self.result);
+impl OpCode for IterNext {
+    fn name(&self) -> &'static str {
+        "IterNext"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let locals = &mut vm.registers.frame(vm.call_stack.last().unwrap());
+        let element = match &mut locals.values[self.iterator] {
+            Value::Iterator(iterator) => {
+                let element = iterator
+                    .next_element(&vm.storage)?
+                    .expect("iterator is exhausted");
+                // The characters of a string are allocated as they are iterated,
+                // whereas the elements of containers and ranges are shared or unboxed.
+                if let IteratorValue::Characters { .. } = iterator {
+                    vm.memory.charge_value(&element)?;
+                }
+                element
+            }
+            value => {
+                return Err(VMError::OperandTypeMismatch {
+                    instruction: self.name(),
+                    actual_type: value.static_type(),
+                })
+            }
+        };
+        locals.set(self.typ, self.result, element);
+        Ok(())
+    }
+}
+
+/*
+*  Enums
+*/
+
+/// Creates the case of an enum type, e.g. `E.a`.
+pub struct NewEnumCase {
+    /// The index of the enum type in the program's composite types.
+    pub type_index: usize,
+    /// The index of the case, which is also its raw value.
+    pub case: usize,
+    pub result: usize,
+}
+
+impl OpCode for NewEnumCase {
+    fn name(&self) -> &'static str {
+        "NewEnumCase"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        vm.meter(ComputationKind::Allocation)?;
+        let typ = &vm.program.composite_types[self.type_index];
+        let value = new_enum_case(typ, self.case);
+        vm.memory.charge_value(&value)?;
+        vm.locals().values[self.result] = value;
+        Ok(())
+    }
+}
+
+fn new_enum_case<'a>(typ: &CompositeType, case: usize) -> Value<'a> {
+    let enum_info = typ.enum_info.as_ref().unwrap();
+    let raw_value = Value::integer(&enum_info.raw_type, case as i128).unwrap();
+    Value::Composite(CompositeValue::new(
+        &typ.identifier,
+        CompositeKind::Enum,
+        vec![(ENUM_RAW_VALUE_FIELD, raw_value)],
+    ))
+}
+
+const ENUM_RAW_VALUE_FIELD: &str = "rawValue";
+
+/// Loads the raw value of an enum case, `e.rawValue`,
+/// into a register of the class of the enum's raw type.
+pub struct EnumRawValue {
+    pub value: usize,
+    pub result: usize,
+}
+
+impl OpCode for EnumRawValue {
+    fn name(&self) -> &'static str {
+        "EnumRawValue"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let locals = &mut vm.locals();
+        let value = &locals.values[self.value];
+        let raw_value = match value {
+            Value::Composite(composite) => composite.get_field(ENUM_RAW_VALUE_FIELD),
+            _ => None,
+        };
+        let raw_value = raw_value.ok_or_else(|| VMError::OperandTypeMismatch {
+            instruction: self.name(),
+            actual_type: value.static_type(),
+        })?;
+        let typ = RegisterType::of(&raw_value.static_type());
+        locals.set(typ, self.result, raw_value);
+        Ok(())
+    }
+}
+
+/// The failable enum constructor `E(rawValue: r)`:
+/// returns the case with the given raw value, or `nil` if there is no such case.
+pub struct EnumLookup {
+    /// The index of the enum type in the program's composite types.
+    pub type_index: usize,
+    pub raw_value: usize,
+    pub typ: RegisterType,
+    pub result: usize,
+}
+
+impl OpCode for EnumLookup {
+    fn name(&self) -> &'static str {
+        "EnumLookup"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let typ = &vm.program.composite_types[self.type_index];
+        let enum_info = typ.enum_info.as_ref().unwrap();
+
+        let locals = &mut vm.locals();
+        let case = locals
+            .get(self.typ, self.raw_value)
+            .to_i128()
+            .and_then(|raw_value| usize::try_from(raw_value).ok())
+            .filter(|case| *case < enum_info.cases.len());
+
+        let result = match case {
+            Some(case) => Value::some(new_enum_case(typ, case)),
+            None => Value::Nil,
+        };
+        locals.values[self.result] = result;
+        Ok(())
+    }
+}
+
+/// Invokes a method on the composite value, or reference to a composite value,
+/// in the receiver register. The implementation is looked up in the method table
+/// of the receiver's dynamic type, so calls through interface types dispatch dynamically.
+/// The receiver register is the value register below the value arguments, see `Call`.
+pub struct InvokeMethod {
+    /// The index of the method name in the program's method names.
+    pub method_name_index: usize,
+    /// The register classes of the arguments, excluding the receiver.
+    pub arguments: Vec<RegisterType>,
+    pub result: usize,
+
+    /// The receiver type and function resolved by the last invocation.
+    resolved: RefCell<Option<(String, usize)>>,
+}
+
+impl InvokeMethod {
+    pub fn new(method_name_index: usize, arguments: Vec<RegisterType>, result: usize) -> Self {
+        InvokeMethod {
+            method_name_index,
+            arguments,
+            result,
+            resolved: RefCell::new(None),
+        }
+    }
+
+    fn resolve(&self, vm: &vm::VM, receiver: &CompositeValue) -> Result<usize, VMError> {
+        if let Some((identifier, function_index)) = self.resolved.borrow().as_ref() {
+            if receiver.has_type(identifier) {
+                return Ok(*function_index);
+            }
+        }
+
+        let identifier = receiver.identifier();
+        let function_index = vm
+            .vtables
+            .get(&identifier)
+            .and_then(|vtable| vtable.get(self.method_name_index).copied().flatten())
+            .ok_or_else(|| VMError::MethodNotFound {
+                type_identifier: identifier.clone(),
+                method_name: vm.program.method_names[self.method_name_index].clone(),
+            })?;
+
+        self.resolved.replace(Some((identifier, function_index)));
+
+        Ok(function_index)
+    }
+
+    /// The error for invoking the method on a value which is not a composite.
+    fn method_not_found(&self, program: &bbq::Program, receiver: &Value) -> VMError {
+        VMError::MethodNotFound {
+            type_identifier: receiver.static_type().to_string(),
+            method_name: program.method_names[self.method_name_index].clone(),
+        }
+    }
+}
+
+impl OpCode for InvokeMethod {
+    fn name(&self) -> &'static str {
+        "InvokeMethod"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let receiver_index = vm.locals().values.len()
+            - 1
+            - self
+                .arguments
+                .iter()
+                .filter(|typ| **typ == RegisterType::Value)
+                .count();
+        let program = vm.program;
+        let receiver = vm.locals().values[receiver_index].clone();
+        let receiver = match vm.dereference(&receiver)? {
+            Value::Composite(composite) => composite,
+            value => return Err(self.method_not_found(program, &value)),
+        };
+
+        let function_index = self.resolve(vm, &receiver)?;
+        let function = FunctionValue::new(&program.functions[function_index]);
+
+        vm.call(
+            &function,
+            Some(Value::Composite(receiver)),
+            &self.arguments,
+            self.result,
+        )
     }
 }
 
@@ -255,3 +1290,105 @@ pub struct Argument {
     pub typ: registers::RegisterType,
     pub index: usize,
 }
+
+/// Moves a value from a register of any class into a value register,
+/// e.g. for the static cast `x as AnyStruct`.
+/// Resources are moved out of the source register, other values are copied.
+pub struct Upcast {
+    pub typ: RegisterType,
+    pub value: usize,
+    pub result: usize,
+}
+
+impl OpCode for Upcast {
+    fn name(&self) -> &'static str {
+        "Upcast"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let mut locals = vm.registers.frame(vm.call_stack.last().unwrap());
+        locals.values[self.result] = locals.read_moving_resources(self.typ, self.value, &vm.types);
+        Ok(())
+    }
+}
+
+/// `value as? T`: results in the value wrapped in an optional
+/// if its dynamic type is a subtype of the target type, and `nil` otherwise.
+/// Resources are moved out of the source register if the cast succeeds.
+pub struct FailableCast {
+    pub value: usize,
+    pub target_type: StaticType,
+    pub result: usize,
+}
+
+impl OpCode for FailableCast {
+    fn name(&self) -> &'static str {
+        "FailableCast"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let mut locals = vm.registers.frame(vm.call_stack.last().unwrap());
+
+        let result = if locals.values[self.value].is_instance(&self.target_type, &vm.types) {
+            let value = locals.read_moving_resources(RegisterType::Value, self.value, &vm.types);
+            Value::some(convert(value, &self.target_type, &vm.types))
+        } else {
+            Value::Nil
+        };
+
+        locals.values[self.result] = result;
+        Ok(())
+    }
+}
+
+/// `value as! T`: results in the value if its dynamic type is a subtype of the target type,
+/// and fails otherwise. The result is stored in the register class of the target type.
+/// Resources are moved out of the source register.
+pub struct ForceCast {
+    pub value: usize,
+    pub target_type: StaticType,
+    pub result: usize,
+}
+
+impl OpCode for ForceCast {
+    fn name(&self) -> &'static str {
+        "ForceCast"
+    }
+
+    fn execute(&self, vm: &mut vm::VM) -> Result<(), VMError> {
+        let mut locals = vm.registers.frame(vm.call_stack.last().unwrap());
+
+        let value = &locals.values[self.value];
+        if !value.is_instance(&self.target_type, &vm.types) {
+            return Err(VMError::ForceCastTypeMismatch {
+                expected_type: self.target_type.clone(),
+                actual_type: value.static_type(),
+            });
+        }
+
+        let value = locals.read_moving_resources(RegisterType::Value, self.value, &vm.types);
+        let value = convert(value, &self.target_type, &vm.types);
+        locals.set(RegisterType::of(&self.target_type), self.result, value);
+        Ok(())
+    }
+}
+
+/// Converts a value to the target type of a successful cast,
+/// wrapping it in optionals up to the optional depth of the target type.
+/// Also used for arguments and values assigned to variables of optional types.
+pub(crate) fn convert<'a>(
+    value: Value<'a>,
+    target_type: &StaticType,
+    types: &TypeRegistry,
+) -> Value<'a> {
+    match (target_type, value) {
+        (StaticType::Optional(_), Value::Nil) => Value::Nil,
+        (StaticType::Optional(inner), value) if value.is_instance(inner, types) => {
+            Value::some(convert(value, inner, types))
+        }
+        (StaticType::Optional(inner), Value::Some(value)) => {
+            Value::some(convert(*value, inner, types))
+        }
+        (_, value) => value,
+    }
+}
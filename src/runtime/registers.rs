@@ -16,14 +16,18 @@
  * limitations under the License.
  */
 
+use crate::runtime::types::StaticType;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct RegisterCounts {
     pub ints: usize,
     pub bools: usize,
     pub funcs: usize,
+    pub values: usize,
 }
 
 impl RegisterCounts {
-    fn next_index(mut self, register_type: RegisterType) -> usize {
+    pub fn next_index(&mut self, register_type: RegisterType) -> usize {
         let index: usize;
         match register_type {
             RegisterType::Int => {
@@ -38,14 +42,33 @@ impl RegisterCounts {
                 index = self.funcs;
                 self.funcs += 1;
             }
+            RegisterType::Value => {
+                index = self.values;
+                self.values += 1;
+            }
         }
 
-        return index;
+        index
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RegisterType {
     Int,
     Bool,
     Func,
+    /// Values of all other types, held as `values::Value`.
+    Value,
+}
+
+impl RegisterType {
+    /// Returns the class of registers that hold values of the given type.
+    pub fn of(typ: &StaticType) -> RegisterType {
+        match typ {
+            StaticType::Int => RegisterType::Int,
+            StaticType::Bool => RegisterType::Bool,
+            StaticType::Function => RegisterType::Func,
+            _ => RegisterType::Value,
+        }
+    }
 }
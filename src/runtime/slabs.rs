@@ -0,0 +1,1158 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt;
+use std::rc::Rc;
+
+use crate::runtime::encoding::Encoder;
+use crate::runtime::errors::VMError;
+use crate::runtime::storage::{Storage, StorageKey};
+use crate::runtime::types::StaticType;
+use crate::runtime::values::{AddressValue, ArrayValue, DictionaryValue, FixedSizeIntValue, Value};
+
+/*
+*  Slabs
+*/
+
+/// The maximum number of elements of an array leaf slab, and of children of an array branch slab.
+pub const ARRAY_SLAB_CAPACITY: usize = 64;
+
+/// The maximum number of entries of a dictionary leaf slab.
+/// Larger leaves are split into a branch, by the next bits of the hashes of their keys.
+pub const DICTIONARY_SLAB_CAPACITY: usize = 64;
+
+/// The number of hash bits that select the child of a dictionary branch slab.
+const DICTIONARY_BRANCH_BITS: u32 = 5;
+
+/// The number of children of a dictionary branch slab.
+pub const DICTIONARY_BRANCH_WIDTH: usize = 1 << DICTIONARY_BRANCH_BITS;
+
+/// A node of a stored array or dictionary, stored in the account at its own slab ID.
+///
+/// Arrays are counted B+-trees: branches keep the number of elements of each child,
+/// so elements are found by index. Dictionaries are hash array mapped tries:
+/// branches select the child by the bits of the key's hash at the branch's depth.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Slab<'a> {
+    ArrayLeaf(Vec<Value<'a>>),
+    ArrayBranch(Vec<ArrayChild>),
+    DictionaryLeaf(Vec<(Value<'a>, Value<'a>)>),
+    DictionaryBranch {
+        /// The number of entries in the branch's children.
+        count: u64,
+        /// The ID of the child slab for each value of the hash bits, if any.
+        children: Vec<Option<u64>>,
+    },
+}
+
+/// A child of an array branch slab.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArrayChild {
+    pub id: u64,
+    /// The number of elements in the child.
+    pub count: u64,
+}
+
+/// A slab, as stored in the account.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlabValue<'a> {
+    /// The number of mutations of the collection, kept by its root slab,
+    /// used to detect mutations during iteration.
+    pub mutations: u64,
+    pub slab: Rc<Slab<'a>>,
+}
+
+impl<'a> fmt::Display for SlabValue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &*self.slab {
+            Slab::ArrayLeaf(elements) => {
+                write!(f, "ArrayLeaf[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Slab::ArrayBranch(children) => {
+                write!(f, "ArrayBranch[")?;
+                for (i, child) in children.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "slab({}): {}", child.id, child.count)?;
+                }
+                write!(f, "]")
+            }
+            Slab::DictionaryLeaf(entries) => {
+                write!(f, "DictionaryLeaf{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Slab::DictionaryBranch { count, children } => {
+                write!(f, "DictionaryBranch(count: {}, children: [", count)?;
+                let children = children
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(slot, id)| id.map(|id| (slot, id)));
+                for (i, (slot, id)) in children.enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: slab({})", slot, id)?;
+                }
+                write!(f, "])")
+            }
+        }
+    }
+}
+
+impl<'a> SlabValue<'a> {
+    /// Returns the values of a leaf: the elements, or the keys and values of the entries.
+    pub(crate) fn values(&self) -> Vec<&Value<'a>> {
+        match &*self.slab {
+            Slab::ArrayLeaf(elements) => elements.iter().collect(),
+            Slab::DictionaryLeaf(entries) => entries
+                .iter()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+            Slab::ArrayBranch(_) | Slab::DictionaryBranch { .. } => vec![],
+        }
+    }
+
+    /// Returns whether the slab contains collections or composites stored inline,
+    /// which can be mutated in place.
+    pub(crate) fn is_mutable_in_place(&self) -> bool {
+        self.values()
+            .into_iter()
+            .any(|value| value.is_mutable_in_place())
+    }
+}
+
+fn read_slab<'a>(
+    storage: &dyn Storage<'a>,
+    address: AddressValue,
+    id: u64,
+) -> Result<SlabValue<'a>, VMError> {
+    match storage.read(address, &StorageKey::Slab(id)) {
+        Some(Value::Slab(slab)) => Ok(slab),
+        None => Err(corrupted(address, format!("missing slab {}", id))),
+        Some(value) => Err(corrupted(
+            address,
+            format!("expected slab {}, got `{}`", id, value),
+        )),
+    }
+}
+
+fn read_node<'a>(
+    storage: &dyn Storage<'a>,
+    address: AddressValue,
+    id: u64,
+) -> Result<Slab<'a>, VMError> {
+    Ok((*read_slab(storage, address, id)?.slab).clone())
+}
+
+fn root_mutations(
+    storage: &dyn Storage,
+    address: AddressValue,
+    root: u64,
+) -> Result<Option<u64>, VMError> {
+    match storage.read(address, &StorageKey::Slab(root)) {
+        Some(Value::Slab(slab)) => Ok(Some(slab.mutations)),
+        None => Ok(None),
+        Some(value) => Err(corrupted(
+            address,
+            format!("expected slab {}, got `{}`", root, value),
+        )),
+    }
+}
+
+fn corrupted(address: AddressValue, message: String) -> VMError {
+    VMError::CorruptedStorage { address, message }
+}
+
+/// Returns the error for a slab of the wrong kind, e.g. a dictionary slab in an array.
+fn unexpected_slab(address: AddressValue, expected: &str, slab: &Slab) -> VMError {
+    corrupted(
+        address,
+        format!(
+            "expected {} slab, got `{}`",
+            expected,
+            SlabValue {
+                mutations: 0,
+                slab: Rc::new(slab.clone()),
+            }
+        ),
+    )
+}
+
+fn write_slab<'a>(
+    storage: &mut dyn Storage<'a>,
+    address: AddressValue,
+    id: u64,
+    mutations: u64,
+    slab: Slab<'a>,
+) {
+    let value = Value::Slab(SlabValue {
+        mutations,
+        slab: Rc::new(slab),
+    });
+    storage.write(address, &StorageKey::Slab(id), Some(value));
+}
+
+fn write_node<'a>(storage: &mut dyn Storage<'a>, address: AddressValue, id: u64, slab: Slab<'a>) {
+    write_slab(storage, address, id, 0, slab);
+}
+
+fn remove_slab(storage: &mut dyn Storage, address: AddressValue, id: u64) {
+    storage.write(address, &StorageKey::Slab(id), None);
+}
+
+/// Returns a new slab ID of the account. IDs start at 1.
+fn allocate_slab_id(storage: &mut dyn Storage, address: AddressValue) -> Result<u64, VMError> {
+    let id = match storage.read(address, &StorageKey::LastSlabId) {
+        Some(Value::FixedSizeInt(FixedSizeIntValue::UInt64(id))) => id + 1,
+        None => 1,
+        Some(value) => {
+            return Err(corrupted(
+                address,
+                format!("invalid last slab ID `{}`", value),
+            ))
+        }
+    };
+    let value = Value::FixedSizeInt(FixedSizeIntValue::UInt64(id));
+    storage.write(address, &StorageKey::LastSlabId, Some(value));
+    Ok(id)
+}
+
+/// Writes the slab at a new ID, and returns the ID.
+fn store_node<'a>(
+    storage: &mut dyn Storage<'a>,
+    address: AddressValue,
+    slab: Slab<'a>,
+) -> Result<u64, VMError> {
+    let id = allocate_slab_id(storage, address)?;
+    write_node(storage, address, id, slab);
+    Ok(id)
+}
+
+/// Applies the mutation to the root slab of a collection, and counts the mutation.
+/// The root slab keeps its ID, so handles to the collection remain valid.
+fn update_root<'a, R>(
+    storage: &mut dyn Storage<'a>,
+    address: AddressValue,
+    root: u64,
+    update: impl FnOnce(&mut dyn Storage<'a>, Slab<'a>) -> Result<(Slab<'a>, R), VMError>,
+) -> Result<R, VMError> {
+    let SlabValue { mutations, slab } = read_slab(storage, address, root)?;
+    let (slab, result) = update(storage, (*slab).clone())?;
+    write_slab(storage, address, root, mutations + 1, slab);
+    Ok(result)
+}
+
+/// Removes the slab and its descendants.
+fn remove_tree(storage: &mut dyn Storage, address: AddressValue, id: u64) -> Result<(), VMError> {
+    match &*read_slab(storage, address, id)?.slab {
+        Slab::ArrayBranch(children) => {
+            for child in children {
+                remove_tree(storage, address, child.id)?;
+            }
+        }
+        Slab::DictionaryBranch { children, .. } => {
+            for id in children.iter().flatten() {
+                remove_tree(storage, address, *id)?;
+            }
+        }
+        Slab::ArrayLeaf(_) | Slab::DictionaryLeaf(_) => {}
+    }
+    remove_slab(storage, address, id);
+    Ok(())
+}
+
+/// Splits the items into chunks of at most the given size.
+fn chunks<T>(items: Vec<T>, size: usize) -> Vec<Vec<T>> {
+    let mut chunks = vec![];
+    let mut items = items.into_iter().peekable();
+    while items.peek().is_some() {
+        chunks.push(items.by_ref().take(size).collect());
+    }
+    chunks
+}
+
+/*
+*  StoredArrayValue
+*/
+
+/// An array stored in slabs of an account, of which only the touched slabs are read and written.
+/// Copies of the handle share the stored elements.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StoredArrayValue {
+    pub address: AddressValue,
+    /// The ID of the root slab, which does not change when the array grows or shrinks.
+    pub root: u64,
+    pub element_type: StaticType,
+}
+
+impl StoredArrayValue {
+    /// Stores the elements in new slabs of the account.
+    pub fn new<'a>(
+        storage: &mut dyn Storage<'a>,
+        address: AddressValue,
+        element_type: StaticType,
+        elements: Vec<Value<'a>>,
+    ) -> Result<Self, VMError> {
+        let root = allocate_slab_id(storage, address)?;
+
+        let mut level: Vec<(Slab<'a>, u64)> = chunks(elements, ARRAY_SLAB_CAPACITY)
+            .into_iter()
+            .map(|elements| {
+                let count = elements.len() as u64;
+                (Slab::ArrayLeaf(elements), count)
+            })
+            .collect();
+        while level.len() > 1 {
+            let children = level
+                .into_iter()
+                .map(|(slab, count)| {
+                    Ok(ArrayChild {
+                        id: store_node(storage, address, slab)?,
+                        count,
+                    })
+                })
+                .collect::<Result<_, VMError>>()?;
+            level = chunks(children, ARRAY_SLAB_CAPACITY)
+                .into_iter()
+                .map(|children| {
+                    let count = children.iter().map(|child| child.count).sum();
+                    (Slab::ArrayBranch(children), count)
+                })
+                .collect();
+        }
+        let slab = level
+            .pop()
+            .map_or(Slab::ArrayLeaf(vec![]), |(slab, _)| slab);
+        write_slab(storage, address, root, 0, slab);
+
+        Ok(StoredArrayValue {
+            address,
+            root,
+            element_type,
+        })
+    }
+
+    pub fn len(&self, storage: &dyn Storage) -> Result<usize, VMError> {
+        let slab = read_slab(storage, self.address, self.root)?.slab;
+        Ok(array_count(self.address, &slab)? as usize)
+    }
+
+    pub fn is_empty(&self, storage: &dyn Storage) -> Result<bool, VMError> {
+        Ok(self.len(storage)? == 0)
+    }
+
+    pub fn get<'a>(
+        &self,
+        storage: &dyn Storage<'a>,
+        index: usize,
+    ) -> Result<Option<Value<'a>>, VMError> {
+        let mut slab = read_slab(storage, self.address, self.root)?.slab;
+        let mut index = index as u64;
+        loop {
+            let id = match &*slab {
+                Slab::ArrayLeaf(elements) => return Ok(elements.get(index as usize).cloned()),
+                Slab::ArrayBranch(children) => {
+                    let Some((position, before)) = locate(children, index) else {
+                        return Ok(None);
+                    };
+                    index -= before;
+                    children[position].id
+                }
+                slab => return Err(unexpected_slab(self.address, "array", slab)),
+            };
+            slab = read_slab(storage, self.address, id)?.slab;
+        }
+    }
+
+    /// Returns all elements, reading all slabs.
+    pub fn elements<'a>(&self, storage: &dyn Storage<'a>) -> Result<Vec<Value<'a>>, VMError> {
+        let mut elements = vec![];
+        collect_elements(storage, self.address, self.root, &mut elements)?;
+        Ok(elements)
+    }
+
+    pub fn append<'a>(
+        &self,
+        storage: &mut dyn Storage<'a>,
+        value: Value<'a>,
+    ) -> Result<(), VMError> {
+        let index = self.len(storage)?;
+        self.insert(storage, index, value)
+    }
+
+    /// Inserts the value at the index, shifting the following elements.
+    /// Panics if the index is out of bounds.
+    pub fn insert<'a>(
+        &self,
+        storage: &mut dyn Storage<'a>,
+        index: usize,
+        value: Value<'a>,
+    ) -> Result<(), VMError> {
+        let address = self.address;
+        update_root(storage, address, self.root, |storage, slab| {
+            assert!(
+                index as u64 <= array_count(address, &slab)?,
+                "index out of bounds"
+            );
+            let slab = match array_insert(storage, address, slab, index as u64, value)? {
+                (slab, None) => slab,
+                // The root keeps its ID, so its halves move to new slabs.
+                (left, Some(right)) => Slab::ArrayBranch(vec![
+                    ArrayChild {
+                        count: array_count(address, &left)?,
+                        id: store_node(storage, address, left)?,
+                    },
+                    ArrayChild {
+                        count: array_count(address, &right)?,
+                        id: store_node(storage, address, right)?,
+                    },
+                ]),
+            };
+            Ok((slab, ()))
+        })
+    }
+
+    /// Replaces the element at the index, returning the previous element,
+    /// or `None` if the index is out of bounds.
+    pub fn set<'a>(
+        &self,
+        storage: &mut dyn Storage<'a>,
+        index: usize,
+        value: Value<'a>,
+    ) -> Result<Option<Value<'a>>, VMError> {
+        if index >= self.len(storage)? {
+            return Ok(None);
+        }
+        let address = self.address;
+        let previous = update_root(storage, address, self.root, |storage, slab| {
+            array_set(storage, address, slab, index as u64, value)
+        })?;
+        Ok(Some(previous))
+    }
+
+    /// Removes the element at the index, or returns `None` if the index is out of bounds.
+    pub fn remove<'a>(
+        &self,
+        storage: &mut dyn Storage<'a>,
+        index: usize,
+    ) -> Result<Option<Value<'a>>, VMError> {
+        if index >= self.len(storage)? {
+            return Ok(None);
+        }
+        let address = self.address;
+        let removed = update_root(storage, address, self.root, |storage, slab| {
+            let (mut slab, removed) = array_remove(storage, address, slab, index as u64)?;
+            // Shrink the tree when the root has a single child left.
+            while let Slab::ArrayBranch(children) = &slab {
+                match children.as_slice() {
+                    [] => slab = Slab::ArrayLeaf(vec![]),
+                    [child] => {
+                        let id = child.id;
+                        slab = read_node(storage, address, id)?;
+                        remove_slab(storage, address, id);
+                    }
+                    _ => break,
+                }
+            }
+            Ok((slab, removed))
+        })?;
+        Ok(Some(removed))
+    }
+
+    /// Removes all slabs of the array.
+    pub fn delete(&self, storage: &mut dyn Storage) -> Result<(), VMError> {
+        remove_tree(storage, self.address, self.root)
+    }
+
+    /// Returns the number of mutations so far, or `None` if the slabs were removed.
+    pub(crate) fn mutations(&self, storage: &dyn Storage) -> Result<Option<u64>, VMError> {
+        root_mutations(storage, self.address, self.root)
+    }
+}
+
+fn array_count(address: AddressValue, slab: &Slab) -> Result<u64, VMError> {
+    match slab {
+        Slab::ArrayLeaf(elements) => Ok(elements.len() as u64),
+        Slab::ArrayBranch(children) => Ok(children.iter().map(|child| child.count).sum()),
+        slab => Err(unexpected_slab(address, "array", slab)),
+    }
+}
+
+/// Returns the position of the child that contains the index,
+/// and the number of elements in the children before it.
+fn locate(children: &[ArrayChild], index: u64) -> Option<(usize, u64)> {
+    let mut before = 0;
+    for (position, child) in children.iter().enumerate() {
+        if index < before + child.count {
+            return Some((position, before));
+        }
+        before += child.count;
+    }
+    None
+}
+
+/// Like `locate`, but the index may also be the end of the last child.
+fn locate_insertion(children: &[ArrayChild], index: u64) -> (usize, u64) {
+    locate(children, index).unwrap_or_else(|| {
+        let position = children.len() - 1;
+        (position, index - children[position].count)
+    })
+}
+
+/// Like `locate`, but the index must be in one of the children,
+/// as the counts of the children of a valid branch add up to the count of the array.
+fn locate_existing(
+    address: AddressValue,
+    children: &[ArrayChild],
+    index: u64,
+) -> Result<(usize, u64), VMError> {
+    locate(children, index).ok_or_else(|| {
+        corrupted(
+            address,
+            format!("array branch has no element at index {}", index),
+        )
+    })
+}
+
+fn collect_elements<'a>(
+    storage: &dyn Storage<'a>,
+    address: AddressValue,
+    id: u64,
+    elements: &mut Vec<Value<'a>>,
+) -> Result<(), VMError> {
+    match &*read_slab(storage, address, id)?.slab {
+        Slab::ArrayLeaf(leaf) => elements.extend(leaf.iter().cloned()),
+        Slab::ArrayBranch(children) => {
+            for child in children {
+                collect_elements(storage, address, child.id, elements)?;
+            }
+        }
+        slab => return Err(unexpected_slab(address, "array", slab)),
+    }
+    Ok(())
+}
+
+/// Inserts the value into the node, writing the modified descendants.
+/// Returns the modified node, and its new right sibling if the node was split.
+fn array_insert<'a>(
+    storage: &mut dyn Storage<'a>,
+    address: AddressValue,
+    slab: Slab<'a>,
+    index: u64,
+    value: Value<'a>,
+) -> Result<(Slab<'a>, Option<Slab<'a>>), VMError> {
+    match slab {
+        Slab::ArrayLeaf(mut elements) => {
+            if index as usize > elements.len() {
+                return Err(corrupted(
+                    address,
+                    format!("array leaf has no element at index {}", index),
+                ));
+            }
+            elements.insert(index as usize, value);
+            if elements.len() <= ARRAY_SLAB_CAPACITY {
+                return Ok((Slab::ArrayLeaf(elements), None));
+            }
+            let right = elements.split_off(elements.len() / 2);
+            Ok((Slab::ArrayLeaf(elements), Some(Slab::ArrayLeaf(right))))
+        }
+        Slab::ArrayBranch(mut children) if !children.is_empty() => {
+            let (position, before) = locate_insertion(&children, index);
+            let child = children[position];
+            let node = read_node(storage, address, child.id)?;
+            let (left, right) = array_insert(storage, address, node, index - before, value)?;
+            children[position].count = array_count(address, &left)?;
+            write_node(storage, address, child.id, left);
+            if let Some(right) = right {
+                let sibling = ArrayChild {
+                    count: array_count(address, &right)?,
+                    id: store_node(storage, address, right)?,
+                };
+                children.insert(position + 1, sibling);
+            }
+            if children.len() <= ARRAY_SLAB_CAPACITY {
+                return Ok((Slab::ArrayBranch(children), None));
+            }
+            let right = children.split_off(children.len() / 2);
+            Ok((Slab::ArrayBranch(children), Some(Slab::ArrayBranch(right))))
+        }
+        slab => Err(unexpected_slab(address, "array", &slab)),
+    }
+}
+
+fn array_set<'a>(
+    storage: &mut dyn Storage<'a>,
+    address: AddressValue,
+    slab: Slab<'a>,
+    index: u64,
+    value: Value<'a>,
+) -> Result<(Slab<'a>, Value<'a>), VMError> {
+    match slab {
+        Slab::ArrayLeaf(mut elements) => {
+            let Some(element) = elements.get_mut(index as usize) else {
+                return Err(corrupted(
+                    address,
+                    format!("array leaf has no element at index {}", index),
+                ));
+            };
+            let previous = std::mem::replace(element, value);
+            Ok((Slab::ArrayLeaf(elements), previous))
+        }
+        Slab::ArrayBranch(children) => {
+            let (position, before) = locate_existing(address, &children, index)?;
+            let id = children[position].id;
+            let node = read_node(storage, address, id)?;
+            let (node, previous) = array_set(storage, address, node, index - before, value)?;
+            write_node(storage, address, id, node);
+            Ok((Slab::ArrayBranch(children), previous))
+        }
+        slab => Err(unexpected_slab(address, "array", &slab)),
+    }
+}
+
+/// Removes the element from the node. Children that become empty are removed.
+fn array_remove<'a>(
+    storage: &mut dyn Storage<'a>,
+    address: AddressValue,
+    slab: Slab<'a>,
+    index: u64,
+) -> Result<(Slab<'a>, Value<'a>), VMError> {
+    match slab {
+        Slab::ArrayLeaf(mut elements) => {
+            if index as usize >= elements.len() {
+                return Err(corrupted(
+                    address,
+                    format!("array leaf has no element at index {}", index),
+                ));
+            }
+            let removed = elements.remove(index as usize);
+            Ok((Slab::ArrayLeaf(elements), removed))
+        }
+        Slab::ArrayBranch(mut children) => {
+            let (position, before) = locate_existing(address, &children, index)?;
+            let id = children[position].id;
+            let node = read_node(storage, address, id)?;
+            let (node, removed) = array_remove(storage, address, node, index - before)?;
+            children[position].count -= 1;
+            if children[position].count == 0 {
+                remove_slab(storage, address, id);
+                children.remove(position);
+            } else {
+                write_node(storage, address, id, node);
+            }
+            Ok((Slab::ArrayBranch(children), removed))
+        }
+        slab => Err(unexpected_slab(address, "array", &slab)),
+    }
+}
+
+/*
+*  StoredDictionaryValue
+*/
+
+/// A dictionary stored in slabs of an account, of which only the touched slabs are read and written.
+/// Entries are iterated in the order of the hashes of their keys, and then in insertion order.
+/// Copies of the handle share the stored entries.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StoredDictionaryValue {
+    pub address: AddressValue,
+    /// The ID of the root slab, which does not change when the dictionary grows or shrinks.
+    pub root: u64,
+    pub key_type: StaticType,
+    pub value_type: StaticType,
+}
+
+impl StoredDictionaryValue {
+    /// Stores the entries in new slabs of the account.
+    pub fn new<'a>(
+        storage: &mut dyn Storage<'a>,
+        address: AddressValue,
+        key_type: StaticType,
+        value_type: StaticType,
+        entries: Vec<(Value<'a>, Value<'a>)>,
+    ) -> Result<Self, VMError> {
+        let root = allocate_slab_id(storage, address)?;
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| (hash(&key), key, value))
+            .collect();
+        let slab = dictionary_build(storage, address, entries, 0)?;
+        write_slab(storage, address, root, 0, slab);
+
+        Ok(StoredDictionaryValue {
+            address,
+            root,
+            key_type,
+            value_type,
+        })
+    }
+
+    /// Returns the key and value types.
+    pub fn types(&self) -> (StaticType, StaticType) {
+        (self.key_type.clone(), self.value_type.clone())
+    }
+
+    pub fn len(&self, storage: &dyn Storage) -> Result<usize, VMError> {
+        let slab = read_slab(storage, self.address, self.root)?.slab;
+        Ok(dictionary_count(self.address, &slab)? as usize)
+    }
+
+    pub fn is_empty(&self, storage: &dyn Storage) -> Result<bool, VMError> {
+        Ok(self.len(storage)? == 0)
+    }
+
+    pub fn get<'a>(
+        &self,
+        storage: &dyn Storage<'a>,
+        key: &Value<'a>,
+    ) -> Result<Option<Value<'a>>, VMError> {
+        let hash = hash(key);
+        let mut slab = read_slab(storage, self.address, self.root)?.slab;
+        let mut depth = 0;
+        loop {
+            let id = match &*slab {
+                Slab::DictionaryLeaf(entries) => {
+                    return Ok(entries
+                        .iter()
+                        .find(|(entry_key, _)| entry_key == key)
+                        .map(|(_, value)| value.clone()));
+                }
+                Slab::DictionaryBranch { children, .. } => match children[slot(hash, depth)] {
+                    Some(id) => id,
+                    None => return Ok(None),
+                },
+                slab => return Err(unexpected_slab(self.address, "dictionary", slab)),
+            };
+            slab = read_slab(storage, self.address, id)?.slab;
+            depth += 1;
+        }
+    }
+
+    /// Returns the entries, in iteration order, reading all slabs.
+    pub fn entries<'a>(
+        &self,
+        storage: &dyn Storage<'a>,
+    ) -> Result<Vec<(Value<'a>, Value<'a>)>, VMError> {
+        let mut entries = vec![];
+        collect_entries(storage, self.address, self.root, &mut entries)?;
+        Ok(entries)
+    }
+
+    /// Inserts the value for the key, returning the previous value, if any.
+    pub fn insert<'a>(
+        &self,
+        storage: &mut dyn Storage<'a>,
+        key: Value<'a>,
+        value: Value<'a>,
+    ) -> Result<Option<Value<'a>>, VMError> {
+        let address = self.address;
+        let hash = hash(&key);
+        update_root(storage, address, self.root, |storage, slab| {
+            dictionary_insert(storage, address, slab, 0, hash, key, value)
+        })
+    }
+
+    /// Removes the entry for the key, returning its value, if any.
+    pub fn remove<'a>(
+        &self,
+        storage: &mut dyn Storage<'a>,
+        key: &Value<'a>,
+    ) -> Result<Option<Value<'a>>, VMError> {
+        if self.get(storage, key)?.is_none() {
+            return Ok(None);
+        }
+        let address = self.address;
+        let hash = hash(key);
+        let removed = update_root(storage, address, self.root, |storage, slab| {
+            dictionary_remove(storage, address, slab, 0, hash, key)
+        })?;
+        Ok(Some(removed))
+    }
+
+    /// Removes all slabs of the dictionary.
+    pub fn delete(&self, storage: &mut dyn Storage) -> Result<(), VMError> {
+        remove_tree(storage, self.address, self.root)
+    }
+
+    /// Returns the number of mutations so far, or `None` if the slabs were removed.
+    pub(crate) fn mutations(&self, storage: &dyn Storage) -> Result<Option<u64>, VMError> {
+        root_mutations(storage, self.address, self.root)
+    }
+
+    /// Returns a cursor at the first key.
+    pub(crate) fn cursor<'a>(
+        &self,
+        storage: &dyn Storage<'a>,
+    ) -> Result<DictionaryCursor<'a>, VMError> {
+        let slab = read_slab(storage, self.address, self.root)?.slab;
+        match &*slab {
+            Slab::DictionaryLeaf(_) => Ok(DictionaryCursor {
+                branches: vec![],
+                leaf: Some((slab, 0)),
+            }),
+            Slab::DictionaryBranch { .. } => Ok(DictionaryCursor {
+                branches: vec![(slab, 0)],
+                leaf: None,
+            }),
+            slab => Err(unexpected_slab(self.address, "dictionary", slab)),
+        }
+    }
+}
+
+/// The stable hash of the key: the FNV-1a hash of its encoding.
+fn hash(key: &Value) -> u64 {
+    let mut encoder = Encoder::new();
+    encoder
+        .write_value(key)
+        .unwrap_or_else(|error| panic!("invalid dictionary key: {}", error));
+    encoder
+        .into_bytes()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// Returns the child of a branch at the depth that contains the hash.
+fn slot(hash: u64, depth: u32) -> usize {
+    ((hash >> (depth * DICTIONARY_BRANCH_BITS)) as usize) & (DICTIONARY_BRANCH_WIDTH - 1)
+}
+
+/// Reports whether leaves at the depth can be split, i.e. whether hash bits are left.
+fn can_branch(depth: u32) -> bool {
+    depth * DICTIONARY_BRANCH_BITS < u64::BITS
+}
+
+fn dictionary_count(address: AddressValue, slab: &Slab) -> Result<u64, VMError> {
+    match slab {
+        Slab::DictionaryLeaf(entries) => Ok(entries.len() as u64),
+        Slab::DictionaryBranch { count, .. } => Ok(*count),
+        slab => Err(unexpected_slab(address, "dictionary", slab)),
+    }
+}
+
+type HashedEntry<'a> = (u64, Value<'a>, Value<'a>);
+
+/// Builds a node of the entries, writing its descendants.
+fn dictionary_build<'a>(
+    storage: &mut dyn Storage<'a>,
+    address: AddressValue,
+    entries: Vec<HashedEntry<'a>>,
+    depth: u32,
+) -> Result<Slab<'a>, VMError> {
+    if entries.len() <= DICTIONARY_SLAB_CAPACITY || !can_branch(depth) {
+        let entries = entries
+            .into_iter()
+            .map(|(_, key, value)| (key, value))
+            .collect();
+        return Ok(Slab::DictionaryLeaf(entries));
+    }
+
+    let count = entries.len() as u64;
+    let mut groups: Vec<Vec<HashedEntry<'a>>> = vec![vec![]; DICTIONARY_BRANCH_WIDTH];
+    for entry in entries {
+        groups[slot(entry.0, depth)].push(entry);
+    }
+    let children = groups
+        .into_iter()
+        .map(|group| {
+            if group.is_empty() {
+                return Ok(None);
+            }
+            let slab = dictionary_build(storage, address, group, depth + 1)?;
+            Ok(Some(store_node(storage, address, slab)?))
+        })
+        .collect::<Result<_, VMError>>()?;
+    Ok(Slab::DictionaryBranch { count, children })
+}
+
+fn dictionary_insert<'a>(
+    storage: &mut dyn Storage<'a>,
+    address: AddressValue,
+    slab: Slab<'a>,
+    depth: u32,
+    hash: u64,
+    key: Value<'a>,
+    value: Value<'a>,
+) -> Result<(Slab<'a>, Option<Value<'a>>), VMError> {
+    match slab {
+        Slab::DictionaryLeaf(mut entries) => {
+            if let Some((_, entry_value)) =
+                entries.iter_mut().find(|(entry_key, _)| *entry_key == key)
+            {
+                let previous = std::mem::replace(entry_value, value);
+                return Ok((Slab::DictionaryLeaf(entries), Some(previous)));
+            }
+            entries.push((key, value));
+            if entries.len() <= DICTIONARY_SLAB_CAPACITY {
+                return Ok((Slab::DictionaryLeaf(entries), None));
+            }
+            let entries = entries
+                .into_iter()
+                .map(|(key, value)| (self::hash(&key), key, value))
+                .collect();
+            Ok((dictionary_build(storage, address, entries, depth)?, None))
+        }
+        Slab::DictionaryBranch {
+            mut count,
+            mut children,
+        } => {
+            let slot = slot(hash, depth);
+            let previous = match children[slot] {
+                Some(id) => {
+                    let node = read_node(storage, address, id)?;
+                    let (node, previous) =
+                        dictionary_insert(storage, address, node, depth + 1, hash, key, value)?;
+                    write_node(storage, address, id, node);
+                    previous
+                }
+                None => {
+                    let leaf = Slab::DictionaryLeaf(vec![(key, value)]);
+                    children[slot] = Some(store_node(storage, address, leaf)?);
+                    None
+                }
+            };
+            if previous.is_none() {
+                count += 1;
+            }
+            Ok((Slab::DictionaryBranch { count, children }, previous))
+        }
+        slab => Err(unexpected_slab(address, "dictionary", &slab)),
+    }
+}
+
+/// Removes the entry of the key, which must exist, from the node.
+/// Branches that become small enough are merged back into a leaf.
+fn dictionary_remove<'a>(
+    storage: &mut dyn Storage<'a>,
+    address: AddressValue,
+    slab: Slab<'a>,
+    depth: u32,
+    hash: u64,
+    key: &Value<'a>,
+) -> Result<(Slab<'a>, Value<'a>), VMError> {
+    match slab {
+        Slab::DictionaryLeaf(mut entries) => {
+            let position = entries
+                .iter()
+                .position(|(entry_key, _)| entry_key == key)
+                .ok_or_else(|| missing_key(address, key))?;
+            let (_, removed) = entries.remove(position);
+            Ok((Slab::DictionaryLeaf(entries), removed))
+        }
+        Slab::DictionaryBranch {
+            mut count,
+            mut children,
+        } => {
+            let slot = slot(hash, depth);
+            let id = children[slot].ok_or_else(|| missing_key(address, key))?;
+            let node = read_node(storage, address, id)?;
+            let (node, removed) = dictionary_remove(storage, address, node, depth + 1, hash, key)?;
+            count -= 1;
+            if dictionary_count(address, &node)? == 0 {
+                remove_slab(storage, address, id);
+                children[slot] = None;
+            } else {
+                write_node(storage, address, id, node);
+            }
+
+            let slab = Slab::DictionaryBranch { count, children };
+            if count as usize > DICTIONARY_SLAB_CAPACITY / 2 {
+                return Ok((slab, removed));
+            }
+            let mut entries = vec![];
+            collect_node_entries(storage, address, &slab, &mut entries)?;
+            if let Slab::DictionaryBranch { children, .. } = &slab {
+                for id in children.iter().flatten() {
+                    remove_tree(storage, address, *id)?;
+                }
+            }
+            Ok((Slab::DictionaryLeaf(entries), removed))
+        }
+        slab => Err(unexpected_slab(address, "dictionary", &slab)),
+    }
+}
+
+/// The error for a key that was found by `get`, but not by the removal.
+fn missing_key(address: AddressValue, key: &Value) -> VMError {
+    corrupted(address, format!("missing dictionary key `{}`", key))
+}
+
+fn collect_entries<'a>(
+    storage: &dyn Storage<'a>,
+    address: AddressValue,
+    id: u64,
+    entries: &mut Vec<(Value<'a>, Value<'a>)>,
+) -> Result<(), VMError> {
+    let slab = read_slab(storage, address, id)?.slab;
+    collect_node_entries(storage, address, &slab, entries)
+}
+
+fn collect_node_entries<'a>(
+    storage: &dyn Storage<'a>,
+    address: AddressValue,
+    slab: &Slab<'a>,
+    entries: &mut Vec<(Value<'a>, Value<'a>)>,
+) -> Result<(), VMError> {
+    match slab {
+        Slab::DictionaryLeaf(leaf) => entries.extend(leaf.iter().cloned()),
+        Slab::DictionaryBranch { children, .. } => {
+            for id in children.iter().flatten() {
+                collect_entries(storage, address, *id, entries)?;
+            }
+        }
+        slab => return Err(unexpected_slab(address, "dictionary", slab)),
+    }
+    Ok(())
+}
+
+/// A position in the traversal of the slabs of a stored dictionary, in iteration order.
+/// Slabs are read as the traversal reaches them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DictionaryCursor<'a> {
+    /// The branches on the path to the current leaf, with the slot of the next child to visit.
+    branches: Vec<(Rc<Slab<'a>>, usize)>,
+    /// The current leaf, with the index of the next entry.
+    leaf: Option<(Rc<Slab<'a>>, usize)>,
+}
+
+impl<'a> DictionaryCursor<'a> {
+    /// Returns the next key, or `None` if the traversal is done.
+    /// The dictionary must not have been mutated since the cursor was created.
+    pub(crate) fn next_key(
+        &mut self,
+        storage: &dyn Storage<'a>,
+        address: AddressValue,
+    ) -> Result<Option<Value<'a>>, VMError> {
+        loop {
+            if let Some((leaf, index)) = &mut self.leaf {
+                if let Slab::DictionaryLeaf(entries) = &**leaf {
+                    if let Some((key, _)) = entries.get(*index) {
+                        *index += 1;
+                        return Ok(Some(key.clone()));
+                    }
+                }
+                self.leaf = None;
+            }
+
+            let Some((branch, next)) = self.branches.last_mut() else {
+                return Ok(None);
+            };
+            let children = match &**branch {
+                Slab::DictionaryBranch { children, .. } => children,
+                slab => return Err(unexpected_slab(address, "dictionary branch", slab)),
+            };
+            let child = children[*next..]
+                .iter()
+                .position(Option::is_some)
+                .map(|offset| *next + offset);
+            let position = match child {
+                Some(position) => position,
+                None => {
+                    self.branches.pop();
+                    continue;
+                }
+            };
+            *next = position + 1;
+
+            let slab = read_slab(storage, address, children[position].unwrap())?.slab;
+            match &*slab {
+                Slab::DictionaryLeaf(_) => self.leaf = Some((slab, 0)),
+                Slab::DictionaryBranch { .. } => self.branches.push((slab, 0)),
+                slab => return Err(unexpected_slab(address, "dictionary", slab)),
+            }
+        }
+    }
+}
+
+/*
+*  Storing collections
+*/
+
+/// Prepares a value to be stored at a path of the account:
+/// arrays and dictionaries are moved into slabs, and replaced by handles.
+/// Nested collections are stored inline, as part of the element that contains them.
+pub fn store<'a>(
+    storage: &mut dyn Storage<'a>,
+    address: AddressValue,
+    value: Value<'a>,
+) -> Result<Value<'a>, VMError> {
+    match value {
+        Value::Array(array) => Ok(Value::StoredArray(StoredArrayValue::new(
+            storage,
+            address,
+            array.element_type(),
+            array.elements(),
+        )?)),
+        Value::Dictionary(dictionary) => {
+            let (key_type, value_type) = dictionary.types();
+            let entries = dictionary
+                .keys()
+                .into_iter()
+                .map(|key| {
+                    let value = dictionary.get(&key).unwrap();
+                    (key, value)
+                })
+                .collect();
+            Ok(Value::StoredDictionary(StoredDictionaryValue::new(
+                storage, address, key_type, value_type, entries,
+            )?))
+        }
+        value => Ok(value),
+    }
+}
+
+/// Reads the contents of a stored array or dictionary into memory, reading all slabs.
+/// Other values are returned as they are.
+pub fn materialize<'a>(storage: &dyn Storage<'a>, value: Value<'a>) -> Result<Value<'a>, VMError> {
+    match value {
+        Value::StoredArray(array) => Ok(Value::Array(ArrayValue::new(
+            array.element_type.clone(),
+            array.elements(storage)?,
+        ))),
+        Value::StoredDictionary(dictionary) => Ok(Value::Dictionary(DictionaryValue::new(
+            dictionary.key_type.clone(),
+            dictionary.value_type.clone(),
+            dictionary.entries(storage)?,
+        ))),
+        value => Ok(value),
+    }
+}
+
+/// Removes the slabs of a stored array or dictionary. Other values have no slabs.
+pub fn delete(storage: &mut dyn Storage, value: &Value) -> Result<(), VMError> {
+    match value {
+        Value::StoredArray(array) => array.delete(storage),
+        Value::StoredDictionary(dictionary) => dictionary.delete(storage),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,806 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::runtime::errors::VMError;
+use crate::runtime::registers::RegisterType;
+use crate::runtime::slabs;
+use crate::runtime::storage::{Storage, StorageKey};
+use crate::runtime::types::{Authorization, StaticType};
+use crate::runtime::values::{
+    AddressValue, BoolValue, CapabilityControllerValue, CapabilityValue, FixedSizeIntValue,
+    PathDomain, PathValue, ReferenceValue, TypeValue, Value,
+};
+use crate::runtime::vm::{VMContext, VM};
+
+/// Registers the functions of the standard library
+/// that do not depend on the host environment.
+///
+/// The host registers the other functions of the standard library:
+/// `log` with `register_log_function`, the account storage functions
+/// with `register_account_functions`, and functions such as `getAccount`,
+/// `getCurrentBlock` and the hashing functions with `VM::register_native_function`.
+pub fn register_standard_functions(vm: &mut VM) {
+    vm.register_native_function("panic", RegisterType::Value, panic);
+    vm.register_native_function("assert", RegisterType::Value, assert);
+    vm.register_native_function("StoragePath", RegisterType::Value, storage_path);
+    vm.register_native_function("PublicPath", RegisterType::Value, public_path);
+    vm.register_native_function("PrivatePath", RegisterType::Value, private_path);
+}
+
+/// Registers `fun log(_ value: AnyStruct)`, which passes the string representation
+/// of the value to the sink, e.g. the host's logger.
+pub fn register_log_function<'a, F>(vm: &mut VM<'a>, sink: F)
+where
+    F: Fn(&str) + 'a,
+{
+    vm.register_native_function("log", RegisterType::Value, move |_, arguments| {
+        sink(&arguments[0].to_string());
+        Ok(Value::Void)
+    });
+}
+
+/// `fun panic(_ message: String): Never`
+fn panic<'a>(_: &mut VMContext<'a>, arguments: &[Value<'a>]) -> Result<Value<'a>, VMError> {
+    Err(VMError::Panic {
+        message: string_argument(arguments, 0),
+    })
+}
+
+/// `fun assert(_ condition: Bool, message: String)`
+fn assert<'a>(_: &mut VMContext<'a>, arguments: &[Value<'a>]) -> Result<Value<'a>, VMError> {
+    match arguments[0] {
+        Value::Bool(condition) if condition.value => Ok(Value::Void),
+        _ => Err(VMError::AssertionFailure {
+            message: string_argument(arguments, 1),
+        }),
+    }
+}
+
+/// `fun StoragePath(identifier: String): StoragePath?`
+fn storage_path<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    path(context, PathDomain::Storage, arguments)
+}
+
+/// `fun PublicPath(identifier: String): PublicPath?`
+fn public_path<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    path(context, PathDomain::Public, arguments)
+}
+
+/// `fun PrivatePath(identifier: String): PrivatePath?`
+fn private_path<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    path(context, PathDomain::Private, arguments)
+}
+
+/// Returns the path with the identifier in the domain, or `nil` if the identifier is invalid.
+/// The path is charged to the memory meter, as it copies the identifier.
+fn path<'a>(
+    context: &mut VMContext<'a>,
+    domain: PathDomain,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    match PathValue::new(domain, &string_argument(arguments, 0)) {
+        Some(path) => {
+            let path = Value::Path(path);
+            context.memory.charge_value(&path)?;
+            Ok(Value::some(path))
+        }
+        None => Ok(Value::Nil),
+    }
+}
+
+fn string_argument(arguments: &[Value], index: usize) -> String {
+    match arguments.get(index) {
+        Some(Value::String(string)) => string.value.to_string(),
+        _ => String::new(),
+    }
+}
+
+/*
+*  Account storage
+*/
+
+/// Registers the functions of account storage, `Account.Storage.*`, which access the VM's storage.
+/// The receiver, an account or a reference to one, is passed as the first argument,
+/// and type arguments are passed as type values after the other arguments.
+pub fn register_account_functions(vm: &mut VM) {
+    vm.register_native_function("Account.Storage.save", RegisterType::Value, storage_save);
+    vm.register_native_function("Account.Storage.load", RegisterType::Value, storage_load);
+    vm.register_native_function("Account.Storage.copy", RegisterType::Value, storage_copy);
+    vm.register_native_function(
+        "Account.Storage.borrow",
+        RegisterType::Value,
+        storage_borrow,
+    );
+    vm.register_native_function("Account.Storage.type", RegisterType::Value, storage_type);
+    vm.register_native_function("Account.Storage.check", RegisterType::Bool, storage_check);
+    vm.register_native_function(
+        "Account.Storage.forEachStored",
+        RegisterType::Value,
+        storage_for_each_stored,
+    );
+}
+
+/// `fun save<T: Storable>(_ value: T, to: StoragePath)`
+fn storage_save<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let path = storage_path_argument(context, arguments, 2)?;
+    let key = StorageKey::Path(path.clone());
+
+    if context.storage.read(address, &key).is_some() {
+        return Err(VMError::StorageOverwrite { address, path });
+    }
+
+    let value = slabs::store(&mut context.storage, address, arguments[1].copy())?;
+    context.write_storage(address, &key, Some(value));
+    Ok(Value::Void)
+}
+
+/// `fun load<T: Storable>(from: StoragePath): T?`
+///
+/// Stored arrays and dictionaries are read into memory, and their slabs are removed.
+fn storage_load<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let key = storage_path_key(context, arguments, 1)?;
+
+    let stored = match context.storage.read(address, &key) {
+        Some(value) => value,
+        None => return Ok(Value::Nil),
+    };
+    check_stored_type(context, &stored, &type_argument(arguments, 2))?;
+
+    let value = materialize(context, stored.clone())?;
+    slabs::delete(&mut context.storage, &stored)?;
+    context.write_storage(address, &key, None);
+    Ok(Value::some(value))
+}
+
+/// `fun copy<T: AnyStruct>(from: StoragePath): T?`
+///
+/// Fails if a resource is stored, as resources cannot be copied.
+fn storage_copy<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let key = storage_path_key(context, arguments, 1)?;
+
+    let value = match context.storage.read(address, &key) {
+        Some(value) => value,
+        None => return Ok(Value::Nil),
+    };
+    if value.is_resource(&context.types) {
+        return Err(VMError::StoredResourceCopy {
+            address,
+            path: storage_path_argument(context, arguments, 1)?,
+        });
+    }
+    check_stored_type(context, &value, &type_argument(arguments, 2))?;
+
+    let value = materialize(context, value)?.copy();
+    context.memory.charge_copy(&value)?;
+    Ok(Value::some(value))
+}
+
+/// Reads a stored array or dictionary into memory, charging it to the memory meter.
+/// Other values are returned as is.
+fn materialize<'a>(context: &mut VMContext<'a>, stored: Value<'a>) -> Result<Value<'a>, VMError> {
+    if !matches!(stored, Value::StoredArray(_) | Value::StoredDictionary(_)) {
+        return Ok(stored);
+    }
+    let value = slabs::materialize(&context.storage, stored)?;
+    context.memory.charge_copy(&value)?;
+    Ok(value)
+}
+
+/// `fun borrow<T: &Any>(from: StoragePath): T?`
+///
+/// The reference reads the value stored at the path each time it is used,
+/// so it observes later mutations of it, and fails once the value is moved out of the path.
+/// References to stored arrays and dictionaries read and write their slabs.
+/// Results in `nil` if no value is stored, or if it does not have the referenced type.
+fn storage_borrow<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let path = storage_path_argument(context, arguments, 1)?;
+    let key = StorageKey::Path(path.clone());
+
+    let (authorization, referenced_type) = match type_argument(arguments, 2) {
+        StaticType::Reference {
+            authorization,
+            referenced_type,
+        } => (authorization, *referenced_type),
+        borrow_type => return Err(VMError::NonReferenceBorrowType { borrow_type }),
+    };
+
+    let reference = match context.storage.read(address, &key) {
+        Some(value) if value.is_instance(&referenced_type, &context.types) => {
+            Value::some(Value::Reference(ReferenceValue::stored(
+                authorization,
+                address,
+                path,
+                referenced_type,
+            )))
+        }
+        _ => Value::Nil,
+    };
+    Ok(reference)
+}
+
+/// `fun type(at: StoragePath): Type?`
+fn storage_type<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let key = storage_path_key(context, arguments, 1)?;
+
+    let typ = match context.storage.read(address, &key) {
+        Some(value) => Value::some(Value::Type(TypeValue {
+            typ: value.static_type(),
+        })),
+        None => Value::Nil,
+    };
+    Ok(typ)
+}
+
+/// `fun check<T: Any>(from: StoragePath): Bool`
+fn storage_check<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let key = storage_path_key(context, arguments, 1)?;
+    let typ = type_argument(arguments, 2);
+
+    let value = context
+        .storage
+        .read(address, &key)
+        .is_some_and(|value| value.is_instance(&typ, &context.types));
+    Ok(Value::Bool(BoolValue { value }))
+}
+
+/// `fun forEachStored(_ function: fun (StoragePath, Type): Bool)`
+///
+/// Calls the function with the path and type of each stored value, until it returns `false`.
+/// Continuing the iteration after the function modified the storage fails.
+fn storage_for_each_stored<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let function = match &arguments[1] {
+        Value::Function(function) => function.clone(),
+        value => panic!("expected function, got {:?}", value),
+    };
+
+    for path in context.storage.paths(address, PathDomain::Storage) {
+        let key = StorageKey::Path(path.clone());
+        let typ = match context.storage.read(address, &key) {
+            Some(value) => value.static_type(),
+            None => continue,
+        };
+
+        let writes = context.storage_writes();
+        let result = context.invoke_function(
+            &function,
+            &[Value::Path(path), Value::Type(TypeValue { typ })],
+        )?;
+
+        if !matches!(result, Value::Bool(BoolValue { value: true })) {
+            break;
+        }
+        if context.storage_writes() != writes {
+            return Err(VMError::StorageMutatedDuringIteration);
+        }
+    }
+    Ok(Value::Void)
+}
+
+/*
+*  Capabilities
+*/
+
+/// Registers the capability functions of accounts, of capabilities,
+/// and of storage capability controllers.
+///
+/// Capabilities are published at public paths. Their controllers are stored
+/// under their capability ID, so borrowing a capability always observes the
+/// current state of its controller.
+pub fn register_capability_functions(vm: &mut VM) {
+    vm.register_native_function(
+        "Account.StorageCapabilities.issue",
+        RegisterType::Value,
+        storage_capabilities_issue,
+    );
+    vm.register_native_function(
+        "Account.StorageCapabilities.getController",
+        RegisterType::Value,
+        storage_capabilities_get_controller,
+    );
+    vm.register_native_function(
+        "Account.Capabilities.publish",
+        RegisterType::Value,
+        capabilities_publish,
+    );
+    vm.register_native_function(
+        "Account.Capabilities.unpublish",
+        RegisterType::Value,
+        capabilities_unpublish,
+    );
+    vm.register_native_function(
+        "Account.Capabilities.get",
+        RegisterType::Value,
+        capabilities_get,
+    );
+    vm.register_native_function(
+        "Account.Capabilities.borrow",
+        RegisterType::Value,
+        capabilities_borrow,
+    );
+    vm.register_native_function("Capability.borrow", RegisterType::Value, capability_borrow);
+    vm.register_native_function("Capability.check", RegisterType::Bool, capability_check);
+    vm.register_native_function(
+        "StorageCapabilityController.target",
+        RegisterType::Value,
+        controller_target,
+    );
+    vm.register_native_function(
+        "StorageCapabilityController.retarget",
+        RegisterType::Value,
+        controller_retarget,
+    );
+    vm.register_native_function(
+        "StorageCapabilityController.delete",
+        RegisterType::Value,
+        controller_delete,
+    );
+}
+
+/// `fun issue<T: &Any>(_ path: StoragePath): Capability<T>`
+fn storage_capabilities_issue<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let target = path_argument(context, arguments, 1)?;
+    let borrow_type = type_argument(arguments, 2);
+    if !matches!(borrow_type, StaticType::Reference { .. }) {
+        return Err(VMError::NonReferenceBorrowType { borrow_type });
+    }
+
+    let id = match context.storage.read(address, &StorageKey::LastCapabilityId) {
+        Some(Value::FixedSizeInt(FixedSizeIntValue::UInt64(id))) => id + 1,
+        _ => 1,
+    };
+    context.write_storage(
+        address,
+        &StorageKey::LastCapabilityId,
+        Some(Value::FixedSizeInt(FixedSizeIntValue::UInt64(id))),
+    );
+
+    let controller = CapabilityControllerValue {
+        address,
+        id,
+        borrow_type: borrow_type.clone(),
+        target,
+    };
+    context.write_storage(
+        address,
+        &StorageKey::CapabilityController(id),
+        Some(Value::CapabilityController(controller)),
+    );
+
+    Ok(Value::Capability(CapabilityValue {
+        address,
+        id,
+        borrow_type,
+    }))
+}
+
+/// `fun getController(byCapabilityID: UInt64): &StorageCapabilityController?`
+fn storage_capabilities_get_controller<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let id = match &arguments[1] {
+        Value::FixedSizeInt(FixedSizeIntValue::UInt64(id)) => *id,
+        value => panic!("expected capability ID, got {:?}", value),
+    };
+
+    let controller = match read_controller(context, address, id) {
+        Some(controller) => Value::some(Value::Reference(ReferenceValue::new(
+            Authorization::Unauthorized,
+            Value::CapabilityController(controller),
+        ))),
+        None => Value::Nil,
+    };
+    Ok(controller)
+}
+
+/// `fun publish(_ capability: Capability, at: PublicPath)`
+fn capabilities_publish<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let capability = capability_argument(arguments, 1);
+    let path = path_argument(context, arguments, 2)?;
+    let key = StorageKey::Path(path.clone());
+
+    if context.storage.read(address, &key).is_some() {
+        return Err(VMError::CapabilityOverwrite { address, path });
+    }
+
+    context.write_storage(address, &key, Some(Value::Capability(capability)));
+    Ok(Value::Void)
+}
+
+/// `fun unpublish(_ path: PublicPath): Capability?`
+fn capabilities_unpublish<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let key = path_key(context, arguments, 1)?;
+
+    match context.storage.read(address, &key) {
+        Some(capability @ Value::Capability(_)) => {
+            context.write_storage(address, &key, None);
+            Ok(Value::some(capability))
+        }
+        _ => Ok(Value::Nil),
+    }
+}
+
+/// `fun get<T: &Any>(_ path: PublicPath): Capability<T>`
+///
+/// Results in an invalid capability with ID 0 if no capability is published at the path,
+/// or if it cannot be borrowed as the given type.
+fn capabilities_get<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let borrow_type = type_argument(arguments, 2);
+
+    let id = match published_capability(context, address, arguments)? {
+        Some(capability)
+            if context
+                .types
+                .is_subtype(&capability.borrow_type, &borrow_type) =>
+        {
+            capability.id
+        }
+        _ => 0,
+    };
+    Ok(Value::Capability(CapabilityValue {
+        address,
+        id,
+        borrow_type,
+    }))
+}
+
+/// `fun borrow<T: &Any>(_ path: PublicPath): T?`
+fn capabilities_borrow<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let address = account_argument(context, arguments)?;
+    let borrow_type = type_argument(arguments, 2);
+
+    let reference = match published_capability(context, address, arguments)? {
+        Some(capability) => borrow_capability(context, &capability, &borrow_type)?,
+        None => None,
+    };
+    Ok(reference.map_or(Value::Nil, Value::some))
+}
+
+/// `fun borrow<T: &Any>(): T?`
+///
+/// Borrows as the capability's borrow type, unless a type argument is given.
+fn capability_borrow<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let capability = capability_argument(arguments, 0);
+    let borrow_type =
+        optional_type_argument(arguments, 1).unwrap_or(capability.borrow_type.clone());
+
+    let reference = borrow_capability(context, &capability, &borrow_type)?;
+    Ok(reference.map_or(Value::Nil, Value::some))
+}
+
+/// `fun check<T: &Any>(): Bool`
+fn capability_check<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let capability = capability_argument(arguments, 0);
+    let borrow_type =
+        optional_type_argument(arguments, 1).unwrap_or(capability.borrow_type.clone());
+
+    let value = borrow_capability(context, &capability, &borrow_type)?.is_some();
+    Ok(Value::Bool(BoolValue { value }))
+}
+
+/// `fun target(): StoragePath`
+fn controller_target<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let controller = current_controller(context, arguments)?;
+    Ok(Value::Path(controller.target))
+}
+
+/// `fun retarget(_ target: StoragePath)`
+fn controller_retarget<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let mut controller = current_controller(context, arguments)?;
+    controller.target = path_argument(context, arguments, 1)?;
+
+    let key = StorageKey::CapabilityController(controller.id);
+    context.write_storage(
+        controller.address,
+        &key,
+        Some(Value::CapabilityController(controller)),
+    );
+    Ok(Value::Void)
+}
+
+/// `fun delete()`
+fn controller_delete<'a>(
+    context: &mut VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<Value<'a>, VMError> {
+    let controller = current_controller(context, arguments)?;
+
+    let key = StorageKey::CapabilityController(controller.id);
+    context.write_storage(controller.address, &key, None);
+    Ok(Value::Void)
+}
+
+/// Returns a reference to the target of the capability, borrowed as the given type.
+/// Like the references of `Account.Storage.borrow`, it reads the targeted path when used.
+///
+/// Results in `None` if the capability's controller was deleted,
+/// if the controller's borrow type is not a subtype of the given type,
+/// or if the targeted value does not have the controller's referenced type.
+fn borrow_capability<'a>(
+    context: &VMContext<'a>,
+    capability: &CapabilityValue,
+    borrow_type: &StaticType,
+) -> Result<Option<Value<'a>>, VMError> {
+    let authorization = match borrow_type {
+        StaticType::Reference { authorization, .. } => authorization.clone(),
+        borrow_type => {
+            return Err(VMError::NonReferenceBorrowType {
+                borrow_type: borrow_type.clone(),
+            })
+        }
+    };
+
+    let Some(controller) = read_controller(context, capability.address, capability.id) else {
+        return Ok(None);
+    };
+    if !context
+        .types
+        .is_subtype(&controller.borrow_type, borrow_type)
+    {
+        return Ok(None);
+    }
+    let referenced_type = match &controller.borrow_type {
+        StaticType::Reference {
+            referenced_type, ..
+        } => (**referenced_type).clone(),
+        _ => return Ok(None),
+    };
+
+    let key = StorageKey::Path(controller.target.clone());
+    match context.storage.read(capability.address, &key) {
+        Some(value) if value.is_instance(&referenced_type, &context.types) => {
+            Ok(Some(Value::Reference(ReferenceValue::stored(
+                authorization,
+                capability.address,
+                controller.target,
+                referenced_type,
+            ))))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Returns the capability published at the path argument, if any.
+fn published_capability(
+    context: &VMContext,
+    address: AddressValue,
+    arguments: &[Value],
+) -> Result<Option<CapabilityValue>, VMError> {
+    match context
+        .storage
+        .read(address, &path_key(context, arguments, 1)?)
+    {
+        Some(Value::Capability(capability)) => Ok(Some(capability)),
+        _ => Ok(None),
+    }
+}
+
+/// Returns the controller with the capability ID, or `None` if it was deleted.
+/// Capability ID 0 never has a controller.
+fn read_controller(
+    context: &VMContext,
+    address: AddressValue,
+    id: u64,
+) -> Option<CapabilityControllerValue> {
+    match context
+        .storage
+        .read(address, &StorageKey::CapabilityController(id))
+    {
+        Some(Value::CapabilityController(controller)) => Some(controller),
+        _ => None,
+    }
+}
+
+/// Returns the stored state of the controller argument,
+/// which may have changed since the controller was obtained.
+/// Fails if the controller was deleted.
+fn current_controller<'a>(
+    context: &VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<CapabilityControllerValue, VMError> {
+    let controller = match context.dereference(&arguments[0])? {
+        Value::CapabilityController(controller) => controller,
+        value => panic!("expected capability controller, got {:?}", value),
+    };
+    read_controller(context, controller.address, controller.id).ok_or(
+        VMError::CapabilityControllerDeleted {
+            address: controller.address,
+            id: controller.id,
+        },
+    )
+}
+
+/// Fails if the stored value does not have the type it is loaded as.
+fn check_stored_type(context: &VMContext, value: &Value, typ: &StaticType) -> Result<(), VMError> {
+    if !value.is_instance(typ, &context.types) {
+        return Err(VMError::ForceCastTypeMismatch {
+            expected_type: typ.clone(),
+            actual_type: value.static_type(),
+        });
+    }
+    Ok(())
+}
+
+/// Returns the address of the receiver, an account or a reference to one.
+fn account_argument<'a>(
+    context: &VMContext<'a>,
+    arguments: &[Value<'a>],
+) -> Result<AddressValue, VMError> {
+    match context.dereference(&arguments[0])? {
+        Value::Account(account) => Ok(account.address),
+        value => Err(argument_type_mismatch(
+            context,
+            "account",
+            StaticType::Account,
+            &value,
+        )),
+    }
+}
+
+fn path_argument(
+    context: &VMContext,
+    arguments: &[Value],
+    index: usize,
+) -> Result<PathValue, VMError> {
+    match &arguments[index] {
+        Value::Path(path) => Ok(path.clone()),
+        value => Err(argument_type_mismatch(
+            context,
+            "path",
+            StaticType::Path,
+            value,
+        )),
+    }
+}
+
+/// Like `path_argument`, but the path must be in the storage domain,
+/// as values are only saved to, and read from, storage paths.
+fn storage_path_argument(
+    context: &VMContext,
+    arguments: &[Value],
+    index: usize,
+) -> Result<PathValue, VMError> {
+    match &arguments[index] {
+        Value::Path(path) if path.domain == PathDomain::Storage => Ok(path.clone()),
+        value => Err(argument_type_mismatch(
+            context,
+            "path",
+            StaticType::StoragePath,
+            value,
+        )),
+    }
+}
+
+fn path_key(context: &VMContext, arguments: &[Value], index: usize) -> Result<StorageKey, VMError> {
+    Ok(StorageKey::Path(path_argument(context, arguments, index)?))
+}
+
+fn storage_path_key(
+    context: &VMContext,
+    arguments: &[Value],
+    index: usize,
+) -> Result<StorageKey, VMError> {
+    Ok(StorageKey::Path(storage_path_argument(
+        context, arguments, index,
+    )?))
+}
+
+/// Returns the error for an argument of the wrong type, at the call of the function.
+fn argument_type_mismatch(
+    context: &VMContext,
+    parameter: &str,
+    expected_type: StaticType,
+    argument: &Value,
+) -> VMError {
+    VMError::ArgumentTypeMismatch {
+        parameter: parameter.to_string(),
+        expected_type,
+        actual_type: argument.static_type(),
+        call_site: context.call_site(),
+    }
+}
+
+fn type_argument(arguments: &[Value], index: usize) -> StaticType {
+    match optional_type_argument(arguments, index) {
+        Some(typ) => typ,
+        None => panic!("expected type argument at index {}", index),
+    }
+}
+
+fn optional_type_argument(arguments: &[Value], index: usize) -> Option<StaticType> {
+    match arguments.get(index)? {
+        Value::Type(value) => Some(value.typ.clone()),
+        value => panic!("expected type, got {:?}", value),
+    }
+}
+
+fn capability_argument(arguments: &[Value], index: usize) -> CapabilityValue {
+    match &arguments[index] {
+        Value::Capability(capability) => capability.clone(),
+        value => panic!("expected capability, got {:?}", value),
+    }
+}
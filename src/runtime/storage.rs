@@ -0,0 +1,359 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::runtime::commitment::StateCommitment;
+use crate::runtime::encoding::EncodeError;
+use crate::runtime::errors::VMError;
+use crate::runtime::values::{AddressValue, PathDomain, PathValue, Snapshot, Value};
+
+/*
+*  Storage
+*/
+
+/// The account storage of the host: the values stored in accounts, keyed by address and key.
+pub trait Storage<'a> {
+    fn read(&self, address: AddressValue, key: &StorageKey) -> Option<Value<'a>>;
+
+    /// Stores the value at the key, or removes the stored value if the value is `None`.
+    fn write(&mut self, address: AddressValue, key: &StorageKey, value: Option<Value<'a>>);
+
+    /// Returns the paths in the domain of the account that store a value, in a deterministic order.
+    fn paths(&self, address: AddressValue, domain: PathDomain) -> Vec<PathValue>;
+
+    /// Persists the writes so far, if the storage is persistent.
+    fn flush(&mut self) -> Result<(), VMError> {
+        Ok(())
+    }
+}
+
+/// A location in the storage of an account:
+/// a path, or one of the entries the VM keeps for the account.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum StorageKey {
+    Path(PathValue),
+    /// The controller of the capability with the ID.
+    CapabilityController(u64),
+    /// The ID of the last capability issued by the account.
+    LastCapabilityId,
+    /// A slab of a stored array or dictionary.
+    Slab(u64),
+    /// The ID of the last slab allocated in the account.
+    LastSlabId,
+}
+
+impl fmt::Display for StorageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageKey::Path(path) => write!(f, "{}", path),
+            StorageKey::CapabilityController(id) => write!(f, "capabilityController({})", id),
+            StorageKey::LastCapabilityId => write!(f, "lastCapabilityID"),
+            StorageKey::Slab(id) => write!(f, "slab({})", id),
+            StorageKey::LastSlabId => write!(f, "lastSlabID"),
+        }
+    }
+}
+
+/*
+*  InMemoryStorage
+*/
+
+/// Storage that keeps the values in memory, e.g. for tests.
+#[derive(Default)]
+pub struct InMemoryStorage<'a> {
+    values: BTreeMap<(AddressValue, StorageKey), Value<'a>>,
+}
+
+impl<'a> InMemoryStorage<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the stored values, ordered by address and key.
+    pub fn entries(&self) -> impl Iterator<Item = (AddressValue, &StorageKey, &Value<'a>)> {
+        self.values
+            .iter()
+            .map(|((address, key), value)| (*address, key, value))
+    }
+}
+
+impl<'a> Storage<'a> for InMemoryStorage<'a> {
+    fn read(&self, address: AddressValue, key: &StorageKey) -> Option<Value<'a>> {
+        self.values.get(&(address, key.clone())).cloned()
+    }
+
+    fn write(&mut self, address: AddressValue, key: &StorageKey, value: Option<Value<'a>>) {
+        let key = (address, key.clone());
+        match value {
+            Some(value) => self.values.insert(key, value),
+            None => self.values.remove(&key),
+        };
+    }
+
+    fn paths(&self, address: AddressValue, domain: PathDomain) -> Vec<PathValue> {
+        // Keys are ordered by address, then domain, then identifier,
+        // and the empty identifier sorts first.
+        let first = StorageKey::Path(PathValue {
+            domain,
+            identifier: Rc::from(""),
+        });
+        self.values
+            .range((address, first)..)
+            .map_while(|((key_address, key), _)| match key {
+                StorageKey::Path(path) if *key_address == address && path.domain == domain => {
+                    Some(path.clone())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/*
+*  JournaledStorage
+*/
+
+/// A journal of writes in front of the host storage, so they can be reverted.
+///
+/// Writes are kept pending until the outermost checkpoint is committed,
+/// and only then written to the host storage. Rolling back a checkpoint
+/// reverts the writes made since, including those of committed nested checkpoints.
+/// Stored values can also be mutated in place, e.g. through references,
+/// or the collections and composites stored inline in slabs: reading such a value
+/// in a checkpoint journals a snapshot of it, which rolling back restores in place.
+/// Committing the outermost checkpoint writes the values mutated in place to the host storage.
+///
+/// If a state commitment is set, it is updated with the writes to the host storage.
+pub struct JournaledStorage<'a> {
+    storage: Box<dyn Storage<'a> + 'a>,
+    commitment: Option<StateCommitment>,
+    /// The first failure to update the commitment, reported by `flush`.
+    commitment_error: Option<EncodeError>,
+    /// The pending writes, where `None` removes the stored value.
+    pending: BTreeMap<Location, Option<Value<'a>>>,
+    /// The writes and reads since the outermost checkpoint, in order.
+    /// Reads are journaled by `read`, so it is shared.
+    journal: RefCell<Vec<JournalEntry<'a>>>,
+    /// The index of the latest snapshot of each location in the journal.
+    snapshots: RefCell<BTreeMap<Location, usize>>,
+    /// The length of the journal at each open checkpoint.
+    checkpoints: Vec<usize>,
+}
+
+type Location = (AddressValue, StorageKey);
+
+enum JournalEntry<'a> {
+    Write {
+        location: Location,
+        /// The pending write the write replaced, if any.
+        previous: Option<Option<Value<'a>>>,
+    },
+    /// A read of a value that can be mutated in place.
+    Read {
+        location: Location,
+        /// The value read, with its contents when it was read.
+        snapshot: Snapshot<'a>,
+    },
+}
+
+impl<'a> JournaledStorage<'a> {
+    pub fn new(storage: Box<dyn Storage<'a> + 'a>) -> Self {
+        JournaledStorage {
+            storage,
+            commitment: None,
+            commitment_error: None,
+            pending: BTreeMap::new(),
+            journal: RefCell::new(vec![]),
+            snapshots: RefCell::new(BTreeMap::new()),
+            checkpoints: vec![],
+        }
+    }
+
+    /// Returns the host storage, which does not include pending writes.
+    pub fn storage(&self) -> &dyn Storage<'a> {
+        self.storage.as_ref()
+    }
+
+    /// Sets the commitment to the contents of the host storage,
+    /// which is then updated with each write to the host storage.
+    pub fn set_commitment(&mut self, commitment: StateCommitment) {
+        self.commitment = Some(commitment);
+    }
+
+    /// Returns the commitment to the contents of the host storage, if set.
+    pub fn commitment(&self) -> Option<&StateCommitment> {
+        self.commitment.as_ref()
+    }
+
+    /// Returns the number of open checkpoints.
+    pub fn depth(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// Opens a checkpoint, which must be committed or rolled back.
+    pub fn checkpoint(&mut self) {
+        self.checkpoints.push(self.journal.get_mut().len());
+    }
+
+    /// Closes the innermost checkpoint, keeping its writes.
+    /// Committing the outermost checkpoint writes the pending writes to the host storage.
+    pub fn commit(&mut self) {
+        self.checkpoints.pop().expect("no open checkpoint");
+        if !self.checkpoints.is_empty() {
+            return;
+        }
+
+        self.snapshots.get_mut().clear();
+        for entry in std::mem::take(self.journal.get_mut()) {
+            if let JournalEntry::Read { location, snapshot } = entry {
+                // Values replaced or removed since they were read are no longer stored.
+                if !snapshot.is_unchanged() {
+                    let value = snapshot.value().clone();
+                    self.pending.entry(location).or_insert(Some(value));
+                }
+            }
+        }
+        for ((address, key), value) in std::mem::take(&mut self.pending) {
+            self.write_through(address, &key, value);
+        }
+    }
+
+    fn write_through(&mut self, address: AddressValue, key: &StorageKey, value: Option<Value<'a>>) {
+        if let Some(commitment) = &mut self.commitment {
+            if let Err(error) = commitment.update(address, key, value.as_ref()) {
+                self.commitment_error.get_or_insert(error);
+            }
+        }
+        self.storage.write(address, key, value);
+    }
+
+    /// Closes the innermost checkpoint, reverting the writes made since it was opened,
+    /// and the mutations in place of the values read since.
+    pub fn rollback(&mut self) {
+        let length = self.checkpoints.pop().expect("no open checkpoint");
+        for entry in self.journal.get_mut().drain(length..).rev() {
+            match entry {
+                JournalEntry::Write {
+                    location,
+                    previous: Some(value),
+                } => {
+                    self.pending.insert(location, value);
+                }
+                JournalEntry::Write {
+                    location,
+                    previous: None,
+                } => {
+                    self.pending.remove(&location);
+                }
+                JournalEntry::Read { snapshot, .. } => snapshot.restore(),
+            }
+        }
+        if self.checkpoints.is_empty() {
+            self.snapshots.get_mut().clear();
+        }
+    }
+
+    /// Journals a snapshot of the value read in the innermost checkpoint,
+    /// unless it was already read in it.
+    fn journal_read(&self, location: Location, value: &Value<'a>, checkpoint: usize) {
+        let mut journal = self.journal.borrow_mut();
+        let mut snapshots = self.snapshots.borrow_mut();
+
+        let journaled = snapshots.get(&location).is_some_and(|index| {
+            *index >= checkpoint
+                && matches!(
+                    journal.get(*index),
+                    Some(JournalEntry::Read { location: read, .. }) if *read == location
+                )
+        });
+        if journaled {
+            return;
+        }
+
+        snapshots.insert(location.clone(), journal.len());
+        journal.push(JournalEntry::Read {
+            location,
+            snapshot: Snapshot::new(value),
+        });
+    }
+}
+
+impl<'a> Storage<'a> for JournaledStorage<'a> {
+    fn read(&self, address: AddressValue, key: &StorageKey) -> Option<Value<'a>> {
+        let location = (address, key.clone());
+        let value = match self.pending.get(&location) {
+            Some(value) => value.clone(),
+            None => self.storage.read(address, key),
+        };
+
+        if let (Some(checkpoint), Some(value)) = (self.checkpoints.last(), &value) {
+            if value.is_mutable_in_place() {
+                self.journal_read(location, value, *checkpoint);
+            }
+        }
+        value
+    }
+
+    /// Writes outside of a checkpoint go to the host storage directly.
+    fn write(&mut self, address: AddressValue, key: &StorageKey, value: Option<Value<'a>>) {
+        if self.checkpoints.is_empty() {
+            self.write_through(address, key, value);
+            return;
+        }
+
+        let location = (address, key.clone());
+        let previous = self.pending.insert(location.clone(), value);
+        self.journal
+            .get_mut()
+            .push(JournalEntry::Write { location, previous });
+    }
+
+    fn paths(&self, address: AddressValue, domain: PathDomain) -> Vec<PathValue> {
+        let mut paths: BTreeSet<PathValue> =
+            self.storage.paths(address, domain).into_iter().collect();
+
+        for ((key_address, key), value) in &self.pending {
+            match key {
+                StorageKey::Path(path) if *key_address == address && path.domain == domain => {
+                    if value.is_some() {
+                        paths.insert(path.clone());
+                    } else {
+                        paths.remove(path);
+                    }
+                }
+                _ => {}
+            }
+        }
+        paths.into_iter().collect()
+    }
+
+    /// Persists the writes to the host storage. Pending writes are not included.
+    /// Fails if a written value could not be included in the state commitment.
+    fn flush(&mut self) -> Result<(), VMError> {
+        if let Some(error) = self.commitment_error.take() {
+            return Err(VMError::StorageFailure {
+                message: error.to_string(),
+            });
+        }
+        self.storage.flush()
+    }
+}
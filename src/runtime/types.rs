@@ -0,0 +1,629 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/*
+*  StaticType
+*/
+
+/// The runtime representation of a Cadence type.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum StaticType {
+    Never,
+    #[default]
+    Void,
+    AnyStruct,
+    AnyResource,
+    Bool,
+    String,
+    Address,
+    /// A single user-perceived character of a string, i.e. an extended grapheme cluster.
+    Character,
+    Number,
+    SignedNumber,
+    Integer,
+    SignedInteger,
+    Int,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    Int128,
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    UInt128,
+    Word8,
+    Word16,
+    Word32,
+    Word64,
+    FixedPoint,
+    SignedFixedPoint,
+    Fix64,
+    UFix64,
+    /// The type of type values, `Type`.
+    MetaType,
+    Path,
+    StoragePath,
+    /// The supertype of public and private paths.
+    CapabilityPath,
+    PublicPath,
+    PrivatePath,
+    Account,
+    StorageCapabilityController,
+    // TODO: Carry the parameter and return types.
+    Function,
+    Optional(Box<StaticType>),
+    /// `[T]`
+    VariableSizedArray(Box<StaticType>),
+    /// `{K: V}`
+    Dictionary {
+        key_type: Box<StaticType>,
+        value_type: Box<StaticType>,
+    },
+    /// `Capability<T>`, where `T` is the reference type the capability can be borrowed as.
+    Capability(Box<StaticType>),
+    /// `InclusiveRange<T>`, where `T` is an integer type.
+    InclusiveRange(Box<StaticType>),
+    /// A concrete composite type, identified by its qualified identifier.
+    Composite(String),
+    /// An intersection of interfaces, e.g. `{I1, I2}`.
+    Intersection(BTreeSet<String>),
+    Reference {
+        authorization: Authorization,
+        referenced_type: Box<StaticType>,
+    },
+}
+
+impl StaticType {
+    pub fn optional(typ: StaticType) -> StaticType {
+        StaticType::Optional(Box::new(typ))
+    }
+
+    pub fn array(element_type: StaticType) -> StaticType {
+        StaticType::VariableSizedArray(Box::new(element_type))
+    }
+
+    pub fn dictionary(key_type: StaticType, value_type: StaticType) -> StaticType {
+        StaticType::Dictionary {
+            key_type: Box::new(key_type),
+            value_type: Box::new(value_type),
+        }
+    }
+
+    pub fn capability(borrow_type: StaticType) -> StaticType {
+        StaticType::Capability(Box::new(borrow_type))
+    }
+
+    pub fn inclusive_range(element_type: StaticType) -> StaticType {
+        StaticType::InclusiveRange(Box::new(element_type))
+    }
+
+    pub fn reference(authorization: Authorization, referenced_type: StaticType) -> StaticType {
+        StaticType::Reference {
+            authorization,
+            referenced_type: Box::new(referenced_type),
+        }
+    }
+
+    pub fn intersection(interfaces: &[&str]) -> StaticType {
+        StaticType::Intersection(interfaces.iter().map(|id| id.to_string()).collect())
+    }
+
+    pub fn is_signed_integer(&self) -> bool {
+        matches!(
+            self,
+            StaticType::SignedInteger
+                | StaticType::Int
+                | StaticType::Int8
+                | StaticType::Int16
+                | StaticType::Int32
+                | StaticType::Int64
+                | StaticType::Int128
+        )
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.is_signed_integer()
+            || matches!(
+                self,
+                StaticType::Integer
+                    | StaticType::UInt8
+                    | StaticType::UInt16
+                    | StaticType::UInt32
+                    | StaticType::UInt64
+                    | StaticType::UInt128
+                    | StaticType::Word8
+                    | StaticType::Word16
+                    | StaticType::Word32
+                    | StaticType::Word64
+            )
+    }
+
+    pub fn is_signed_fixed_point(&self) -> bool {
+        matches!(self, StaticType::SignedFixedPoint | StaticType::Fix64)
+    }
+
+    pub fn is_fixed_point(&self) -> bool {
+        self.is_signed_fixed_point() || matches!(self, StaticType::FixedPoint | StaticType::UFix64)
+    }
+
+    pub fn is_signed_number(&self) -> bool {
+        matches!(self, StaticType::SignedNumber)
+            || self.is_signed_integer()
+            || self.is_signed_fixed_point()
+    }
+
+    pub fn is_path(&self) -> bool {
+        matches!(self, StaticType::Path | StaticType::StoragePath) || self.is_capability_path()
+    }
+
+    pub fn is_capability_path(&self) -> bool {
+        matches!(
+            self,
+            StaticType::CapabilityPath | StaticType::PublicPath | StaticType::PrivatePath
+        )
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, StaticType::Number)
+            || self.is_signed_number()
+            || self.is_integer()
+            || self.is_fixed_point()
+    }
+}
+
+impl fmt::Display for StaticType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StaticType::Never => write!(f, "Never"),
+            StaticType::Void => write!(f, "Void"),
+            StaticType::AnyStruct => write!(f, "AnyStruct"),
+            StaticType::AnyResource => write!(f, "AnyResource"),
+            StaticType::Bool => write!(f, "Bool"),
+            StaticType::String => write!(f, "String"),
+            StaticType::Address => write!(f, "Address"),
+            StaticType::Character => write!(f, "Character"),
+            StaticType::Number => write!(f, "Number"),
+            StaticType::SignedNumber => write!(f, "SignedNumber"),
+            StaticType::Integer => write!(f, "Integer"),
+            StaticType::SignedInteger => write!(f, "SignedInteger"),
+            StaticType::Int => write!(f, "Int"),
+            StaticType::Int8 => write!(f, "Int8"),
+            StaticType::Int16 => write!(f, "Int16"),
+            StaticType::Int32 => write!(f, "Int32"),
+            StaticType::Int64 => write!(f, "Int64"),
+            StaticType::Int128 => write!(f, "Int128"),
+            StaticType::UInt8 => write!(f, "UInt8"),
+            StaticType::UInt16 => write!(f, "UInt16"),
+            StaticType::UInt32 => write!(f, "UInt32"),
+            StaticType::UInt64 => write!(f, "UInt64"),
+            StaticType::UInt128 => write!(f, "UInt128"),
+            StaticType::Word8 => write!(f, "Word8"),
+            StaticType::Word16 => write!(f, "Word16"),
+            StaticType::Word32 => write!(f, "Word32"),
+            StaticType::Word64 => write!(f, "Word64"),
+            StaticType::FixedPoint => write!(f, "FixedPoint"),
+            StaticType::SignedFixedPoint => write!(f, "SignedFixedPoint"),
+            StaticType::Fix64 => write!(f, "Fix64"),
+            StaticType::UFix64 => write!(f, "UFix64"),
+            StaticType::MetaType => write!(f, "Type"),
+            StaticType::Path => write!(f, "Path"),
+            StaticType::StoragePath => write!(f, "StoragePath"),
+            StaticType::CapabilityPath => write!(f, "CapabilityPath"),
+            StaticType::PublicPath => write!(f, "PublicPath"),
+            StaticType::PrivatePath => write!(f, "PrivatePath"),
+            StaticType::Account => write!(f, "Account"),
+            StaticType::StorageCapabilityController => write!(f, "StorageCapabilityController"),
+            StaticType::Function => write!(f, "Function"),
+            StaticType::Optional(typ) => write!(f, "{}?", typ),
+            StaticType::VariableSizedArray(typ) => write!(f, "[{}]", typ),
+            StaticType::Dictionary {
+                key_type,
+                value_type,
+            } => write!(f, "{{{}: {}}}", key_type, value_type),
+            StaticType::Capability(typ) => write!(f, "Capability<{}>", typ),
+            StaticType::InclusiveRange(typ) => write!(f, "InclusiveRange<{}>", typ),
+            StaticType::Composite(identifier) => write!(f, "{}", identifier),
+            StaticType::Intersection(interfaces) => {
+                write!(f, "{{")?;
+                write_joined(f, interfaces, ", ")?;
+                write!(f, "}}")
+            }
+            StaticType::Reference {
+                authorization,
+                referenced_type,
+            } => match authorization {
+                Authorization::Unauthorized => write!(f, "&{}", referenced_type),
+                _ => write!(f, "{} &{}", authorization, referenced_type),
+            },
+        }
+    }
+}
+
+fn write_joined(
+    f: &mut fmt::Formatter<'_>,
+    items: &BTreeSet<String>,
+    separator: &str,
+) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", separator)?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+/*
+*  Authorization
+*/
+
+/// The entitlements a reference is authorized for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Authorization {
+    Unauthorized,
+    /// `auth(E1, E2)`: all of the entitlements.
+    Conjunction(BTreeSet<String>),
+    /// `auth(E1 | E2)`: one of the entitlements.
+    Disjunction(BTreeSet<String>),
+}
+
+impl Authorization {
+    pub fn conjunction(entitlements: &[&str]) -> Authorization {
+        Authorization::Conjunction(entitlements.iter().map(|e| e.to_string()).collect())
+    }
+
+    pub fn disjunction(entitlements: &[&str]) -> Authorization {
+        Authorization::Disjunction(entitlements.iter().map(|e| e.to_string()).collect())
+    }
+
+    /// Reports whether a reference with this authorization may be used
+    /// where `other` is expected, i.e. whether `other` permits this access.
+    pub fn is_subtype_of(&self, other: &Authorization) -> bool {
+        match (self, other) {
+            (_, Authorization::Unauthorized) => true,
+            (Authorization::Unauthorized, _) => false,
+
+            // `auth(E, F)` can be used as `auth(E)`.
+            (Authorization::Conjunction(sub), Authorization::Conjunction(sup)) => {
+                sup.is_subset(sub)
+            }
+
+            // `auth(E, F)` can be used as `auth(E | G)`.
+            (Authorization::Conjunction(sub), Authorization::Disjunction(sup)) => {
+                !sup.is_disjoint(sub)
+            }
+
+            // `auth(E | F)` can be used as `auth(E | F | G)`.
+            (Authorization::Disjunction(sub), Authorization::Disjunction(sup)) => {
+                sub.is_subset(sup)
+            }
+
+            // `auth(E | F)` can only be used as `auth(E)` if both sides
+            // consist of the single entitlement `E`.
+            (Authorization::Disjunction(sub), Authorization::Conjunction(sup)) => sub
+                .iter()
+                .all(|sub_entitlement| sup.iter().all(|e| e == sub_entitlement)),
+        }
+    }
+}
+
+impl fmt::Display for Authorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Authorization::Unauthorized => Ok(()),
+            Authorization::Conjunction(entitlements) => {
+                write!(f, "auth(")?;
+                write_joined(f, entitlements, ", ")?;
+                write!(f, ")")
+            }
+            Authorization::Disjunction(entitlements) => {
+                write!(f, "auth(")?;
+                write_joined(f, entitlements, " | ")?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/*
+*  Access and purity
+*/
+
+/// The access modifier of a declaration.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Access {
+    /// `access(self)`
+    Private,
+    /// `access(contract)`
+    Contract,
+    /// `access(account)`
+    Account,
+    /// `access(all)`
+    #[default]
+    All,
+    /// `access(E)`: only accessible through references authorized for the entitlements.
+    Entitled(Authorization),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Purity {
+    #[default]
+    Impure,
+    /// A `view` function, which may not modify state.
+    View,
+}
+
+/*
+*  Composite and interface types
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CompositeKind {
+    Structure,
+    Resource,
+    Contract,
+    Enum,
+}
+
+impl CompositeKind {
+    pub fn is_resource(&self) -> bool {
+        matches!(self, CompositeKind::Resource)
+    }
+}
+
+#[derive(Clone)]
+pub struct CompositeType {
+    pub identifier: String,
+    pub kind: CompositeKind,
+    /// The interfaces the type explicitly declares conformance to.
+    pub conformances: Vec<String>,
+    pub methods: Vec<Method>,
+    /// The raw type and cases of enum types.
+    pub enum_info: Option<EnumInfo>,
+}
+
+#[derive(Clone)]
+pub struct EnumInfo {
+    /// The integer type of the cases' raw values.
+    pub raw_type: StaticType,
+    /// The names of the cases. The raw value of each case is its index.
+    pub cases: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct InterfaceType {
+    pub identifier: String,
+    pub kind: CompositeKind,
+    /// The interfaces this interface inherits from.
+    pub conformances: Vec<String>,
+    /// The default implementations of the interface's functions.
+    pub methods: Vec<Method>,
+}
+
+#[derive(Clone)]
+pub struct Method {
+    pub name: String,
+    /// The index of the implementing function in the program's functions.
+    pub function: usize,
+}
+
+/*
+*  TypeRegistry
+*/
+
+/// The user-defined types known to the VM, used to answer subtyping queries
+/// that depend on declarations, like interface conformance.
+#[derive(Default)]
+pub struct TypeRegistry {
+    composites: HashMap<String, CompositeType>,
+    interfaces: HashMap<String, InterfaceType>,
+}
+
+impl TypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_composite_type(&mut self, typ: CompositeType) {
+        self.composites.insert(typ.identifier.clone(), typ);
+    }
+
+    pub fn add_interface_type(&mut self, typ: InterfaceType) {
+        self.interfaces.insert(typ.identifier.clone(), typ);
+    }
+
+    pub fn composite_type(&self, identifier: &str) -> Option<&CompositeType> {
+        self.composites.get(identifier)
+    }
+
+    pub fn interface_type(&self, identifier: &str) -> Option<&InterfaceType> {
+        self.interfaces.get(identifier)
+    }
+
+    pub fn is_resource(&self, typ: &StaticType) -> bool {
+        match typ {
+            StaticType::AnyResource => true,
+            StaticType::Optional(typ) | StaticType::VariableSizedArray(typ) => {
+                self.is_resource(typ)
+            }
+            StaticType::Dictionary { value_type, .. } => self.is_resource(value_type),
+            StaticType::Composite(identifier) => self
+                .composite_type(identifier)
+                .is_some_and(|typ| typ.kind.is_resource()),
+            StaticType::Intersection(interfaces) => interfaces.iter().any(|identifier| {
+                self.interface_type(identifier)
+                    .is_some_and(|typ| typ.kind.is_resource())
+            }),
+            _ => false,
+        }
+    }
+
+    /// Reports whether the composite type conforms to the interface,
+    /// either directly or through interface inheritance.
+    pub fn conforms_to(&self, composite: &str, interface: &str) -> bool {
+        match self.composite_type(composite) {
+            Some(typ) => self.any_conformance(&typ.conformances, interface),
+            None => false,
+        }
+    }
+
+    /// Reports whether the interface is the other interface, or inherits from it.
+    fn interface_conforms_to(&self, interface: &str, other: &str) -> bool {
+        interface == other
+            || self
+                .interface_type(interface)
+                .is_some_and(|typ| self.any_conformance(&typ.conformances, other))
+    }
+
+    fn any_conformance(&self, conformances: &[String], interface: &str) -> bool {
+        conformances.iter().any(|conformance| {
+            conformance == interface
+                || self
+                    .interface_type(conformance)
+                    .is_some_and(|typ| self.any_conformance(&typ.conformances, interface))
+        })
+    }
+
+    /// Finds the function implementing the method of the composite type:
+    /// the type's own method, or else the default implementation
+    /// provided by one of the interfaces it conforms to.
+    pub fn find_method(&self, composite: &str, name: &str) -> Option<usize> {
+        let typ = self.composite_type(composite)?;
+
+        find_function(&typ.methods, name)
+            .or_else(|| self.find_default_method(&typ.conformances, name))
+    }
+
+    fn find_default_method(&self, conformances: &[String], name: &str) -> Option<usize> {
+        conformances.iter().find_map(|conformance| {
+            let typ = self.interface_type(conformance)?;
+
+            find_function(&typ.methods, name)
+                .or_else(|| self.find_default_method(&typ.conformances, name))
+        })
+    }
+
+    /// Reports whether `sub` is a subtype of `sup`, following Cadence's subtyping rules.
+    pub fn is_subtype(&self, sub: &StaticType, sup: &StaticType) -> bool {
+        if sub == sup {
+            return true;
+        }
+
+        if let StaticType::Never = sub {
+            return true;
+        }
+
+        match sup {
+            StaticType::AnyStruct => !self.is_resource(sub),
+            StaticType::AnyResource => self.is_resource(sub),
+
+            StaticType::Number => sub.is_number(),
+            StaticType::SignedNumber => sub.is_signed_number(),
+            StaticType::Integer => sub.is_integer(),
+            StaticType::SignedInteger => sub.is_signed_integer(),
+            StaticType::FixedPoint => sub.is_fixed_point(),
+            StaticType::SignedFixedPoint => sub.is_signed_fixed_point(),
+            StaticType::Path => sub.is_path(),
+            StaticType::CapabilityPath => sub.is_capability_path(),
+
+            StaticType::Optional(sup_inner) => match sub {
+                StaticType::Optional(sub_inner) => self.is_subtype(sub_inner, sup_inner),
+                _ => self.is_subtype(sub, sup_inner),
+            },
+
+            StaticType::VariableSizedArray(sup_element_type) => match sub {
+                StaticType::VariableSizedArray(sub_element_type) => {
+                    self.is_subtype(sub_element_type, sup_element_type)
+                }
+                _ => false,
+            },
+
+            StaticType::Dictionary {
+                key_type: sup_key_type,
+                value_type: sup_value_type,
+            } => match sub {
+                StaticType::Dictionary {
+                    key_type: sub_key_type,
+                    value_type: sub_value_type,
+                } => {
+                    self.is_subtype(sub_key_type, sup_key_type)
+                        && self.is_subtype(sub_value_type, sup_value_type)
+                }
+                _ => false,
+            },
+
+            StaticType::Capability(sup_borrow_type) => match sub {
+                StaticType::Capability(sub_borrow_type) => {
+                    self.is_subtype(sub_borrow_type, sup_borrow_type)
+                }
+                _ => false,
+            },
+
+            StaticType::InclusiveRange(sup_element_type) => match sub {
+                StaticType::InclusiveRange(sub_element_type) => {
+                    self.is_subtype(sub_element_type, sup_element_type)
+                }
+                _ => false,
+            },
+
+            StaticType::Intersection(sup_interfaces) => match sub {
+                StaticType::Composite(identifier) => sup_interfaces
+                    .iter()
+                    .all(|interface| self.conforms_to(identifier, interface)),
+                // Each interface of the supertype must be one of the subtype's interfaces,
+                // or inherited by one of them.
+                StaticType::Intersection(sub_interfaces) => {
+                    sup_interfaces.iter().all(|sup_interface| {
+                        sub_interfaces.iter().any(|sub_interface| {
+                            self.interface_conforms_to(sub_interface, sup_interface)
+                        })
+                    })
+                }
+                _ => false,
+            },
+
+            StaticType::Reference {
+                authorization: sup_authorization,
+                referenced_type: sup_referenced_type,
+            } => match sub {
+                StaticType::Reference {
+                    authorization: sub_authorization,
+                    referenced_type: sub_referenced_type,
+                } => {
+                    sub_authorization.is_subtype_of(sup_authorization)
+                        && self.is_subtype(sub_referenced_type, sup_referenced_type)
+                }
+                _ => false,
+            },
+
+            _ => false,
+        }
+    }
+}
+
+fn find_function(methods: &[Method], name: &str) -> Option<usize> {
+    methods
+        .iter()
+        .find(|method| method.name == name)
+        .map(|method| method.function)
+}
@@ -16,54 +16,526 @@
  * limitations under the License.
  */
 
-use crate::runtime::bbq;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::rc::Rc;
+
+use unicode_segmentation::UnicodeSegmentation;
 
-pub(crate) trait Value {}
+use crate::runtime::bbq;
+use crate::runtime::errors::VMError;
+use crate::runtime::registers::RegisterType;
+use crate::runtime::slabs::{DictionaryCursor, SlabValue, StoredArrayValue, StoredDictionaryValue};
+use crate::runtime::storage::{Storage, StorageKey};
+use crate::runtime::types::{Authorization, CompositeKind, StaticType, TypeRegistry};
+use crate::runtime::vm::VMContext;
 
 /*
-*  IntValue
+*  Value
 */
-pub struct IntValue {
-    pub value: isize,
+
+/// A value of any type, as held by value registers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value<'a> {
+    Void,
+    Nil,
+    Some(Box<Value<'a>>),
+    Bool(BoolValue),
+    Int(IntValue),
+    FixedSizeInt(FixedSizeIntValue),
+    Fix64(Fix64Value),
+    UFix64(UFix64Value),
+    String(StringValue),
+    Character(CharacterValue),
+    Address(AddressValue),
+    Path(PathValue),
+    Account(AccountValue),
+    Capability(CapabilityValue),
+    CapabilityController(CapabilityControllerValue),
+    Function(FunctionValue<'a>),
+    Array(ArrayValue<'a>),
+    Dictionary(DictionaryValue<'a>),
+    StoredArray(StoredArrayValue),
+    StoredDictionary(StoredDictionaryValue),
+    /// A node of a stored array or dictionary, only found in storage.
+    Slab(SlabValue<'a>),
+    InclusiveRange(InclusiveRangeValue),
+    Composite(CompositeValue<'a>),
+    Reference(ReferenceValue<'a>),
+    Upvalue(UpvalueValue<'a>),
+    Type(TypeValue),
+    Iterator(IteratorValue<'a>),
 }
 
-pub(crate) const INT_ZERO_VALUE: IntValue = IntValue { value: 0 };
+impl<'a> Value<'a> {
+    pub fn some(value: Value<'a>) -> Value<'a> {
+        Value::Some(Box::new(value))
+    }
+
+    /// Returns the dynamic type of the value.
+    pub fn static_type(&self) -> StaticType {
+        match self {
+            Value::Void => StaticType::Void,
+            Value::Nil => StaticType::optional(StaticType::Never),
+            Value::Some(value) => StaticType::optional(value.static_type()),
+            Value::Bool(_) => StaticType::Bool,
+            Value::Int(_) => StaticType::Int,
+            Value::FixedSizeInt(value) => value.static_type(),
+            Value::Fix64(_) => StaticType::Fix64,
+            Value::UFix64(_) => StaticType::UFix64,
+            Value::String(_) => StaticType::String,
+            Value::Character(_) => StaticType::Character,
+            Value::Address(_) => StaticType::Address,
+            Value::Path(path) => path.static_type(),
+            Value::Account(_) => StaticType::Account,
+            Value::Capability(capability) => StaticType::capability(capability.borrow_type.clone()),
+            Value::CapabilityController(_) => StaticType::StorageCapabilityController,
+            Value::Function(_) => StaticType::Function,
+            Value::Array(array) => StaticType::array(array.element_type()),
+            Value::Dictionary(dictionary) => {
+                let (key_type, value_type) = dictionary.types();
+                StaticType::dictionary(key_type, value_type)
+            }
+            Value::StoredArray(array) => StaticType::array(array.element_type.clone()),
+            Value::StoredDictionary(dictionary) => {
+                StaticType::dictionary(dictionary.key_type.clone(), dictionary.value_type.clone())
+            }
+            // Slabs are internal to storage and have no Cadence type.
+            Value::Slab(_) => StaticType::Never,
+            Value::InclusiveRange(range) => StaticType::inclusive_range(range.element_type()),
+            Value::Composite(composite) => StaticType::Composite(composite.identifier()),
+            Value::Reference(reference) => StaticType::reference(
+                reference.authorization.clone(),
+                match &reference.target {
+                    ReferenceTarget::Value(value) => value.static_type(),
+                    ReferenceTarget::Storage {
+                        referenced_type, ..
+                    } => referenced_type.clone(),
+                },
+            ),
+            Value::Upvalue(upvalue) => upvalue.get().static_type(),
+            Value::Type(_) => StaticType::MetaType,
+            // Iterators are internal to loops and have no Cadence type.
+            Value::Iterator(_) => StaticType::AnyStruct,
+        }
+    }
+
+    /// Returns a copy of the value with Cadence's value semantics:
+    /// structs and containers are copied, whereas resources and upvalues remain shared.
+    pub(crate) fn copy(&self) -> Value<'a> {
+        match self {
+            Value::Some(value) => Value::some(value.copy()),
+            Value::Array(array) => Value::Array(array.copy(Value::copy)),
+            Value::Dictionary(dictionary) => Value::Dictionary(dictionary.copy(Value::copy)),
+            Value::Composite(composite) if composite.kind() == CompositeKind::Structure => {
+                Value::Composite(composite.copy(Value::copy))
+            }
+            _ => self.clone(),
+        }
+    }
+
+    /// Returns whether the contents of the value can be mutated in place,
+    /// e.g. through a reference, without writing the value.
+    pub(crate) fn is_mutable_in_place(&self) -> bool {
+        match self {
+            Value::Some(value) => value.is_mutable_in_place(),
+            Value::Array(_) | Value::Dictionary(_) | Value::Composite(_) => true,
+            Value::Slab(slab) => slab.is_mutable_in_place(),
+            _ => false,
+        }
+    }
+
+    /// Returns the value of the given integer type,
+    /// or `None` if the type is not an integer type or the value is out of its range.
+    pub fn integer(typ: &StaticType, value: i128) -> Option<Value<'a>> {
+        let value = match typ {
+            StaticType::Int => Value::Int(IntValue {
+                value: value.try_into().ok()?,
+            }),
+            StaticType::Int8 => {
+                Value::FixedSizeInt(FixedSizeIntValue::Int8(value.try_into().ok()?))
+            }
+            StaticType::Int16 => {
+                Value::FixedSizeInt(FixedSizeIntValue::Int16(value.try_into().ok()?))
+            }
+            StaticType::Int32 => {
+                Value::FixedSizeInt(FixedSizeIntValue::Int32(value.try_into().ok()?))
+            }
+            StaticType::Int64 => {
+                Value::FixedSizeInt(FixedSizeIntValue::Int64(value.try_into().ok()?))
+            }
+            StaticType::Int128 => Value::FixedSizeInt(FixedSizeIntValue::Int128(value)),
+            StaticType::UInt8 => {
+                Value::FixedSizeInt(FixedSizeIntValue::UInt8(value.try_into().ok()?))
+            }
+            StaticType::UInt16 => {
+                Value::FixedSizeInt(FixedSizeIntValue::UInt16(value.try_into().ok()?))
+            }
+            StaticType::UInt32 => {
+                Value::FixedSizeInt(FixedSizeIntValue::UInt32(value.try_into().ok()?))
+            }
+            StaticType::UInt64 => {
+                Value::FixedSizeInt(FixedSizeIntValue::UInt64(value.try_into().ok()?))
+            }
+            StaticType::UInt128 => {
+                Value::FixedSizeInt(FixedSizeIntValue::UInt128(value.try_into().ok()?))
+            }
+            StaticType::Word8 => {
+                Value::FixedSizeInt(FixedSizeIntValue::Word8(value.try_into().ok()?))
+            }
+            StaticType::Word16 => {
+                Value::FixedSizeInt(FixedSizeIntValue::Word16(value.try_into().ok()?))
+            }
+            StaticType::Word32 => {
+                Value::FixedSizeInt(FixedSizeIntValue::Word32(value.try_into().ok()?))
+            }
+            StaticType::Word64 => {
+                Value::FixedSizeInt(FixedSizeIntValue::Word64(value.try_into().ok()?))
+            }
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Returns the value of an integer as an `i128`,
+    /// or `None` if the value is not an integer or does not fit.
+    pub fn to_i128(&self) -> Option<i128> {
+        match self {
+            Value::Int(value) => Some(value.value as i128),
+            Value::FixedSizeInt(value) => value.to_i128(),
+            _ => None,
+        }
+    }
+
+    /// Reports whether the value is a resource, or contains resources.
+    /// Resources are moved rather than copied, e.g. when they are passed as arguments.
+    pub fn is_resource(&self, types: &TypeRegistry) -> bool {
+        match self {
+            Value::Some(value) => value.is_resource(types),
+            Value::Composite(composite) => composite.kind().is_resource(),
+            Value::Array(array) => types.is_resource(&array.element_type()),
+            Value::Dictionary(dictionary) => types.is_resource(&dictionary.types().1),
+            Value::StoredArray(array) => types.is_resource(&array.element_type),
+            Value::StoredDictionary(dictionary) => types.is_resource(&dictionary.value_type),
+            _ => false,
+        }
+    }
 
-impl Value for IntValue {}
+    /// Reports whether the value's dynamic type is a subtype of the given type.
+    pub fn is_instance(&self, typ: &StaticType, types: &TypeRegistry) -> bool {
+        types.is_subtype(&self.static_type(), typ)
+    }
+}
 
-impl Clone for IntValue {
-    fn clone(&self) -> Self {
-        IntValue { value: self.value }
+impl<'a> fmt::Display for Value<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Void => write!(f, "()"),
+            Value::Nil => write!(f, "nil"),
+            Value::Some(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value.value),
+            Value::Int(value) => write!(f, "{}", value.value),
+            Value::FixedSizeInt(value) => write!(f, "{}", value),
+            Value::Fix64(value) => write!(f, "{}", value),
+            Value::UFix64(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{:?}", value.value),
+            Value::Character(value) => write!(f, "{:?}", value.value),
+            Value::Address(value) => write!(f, "{}", value),
+            Value::Path(value) => write!(f, "{}", value),
+            Value::Account(account) => write!(f, "Account({})", account.address),
+            Value::Capability(capability) => write!(
+                f,
+                "Capability<{}>(address: {}, id: {})",
+                capability.borrow_type, capability.address, capability.id
+            ),
+            Value::CapabilityController(controller) => write!(
+                f,
+                "StorageCapabilityController(borrowType: {}, capabilityID: {}, target: {})",
+                controller.borrow_type, controller.id, controller.target
+            ),
+            Value::Function(_) => write!(f, "Function(...)"),
+            Value::Array(array) => {
+                let array = array.array.borrow();
+                write!(f, "[")?;
+                for (i, element) in array.elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Value::Dictionary(dictionary) => {
+                let dictionary = dictionary.dictionary.borrow();
+                write!(f, "{{")?;
+                for (i, (key, value)) in dictionary.entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::StoredArray(array) => write!(
+                f,
+                "StoredArray(address: {}, slab: {})",
+                array.address, array.root
+            ),
+            Value::StoredDictionary(dictionary) => write!(
+                f,
+                "StoredDictionary(address: {}, slab: {})",
+                dictionary.address, dictionary.root
+            ),
+            Value::Slab(slab) => write!(f, "{}", slab),
+            Value::InclusiveRange(range) => write!(
+                f,
+                "InclusiveRange<{}>(start: {}, end: {}, step: {})",
+                range.element_type(),
+                range.start(),
+                range.end(),
+                range.step()
+            ),
+            Value::Composite(composite) => {
+                let composite = composite.composite.borrow();
+                write!(f, "{}(", composite.identifier)?;
+                for (i, (name, value)) in composite.fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", name, value)?;
+                }
+                write!(f, ")")
+            }
+            Value::Reference(reference) => match &reference.target {
+                ReferenceTarget::Value(value) => write!(f, "{}", value),
+                ReferenceTarget::Storage { address, path, .. } => {
+                    write!(f, "StorageReference({}, {})", address, path)
+                }
+            },
+            Value::Upvalue(upvalue) => write!(f, "{}", upvalue.get()),
+            Value::Type(value) => write!(f, "Type<{}>()", value.typ),
+            Value::Iterator(_) => write!(f, "Iterator(...)"),
+        }
     }
 }
 
-impl Copy for IntValue {}
+impl<'a> Eq for Value<'a> {}
+
+/// Values are hashed consistently with their equality, so they can be dictionary keys.
+/// Only the kind of values that are not hashable in Cadence, like functions, is hashed.
+impl<'a> Hash for Value<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Value::Some(value) => value.hash(state),
+            Value::Bool(value) => value.value.hash(state),
+            Value::Int(value) => value.value.hash(state),
+            Value::FixedSizeInt(value) => value.hash(state),
+            Value::Fix64(value) => value.hash(state),
+            Value::UFix64(value) => value.hash(state),
+            Value::String(value) => value.value.hash(state),
+            Value::Character(value) => value.value.hash(state),
+            Value::Address(value) => value.hash(state),
+            Value::Path(value) => value.hash(state),
+            Value::Account(account) => account.address.hash(state),
+            Value::Capability(capability) => capability.hash(state),
+            Value::CapabilityController(controller) => controller.hash(state),
+            Value::StoredArray(array) => array.hash(state),
+            Value::StoredDictionary(dictionary) => dictionary.hash(state),
+            Value::Composite(composite) => {
+                let composite = composite.composite.borrow();
+                composite.identifier.hash(state);
+                composite.fields.hash(state);
+            }
+            Value::InclusiveRange(range) => range.range.hash(state),
+            Value::Type(value) => value.typ.hash(state),
+            _ => {}
+        }
+    }
+}
+
+/*
+*  IntValue
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IntValue {
+    pub value: isize,
+}
+
+pub(crate) const INT_ZERO_VALUE: IntValue = IntValue { value: 0 };
 
 impl IntValue {
     pub(crate) fn add(&self, other: &IntValue) -> IntValue {
-        return IntValue {
+        IntValue {
             value: self.value + other.value,
-        };
+        }
     }
 
     pub(crate) fn subtract(&self, other: &IntValue) -> IntValue {
-        return IntValue {
+        IntValue {
             value: self.value - other.value,
-        };
+        }
+    }
+
+    pub(crate) fn equal(&self, other: &IntValue) -> BoolValue {
+        BoolValue {
+            value: self.value == other.value,
+        }
     }
 
     pub(crate) fn less(&self, other: &IntValue) -> BoolValue {
         if self.value < other.value {
             return TRUE_VALUE;
         }
-        return FALSE_VALUE;
+        FALSE_VALUE
     }
 
     pub(crate) fn greater(&self, other: &IntValue) -> BoolValue {
         if self.value < other.value {
             return FALSE_VALUE;
         }
-        return TRUE_VALUE;
+        TRUE_VALUE
+    }
+}
+
+/*
+*  FixedSizeIntValue
+*/
+
+/// A value of one of the fixed-size integer types, e.g. `UInt8` or `Word64`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FixedSizeIntValue {
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    Int128(i128),
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    UInt128(u128),
+    Word8(u8),
+    Word16(u16),
+    Word32(u32),
+    Word64(u64),
+}
+
+impl FixedSizeIntValue {
+    pub fn static_type(&self) -> StaticType {
+        match self {
+            FixedSizeIntValue::Int8(_) => StaticType::Int8,
+            FixedSizeIntValue::Int16(_) => StaticType::Int16,
+            FixedSizeIntValue::Int32(_) => StaticType::Int32,
+            FixedSizeIntValue::Int64(_) => StaticType::Int64,
+            FixedSizeIntValue::Int128(_) => StaticType::Int128,
+            FixedSizeIntValue::UInt8(_) => StaticType::UInt8,
+            FixedSizeIntValue::UInt16(_) => StaticType::UInt16,
+            FixedSizeIntValue::UInt32(_) => StaticType::UInt32,
+            FixedSizeIntValue::UInt64(_) => StaticType::UInt64,
+            FixedSizeIntValue::UInt128(_) => StaticType::UInt128,
+            FixedSizeIntValue::Word8(_) => StaticType::Word8,
+            FixedSizeIntValue::Word16(_) => StaticType::Word16,
+            FixedSizeIntValue::Word32(_) => StaticType::Word32,
+            FixedSizeIntValue::Word64(_) => StaticType::Word64,
+        }
+    }
+}
+
+impl FixedSizeIntValue {
+    pub fn to_i128(&self) -> Option<i128> {
+        match *self {
+            FixedSizeIntValue::Int8(value) => Some(value.into()),
+            FixedSizeIntValue::Int16(value) => Some(value.into()),
+            FixedSizeIntValue::Int32(value) => Some(value.into()),
+            FixedSizeIntValue::Int64(value) => Some(value.into()),
+            FixedSizeIntValue::Int128(value) => Some(value),
+            FixedSizeIntValue::UInt8(value) | FixedSizeIntValue::Word8(value) => Some(value.into()),
+            FixedSizeIntValue::UInt16(value) | FixedSizeIntValue::Word16(value) => {
+                Some(value.into())
+            }
+            FixedSizeIntValue::UInt32(value) | FixedSizeIntValue::Word32(value) => {
+                Some(value.into())
+            }
+            FixedSizeIntValue::UInt64(value) | FixedSizeIntValue::Word64(value) => {
+                Some(value.into())
+            }
+            FixedSizeIntValue::UInt128(value) => value.try_into().ok(),
+        }
+    }
+}
+
+impl fmt::Display for FixedSizeIntValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixedSizeIntValue::Int8(value) => write!(f, "{}", value),
+            FixedSizeIntValue::Int16(value) => write!(f, "{}", value),
+            FixedSizeIntValue::Int32(value) => write!(f, "{}", value),
+            FixedSizeIntValue::Int64(value) => write!(f, "{}", value),
+            FixedSizeIntValue::Int128(value) => write!(f, "{}", value),
+            FixedSizeIntValue::UInt8(value) | FixedSizeIntValue::Word8(value) => {
+                write!(f, "{}", value)
+            }
+            FixedSizeIntValue::UInt16(value) | FixedSizeIntValue::Word16(value) => {
+                write!(f, "{}", value)
+            }
+            FixedSizeIntValue::UInt32(value) | FixedSizeIntValue::Word32(value) => {
+                write!(f, "{}", value)
+            }
+            FixedSizeIntValue::UInt64(value) | FixedSizeIntValue::Word64(value) => {
+                write!(f, "{}", value)
+            }
+            FixedSizeIntValue::UInt128(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/*
+*  Fix64Value and UFix64Value
+*/
+
+/// The number of decimal places of `Fix64` and `UFix64` values.
+pub const FIX64_SCALE: u32 = 8;
+
+pub const FIX64_FACTOR: u64 = 10u64.pow(FIX64_SCALE);
+
+/// A `Fix64` value, represented as the value multiplied by `FIX64_FACTOR`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Fix64Value {
+    pub value: i64,
+}
+
+impl fmt::Display for Fix64Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.value < 0 { "-" } else { "" };
+        let value = self.value.unsigned_abs();
+        write!(
+            f,
+            "{}{}.{:08}",
+            sign,
+            value / FIX64_FACTOR,
+            value % FIX64_FACTOR
+        )
+    }
+}
+
+/// A `UFix64` value, represented as the value multiplied by `FIX64_FACTOR`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UFix64Value {
+    pub value: u64,
+}
+
+impl fmt::Display for UFix64Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:08}",
+            self.value / FIX64_FACTOR,
+            self.value % FIX64_FACTOR
+        )
     }
 }
 
@@ -71,39 +543,1198 @@ impl IntValue {
 *  BoolValue
 */
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct BoolValue {
     pub value: bool,
 }
 
-impl Value for BoolValue {}
+pub(crate) const TRUE_VALUE: BoolValue = BoolValue { value: true };
+
+pub(crate) const FALSE_VALUE: BoolValue = BoolValue { value: false };
+
+/*
+*  StringValue
+*/
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StringValue {
+    pub value: Rc<str>,
+}
 
-impl Clone for BoolValue {
-    fn clone(&self) -> Self {
-        BoolValue { value: self.value }
+impl StringValue {
+    pub fn new(value: &str) -> Self {
+        StringValue {
+            value: Rc::from(value),
+        }
     }
 }
 
-impl Copy for BoolValue {}
+/*
+*  CharacterValue
+*/
 
-pub(crate) const TRUE_VALUE: BoolValue = BoolValue { value: true };
+/// A single user-perceived character, i.e. an extended grapheme cluster,
+/// which may consist of several Unicode scalar values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CharacterValue {
+    pub value: Rc<str>,
+}
 
-pub(crate) const FALSE_VALUE: BoolValue = BoolValue { value: false };
+impl CharacterValue {
+    pub fn new(value: &str) -> Self {
+        CharacterValue {
+            value: Rc::from(value),
+        }
+    }
+}
+
+/*
+*  AddressValue
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AddressValue {
+    pub value: u64,
+}
+
+impl fmt::Display for AddressValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:016x}", self.value)
+    }
+}
+
+/*
+*  PathValue
+*/
+
+/// The domain of a path, i.e. the part of account storage it refers to.
+/// The discriminants are part of the encoding of path constants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PathDomain {
+    Storage = 1,
+    Private = 2,
+    Public = 3,
+}
+
+impl PathDomain {
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            PathDomain::Storage => "storage",
+            PathDomain::Private => "private",
+            PathDomain::Public => "public",
+        }
+    }
+
+    pub fn from_identifier(identifier: &str) -> Option<PathDomain> {
+        match identifier {
+            "storage" => Some(PathDomain::Storage),
+            "private" => Some(PathDomain::Private),
+            "public" => Some(PathDomain::Public),
+            _ => None,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<PathDomain> {
+        match value {
+            1 => Some(PathDomain::Storage),
+            2 => Some(PathDomain::Private),
+            3 => Some(PathDomain::Public),
+            _ => None,
+        }
+    }
+}
+
+/// A path, e.g. `/storage/vault`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PathValue {
+    pub domain: PathDomain,
+    pub identifier: Rc<str>,
+}
+
+impl PathValue {
+    /// Returns the path in the domain, or `None` if the identifier is not a valid identifier,
+    /// as for `StoragePath(identifier: "vault")`.
+    pub fn new(domain: PathDomain, identifier: &str) -> Option<Self> {
+        if !is_valid_identifier(identifier) {
+            return None;
+        }
+        Some(PathValue {
+            domain,
+            identifier: Rc::from(identifier),
+        })
+    }
+
+    pub fn static_type(&self) -> StaticType {
+        match self.domain {
+            PathDomain::Storage => StaticType::StoragePath,
+            PathDomain::Private => StaticType::PrivatePath,
+            PathDomain::Public => StaticType::PublicPath,
+        }
+    }
+}
+
+impl fmt::Display for PathValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/{}/{}", self.domain.identifier(), self.identifier)
+    }
+}
+
+fn is_valid_identifier(identifier: &str) -> bool {
+    let mut chars = identifier.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/*
+*  AccountValue
+*/
+
+/// An account, through which the values stored in it are accessed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccountValue {
+    pub address: AddressValue,
+}
+
+/*
+*  CapabilityValue
+*/
+
+/// A capability to borrow a reference to a value stored in an account,
+/// through the capability controller with the capability's ID.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CapabilityValue {
+    pub address: AddressValue,
+    /// The ID of the capability. Invalid capabilities have ID 0.
+    pub id: u64,
+    pub borrow_type: StaticType,
+}
+
+/// The state of a storage capability controller:
+/// the type the capability can be borrowed as, and the path it targets.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CapabilityControllerValue {
+    pub address: AddressValue,
+    /// The ID of the controlled capability.
+    pub id: u64,
+    pub borrow_type: StaticType,
+    pub target: PathValue,
+}
 
 /*
 *  FunctionValue
 */
-pub struct FunctionValue<'a> {
-    pub function: &'a bbq::Function,
+#[derive(Clone)]
+pub enum FunctionValue<'a> {
+    /// A function of the program, or a closure of it.
+    Compiled {
+        function: &'a bbq::Function,
+        /// The values captured by a closure, in the order they are loaded by `CaptureLoad`.
+        captures: Option<Rc<[Value<'a>]>>,
+    },
+    /// A function implemented by the host.
+    Native(NativeFunctionValue<'a>),
+}
+
+impl<'a> FunctionValue<'a> {
+    pub fn new(function: &'a bbq::Function) -> Self {
+        FunctionValue::Compiled {
+            function,
+            captures: None,
+        }
+    }
+}
+
+impl<'a> fmt::Debug for FunctionValue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionValue::Compiled { function, .. } => write!(f, "FunctionValue({:p})", *function),
+            FunctionValue::Native(native) => write!(f, "NativeFunctionValue({})", native.name),
+        }
+    }
+}
+
+/// Functions are equal if they are the same function, with the same captured values.
+impl<'a> PartialEq for FunctionValue<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                FunctionValue::Compiled { function, captures },
+                FunctionValue::Compiled {
+                    function: other_function,
+                    captures: other_captures,
+                },
+            ) => {
+                std::ptr::eq(*function, *other_function)
+                    && match (captures, other_captures) {
+                        (Some(captures), Some(other_captures)) => {
+                            Rc::ptr_eq(captures, other_captures)
+                        }
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }
+            (FunctionValue::Native(native), FunctionValue::Native(other_native)) => {
+                Rc::ptr_eq(&native.function, &other_native.function)
+            }
+            _ => false,
+        }
+    }
+}
+
+/*
+*  NativeFunctionValue
+*/
+
+pub type NativeFunction<'a> =
+    dyn Fn(&mut VMContext<'a>, &[Value<'a>]) -> Result<Value<'a>, VMError> + 'a;
+
+#[derive(Clone)]
+pub struct NativeFunctionValue<'a> {
+    pub name: Rc<str>,
+    /// The class of the register the result is stored in.
+    pub return_type: RegisterType,
+    pub function: Rc<NativeFunction<'a>>,
+}
+
+/*
+*  ArrayValue
+*/
+
+/// A variable-sized array. Like composites, copies of an array value share the same
+/// underlying elements; `copy` creates an independent array.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArrayValue<'a> {
+    array: Rc<RefCell<Array<'a>>>,
+}
+
+#[derive(Debug)]
+struct Array<'a> {
+    element_type: StaticType,
+    elements: Vec<Value<'a>>,
+    /// The number of mutations so far, used to detect mutations during iteration.
+    mutations: u64,
+}
+
+impl<'a> PartialEq for Array<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.element_type == other.element_type && self.elements == other.elements
+    }
+}
+
+impl<'a> ArrayValue<'a> {
+    pub fn new(element_type: StaticType, elements: Vec<Value<'a>>) -> Self {
+        ArrayValue {
+            array: Rc::new(RefCell::new(Array {
+                element_type,
+                elements,
+                mutations: 0,
+            })),
+        }
+    }
+
+    pub fn element_type(&self) -> StaticType {
+        self.array.borrow().element_type.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.array.borrow().elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Value<'a>> {
+        self.array.borrow().elements.get(index).cloned()
+    }
+
+    pub fn elements(&self) -> Vec<Value<'a>> {
+        self.array.borrow().elements.clone()
+    }
+
+    pub fn append(&self, value: Value<'a>) {
+        let mut array = self.array.borrow_mut();
+        array.elements.push(value);
+        array.mutations += 1;
+    }
+
+    /// Replaces the element at the index, returning the previous element,
+    /// or `None` if the index is out of bounds.
+    pub fn set(&self, index: usize, value: Value<'a>) -> Option<Value<'a>> {
+        let mut array = self.array.borrow_mut();
+        let element = array.elements.get_mut(index)?;
+        let previous = mem::replace(element, value);
+        array.mutations += 1;
+        Some(previous)
+    }
+
+    /// Removes the element at the index, or returns `None` if the index is out of bounds.
+    pub fn remove(&self, index: usize) -> Option<Value<'a>> {
+        let mut array = self.array.borrow_mut();
+        if index >= array.elements.len() {
+            return None;
+        }
+        array.mutations += 1;
+        Some(array.elements.remove(index))
+    }
+
+    fn mutations(&self) -> u64 {
+        self.array.borrow().mutations
+    }
+
+    fn copy(&self, copy_element: fn(&Value<'a>) -> Value<'a>) -> Self {
+        let array = self.array.borrow();
+        let elements = array.elements.iter().map(copy_element).collect();
+        ArrayValue::new(array.element_type.clone(), elements)
+    }
+
+    fn restore(&self, elements: Vec<Value<'a>>) {
+        let mut array = self.array.borrow_mut();
+        array.elements = elements;
+        array.mutations += 1;
+    }
+}
+
+/*
+*  DictionaryValue
+*/
+
+/// A dictionary. Entries are kept in insertion order, so iteration is deterministic.
+/// Like arrays, copies of a dictionary value share the same underlying entries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DictionaryValue<'a> {
+    dictionary: Rc<RefCell<Dictionary<'a>>>,
+}
+
+#[derive(Debug)]
+struct Dictionary<'a> {
+    key_type: StaticType,
+    value_type: StaticType,
+    entries: Vec<(Value<'a>, Value<'a>)>,
+    /// The index of each key's entry.
+    indices: HashMap<Value<'a>, usize>,
+    /// The number of mutations so far, used to detect mutations during iteration.
+    mutations: u64,
+}
+
+/// Dictionaries are equal if they have the same entries, in any order.
+impl<'a> PartialEq for Dictionary<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key_type == other.key_type
+            && self.value_type == other.value_type
+            && self.entries.len() == other.entries.len()
+            && self.entries.iter().all(|(key, value)| {
+                other
+                    .indices
+                    .get(key)
+                    .is_some_and(|index| other.entries[*index].1 == *value)
+            })
+    }
+}
+
+impl<'a> DictionaryValue<'a> {
+    pub fn new(
+        key_type: StaticType,
+        value_type: StaticType,
+        entries: Vec<(Value<'a>, Value<'a>)>,
+    ) -> Self {
+        let dictionary = DictionaryValue {
+            dictionary: Rc::new(RefCell::new(Dictionary {
+                key_type,
+                value_type,
+                entries: Vec::with_capacity(entries.len()),
+                indices: HashMap::with_capacity(entries.len()),
+                mutations: 0,
+            })),
+        };
+        for (key, value) in entries {
+            dictionary.insert(key, value);
+        }
+        dictionary
+    }
+
+    /// Returns the key and value types.
+    pub fn types(&self) -> (StaticType, StaticType) {
+        let dictionary = self.dictionary.borrow();
+        (dictionary.key_type.clone(), dictionary.value_type.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.dictionary.borrow().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, key: &Value<'a>) -> Option<Value<'a>> {
+        let dictionary = self.dictionary.borrow();
+        let index = *dictionary.indices.get(key)?;
+        Some(dictionary.entries[index].1.clone())
+    }
+
+    /// Returns the keys, in iteration order.
+    pub fn keys(&self) -> Vec<Value<'a>> {
+        let dictionary = self.dictionary.borrow();
+        dictionary
+            .entries
+            .iter()
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Inserts the value for the key, returning the previous value, if any.
+    pub fn insert(&self, key: Value<'a>, value: Value<'a>) -> Option<Value<'a>> {
+        let mut dictionary = self.dictionary.borrow_mut();
+        dictionary.mutations += 1;
+        if let Some(index) = dictionary.indices.get(&key).copied() {
+            return Some(mem::replace(&mut dictionary.entries[index].1, value));
+        }
+        let index = dictionary.entries.len();
+        dictionary.indices.insert(key.clone(), index);
+        dictionary.entries.push((key, value));
+        None
+    }
+
+    /// Removes the entry for the key, returning its value, if any.
+    /// The last entry takes the place of the removed one.
+    pub fn remove(&self, key: &Value<'a>) -> Option<Value<'a>> {
+        let mut dictionary = self.dictionary.borrow_mut();
+        let index = dictionary.indices.remove(key)?;
+        dictionary.mutations += 1;
+        let (_, value) = dictionary.entries.swap_remove(index);
+        if let Some((moved_key, _)) = dictionary.entries.get(index) {
+            let moved_key = moved_key.clone();
+            dictionary.indices.insert(moved_key, index);
+        }
+        Some(value)
+    }
+
+    fn key(&self, index: usize) -> Option<Value<'a>> {
+        let dictionary = self.dictionary.borrow();
+        dictionary.entries.get(index).map(|(key, _)| key.clone())
+    }
+
+    fn mutations(&self) -> u64 {
+        self.dictionary.borrow().mutations
+    }
+
+    fn copy(&self, copy_value: fn(&Value<'a>) -> Value<'a>) -> Self {
+        let dictionary = self.dictionary.borrow();
+        let entries = dictionary
+            .entries
+            .iter()
+            .map(|(key, value)| (copy_value(key), copy_value(value)))
+            .collect();
+        DictionaryValue::new(
+            dictionary.key_type.clone(),
+            dictionary.value_type.clone(),
+            entries,
+        )
+    }
+
+    fn entries(&self) -> Vec<(Value<'a>, Value<'a>)> {
+        self.dictionary.borrow().entries.clone()
+    }
+
+    fn restore(&self, entries: Vec<(Value<'a>, Value<'a>)>) {
+        let mut dictionary = self.dictionary.borrow_mut();
+        dictionary.indices = entries
+            .iter()
+            .enumerate()
+            .map(|(index, (key, _))| (key.clone(), index))
+            .collect();
+        dictionary.entries = entries;
+        dictionary.mutations += 1;
+    }
+}
+
+/*
+*  InclusiveRangeValue
+*/
+
+/// `InclusiveRange<T>`: the integers of type `T` from `start` to `end`, inclusive,
+/// in increments of `step`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InclusiveRangeValue {
+    range: Rc<InclusiveRange>,
+}
+
+/// The bounds of a range are held as ordinals: integers of all types mapped
+/// to `u128` in an order-preserving way, so one implementation serves all types.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct InclusiveRange {
+    element_type: StaticType,
+    start: u128,
+    end: u128,
+    /// The magnitude of the step.
+    step: u128,
+    /// Whether the step is negative.
+    descending: bool,
+}
+
+const SIGN_BIT: u128 = 1 << 127;
+
+impl InclusiveRangeValue {
+    /// Creates the range `InclusiveRange(start, end, step: step)`.
+    /// Without a step, the range counts up by one, or down by one if `end` is less than `start`.
+    /// Fails if the step is zero or moves away from the end.
+    pub fn new<'a>(
+        start: &Value<'a>,
+        end: &Value<'a>,
+        step: Option<&Value<'a>>,
+    ) -> Result<Self, VMError> {
+        let element_type = start.static_type();
+        let mismatched_type = |value: &Value<'a>| value.static_type() != element_type;
+        if !element_type.is_integer() || mismatched_type(end) || step.is_some_and(mismatched_type) {
+            return Err(range_construction_error(format!(
+                "start, end and step must have the same integer type, got `{}`",
+                element_type
+            )));
+        }
+
+        let (start_ordinal, end_ordinal) = (ordinal(start), ordinal(end));
+        let (step_magnitude, descending) = match step {
+            Some(step) => step_magnitude(step),
+            // Unsigned ranges cannot count down.
+            None => (
+                1,
+                end_ordinal < start_ordinal && element_type.is_signed_integer(),
+            ),
+        };
+
+        if step_magnitude == 0 {
+            return Err(range_construction_error(
+                "step value cannot be zero".to_string(),
+            ));
+        }
+
+        if (!descending && end_ordinal < start_ordinal)
+            || (descending && end_ordinal > start_ordinal)
+        {
+            let step = step.map_or_else(|| "1".to_string(), Value::to_string);
+            return Err(range_construction_error(format!(
+                "sequence is non-terminating as the difference between the end ({}) \
+                 and start ({}) does not have the same sign as the step ({})",
+                end, start, step
+            )));
+        }
+
+        Ok(InclusiveRangeValue {
+            range: Rc::new(InclusiveRange {
+                element_type,
+                start: start_ordinal,
+                end: end_ordinal,
+                step: step_magnitude,
+                descending,
+            }),
+        })
+    }
+
+    pub fn element_type(&self) -> StaticType {
+        self.range.element_type.clone()
+    }
+
+    pub fn start<'a>(&self) -> Value<'a> {
+        from_ordinal(&self.range.element_type, self.range.start)
+    }
+
+    pub fn end<'a>(&self) -> Value<'a> {
+        from_ordinal(&self.range.element_type, self.range.end)
+    }
+
+    pub fn step<'a>(&self) -> Value<'a> {
+        let range = &self.range;
+        if range.element_type == StaticType::UInt128 {
+            return Value::FixedSizeInt(FixedSizeIntValue::UInt128(range.step));
+        }
+        // The magnitude of the minimum of a signed type wraps to itself when negated.
+        let step = range.step as i128;
+        let step = if range.descending {
+            step.wrapping_neg()
+        } else {
+            step
+        };
+        Value::integer(&range.element_type, step).unwrap()
+    }
+
+    /// Reports whether the value is one of the elements of the range,
+    /// i.e. whether it lies between start and end and is reached by stepping from start.
+    pub fn contains(&self, value: &Value) -> bool {
+        let range = &self.range;
+        if value.static_type() != range.element_type {
+            return false;
+        }
+
+        let value = ordinal(value);
+        let offset = if range.descending {
+            (range.end..=range.start)
+                .contains(&value)
+                .then(|| range.start - value)
+        } else {
+            (range.start..=range.end)
+                .contains(&value)
+                .then(|| value - range.start)
+        };
+        offset.is_some_and(|offset| offset % range.step == 0)
+    }
+
+    /// Returns the ordinal of the element after the one with the given ordinal, if any.
+    fn next_ordinal(&self, ordinal: u128) -> Option<u128> {
+        let range = &self.range;
+        if range.descending {
+            ordinal
+                .checked_sub(range.step)
+                .filter(|next| *next >= range.end)
+        } else {
+            ordinal
+                .checked_add(range.step)
+                .filter(|next| *next <= range.end)
+        }
+    }
+}
+
+fn range_construction_error(message: String) -> VMError {
+    VMError::InclusiveRangeConstruction { message }
+}
+
+fn ordinal(value: &Value) -> u128 {
+    match value {
+        Value::FixedSizeInt(FixedSizeIntValue::UInt128(value)) => *value,
+        _ => {
+            let integer = value.to_i128().unwrap();
+            if value.static_type().is_signed_integer() {
+                integer as u128 ^ SIGN_BIT
+            } else {
+                integer as u128
+            }
+        }
+    }
+}
+
+fn from_ordinal<'a>(typ: &StaticType, ordinal: u128) -> Value<'a> {
+    if *typ == StaticType::UInt128 {
+        return Value::FixedSizeInt(FixedSizeIntValue::UInt128(ordinal));
+    }
+    let integer = if typ.is_signed_integer() {
+        (ordinal ^ SIGN_BIT) as i128
+    } else {
+        ordinal as i128
+    };
+    Value::integer(typ, integer).unwrap()
+}
+
+/// Returns the magnitude of the step, and whether it is negative.
+fn step_magnitude(step: &Value) -> (u128, bool) {
+    match step {
+        Value::FixedSizeInt(FixedSizeIntValue::UInt128(value)) => (*value, false),
+        _ => {
+            let step = step.to_i128().unwrap();
+            (step.unsigned_abs(), step < 0)
+        }
+    }
+}
+
+/*
+*  CompositeValue
+*/
+
+/// A struct, resource or contract value.
+/// Copies of a composite value share the same underlying fields,
+/// so mutations are observed through all of them, e.g. through references.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompositeValue<'a> {
+    composite: Rc<RefCell<Composite<'a>>>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Composite<'a> {
+    identifier: String,
+    kind: CompositeKind,
+    fields: BTreeMap<String, Value<'a>>,
+}
+
+impl<'a> CompositeValue<'a> {
+    pub fn new(identifier: &str, kind: CompositeKind, fields: Vec<(&str, Value<'a>)>) -> Self {
+        let fields = fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+
+        CompositeValue {
+            composite: Rc::new(RefCell::new(Composite {
+                identifier: identifier.to_string(),
+                kind,
+                fields,
+            })),
+        }
+    }
+
+    pub fn identifier(&self) -> String {
+        self.composite.borrow().identifier.clone()
+    }
+
+    pub fn has_type(&self, identifier: &str) -> bool {
+        self.composite.borrow().identifier == identifier
+    }
+
+    pub fn kind(&self) -> CompositeKind {
+        self.composite.borrow().kind
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<Value<'a>> {
+        self.composite.borrow().fields.get(name).cloned()
+    }
+
+    /// Returns the fields, ordered by name.
+    pub fn fields(&self) -> Vec<(String, Value<'a>)> {
+        let composite = self.composite.borrow();
+        composite
+            .fields
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    pub fn set_field(&self, name: &str, value: Value<'a>) {
+        self.composite
+            .borrow_mut()
+            .fields
+            .insert(name.to_string(), value);
+    }
+
+    fn copy(&self, copy_field: fn(&Value<'a>) -> Value<'a>) -> Self {
+        let composite = self.composite.borrow();
+        let fields = composite
+            .fields
+            .iter()
+            .map(|(name, value)| (name.clone(), copy_field(value)))
+            .collect();
+
+        CompositeValue {
+            composite: Rc::new(RefCell::new(Composite {
+                identifier: composite.identifier.clone(),
+                kind: composite.kind,
+                fields,
+            })),
+        }
+    }
+
+    fn restore(&self, fields: BTreeMap<String, Value<'a>>) {
+        self.composite.borrow_mut().fields = fields;
+    }
+}
+
+/*
+*  Snapshot
+*/
+
+/// The contents of a value that can be mutated in place, as of when the snapshot was taken,
+/// so they can later be reverted in place with `restore`.
+///
+/// The containers and composites nested in the value are kept themselves, not copied,
+/// along with snapshots of their own contents. Restoring puts them back and reverts them
+/// in place too, so the reversion is observed through all copies sharing them,
+/// e.g. references to a nested container.
+pub(crate) struct Snapshot<'a> {
+    /// The value, sharing its contents.
+    value: Value<'a>,
+    contents: Contents<'a>,
 }
 
-impl<'a> Value for FunctionValue<'a> {}
+enum Contents<'a> {
+    /// The value has no contents that can be mutated in place.
+    None,
+    Some(Box<Snapshot<'a>>),
+    Array(Vec<Snapshot<'a>>),
+    Dictionary(Vec<(Value<'a>, Snapshot<'a>)>),
+    Composite(Vec<(String, Snapshot<'a>)>),
+    /// The values stored inline in a slab. The slab itself is immutable.
+    Slab(Vec<Snapshot<'a>>),
+}
 
-impl<'a> Clone for FunctionValue<'a> {
-    fn clone(&self) -> Self {
-        FunctionValue {
-            function: self.function,
+impl<'a> Snapshot<'a> {
+    pub(crate) fn new(value: &Value<'a>) -> Self {
+        let contents = match value {
+            Value::Some(value) => Contents::Some(Box::new(Snapshot::new(value))),
+            Value::Array(array) => {
+                Contents::Array(array.elements().iter().map(Snapshot::new).collect())
+            }
+            Value::Dictionary(dictionary) => Contents::Dictionary(
+                dictionary
+                    .entries()
+                    .into_iter()
+                    .map(|(key, value)| (key, Snapshot::new(&value)))
+                    .collect(),
+            ),
+            Value::Composite(composite) => Contents::Composite(
+                composite
+                    .fields()
+                    .into_iter()
+                    .map(|(name, value)| (name, Snapshot::new(&value)))
+                    .collect(),
+            ),
+            Value::Slab(slab) => {
+                Contents::Slab(slab.values().into_iter().map(Snapshot::new).collect())
+            }
+            _ => Contents::None,
+        };
+        Snapshot {
+            value: value.clone(),
+            contents,
+        }
+    }
+
+    /// Returns the value the snapshot was taken of.
+    pub(crate) fn value(&self) -> &Value<'a> {
+        &self.value
+    }
+
+    /// Returns whether the contents of the value, including those of nested values,
+    /// are the same as when the snapshot was taken.
+    pub(crate) fn is_unchanged(&self) -> bool {
+        let unchanged = match (&self.value, &self.contents) {
+            (Value::Array(array), Contents::Array(elements)) => array
+                .elements()
+                .iter()
+                .eq(elements.iter().map(Snapshot::value)),
+            (Value::Dictionary(dictionary), Contents::Dictionary(entries)) => dictionary
+                .entries()
+                .iter()
+                .map(|(key, value)| (key, value))
+                .eq(entries.iter().map(|(key, value)| (key, value.value()))),
+            (Value::Composite(composite), Contents::Composite(fields)) => composite
+                .fields()
+                .iter()
+                .map(|(name, value)| (name, value))
+                .eq(fields.iter().map(|(name, value)| (name, value.value()))),
+            _ => true,
+        };
+        unchanged && self.children().all(Snapshot::is_unchanged)
+    }
+
+    /// Reverts the contents of the value in place, including those of nested values.
+    pub(crate) fn restore(&self) {
+        match (&self.value, &self.contents) {
+            (Value::Array(array), Contents::Array(elements)) => array.restore(
+                elements
+                    .iter()
+                    .map(|element| element.value.clone())
+                    .collect(),
+            ),
+            (Value::Dictionary(dictionary), Contents::Dictionary(entries)) => dictionary.restore(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.value.clone()))
+                    .collect(),
+            ),
+            (Value::Composite(composite), Contents::Composite(fields)) => composite.restore(
+                fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.value.clone()))
+                    .collect(),
+            ),
+            _ => {}
+        }
+        for child in self.children() {
+            child.restore();
+        }
+    }
+
+    fn children(&self) -> Box<dyn Iterator<Item = &Snapshot<'a>> + '_> {
+        match &self.contents {
+            Contents::None => Box::new(std::iter::empty()),
+            Contents::Some(value) => Box::new(std::iter::once(value.as_ref())),
+            Contents::Array(elements) | Contents::Slab(elements) => Box::new(elements.iter()),
+            Contents::Dictionary(entries) => Box::new(entries.iter().map(|(_, value)| value)),
+            Contents::Composite(fields) => Box::new(fields.iter().map(|(_, value)| value)),
         }
     }
 }
 
-impl<'a> Copy for FunctionValue<'a> {}
+/*
+*  ReferenceValue
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReferenceValue<'a> {
+    pub authorization: Authorization,
+    pub target: ReferenceTarget<'a>,
+}
+
+/// What a reference refers to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReferenceTarget<'a> {
+    /// A value the reference shares, e.g. a capability controller.
+    Value(Box<Value<'a>>),
+    /// The value stored at a path of an account, read from storage each time the reference
+    /// is used, so the reference observes the value stored there at that time.
+    Storage {
+        address: AddressValue,
+        path: PathValue,
+        referenced_type: StaticType,
+    },
+}
+
+impl<'a> ReferenceValue<'a> {
+    /// Returns a reference that shares the value.
+    pub fn new(authorization: Authorization, value: Value<'a>) -> Self {
+        ReferenceValue {
+            authorization,
+            target: ReferenceTarget::Value(Box::new(value)),
+        }
+    }
+
+    /// Returns a reference to the value stored at the path,
+    /// which must have the referenced type when the reference is used.
+    pub fn stored(
+        authorization: Authorization,
+        address: AddressValue,
+        path: PathValue,
+        referenced_type: StaticType,
+    ) -> Self {
+        ReferenceValue {
+            authorization,
+            target: ReferenceTarget::Storage {
+                address,
+                path,
+                referenced_type,
+            },
+        }
+    }
+
+    /// Returns the referenced value, reading it from storage if the reference is to a path.
+    /// Fails if the path no longer stores a value of the referenced type.
+    pub fn resolve(
+        &self,
+        storage: &dyn Storage<'a>,
+        types: &TypeRegistry,
+    ) -> Result<Value<'a>, VMError> {
+        match &self.target {
+            ReferenceTarget::Value(value) => Ok((**value).clone()),
+            ReferenceTarget::Storage {
+                address,
+                path,
+                referenced_type,
+            } => match storage.read(*address, &StorageKey::Path(path.clone())) {
+                Some(value) if value.is_instance(referenced_type, types) => Ok(value),
+                _ => Err(VMError::DereferenceFailure {
+                    address: *address,
+                    path: path.clone(),
+                }),
+            },
+        }
+    }
+}
+
+/*
+*  TypeValue
+*/
+
+/// A type literal, e.g. `Type<Int>()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeValue {
+    pub typ: StaticType,
+}
+
+/*
+*  IteratorValue
+*/
+
+/// The state of a `for` loop over an array, the keys of a dictionary,
+/// the elements of a range, or the characters of a string.
+/// Iterating over an array or dictionary fails if it is mutated during the loop.
+/// The slabs of stored arrays and dictionaries are read as the loop reaches them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IteratorValue<'a> {
+    Array {
+        array: ArrayValue<'a>,
+        index: usize,
+        mutations: u64,
+    },
+    DictionaryKeys {
+        dictionary: DictionaryValue<'a>,
+        index: usize,
+        mutations: u64,
+    },
+    StoredArray {
+        array: StoredArrayValue,
+        index: usize,
+        mutations: Option<u64>,
+    },
+    StoredDictionaryKeys {
+        dictionary: StoredDictionaryValue,
+        cursor: DictionaryCursor<'a>,
+        /// The number of keys returned so far.
+        index: usize,
+        mutations: Option<u64>,
+    },
+    InclusiveRange {
+        range: InclusiveRangeValue,
+        /// The ordinal of the next element, if any.
+        next: Option<u128>,
+    },
+    Characters {
+        string: Rc<str>,
+        /// The byte offset of the next character.
+        offset: usize,
+    },
+}
+
+impl<'a> IteratorValue<'a> {
+    /// Returns an iterator over the value, or `None` if the value is not iterable.
+    pub fn new(value: &Value<'a>, storage: &dyn Storage<'a>) -> Result<Option<Self>, VMError> {
+        let iterator = match value {
+            Value::Array(array) => IteratorValue::Array {
+                array: array.clone(),
+                index: 0,
+                mutations: array.mutations(),
+            },
+            Value::Dictionary(dictionary) => IteratorValue::DictionaryKeys {
+                dictionary: dictionary.clone(),
+                index: 0,
+                mutations: dictionary.mutations(),
+            },
+            Value::StoredArray(array) => IteratorValue::StoredArray {
+                array: array.clone(),
+                index: 0,
+                mutations: array.mutations(storage)?,
+            },
+            Value::StoredDictionary(dictionary) => IteratorValue::StoredDictionaryKeys {
+                dictionary: dictionary.clone(),
+                cursor: dictionary.cursor(storage)?,
+                index: 0,
+                mutations: dictionary.mutations(storage)?,
+            },
+            Value::InclusiveRange(range) => IteratorValue::InclusiveRange {
+                range: range.clone(),
+                next: Some(range.range.start),
+            },
+            Value::String(string) => IteratorValue::Characters {
+                string: string.value.clone(),
+                offset: 0,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(iterator))
+    }
+
+    pub fn has_next(&self, storage: &dyn Storage<'a>) -> Result<bool, VMError> {
+        self.check_mutations(storage)?;
+        let has_next = match self {
+            IteratorValue::Array { array, index, .. } => *index < array.len(),
+            IteratorValue::DictionaryKeys {
+                dictionary, index, ..
+            } => *index < dictionary.len(),
+            IteratorValue::StoredArray { array, index, .. } => *index < array.len(storage)?,
+            IteratorValue::StoredDictionaryKeys {
+                dictionary, index, ..
+            } => *index < dictionary.len(storage)?,
+            IteratorValue::InclusiveRange { next, .. } => next.is_some(),
+            IteratorValue::Characters { string, offset } => *offset < string.len(),
+        };
+        Ok(has_next)
+    }
+
+    /// Returns the next element, or `None` if the iteration is done.
+    pub fn next_element(
+        &mut self,
+        storage: &dyn Storage<'a>,
+    ) -> Result<Option<Value<'a>>, VMError> {
+        self.check_mutations(storage)?;
+        let next = match self {
+            IteratorValue::Array { array, index, .. } => {
+                let element = array.get(*index);
+                *index += 1;
+                element
+            }
+            IteratorValue::DictionaryKeys {
+                dictionary, index, ..
+            } => {
+                let key = dictionary.key(*index);
+                *index += 1;
+                key
+            }
+            IteratorValue::StoredArray { array, index, .. } => {
+                let element = array.get(storage, *index)?;
+                *index += 1;
+                element
+            }
+            IteratorValue::StoredDictionaryKeys {
+                dictionary,
+                cursor,
+                index,
+                ..
+            } => {
+                let key = cursor.next_key(storage, dictionary.address)?;
+                *index += 1;
+                key
+            }
+            IteratorValue::InclusiveRange { range, next } => next.map(|ordinal| {
+                *next = range.next_ordinal(ordinal);
+                from_ordinal(&range.range.element_type, ordinal)
+            }),
+            IteratorValue::Characters { string, offset } => {
+                string[*offset..].graphemes(true).next().map(|character| {
+                    *offset += character.len();
+                    Value::Character(CharacterValue::new(character))
+                })
+            }
+        };
+        Ok(next)
+    }
+
+    fn check_mutations(&self, storage: &dyn Storage<'a>) -> Result<(), VMError> {
+        let mutated = match self {
+            IteratorValue::Array {
+                array, mutations, ..
+            } => array.mutations() != *mutations,
+            IteratorValue::DictionaryKeys {
+                dictionary,
+                mutations,
+                ..
+            } => dictionary.mutations() != *mutations,
+            IteratorValue::StoredArray {
+                array, mutations, ..
+            } => array.mutations(storage)? != *mutations,
+            IteratorValue::StoredDictionaryKeys {
+                dictionary,
+                mutations,
+                ..
+            } => dictionary.mutations(storage)? != *mutations,
+            IteratorValue::InclusiveRange { .. } | IteratorValue::Characters { .. } => false,
+        };
+        if mutated {
+            return Err(VMError::ContainerMutatedDuringIteration);
+        }
+        Ok(())
+    }
+}
+
+/*
+*  UpvalueValue
+*/
+
+/// A variable that is shared between a function and the closures capturing it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpvalueValue<'a> {
+    value: Rc<RefCell<Value<'a>>>,
+}
+
+impl<'a> UpvalueValue<'a> {
+    pub fn new(value: Value<'a>) -> Self {
+        UpvalueValue {
+            value: Rc::new(RefCell::new(value)),
+        }
+    }
+
+    pub fn get(&self) -> Value<'a> {
+        self.value.borrow().clone()
+    }
+
+    pub fn set(&self, value: Value<'a>) {
+        self.value.replace(value);
+    }
+}
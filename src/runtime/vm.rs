@@ -16,162 +16,877 @@
  * limitations under the License.
  */
 
-use crate::runtime::opcodes::Argument;
-use crate::runtime::values::{BoolValue, FunctionValue, IntValue, FALSE_VALUE, INT_ZERO_VALUE};
-use crate::runtime::{bbq, registers};
-
-use crate::runtime::bbq::Function;
-use crate::runtime::registers::RegisterType;
+use crate::runtime::bbq;
+use crate::runtime::errors::{StackFrame, VMError};
+use crate::runtime::metering::{
+    ComputationKind, ComputationMeter, MemoryKind, MemoryMeter, MemoryUsage,
+};
+use crate::runtime::opcodes::convert;
+use crate::runtime::storage::{InMemoryStorage, JournaledStorage, Storage, StorageKey};
+use crate::runtime::types::{StaticType, TypeRegistry};
+use crate::runtime::values::{
+    AddressValue, BoolValue, FunctionValue, IntValue, NativeFunctionValue, Value, FALSE_VALUE,
+    INT_ZERO_VALUE,
+};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::runtime::bbq::{Function, Parameter};
+use crate::runtime::registers::{RegisterCounts, RegisterType};
 
 pub struct VM<'a> {
-    // Program   *bbq.Program
-    pub globals: Vec<FunctionValue<'a>>,
-    pub constants: Vec<IntValue>,
-    // functions map[string]*bbq.Function
+    pub program: &'a bbq::Program,
+    /// The values of the program's globals, `None` if not yet initialized.
+    pub globals: Vec<Option<Value<'a>>>,
+    initialized: bool,
+    /// The decoded constants of the program, initialized on first use.
+    pub(crate) constants: Vec<Option<Value<'a>>>,
+    pub types: TypeRegistry,
+    pub functions: HashMap<&'a str, &'a bbq::Function>,
+    /// The method tables of the program's composite types,
+    /// mapping each method name index to the implementing function.
+    pub(crate) vtables: HashMap<String, Vec<Option<usize>>>,
     pub call_stack: Vec<CallFrame<'a>>,
+    /// The registers of the frames of the call stack.
+    pub(crate) registers: RegisterStack<'a>,
     pub current_index: usize,
 
-    pub return_value: IntValue,
+    pub return_value: Value<'a>,
+
+    /// The account storage of the host, behind a journal of the writes of the current invocation.
+    /// Defaults to an empty in-memory storage.
+    pub storage: JournaledStorage<'a>,
+    /// The number of writes to the storage through `write_storage`.
+    storage_writes: u64,
+    /// Meters the computation of the current invocation. Defaults to no limit.
+    computation: ComputationMeter,
+    /// Meters the memory allocated by the current invocation. Defaults to no limit.
+    pub(crate) memory: MemoryMeter,
+    /// The maximum number of frames on the call stack.
+    max_call_depth: usize,
 }
 
+/// The default maximum number of frames on the call stack.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+/// The number of innermost frames reported by `CallStackOverflow`.
+const REPORTED_FRAMES: usize = 10;
+
+/// The execution context passed to native functions.
+pub type VMContext<'a> = VM<'a>;
+
 pub struct CallFrame<'a> {
-    pub(crate) locals: Registers<'a>,
+    /// The frame's registers in the register stack.
+    window: Window,
     function: &'a bbq::Function,
+    pub(crate) captures: Option<Rc<[Value<'a>]>>,
     pub(crate) ip: usize,
 
     return_to_index: usize,
+    /// Whether the frame was called by the host or a native function,
+    /// rather than by the preceding frame.
+    returns_to_host: bool,
 }
 
-pub struct Registers<'a> {
-    pub(crate) ints: Vec<IntValue>,
-    pub(crate) bools: Vec<BoolValue>,
-    pub(crate) funcs: Vec<Option<FunctionValue<'a>>>,
+impl CallFrame<'_> {
+    /// Returns the frame as reported in errors, at the instruction being executed.
+    fn stack_frame(&self) -> StackFrame {
+        let instruction = self.ip.saturating_sub(1);
+        StackFrame {
+            function: self.function.name.clone(),
+            instruction,
+            position: self
+                .function
+                .position(instruction)
+                .map(|position| (position.line, position.column)),
+        }
+    }
 }
 
-impl<'a> Registers<'a> {
-    fn new(function: &'a Function) -> Self {
+/// The indices of a call frame's registers in the register stack:
+/// the index of its first register, and the index past its last register, of each class.
+#[derive(Clone, Copy)]
+struct Window {
+    start: RegisterCounts,
+    end: RegisterCounts,
+}
+
+/// The registers of all frames of the call stack, one stack per register class.
+///
+/// The registers of a frame are a window of each stack, above the windows of its callers.
+/// Returning truncates the stacks, but keeps their capacity,
+/// so calls do not allocate once the stacks have grown to the deepest call.
+#[derive(Default)]
+pub(crate) struct RegisterStack<'a> {
+    ints: Vec<IntValue>,
+    bools: Vec<BoolValue>,
+    funcs: Vec<Option<FunctionValue<'a>>>,
+    values: Vec<Value<'a>>,
+}
+
+impl<'a> RegisterStack<'a> {
+    /// Returns the index past the top register of each class.
+    fn top(&self) -> RegisterCounts {
+        RegisterCounts {
+            ints: self.ints.len(),
+            bools: self.bools.len(),
+            funcs: self.funcs.len(),
+            values: self.values.len(),
+        }
+    }
+
+    /// Pushes the registers of the function, starting at the given index of each class.
+    /// Registers below the top of the stack are shared with the frame below,
+    /// e.g. the caller's argument registers are the callee's parameter registers.
+    /// The registers added to the stack are charged to the memory meter.
+    fn push(
+        &mut self,
+        start: RegisterCounts,
+        function: &'a Function,
+        memory: &mut MemoryMeter,
+    ) -> Result<Window, VMError> {
+        let counts = &function.local_count;
+        let end = RegisterCounts {
+            ints: start.ints + counts.ints,
+            bools: start.bools + counts.bools,
+            funcs: start.funcs + counts.funcs,
+            values: start.values + counts.values,
+        };
+
+        let top = self.top();
+        let added = end.ints.saturating_sub(top.ints)
+            + end.bools.saturating_sub(top.bools)
+            + end.funcs.saturating_sub(top.funcs)
+            + end.values.saturating_sub(top.values);
+        memory.charge(MemoryKind::Registers, added as u64)?;
+
+        self.ints.resize(end.ints, INT_ZERO_VALUE);
+        self.bools.resize(end.bools, FALSE_VALUE);
+        self.funcs.resize(end.funcs, None);
+        self.values.resize(end.values, Value::Void);
+        Ok(Window { start, end })
+    }
+
+    /// Restores the registers of the window at the top of the stack
+    /// after popping a frame which shared some of them, clearing the shared registers.
+    fn restore(&mut self, window: &Window) {
+        self.ints.resize(window.end.ints, INT_ZERO_VALUE);
+        self.bools.resize(window.end.bools, FALSE_VALUE);
+        self.funcs.resize(window.end.funcs, None);
+        self.values.resize(window.end.values, Value::Void);
+    }
+
+    /// Pops the registers of the window, and of all windows above it.
+    fn truncate(&mut self, window: &Window) {
+        self.ints.truncate(window.start.ints);
+        self.bools.truncate(window.start.bools);
+        self.funcs.truncate(window.start.funcs);
+        self.values.truncate(window.start.values);
+    }
+
+    /// Returns the registers of the call frame.
+    pub(crate) fn frame(&mut self, frame: &CallFrame<'a>) -> Registers<'_, 'a> {
+        let Window { start, end } = &frame.window;
         Registers {
-            ints: vec![INT_ZERO_VALUE; function.local_count.ints],
-            bools: vec![FALSE_VALUE; function.local_count.bools],
-            funcs: vec![None; function.local_count.funcs],
+            ints: &mut self.ints[start.ints..end.ints],
+            bools: &mut self.bools[start.bools..end.bools],
+            funcs: &mut self.funcs[start.funcs..end.funcs],
+            values: &mut self.values[start.values..end.values],
+        }
+    }
+
+    /// Returns the int registers of the call frame.
+    pub(crate) fn frame_ints(&mut self, frame: &CallFrame<'a>) -> &mut [IntValue] {
+        &mut self.ints[frame.window.start.ints..frame.window.end.ints]
+    }
+
+    /// Returns the bool registers of the call frame.
+    pub(crate) fn frame_bools(&mut self, frame: &CallFrame<'a>) -> &mut [BoolValue] {
+        &mut self.bools[frame.window.start.bools..frame.window.end.bools]
+    }
+}
+
+/// The registers of a call frame: its window of the register stack.
+pub struct Registers<'r, 'a> {
+    pub(crate) ints: &'r mut [IntValue],
+    pub(crate) bools: &'r mut [BoolValue],
+    pub(crate) funcs: &'r mut [Option<FunctionValue<'a>>],
+    pub(crate) values: &'r mut [Value<'a>],
+}
+
+impl<'r, 'a> Registers<'r, 'a> {
+    /// Reads a register of any class as a `Value`.
+    pub(crate) fn get(&self, typ: RegisterType, index: usize) -> Value<'a> {
+        match typ {
+            RegisterType::Int => Value::Int(self.ints[index]),
+            RegisterType::Bool => Value::Bool(self.bools[index]),
+            RegisterType::Func => Value::Function(self.funcs[index].clone().unwrap()),
+            RegisterType::Value => self.values[index].clone(),
+        }
+    }
+
+    /// Writes a `Value` to a register of any class.
+    /// The value must be of the type held by the register class.
+    pub(crate) fn set(&mut self, typ: RegisterType, index: usize, value: Value<'a>) {
+        match (typ, value) {
+            (RegisterType::Int, Value::Int(value)) => self.ints[index] = value,
+            (RegisterType::Bool, Value::Bool(value)) => self.bools[index] = value,
+            (RegisterType::Func, Value::Function(value)) => self.funcs[index] = Some(value),
+            (RegisterType::Value, value) => self.values[index] = value,
+            (typ, value) => panic!("cannot store {:?} in {:?} register", value, typ),
+        }
+    }
+
+    /// Reads a register of any class as a `Value`, moving resources out of the register.
+    pub(crate) fn read_moving_resources(
+        &mut self,
+        typ: RegisterType,
+        index: usize,
+        types: &TypeRegistry,
+    ) -> Value<'a> {
+        match typ {
+            RegisterType::Value if self.values[index].is_resource(types) => self.take(typ, index),
+            typ => self.get(typ, index),
+        }
+    }
+
+    /// Reads a register of any class as a `Value`, moving values out of value registers.
+    pub(crate) fn take(&mut self, typ: RegisterType, index: usize) -> Value<'a> {
+        match typ {
+            RegisterType::Value => std::mem::replace(&mut self.values[index], Value::Void),
+            typ => self.get(typ, index),
         }
     }
 }
 
 impl<'a> VM<'a> {
-    pub fn invoke(&mut self, function: &'a Function, argument: IntValue) -> IntValue {
-        // TODO: pass in the function name and look it up from the functions map.
+    pub fn new(program: &'a bbq::Program) -> Self {
+        let mut types = TypeRegistry::new();
+        for typ in &program.interface_types {
+            types.add_interface_type(typ.clone());
+        }
+        for typ in &program.composite_types {
+            types.add_composite_type(typ.clone());
+        }
+
+        let vtables = program
+            .composite_types
+            .iter()
+            .map(|typ| {
+                let vtable = program
+                    .method_names
+                    .iter()
+                    .map(|name| types.find_method(&typ.identifier, name))
+                    .collect();
+                (typ.identifier.clone(), vtable)
+            })
+            .collect();
+
+        VM {
+            program,
+            globals: program
+                .functions
+                .iter()
+                .map(FunctionValue::new)
+                .chain(program.native_functions.iter().map(|name| {
+                    let name: Rc<str> = Rc::from(name.as_str());
+                    FunctionValue::Native(NativeFunctionValue {
+                        name: name.clone(),
+                        return_type: RegisterType::Value,
+                        function: Rc::new(move |_, _| {
+                            Err(VMError::UndefinedFunction {
+                                name: name.to_string(),
+                            })
+                        }),
+                    })
+                }))
+                .map(|function| Some(Value::Function(function)))
+                .chain(program.variables.iter().map(|_| None))
+                .collect(),
+            initialized: false,
+            constants: vec![None; program.constants.len()],
+            types,
+            functions: program
+                .functions
+                .iter()
+                .map(|function| (function.name.as_str(), function))
+                .collect(),
+            vtables,
+            call_stack: vec![],
+            registers: RegisterStack::default(),
+            current_index: 0,
+            return_value: Value::Void,
+            storage: JournaledStorage::new(Box::new(InMemoryStorage::new())),
+            storage_writes: 0,
+            computation: ComputationMeter::default(),
+            memory: MemoryMeter::default(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        }
+    }
+
+    /// Registers a function implemented by the host,
+    /// linking it to the program's global of the same name, if any.
+    pub fn register_native_function<F>(
+        &mut self,
+        name: &str,
+        return_type: RegisterType,
+        function: F,
+    ) where
+        F: Fn(&mut VMContext<'a>, &[Value<'a>]) -> Result<Value<'a>, VMError> + 'a,
+    {
+        let position = self
+            .program
+            .native_functions
+            .iter()
+            .position(|native_function| native_function == name);
+
+        if let Some(position) = position {
+            let function = FunctionValue::Native(NativeFunctionValue {
+                name: Rc::from(name),
+                return_type,
+                function: Rc::new(function),
+            });
+            self.globals[self.program.functions.len() + position] = Some(Value::Function(function));
+        }
+    }
+
+    /// Stores the value at the key of the account, or removes the stored value if it is `None`.
+    pub fn write_storage(
+        &mut self,
+        address: AddressValue,
+        key: &StorageKey,
+        value: Option<Value<'a>>,
+    ) {
+        self.storage_writes += 1;
+        self.storage.write(address, key, value);
+    }
+
+    /// Returns the referenced value if the value is a reference, or else the value itself.
+    /// References to stored values read the value from storage.
+    pub fn dereference(&self, value: &Value<'a>) -> Result<Value<'a>, VMError> {
+        match value {
+            Value::Reference(reference) => reference.resolve(&self.storage, &self.types),
+            value => Ok(value.clone()),
+        }
+    }
 
-        let mut locals = Registers::new(function);
+    /// Returns the frame of the instruction being executed, if any,
+    /// e.g. the call of a native function, to report it in errors.
+    pub(crate) fn call_site(&self) -> Option<Box<StackFrame>> {
+        self.call_stack
+            .last()
+            .map(|frame| Box::new(frame.stack_frame()))
+    }
 
-        locals.ints[0] = argument;
+    /// Returns the number of writes to the storage so far,
+    /// e.g. to detect mutations while iterating over stored values.
+    pub fn storage_writes(&self) -> u64 {
+        self.storage_writes
+    }
+
+    /// Sets the meter of the computation of each invocation,
+    /// which fails with `ComputationLimitExceeded` once the meter's limit is exceeded.
+    pub fn set_computation_meter(&mut self, meter: ComputationMeter) {
+        self.computation = meter;
+    }
+
+    /// Returns the computation used by the last invocation.
+    pub fn computation_used(&self) -> u64 {
+        self.computation.used()
+    }
+
+    /// Sets the meter of the memory allocated by each invocation,
+    /// which fails with `MemoryLimitExceeded` once the meter's limit is exceeded.
+    pub fn set_memory_meter(&mut self, meter: MemoryMeter) {
+        self.memory = meter;
+    }
+
+    /// Returns the memory allocated by the last invocation, by kind.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.memory.usage()
+    }
+
+    /// Sets the maximum number of frames on the call stack.
+    /// Calls beyond it fail with `CallStackOverflow`.
+    pub fn set_max_call_depth(&mut self, max_depth: usize) {
+        self.max_call_depth = max_depth;
+    }
+
+    /// Fails if the call stack has no room for another frame.
+    fn check_call_depth(&self) -> Result<(), VMError> {
+        if self.call_stack.len() < self.max_call_depth {
+            return Ok(());
+        }
+        let frames = self
+            .call_stack
+            .iter()
+            .rev()
+            .take(REPORTED_FRAMES)
+            .map(CallFrame::stack_frame)
+            .collect();
+        Err(VMError::CallStackOverflow {
+            max_depth: self.max_call_depth,
+            frames,
+        })
+    }
+
+    /// Charges the computation meter for the kind of work.
+    pub(crate) fn meter(&mut self, kind: ComputationKind) -> Result<(), VMError> {
+        self.computation.charge(kind)
+    }
+
+    /// Continues execution of the current function at the target instruction.
+    /// Jumps back to an earlier instruction are charged as loop iterations.
+    pub(crate) fn jump(&mut self, target: usize) -> Result<(), VMError> {
+        let call_frame = self.call_frame();
+        let backward = target < call_frame.ip;
+        call_frame.ip = target;
+        if backward {
+            self.meter(ComputationKind::LoopIteration)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the value of the global variable with the given name,
+    /// or `None` if there is no such variable or it is not initialized.
+    pub fn global(&self, name: &str) -> Option<Value<'a>> {
+        let index = self.variable_index(name)?;
+        self.globals[index].clone()
+    }
+
+    /// Sets the value of the global variable with the given name,
+    /// e.g. to provide the value of an imported contract.
+    pub fn set_global(&mut self, name: &str, value: Value<'a>) -> Result<(), VMError> {
+        let index = self
+            .variable_index(name)
+            .ok_or_else(|| VMError::UndefinedVariable {
+                name: name.to_string(),
+            })?;
+
+        self.store_global(index, value)
+    }
+
+    fn variable_index(&self, name: &str) -> Option<usize> {
+        let first = self.globals.len() - self.program.variables.len();
+        self.program
+            .variables
+            .iter()
+            .position(|variable| variable.name == name)
+            .map(|position| first + position)
+    }
+
+    fn variable(&self, index: usize) -> Option<&'a bbq::Variable> {
+        let first = self.globals.len() - self.program.variables.len();
+        let program = self.program;
+        index
+            .checked_sub(first)
+            .and_then(|position| program.variables.get(position))
+    }
+
+    /// Returns the value of the global with the given index.
+    pub(crate) fn load_global(&self, index: usize) -> Result<Value<'a>, VMError> {
+        match &self.globals[index] {
+            Some(value) => Ok(value.clone()),
+            None => Err(VMError::UninitializedVariable {
+                name: self.variable(index).unwrap().name.clone(),
+            }),
+        }
+    }
+
+    /// Stores the value in the global variable with the given index.
+    /// Fails if the global is not a variable, or the value does not have the variable's type.
+    pub(crate) fn store_global(&mut self, index: usize, value: Value<'a>) -> Result<(), VMError> {
+        let variable = match self.variable(index) {
+            Some(variable) => variable,
+            None => {
+                return Err(VMError::NotAVariable {
+                    name: self.global_name(index),
+                })
+            }
+        };
+        if !value.is_instance(&variable.typ, &self.types) {
+            return Err(VMError::VariableTypeMismatch {
+                name: variable.name.clone(),
+                expected_type: variable.typ.clone(),
+                actual_type: value.static_type(),
+            });
+        }
+
+        self.globals[index] = Some(convert(value, &variable.typ, &self.types));
+        Ok(())
+    }
+
+    /// Returns the name of the function, native function or variable with the global index.
+    fn global_name(&self, index: usize) -> String {
+        let program = self.program;
+        let first_native = program.functions.len();
+        let first_variable = first_native + program.native_functions.len();
+        if index < first_native {
+            program.functions[index].name.clone()
+        } else if index < first_variable {
+            program.native_functions[index - first_native].clone()
+        } else {
+            match self.variable(index) {
+                Some(variable) => variable.name.clone(),
+                None => format!("global {}", index),
+            }
+        }
+    }
+
+    /// Runs the program's initializer, if the program is not initialized yet.
+    fn initialize(&mut self) -> Result<(), VMError> {
+        if self.initialized {
+            return Ok(());
+        }
+
+        if let Some(index) = self.program.initializer {
+            let program = self.program;
+            self.run_function(&program.functions[index], &None, &[])?;
+        }
+
+        self.initialized = true;
+        Ok(())
+    }
+
+    /// Invokes the function of the program with the given name.
+    /// The arguments must match the function's parameters,
+    /// and are passed in the registers of the parameters' types.
+    pub fn invoke(&mut self, name: &str, arguments: &[Value<'a>]) -> Result<Value<'a>, VMError> {
+        let function = *self
+            .functions
+            .get(name)
+            .ok_or_else(|| VMError::UndefinedFunction {
+                name: name.to_string(),
+            })?;
+
+        // Invocations by the host are metered separately,
+        // but invocations by native functions count towards the calling invocation.
+        if self.call_stack.is_empty() {
+            self.computation.reset();
+            self.memory.reset();
+        }
+
+        self.initialize()?;
+
+        self.run_function(function, &None, arguments)
+    }
+
+    /// Calls a function value with the given arguments, e.g. a callback passed to a native function,
+    /// and returns its result.
+    pub fn invoke_function(
+        &mut self,
+        function: &FunctionValue<'a>,
+        arguments: &[Value<'a>],
+    ) -> Result<Value<'a>, VMError> {
+        match function {
+            FunctionValue::Compiled { function, captures } => {
+                self.run_function(function, captures, arguments)
+            }
+            FunctionValue::Native(native) => {
+                self.meter(ComputationKind::NativeCall)?;
+                (native.function)(self, arguments)
+            }
+        }
+    }
+
+    /// Runs the function with the given arguments to completion.
+    fn run_function(
+        &mut self,
+        function: &'a Function,
+        captures: &Option<Rc<[Value<'a>]>>,
+        arguments: &[Value<'a>],
+    ) -> Result<Value<'a>, VMError> {
+        check_argument_count(function, arguments.len())?;
+        self.check_call_depth()?;
+        self.meter(ComputationKind::FunctionCall)?;
+
+        for (parameter, argument) in function.parameters.iter().zip(arguments) {
+            if !argument.is_instance(&parameter.typ, &self.types) {
+                return Err(argument_type_mismatch(
+                    parameter,
+                    argument.static_type(),
+                    None,
+                ));
+            }
+        }
 
         let call_frame = CallFrame {
-            locals: locals,
-            function: function,
+            window: self
+                .registers
+                .push(self.registers.top(), function, &mut self.memory)?,
+            function,
+            captures: captures.clone(),
             ip: 0,
             return_to_index: 0,
+            returns_to_host: true,
         };
 
+        let mut locals = self.registers.frame(&call_frame);
+        let mut reg_counts = RegisterCounts::default();
+        for (parameter, argument) in function.parameters.iter().zip(arguments) {
+            let typ = parameter.register_type();
+            let index = reg_counts.next_index(typ);
+            let argument = convert(argument.clone(), &parameter.typ, &self.types);
+            locals.set(typ, index, argument);
+        }
+
+        let depth = self.call_stack.len();
         self.call_stack.push(call_frame);
 
-        self.run();
+        // The storage writes of the function, and of nested invocations,
+        // are committed and persisted when the outermost invocation completes,
+        // and reverted when the function fails.
+        self.storage.checkpoint();
+        if let Err(err) = self.run(depth) {
+            self.unwind(depth);
+            self.storage.rollback();
+            return Err(err);
+        }
+        self.storage.commit();
+        if self.storage.depth() == 0 {
+            self.storage.flush()?;
+        }
 
-        return self.return_value;
+        Ok(std::mem::replace(&mut self.return_value, Value::Void))
     }
 
     pub(crate) fn call_frame(&mut self) -> &mut CallFrame<'a> {
         let size = self.call_stack.len() - 1;
-        return &mut self.call_stack[size];
+        &mut self.call_stack[size]
+    }
+
+    /// Returns the registers of the current call frame.
+    pub(crate) fn locals(&mut self) -> Registers<'_, 'a> {
+        self.registers.frame(self.call_stack.last().unwrap())
     }
 
-    pub(crate) fn run(&mut self) {
+    /// Returns the int registers of the current call frame,
+    /// without the cost of slicing the registers of the other classes.
+    pub(crate) fn ints(&mut self) -> &mut [IntValue] {
+        self.registers.frame_ints(self.call_stack.last().unwrap())
+    }
+
+    /// Returns the bool registers of the current call frame.
+    pub(crate) fn bools(&mut self) -> &mut [BoolValue] {
+        self.registers.frame_bools(self.call_stack.last().unwrap())
+    }
+
+    /// Pops the call frames above the given depth, and their registers.
+    fn unwind(&mut self, depth: usize) {
+        if let Some(call_frame) = self.call_stack.get(depth) {
+            self.registers.truncate(&call_frame.window);
+        }
+        self.call_stack.truncate(depth);
+    }
+
+    /// Runs until the call stack is back to the given depth.
+    pub(crate) fn run(&mut self, depth: usize) -> Result<(), VMError> {
         loop {
-            if self.call_stack.is_empty() {
-                return;
+            if self.call_stack.len() == depth {
+                return Ok(());
             }
 
-            let mut call_frame = self.call_frame();
+            let call_frame = self.call_frame();
             let ip = call_frame.ip;
 
+            // Falling off the end of the function returns `Void`.
             if ip >= call_frame.function.code.len() {
-                return;
+                self.pop_call_frame(None);
+                continue;
             }
 
             call_frame.ip += 1;
-            call_frame.function.code[ip].execute(self);
+            let instruction = &call_frame.function.code[ip];
+            self.meter(ComputationKind::Instruction(instruction.name()))?;
+            instruction.execute(self)?;
         }
     }
 
-    pub(crate) fn push_call_frame(
+    /// Calls the function with the arguments from the current call frame.
+    /// Functions of the program get a new call frame, native functions are called directly.
+    ///
+    /// The arguments are passed in the top registers of each class of the current call frame,
+    /// in order, after the receiver of method calls in the first value argument register.
+    /// The argument registers are cleared by the call.
+    pub(crate) fn call(
         &mut self,
-        function: &'a Function,
-        arguments: &[Argument],
+        function: &FunctionValue<'a>,
+        receiver: Option<Value<'a>>,
+        arguments: &[RegisterType],
         result_index: usize,
-    ) {
-        let mut locals = Registers::new(function);
+    ) -> Result<(), VMError> {
+        match function {
+            FunctionValue::Compiled { function, captures } => {
+                self.push_call_frame(function, captures, receiver, arguments, result_index)?;
+            }
+            FunctionValue::Native(native) => {
+                self.meter(ComputationKind::NativeCall)?;
+                let mut next = self.argument_registers(receiver.is_some(), arguments);
+                if receiver.is_some() {
+                    next.values += 1;
+                }
+                let locals = &mut self.locals();
+                let arguments: Vec<Value<'a>> = receiver
+                    .into_iter()
+                    .chain(
+                        arguments
+                            .iter()
+                            .map(|typ| locals.take(*typ, next.next_index(*typ))),
+                    )
+                    .collect();
+
+                let result = (native.function)(self, &arguments)?;
+
+                if !matches!(result, Value::Void) {
+                    self.locals().set(native.return_type, result_index, result);
+                }
+            }
+        }
+        Ok(())
+    }
 
-        let current_call_frame = self.call_frame();
+    /// Returns the index of the first argument register of each class in the current call frame.
+    fn argument_registers(&self, has_receiver: bool, arguments: &[RegisterType]) -> RegisterCounts {
+        let mut counts = RegisterCounts::default();
+        if has_receiver {
+            counts.values += 1;
+        }
+        for typ in arguments {
+            counts.next_index(*typ);
+        }
 
-        current_call_frame
-            .locals
-            .copy_arguments_to(&mut locals, arguments);
+        let window = &self.call_stack.last().unwrap().window;
+        RegisterCounts {
+            ints: window.end.ints - window.start.ints - counts.ints,
+            bools: window.end.bools - window.start.bools - counts.bools,
+            funcs: window.end.funcs - window.start.funcs - counts.funcs,
+            values: window.end.values - window.start.values - counts.values,
+        }
+    }
 
+    /// Pushes a call frame for the function whose parameter registers
+    /// are the argument registers of the current call frame.
+    /// For method calls, the receiver is passed as the first value argument.
+    fn push_call_frame(
+        &mut self,
+        function: &'a Function,
+        captures: &Option<Rc<[Value<'a>]>>,
+        receiver: Option<Value<'a>>,
+        arguments: &[RegisterType],
+        result_index: usize,
+    ) -> Result<(), VMError> {
+        check_argument_count(function, arguments.len())?;
+        self.check_call_depth()?;
+        self.meter(ComputationKind::FunctionCall)?;
+
+        let first = self.argument_registers(receiver.is_some(), arguments);
+        let mut next = first;
+        if receiver.is_some() {
+            next.values += 1;
+        }
+        let caller = self.call_stack.last().unwrap();
+        let locals = self.registers.frame(caller);
+        for (parameter, typ) in function.parameters.iter().zip(arguments) {
+            let index = next.next_index(*typ);
+            // Registers of the other classes only hold values of the type of their class.
+            let is_instance = *typ == parameter.register_type()
+                && (*typ != RegisterType::Value
+                    || locals.values[index].is_instance(&parameter.typ, &self.types));
+            if !is_instance {
+                let actual_type = locals.get(*typ, index).static_type();
+                return Err(argument_type_mismatch(
+                    parameter,
+                    actual_type,
+                    Some(Box::new(caller.stack_frame())),
+                ));
+            }
+        }
+
+        if let Some(receiver) = receiver {
+            self.locals().values[first.values] = receiver;
+        }
+
+        let caller = &self.call_stack.last().unwrap().window;
+        let start = RegisterCounts {
+            ints: caller.start.ints + first.ints,
+            bools: caller.start.bools + first.bools,
+            funcs: caller.start.funcs + first.funcs,
+            values: caller.start.values + first.values,
+        };
         let call_frame = CallFrame {
-            locals: locals,
-            function: function,
+            window: self.registers.push(start, function, &mut self.memory)?,
+            function,
+            captures: captures.clone(),
             ip: 0,
             return_to_index: result_index,
+            returns_to_host: false,
         };
-
         self.call_stack.push(call_frame);
+
+        Ok(())
     }
 
-    pub(crate) fn pop_call_frame(&mut self, return_value_index: usize) {
+    /// Pops the current call frame, copying the value of the given register, if any,
+    /// to the result register of the caller.
+    pub(crate) fn pop_call_frame(&mut self, return_value_index: Option<usize>) {
         let call_frame = self.call_stack.pop().unwrap();
-        let return_value = call_frame.locals.ints[return_value_index];
+        let return_type = call_frame.function.return_register_type();
+        let return_value = return_value_index
+            .map(|index| self.registers.frame(&call_frame).get(return_type, index));
+        self.registers.truncate(&call_frame.window);
 
-        if self.call_stack.is_empty() {
-            self.return_value = return_value;
+        if call_frame.returns_to_host {
+            self.return_value = return_value.unwrap_or(Value::Void);
             return;
         }
 
-        let parent = self.call_frame();
+        // The argument registers of the caller were the parameter registers of the callee.
+        self.registers
+            .restore(&self.call_stack.last().unwrap().window);
+        if let Some(return_value) = return_value {
+            self.locals()
+                .set(return_type, call_frame.return_to_index, return_value);
+        }
+    }
 
-        // Copy the return value from callee to caller.
-        // TODO: Currently assumes the return value is always Integer.
-        //  Fix this to copy from/to the correct register based on the return value type.
-        parent.locals.ints[call_frame.return_to_index] = return_value;
+    /// Returns the constant with the given index, decoding it on first use.
+    pub(crate) fn constant(&mut self, index: usize) -> Result<Value<'a>, VMError> {
+        match &self.constants[index] {
+            Some(value) => Ok(value.clone()),
+            None => self.initialize_constant(index),
+        }
     }
 
-    fn initialize_constant(&mut self, index: usize) {
-        // TODO
+    fn initialize_constant(&mut self, index: usize) -> Result<Value<'a>, VMError> {
+        let value = self.program.constants[index]
+            .decode()
+            .map_err(|error| VMError::InvalidConstant { index, error })?;
+        self.memory.charge_value(&value)?;
+        self.constants[index] = Some(value.clone());
+        Ok(value)
     }
 }
 
-impl<'a> Registers<'a> {
-    fn copy_arguments_to(&self, target_registers: &mut Registers<'a>, arguments: &[Argument]) {
-        let mut reg_counts = registers::RegisterCounts {
-            ints: 0,
-            bools: 0,
-            funcs: 0,
-        };
+fn check_argument_count(function: &Function, count: usize) -> Result<(), VMError> {
+    if count != function.parameters.len() {
+        return Err(VMError::ArgumentCountMismatch {
+            function: function.name.clone(),
+            expected: function.parameters.len(),
+            actual: count,
+        });
+    }
+    Ok(())
+}
 
-        for argument in arguments {
-            match argument.typ {
-                RegisterType::Int => {
-                    target_registers.ints[reg_counts.ints] = self.ints[argument.index];
-                    reg_counts.ints += 1;
-                }
-                RegisterType::Bool => {
-                    target_registers.bools[reg_counts.bools] = self.bools[argument.index];
-                    reg_counts.bools += 1;
-                }
-                RegisterType::Func => {
-                    target_registers.funcs[reg_counts.funcs] = self.funcs[argument.index];
-                    reg_counts.funcs += 1;
-                }
-            }
-        }
+fn argument_type_mismatch(
+    parameter: &Parameter,
+    actual_type: StaticType,
+    call_site: Option<Box<StackFrame>>,
+) -> VMError {
+    VMError::ArgumentTypeMismatch {
+        parameter: parameter.identifier.clone(),
+        expected_type: parameter.typ.clone(),
+        actual_type,
+        call_site,
     }
 }
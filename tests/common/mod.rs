@@ -0,0 +1,108 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Fixtures shared by the integration tests.
+
+#![allow(dead_code)]
+
+use cadence_vm::runtime::bbq::{Function, Parameter};
+use cadence_vm::runtime::opcodes::{Call, ConstantLoad, GlobalFuncLoad, OpCode, ReturnValue};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::storage::StorageKey;
+use cadence_vm::runtime::types::{CompositeKind, StaticType};
+use cadence_vm::runtime::values::{
+    AccountValue, AddressValue, CompositeValue, IntValue, PathDomain, PathValue, Value,
+};
+
+pub const ADDRESS: AddressValue = AddressValue { value: 1 };
+pub const OTHER_ADDRESS: AddressValue = AddressValue { value: 2 };
+
+pub fn parameter(identifier: &str, typ: StaticType) -> Parameter {
+    Parameter::unlabeled(identifier, typ)
+}
+
+/// Returns a function that calls the native function with its parameters,
+/// followed by the type constant, if any, and returns the result.
+/// The arguments are all the value registers, and the result replaces the first.
+pub fn forward(
+    name: &str,
+    parameters: Vec<Parameter>,
+    return_type: StaticType,
+    native: usize,
+    type_constant: Option<usize>,
+) -> Function {
+    let parameter_count = parameters.len();
+
+    let mut code: Vec<Box<dyn OpCode>> = vec![Box::new(GlobalFuncLoad {
+        index: native,
+        result: 0,
+    })];
+    let mut argument_count = parameter_count;
+    if let Some(index) = type_constant {
+        code.push(Box::new(ConstantLoad {
+            index,
+            result: parameter_count,
+        }));
+        argument_count += 1;
+    }
+    code.push(Box::new(Call {
+        func_index: 0,
+        arguments: vec![RegisterType::Value; argument_count],
+        result: 0,
+    }));
+    code.push(Box::new(ReturnValue { index: 0 }));
+
+    Function {
+        name: name.to_string(),
+        parameters,
+        return_type,
+        local_count: RegisterCounts {
+            ints: 0,
+            bools: 1,
+            funcs: 1,
+            values: argument_count.max(1),
+        },
+        code,
+        ..Default::default()
+    }
+}
+
+pub fn account<'a>() -> Value<'a> {
+    Value::Account(AccountValue { address: ADDRESS })
+}
+
+pub fn path(identifier: &str) -> PathValue {
+    PathValue::new(PathDomain::Storage, identifier).unwrap()
+}
+
+pub fn storage_path<'a>(identifier: &str) -> Value<'a> {
+    Value::Path(path(identifier))
+}
+
+pub fn key(identifier: &str) -> StorageKey {
+    StorageKey::Path(path(identifier))
+}
+
+pub fn int<'a>(value: isize) -> Value<'a> {
+    Value::Int(IntValue { value })
+}
+
+/// Returns a struct `S(count: count)`.
+pub fn structure<'a>(count: isize) -> CompositeValue<'a> {
+    CompositeValue::new("S", CompositeKind::Structure, vec![("count", int(count))])
+}
@@ -0,0 +1,146 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::opcodes::{
+    Call, GlobalFuncLoad, IntConstantLoad, IntEqual, IntSubtract, JumpIfFalse, ReturnValue,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::types::StaticType;
+use cadence_vm::runtime::values::{IntValue, Value};
+use cadence_vm::runtime::vm::VM;
+
+/// The global allocator of this test binary, which counts the allocations of each thread,
+/// so tests running concurrently do not interfere.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Returns the number of allocations made by the closure on this thread.
+fn allocations<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    let count = ALLOCATIONS.with(Cell::get) - before;
+    drop(result);
+    count
+}
+
+fn int<'a>(value: isize) -> Value<'a> {
+    Value::Int(IntValue { value })
+}
+
+fn test_program() -> Program {
+    Program {
+        functions: vec![
+            // fun countDown(_ n: Int): Int {
+            //     if n == 0 { return n }
+            //     return countDown(n - 1)
+            // }
+            Function {
+                name: "countDown".to_string(),
+                parameters: vec![Parameter::unlabeled("n", StaticType::Int)],
+                return_type: StaticType::Int,
+                local_count: RegisterCounts {
+                    ints: 2,
+                    bools: 1,
+                    funcs: 1,
+                    values: 0,
+                },
+                code: vec![
+                    Box::new(IntConstantLoad {
+                        index: 0,
+                        target: 1,
+                    }),
+                    Box::new(IntEqual {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 0,
+                    }),
+                    Box::new(JumpIfFalse {
+                        condition: 0,
+                        target: 4,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                    Box::new(IntConstantLoad {
+                        index: 1,
+                        target: 1,
+                    }),
+                    Box::new(IntSubtract {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 1,
+                    }),
+                    Box::new(GlobalFuncLoad {
+                        index: 0,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Int],
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+                ..Default::default()
+            },
+        ],
+        constants: vec![Constant::int(0), Constant::int(1)],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_calls_allocate_nothing_in_steady_state() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    // The first invocation grows the register stack and the call stack,
+    // and decodes the constants.
+    assert_eq!(vm.invoke("countDown", &[int(100)]), Ok(int(0)));
+
+    // Calls reuse the registers of the register stack, so later invocations
+    // allocate the same regardless of the number of calls.
+    let shallow = allocations(|| vm.invoke("countDown", &[int(1)]));
+    let deep = allocations(|| vm.invoke("countDown", &[int(100)]));
+    assert_eq!(deep, shallow);
+}
@@ -0,0 +1,182 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Position, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::{StackFrame, VMError};
+use cadence_vm::runtime::opcodes::{
+    Call, GlobalFuncLoad, IntConstantLoad, IntEqual, IntSubtract, JumpIfFalse, OpCode, ReturnValue,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::types::StaticType;
+use cadence_vm::runtime::values::{IntValue, Value};
+use cadence_vm::runtime::vm::{DEFAULT_MAX_CALL_DEPTH, VM};
+
+fn function(name: &str, ints: usize, code: Vec<Box<dyn OpCode>>) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters: vec![Parameter::unlabeled("n", StaticType::Int)],
+        return_type: StaticType::Int,
+        local_count: RegisterCounts {
+            ints,
+            bools: 1,
+            funcs: 1,
+            values: 0,
+        },
+        code,
+        ..Default::default()
+    }
+}
+
+fn test_program() -> Program {
+    Program {
+        functions: vec![
+            // fun countDown(_ n: Int): Int {
+            //     if n == 0 { return n }
+            //     return countDown(n - 1)
+            // }
+            function(
+                "countDown",
+                2,
+                vec![
+                    Box::new(IntConstantLoad {
+                        index: 0,
+                        target: 1,
+                    }),
+                    Box::new(IntEqual {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 0,
+                    }),
+                    Box::new(JumpIfFalse {
+                        condition: 0,
+                        target: 4,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                    Box::new(IntConstantLoad {
+                        index: 1,
+                        target: 1,
+                    }),
+                    Box::new(IntSubtract {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 1,
+                    }),
+                    Box::new(GlobalFuncLoad {
+                        index: 0,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Int],
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // fun recurse(_ n: Int): Int {
+            //     return recurse(n)
+            // }
+            Function {
+                positions: vec![Position {
+                    instruction: 0,
+                    line: 2,
+                    column: 11,
+                }],
+                ..function(
+                    "recurse",
+                    1,
+                    vec![
+                        Box::new(GlobalFuncLoad {
+                            index: 1,
+                            result: 0,
+                        }),
+                        Box::new(Call {
+                            func_index: 0,
+                            arguments: vec![RegisterType::Int],
+                            result: 0,
+                        }),
+                        Box::new(ReturnValue { index: 0 }),
+                    ],
+                )
+            },
+        ],
+        constants: vec![Constant::int(0), Constant::int(1)],
+        ..Default::default()
+    }
+}
+
+fn int<'a>(value: isize) -> Value<'a> {
+    Value::Int(IntValue { value })
+}
+
+#[test]
+fn test_unbounded_recursion() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm.invoke("recurse", &[int(1)]);
+    let frame = StackFrame {
+        function: "recurse".to_string(),
+        instruction: 1,
+        position: Some((2, 11)),
+    };
+    assert_eq!(
+        result,
+        Err(VMError::CallStackOverflow {
+            max_depth: DEFAULT_MAX_CALL_DEPTH,
+            frames: vec![frame; 10],
+        })
+    );
+    assert!(result.unwrap_err().to_string().starts_with(
+        "call stack overflow: maximum depth 1024 exceeded, at recurse:1 (2:11), recurse:1 (2:11), "
+    ));
+
+    // The call stack is unwound, and the VM can be used again.
+    assert!(vm.call_stack.is_empty());
+    assert_eq!(vm.invoke("countDown", &[int(10)]), Ok(int(0)));
+}
+
+#[test]
+fn test_stack_frame_without_position() {
+    let frame = StackFrame {
+        function: "countDown".to_string(),
+        instruction: 7,
+        position: None,
+    };
+    assert_eq!(frame.to_string(), "countDown:7");
+}
+
+#[test]
+fn test_max_call_depth() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+    vm.set_max_call_depth(50);
+
+    // Counting down from n uses n + 1 frames.
+    assert_eq!(vm.invoke("countDown", &[int(49)]), Ok(int(0)));
+    assert!(matches!(
+        vm.invoke("countDown", &[int(50)]),
+        Err(VMError::CallStackOverflow { max_depth: 50, .. })
+    ));
+
+    // Only the invoked function and a single recursive call fit.
+    vm.set_max_call_depth(2);
+    assert_eq!(vm.invoke("countDown", &[int(1)]), Ok(int(0)));
+    assert!(vm.invoke("countDown", &[int(2)]).is_err());
+}
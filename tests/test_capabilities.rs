@@ -0,0 +1,474 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+mod common;
+
+use cadence_vm::runtime::bbq::Program;
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::stdlib;
+use cadence_vm::runtime::types::{Authorization, CompositeKind, CompositeType, StaticType};
+use cadence_vm::runtime::values::{
+    BoolValue, CapabilityValue, CompositeValue, FixedSizeIntValue, IntValue, PathDomain, PathValue,
+    Value,
+};
+use cadence_vm::runtime::vm::VM;
+use common::{account, forward, parameter, path, storage_path, structure, ADDRESS};
+
+const FUNCTION_COUNT: usize = 16;
+
+// Natives, following the functions
+const NATIVE_SAVE: usize = FUNCTION_COUNT;
+const NATIVE_ISSUE: usize = FUNCTION_COUNT + 1;
+const NATIVE_GET_CONTROLLER: usize = FUNCTION_COUNT + 2;
+const NATIVE_PUBLISH: usize = FUNCTION_COUNT + 3;
+const NATIVE_UNPUBLISH: usize = FUNCTION_COUNT + 4;
+const NATIVE_GET: usize = FUNCTION_COUNT + 5;
+const NATIVE_BORROW: usize = FUNCTION_COUNT + 6;
+const NATIVE_CAPABILITY_BORROW: usize = FUNCTION_COUNT + 7;
+const NATIVE_CAPABILITY_CHECK: usize = FUNCTION_COUNT + 8;
+const NATIVE_TARGET: usize = FUNCTION_COUNT + 9;
+const NATIVE_RETARGET: usize = FUNCTION_COUNT + 10;
+const NATIVE_DELETE: usize = FUNCTION_COUNT + 11;
+const NATIVE_LOAD: usize = FUNCTION_COUNT + 12;
+
+// Constants
+const R_REFERENCE_TYPE: usize = 0;
+const S_REFERENCE_TYPE: usize = 1;
+const R_TYPE: usize = 2;
+
+fn r_type() -> StaticType {
+    StaticType::Composite("R".to_string())
+}
+
+fn r_reference_type() -> StaticType {
+    StaticType::reference(Authorization::Unauthorized, r_type())
+}
+
+fn s_reference_type() -> StaticType {
+    StaticType::reference(
+        Authorization::Unauthorized,
+        StaticType::Composite("S".to_string()),
+    )
+}
+
+fn test_program() -> Program {
+    let account = || parameter("account", StaticType::Account);
+    let storage_path = || parameter("path", StaticType::StoragePath);
+    let public_path = || parameter("path", StaticType::PublicPath);
+    let capability = || parameter("capability", StaticType::capability(r_reference_type()));
+    let controller_reference = || {
+        StaticType::reference(
+            Authorization::Unauthorized,
+            StaticType::StorageCapabilityController,
+        )
+    };
+    let controller = || parameter("controller", controller_reference());
+
+    Program {
+        functions: vec![
+            forward(
+                "save",
+                vec![
+                    account(),
+                    parameter("value", StaticType::AnyResource),
+                    storage_path(),
+                ],
+                StaticType::Void,
+                NATIVE_SAVE,
+                None,
+            ),
+            forward(
+                "saveStruct",
+                vec![
+                    account(),
+                    parameter("value", StaticType::AnyStruct),
+                    storage_path(),
+                ],
+                StaticType::Void,
+                NATIVE_SAVE,
+                None,
+            ),
+            forward(
+                "issue",
+                vec![account(), storage_path()],
+                StaticType::capability(r_reference_type()),
+                NATIVE_ISSUE,
+                Some(R_REFERENCE_TYPE),
+            ),
+            forward(
+                "getController",
+                vec![account(), parameter("id", StaticType::UInt64)],
+                StaticType::optional(controller_reference()),
+                NATIVE_GET_CONTROLLER,
+                None,
+            ),
+            forward(
+                "publish",
+                vec![account(), capability(), public_path()],
+                StaticType::Void,
+                NATIVE_PUBLISH,
+                None,
+            ),
+            forward(
+                "unpublish",
+                vec![account(), public_path()],
+                StaticType::optional(StaticType::capability(r_reference_type())),
+                NATIVE_UNPUBLISH,
+                None,
+            ),
+            forward(
+                "getR",
+                vec![account(), public_path()],
+                StaticType::capability(r_reference_type()),
+                NATIVE_GET,
+                Some(R_REFERENCE_TYPE),
+            ),
+            forward(
+                "getS",
+                vec![account(), public_path()],
+                StaticType::capability(s_reference_type()),
+                NATIVE_GET,
+                Some(S_REFERENCE_TYPE),
+            ),
+            forward(
+                "borrow",
+                vec![account(), public_path()],
+                StaticType::optional(r_reference_type()),
+                NATIVE_BORROW,
+                Some(R_REFERENCE_TYPE),
+            ),
+            forward(
+                "capabilityBorrow",
+                vec![capability()],
+                StaticType::optional(r_reference_type()),
+                NATIVE_CAPABILITY_BORROW,
+                None,
+            ),
+            forward(
+                "capabilityBorrowAsS",
+                vec![capability()],
+                StaticType::optional(s_reference_type()),
+                NATIVE_CAPABILITY_BORROW,
+                Some(S_REFERENCE_TYPE),
+            ),
+            forward(
+                "capabilityCheck",
+                vec![capability()],
+                StaticType::Bool,
+                NATIVE_CAPABILITY_CHECK,
+                None,
+            ),
+            forward(
+                "target",
+                vec![controller()],
+                StaticType::StoragePath,
+                NATIVE_TARGET,
+                None,
+            ),
+            forward(
+                "retarget",
+                vec![controller(), storage_path()],
+                StaticType::Void,
+                NATIVE_RETARGET,
+                None,
+            ),
+            forward(
+                "delete",
+                vec![controller()],
+                StaticType::Void,
+                NATIVE_DELETE,
+                None,
+            ),
+            forward(
+                "load",
+                vec![account(), storage_path()],
+                StaticType::optional(r_type()),
+                NATIVE_LOAD,
+                Some(R_TYPE),
+            ),
+        ],
+        constants: vec![
+            Constant::type_literal(&r_reference_type()),
+            Constant::type_literal(&s_reference_type()),
+            Constant::type_literal(&r_type()),
+        ],
+        composite_types: vec![
+            CompositeType {
+                identifier: "R".to_string(),
+                kind: CompositeKind::Resource,
+                conformances: vec![],
+                methods: vec![],
+                enum_info: None,
+            },
+            CompositeType {
+                identifier: "S".to_string(),
+                kind: CompositeKind::Structure,
+                conformances: vec![],
+                methods: vec![],
+                enum_info: None,
+            },
+        ],
+        native_functions: [
+            "Account.Storage.save",
+            "Account.StorageCapabilities.issue",
+            "Account.StorageCapabilities.getController",
+            "Account.Capabilities.publish",
+            "Account.Capabilities.unpublish",
+            "Account.Capabilities.get",
+            "Account.Capabilities.borrow",
+            "Capability.borrow",
+            "Capability.check",
+            "StorageCapabilityController.target",
+            "StorageCapabilityController.retarget",
+            "StorageCapabilityController.delete",
+            "Account.Storage.load",
+        ]
+        .iter()
+        .map(|name| name.to_string())
+        .collect(),
+        ..Default::default()
+    }
+}
+
+fn new_vm(program: &Program) -> VM<'_> {
+    let mut vm = VM::new(program);
+    stdlib::register_account_functions(&mut vm);
+    stdlib::register_capability_functions(&mut vm);
+    vm
+}
+
+fn public_path<'a>(identifier: &str) -> Value<'a> {
+    Value::Path(PathValue::new(PathDomain::Public, identifier).unwrap())
+}
+
+fn resource<'a>(id: isize) -> Value<'a> {
+    Value::Composite(CompositeValue::new(
+        "R",
+        CompositeKind::Resource,
+        vec![("id", Value::Int(IntValue { value: id }))],
+    ))
+}
+
+fn capability<'a>(id: u64, borrow_type: StaticType) -> Value<'a> {
+    Value::Capability(CapabilityValue {
+        address: ADDRESS,
+        id,
+        borrow_type,
+    })
+}
+
+fn uint64<'a>(value: u64) -> Value<'a> {
+    Value::FixedSizeInt(FixedSizeIntValue::UInt64(value))
+}
+
+/// Returns the referenced value of the optional reference, if any.
+fn referenced<'a>(vm: &VM<'a>, result: Result<Value<'a>, VMError>) -> Option<Value<'a>> {
+    match result.unwrap() {
+        Value::Some(value) => match *value {
+            reference @ Value::Reference(_) => Some(vm.dereference(&reference).unwrap()),
+            value => panic!("unexpected {:?}", value),
+        },
+        Value::Nil => None,
+        value => panic!("unexpected {:?}", value),
+    }
+}
+
+/// Saves a resource at `/storage/r` and issues a capability for it.
+fn issue_resource_capability<'a>(vm: &mut VM<'a>) -> Value<'a> {
+    vm.invoke("save", &[account(), resource(1), storage_path("r")])
+        .unwrap();
+    vm.invoke("issue", &[account(), storage_path("r")]).unwrap()
+}
+
+#[test]
+fn test_issue_and_borrow() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let first = issue_resource_capability(&mut vm);
+    assert_eq!(first, capability(1, r_reference_type()));
+    assert_eq!(
+        first.to_string(),
+        "Capability<&R>(address: 0x0000000000000001, id: 1)"
+    );
+
+    let second = vm.invoke("issue", &[account(), storage_path("r")]);
+    assert_eq!(second, Ok(capability(2, r_reference_type())));
+
+    let result = vm.invoke("capabilityBorrow", std::slice::from_ref(&first));
+    assert_eq!(referenced(&vm, result), Some(resource(1)));
+    let result = vm.invoke("capabilityCheck", std::slice::from_ref(&first));
+    assert_eq!(result, Ok(Value::Bool(BoolValue { value: true })));
+
+    // The capability cannot be borrowed as a type its controller does not allow.
+    let result = vm.invoke("capabilityBorrowAsS", &[first]);
+    assert_eq!(referenced(&vm, result), None);
+}
+
+#[test]
+fn test_borrowed_capability_reads_target() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let issued = issue_resource_capability(&mut vm);
+    let reference = match vm.invoke("capabilityBorrow", &[issued]).unwrap() {
+        Value::Some(reference) => *reference,
+        value => panic!("unexpected {:?}", value),
+    };
+
+    // The reference reads the target when used, so it fails once the value is moved out.
+    let result = vm.invoke("load", &[account(), storage_path("r")]);
+    assert_eq!(result, Ok(Value::some(resource(1))));
+    assert_eq!(
+        vm.dereference(&reference),
+        Err(VMError::DereferenceFailure {
+            address: ADDRESS,
+            path: path("r"),
+        })
+    );
+
+    vm.invoke("save", &[account(), resource(2), storage_path("r")])
+        .unwrap();
+    assert_eq!(vm.dereference(&reference), Ok(resource(2)));
+}
+
+#[test]
+fn test_publish_and_get() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let issued = issue_resource_capability(&mut vm);
+    let result = vm.invoke("publish", &[account(), issued.clone(), public_path("r")]);
+    assert_eq!(result, Ok(Value::Void));
+
+    let err = vm
+        .invoke("publish", &[account(), issued.clone(), public_path("r")])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "cannot publish capability: path /public/r in account 0x0000000000000001 \
+         already stores a value"
+    );
+
+    let result = vm.invoke("getR", &[account(), public_path("r")]);
+    assert_eq!(result, Ok(issued.clone()));
+    let result = vm.invoke("borrow", &[account(), public_path("r")]);
+    assert_eq!(referenced(&vm, result), Some(resource(1)));
+
+    // Getting the capability with an incompatible type, or from an empty path,
+    // results in an invalid capability.
+    let result = vm.invoke("getS", &[account(), public_path("r")]);
+    assert_eq!(result, Ok(capability(0, s_reference_type())));
+    let invalid = vm.invoke("getR", &[account(), public_path("s")]).unwrap();
+    assert_eq!(invalid, capability(0, r_reference_type()));
+    let result = vm.invoke("capabilityCheck", &[invalid]);
+    assert_eq!(result, Ok(Value::Bool(BoolValue { value: false })));
+
+    let result = vm.invoke("unpublish", &[account(), public_path("r")]);
+    assert_eq!(result, Ok(Value::some(issued)));
+
+    let result = vm.invoke("borrow", &[account(), public_path("r")]);
+    assert_eq!(referenced(&vm, result), None);
+    let result = vm.invoke("unpublish", &[account(), public_path("r")]);
+    assert_eq!(result, Ok(Value::Nil));
+}
+
+#[test]
+fn test_retarget_controller() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let issued = issue_resource_capability(&mut vm);
+    vm.invoke(
+        "saveStruct",
+        &[account(), Value::Composite(structure(0)), storage_path("s")],
+    )
+    .unwrap();
+    vm.invoke("save", &[account(), resource(2), storage_path("r2")])
+        .unwrap();
+
+    let controller = match vm.invoke("getController", &[account(), uint64(1)]).unwrap() {
+        Value::Some(controller) => *controller,
+        value => panic!("unexpected {:?}", value),
+    };
+    let result = vm.invoke("target", std::slice::from_ref(&controller));
+    assert_eq!(result, Ok(storage_path("r")));
+
+    // Retargeting to a value of an incompatible type makes borrowing fail.
+    vm.invoke("retarget", &[controller.clone(), storage_path("s")])
+        .unwrap();
+    let result = vm.invoke("target", std::slice::from_ref(&controller));
+    assert_eq!(result, Ok(storage_path("s")));
+    let result = vm.invoke("capabilityBorrow", std::slice::from_ref(&issued));
+    assert_eq!(referenced(&vm, result), None);
+
+    vm.invoke("retarget", &[controller, storage_path("r2")])
+        .unwrap();
+    let result = vm.invoke("capabilityBorrow", &[issued]);
+    assert_eq!(referenced(&vm, result), Some(resource(2)));
+}
+
+#[test]
+fn test_delete_controller() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let issued = issue_resource_capability(&mut vm);
+    vm.invoke("publish", &[account(), issued.clone(), public_path("r")])
+        .unwrap();
+
+    let controller = match vm.invoke("getController", &[account(), uint64(1)]).unwrap() {
+        Value::Some(controller) => *controller,
+        value => panic!("unexpected {:?}", value),
+    };
+    vm.invoke("delete", std::slice::from_ref(&controller))
+        .unwrap();
+
+    // The deleted controller can no longer be used.
+    let error = VMError::CapabilityControllerDeleted {
+        address: ADDRESS,
+        id: 1,
+    };
+    let deleted = Err(error.clone());
+    assert_eq!(
+        vm.invoke("delete", std::slice::from_ref(&controller)),
+        deleted
+    );
+    assert_eq!(
+        vm.invoke("target", std::slice::from_ref(&controller)),
+        deleted
+    );
+    assert_eq!(
+        vm.invoke("retarget", &[controller, storage_path("r")]),
+        deleted
+    );
+    assert_eq!(
+        error.to_string(),
+        "capability controller 1 in account 0x0000000000000001 was deleted"
+    );
+
+    let result = vm.invoke("capabilityBorrow", std::slice::from_ref(&issued));
+    assert_eq!(referenced(&vm, result), None);
+    let result = vm.invoke("capabilityCheck", &[issued]);
+    assert_eq!(result, Ok(Value::Bool(BoolValue { value: false })));
+    let result = vm.invoke("borrow", &[account(), public_path("r")]);
+    assert_eq!(referenced(&vm, result), None);
+    let result = vm.invoke("getController", &[account(), uint64(1)]);
+    assert_eq!(result, Ok(Value::Nil));
+}
@@ -0,0 +1,741 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::opcodes::{FailableCast, ForceCast, OpCode, ReturnValue, Upcast};
+use cadence_vm::runtime::registers::{self, RegisterType};
+use cadence_vm::runtime::types::{
+    Authorization, CompositeKind, CompositeType, InterfaceType, StaticType, TypeRegistry,
+};
+use cadence_vm::runtime::values::{
+    BoolValue, CompositeValue, IntValue, ReferenceValue, StringValue, Value,
+};
+use cadence_vm::runtime::vm::VM;
+
+fn interface_types() -> Vec<InterfaceType> {
+    vec![
+        InterfaceType {
+            identifier: "I".to_string(),
+            kind: CompositeKind::Structure,
+            conformances: vec![],
+            methods: vec![],
+        },
+        InterfaceType {
+            identifier: "J".to_string(),
+            kind: CompositeKind::Structure,
+            conformances: vec!["I".to_string()],
+            methods: vec![],
+        },
+        InterfaceType {
+            identifier: "RI".to_string(),
+            kind: CompositeKind::Resource,
+            conformances: vec![],
+            methods: vec![],
+        },
+    ]
+}
+
+fn composite_types() -> Vec<CompositeType> {
+    vec![
+        // S conforms to J, and through it, to I.
+        CompositeType {
+            identifier: "S".to_string(),
+            kind: CompositeKind::Structure,
+            conformances: vec!["J".to_string()],
+            methods: vec![],
+            enum_info: None,
+        },
+        CompositeType {
+            identifier: "T".to_string(),
+            kind: CompositeKind::Structure,
+            conformances: vec![],
+            methods: vec![],
+            enum_info: None,
+        },
+        CompositeType {
+            identifier: "R".to_string(),
+            kind: CompositeKind::Resource,
+            conformances: vec!["RI".to_string()],
+            methods: vec![],
+            enum_info: None,
+        },
+    ]
+}
+
+fn test_types() -> TypeRegistry {
+    let mut types = TypeRegistry::new();
+    for typ in interface_types() {
+        types.add_interface_type(typ);
+    }
+    for typ in composite_types() {
+        types.add_composite_type(typ);
+    }
+    types
+}
+
+fn composite(identifier: &str) -> StaticType {
+    StaticType::Composite(identifier.to_string())
+}
+
+fn reference(authorization: Authorization, typ: StaticType) -> StaticType {
+    StaticType::reference(authorization, typ)
+}
+
+#[test]
+fn test_subtyping_matrix() {
+    let types = test_types();
+
+    let any_struct = StaticType::AnyStruct;
+    let any_resource = StaticType::AnyResource;
+    let int = StaticType::Int;
+    let optional_int = StaticType::optional(StaticType::Int);
+
+    let cases: Vec<(StaticType, StaticType, bool)> = vec![
+        // Reflexivity, Never and the top types
+        (int.clone(), int.clone(), true),
+        (StaticType::Never, int.clone(), true),
+        (StaticType::Never, any_resource.clone(), true),
+        (int.clone(), StaticType::Never, false),
+        (int.clone(), any_struct.clone(), true),
+        (int.clone(), any_resource.clone(), false),
+        (StaticType::String, any_struct.clone(), true),
+        (composite("S"), any_struct.clone(), true),
+        (composite("S"), any_resource.clone(), false),
+        (composite("R"), any_resource.clone(), true),
+        (composite("R"), any_struct.clone(), false),
+        (any_struct.clone(), int.clone(), false),
+        (any_struct.clone(), any_resource.clone(), false),
+        // Numbers
+        (int.clone(), StaticType::SignedInteger, true),
+        (int.clone(), StaticType::Integer, true),
+        (int.clone(), StaticType::SignedNumber, true),
+        (int.clone(), StaticType::Number, true),
+        (StaticType::Integer, StaticType::Number, true),
+        (StaticType::Number, int.clone(), false),
+        (StaticType::Bool, StaticType::Number, false),
+        (StaticType::UInt8, StaticType::Integer, true),
+        (StaticType::UInt8, StaticType::SignedInteger, false),
+        (StaticType::Int128, StaticType::SignedNumber, true),
+        (StaticType::Fix64, StaticType::SignedNumber, true),
+        (StaticType::UFix64, StaticType::FixedPoint, true),
+        (StaticType::UFix64, StaticType::SignedFixedPoint, false),
+        (StaticType::Fix64, StaticType::Integer, false),
+        // Optionals
+        (int.clone(), optional_int.clone(), true),
+        (optional_int.clone(), int.clone(), false),
+        (
+            StaticType::optional(StaticType::Never),
+            optional_int.clone(),
+            true,
+        ),
+        (
+            optional_int.clone(),
+            StaticType::optional(any_struct.clone()),
+            true,
+        ),
+        (optional_int.clone(), any_struct.clone(), true),
+        (
+            StaticType::optional(composite("R")),
+            any_resource.clone(),
+            true,
+        ),
+        (
+            StaticType::optional(composite("R")),
+            any_struct.clone(),
+            false,
+        ),
+        (
+            StaticType::optional(optional_int.clone()),
+            optional_int.clone(),
+            false,
+        ),
+        (
+            optional_int.clone(),
+            StaticType::optional(optional_int.clone()),
+            true,
+        ),
+        // Interfaces and intersection types
+        (composite("S"), StaticType::intersection(&["J"]), true),
+        (composite("S"), StaticType::intersection(&["I"]), true),
+        (composite("S"), StaticType::intersection(&["I", "J"]), true),
+        (composite("T"), StaticType::intersection(&["I"]), false),
+        (composite("R"), StaticType::intersection(&["RI"]), true),
+        (
+            StaticType::intersection(&["I", "J"]),
+            StaticType::intersection(&["I"]),
+            true,
+        ),
+        (
+            StaticType::intersection(&["I"]),
+            StaticType::intersection(&["I", "J"]),
+            false,
+        ),
+        // J inherits I.
+        (
+            StaticType::intersection(&["J"]),
+            StaticType::intersection(&["I"]),
+            true,
+        ),
+        (
+            StaticType::intersection(&["I"]),
+            StaticType::intersection(&["J"]),
+            false,
+        ),
+        (
+            StaticType::intersection(&["J"]),
+            StaticType::intersection(&["I", "J"]),
+            true,
+        ),
+        (StaticType::intersection(&["I"]), composite("S"), false),
+        (StaticType::intersection(&["I"]), any_struct.clone(), true),
+        (
+            StaticType::intersection(&["RI"]),
+            any_resource.clone(),
+            true,
+        ),
+        (composite("S"), composite("T"), false),
+        // References
+        (
+            reference(Authorization::Unauthorized, composite("S")),
+            any_struct.clone(),
+            true,
+        ),
+        (
+            reference(Authorization::Unauthorized, composite("R")),
+            any_struct.clone(),
+            true,
+        ),
+        (
+            reference(Authorization::Unauthorized, composite("S")),
+            reference(
+                Authorization::Unauthorized,
+                StaticType::intersection(&["I"]),
+            ),
+            true,
+        ),
+        (
+            reference(Authorization::Unauthorized, composite("T")),
+            reference(
+                Authorization::Unauthorized,
+                StaticType::intersection(&["I"]),
+            ),
+            false,
+        ),
+        (
+            reference(Authorization::Unauthorized, composite("S")),
+            composite("S"),
+            false,
+        ),
+        // Entitlements
+        (
+            reference(Authorization::conjunction(&["E"]), composite("S")),
+            reference(Authorization::Unauthorized, composite("S")),
+            true,
+        ),
+        (
+            reference(Authorization::Unauthorized, composite("S")),
+            reference(Authorization::conjunction(&["E"]), composite("S")),
+            false,
+        ),
+        (
+            reference(Authorization::conjunction(&["E", "F"]), composite("S")),
+            reference(Authorization::conjunction(&["E"]), composite("S")),
+            true,
+        ),
+        (
+            reference(Authorization::conjunction(&["E"]), composite("S")),
+            reference(Authorization::conjunction(&["E", "F"]), composite("S")),
+            false,
+        ),
+        (
+            reference(Authorization::conjunction(&["E", "F"]), composite("S")),
+            reference(Authorization::disjunction(&["E", "G"]), composite("S")),
+            true,
+        ),
+        (
+            reference(Authorization::conjunction(&["F"]), composite("S")),
+            reference(Authorization::disjunction(&["E", "G"]), composite("S")),
+            false,
+        ),
+        (
+            reference(Authorization::disjunction(&["E", "F"]), composite("S")),
+            reference(Authorization::disjunction(&["E", "F", "G"]), composite("S")),
+            true,
+        ),
+        (
+            reference(Authorization::disjunction(&["E", "F"]), composite("S")),
+            reference(Authorization::disjunction(&["E"]), composite("S")),
+            false,
+        ),
+        (
+            reference(Authorization::disjunction(&["E", "F"]), composite("S")),
+            reference(Authorization::conjunction(&["E"]), composite("S")),
+            false,
+        ),
+        (
+            reference(Authorization::disjunction(&["E"]), composite("S")),
+            reference(Authorization::conjunction(&["E"]), composite("S")),
+            true,
+        ),
+        (
+            reference(Authorization::conjunction(&["E"]), composite("S")),
+            reference(
+                Authorization::conjunction(&["E"]),
+                StaticType::intersection(&["I"]),
+            ),
+            true,
+        ),
+    ];
+
+    for (sub, sup, expected) in cases {
+        assert_eq!(
+            types.is_subtype(&sub, &sup),
+            expected,
+            "expected `{}` <: `{}` to be {}",
+            sub,
+            sup,
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_value_is_instance() {
+    let types = test_types();
+
+    let s = Value::Composite(CompositeValue::new("S", CompositeKind::Structure, vec![]));
+    let r = Value::Composite(CompositeValue::new("R", CompositeKind::Resource, vec![]));
+    let int = Value::Int(IntValue { value: 1 });
+
+    let cases: Vec<(Value, StaticType, bool)> = vec![
+        (int.clone(), StaticType::Int, true),
+        (int.clone(), StaticType::AnyStruct, true),
+        (int.clone(), StaticType::Bool, false),
+        (int.clone(), StaticType::optional(StaticType::Int), true),
+        (Value::some(int.clone()), StaticType::Int, false),
+        (
+            Value::some(int.clone()),
+            StaticType::optional(StaticType::Int),
+            true,
+        ),
+        (Value::Nil, StaticType::optional(StaticType::String), true),
+        (Value::Nil, StaticType::String, false),
+        (
+            Value::String(StringValue::new("hello")),
+            StaticType::String,
+            true,
+        ),
+        (
+            Value::Bool(BoolValue { value: true }),
+            StaticType::Bool,
+            true,
+        ),
+        (s.clone(), StaticType::intersection(&["I"]), true),
+        (s.clone(), StaticType::AnyResource, false),
+        (r.clone(), StaticType::AnyResource, true),
+        (r.clone(), StaticType::intersection(&["I"]), false),
+        (
+            Value::Reference(ReferenceValue::new(
+                Authorization::conjunction(&["E"]),
+                s.clone(),
+            )),
+            StaticType::reference(
+                Authorization::conjunction(&["E"]),
+                StaticType::intersection(&["J"]),
+            ),
+            true,
+        ),
+        (
+            Value::Reference(ReferenceValue::new(Authorization::Unauthorized, s.clone())),
+            StaticType::reference(
+                Authorization::conjunction(&["E"]),
+                StaticType::intersection(&["J"]),
+            ),
+            false,
+        ),
+    ];
+
+    for (value, typ, expected) in cases {
+        assert_eq!(
+            value.is_instance(&typ, &types),
+            expected,
+            "expected `{}` to be an instance of `{}`: {}",
+            value.static_type(),
+            typ,
+            expected
+        );
+    }
+}
+
+fn cast_function(name: &str, target_type: StaticType) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters: vec![Parameter::unlabeled("n", StaticType::Int)],
+        local_count: registers::RegisterCounts {
+            ints: 2,
+            bools: 1,
+            funcs: 0,
+            values: 1,
+        },
+        return_type: StaticType::Int,
+        code: vec![
+            // let x: AnyStruct = n
+            Box::new(Upcast {
+                typ: RegisterType::Int,
+                value: 0,
+                result: 0,
+            }),
+            // return x as! T
+            Box::new(ForceCast {
+                value: 0,
+                target_type,
+                result: 1,
+            }),
+            Box::new(ReturnValue { index: 1 }),
+        ],
+        ..Default::default()
+    }
+}
+
+fn cast_program(target_type: StaticType) -> Program {
+    Program {
+        functions: vec![cast_function("cast", target_type)],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_force_cast() {
+    let program = cast_program(StaticType::Int);
+    let mut vm = VM::new(&program);
+
+    let result = vm
+        .invoke("cast", &[Value::Int(IntValue { value: 42 })])
+        .unwrap();
+
+    assert_eq!(result, Value::Int(IntValue { value: 42 }));
+}
+
+#[test]
+fn test_force_cast_failure() {
+    let program = Program {
+        functions: vec![
+            cast_function("castToBool", StaticType::Bool),
+            cast_function("castToInt", StaticType::Int),
+        ],
+        ..Default::default()
+    };
+    let mut vm = VM::new(&program);
+
+    let err = vm
+        .invoke("castToBool", &[Value::Int(IntValue { value: 42 })])
+        .unwrap_err();
+
+    assert_eq!(
+        err,
+        VMError::ForceCastTypeMismatch {
+            expected_type: StaticType::Bool,
+            actual_type: StaticType::Int,
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "failed to force-cast value: expected type `Bool`, got `Int`"
+    );
+
+    // The VM can be reused after a failed invocation
+    let result = vm
+        .invoke("castToInt", &[Value::Int(IntValue { value: 1 })])
+        .unwrap();
+    assert_eq!(result, Value::Int(IntValue { value: 1 }));
+}
+
+/// Returns a function which casts its parameter `x` with the given cast,
+/// which reads value register 0 and writes register 1, and returns the result.
+fn value_cast_function(
+    name: &str,
+    parameter_type: StaticType,
+    cast: Box<dyn OpCode>,
+    return_type: StaticType,
+) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters: vec![Parameter::unlabeled("x", parameter_type)],
+        local_count: registers::RegisterCounts {
+            ints: 2,
+            bools: 2,
+            funcs: 0,
+            values: 2,
+        },
+        return_type,
+        code: vec![cast, Box::new(ReturnValue { index: 1 })],
+        ..Default::default()
+    }
+}
+
+/// Returns a function for `x as? T`.
+fn failable_cast_function(
+    name: &str,
+    parameter_type: StaticType,
+    target_type: StaticType,
+) -> Function {
+    value_cast_function(
+        name,
+        parameter_type,
+        Box::new(FailableCast {
+            value: 0,
+            target_type: target_type.clone(),
+            result: 1,
+        }),
+        StaticType::optional(target_type),
+    )
+}
+
+/// Returns a function for `x as! T`.
+fn force_cast_function(
+    name: &str,
+    parameter_type: StaticType,
+    target_type: StaticType,
+) -> Function {
+    value_cast_function(
+        name,
+        parameter_type,
+        Box::new(ForceCast {
+            value: 0,
+            target_type: target_type.clone(),
+            result: 1,
+        }),
+        target_type,
+    )
+}
+
+fn typed_cast_program(functions: Vec<Function>) -> Program {
+    Program {
+        functions,
+        interface_types: interface_types(),
+        composite_types: composite_types(),
+        ..Default::default()
+    }
+}
+
+fn reference_to<'a>(value: &Value<'a>) -> Value<'a> {
+    Value::Reference(ReferenceValue::new(
+        Authorization::Unauthorized,
+        value.clone(),
+    ))
+}
+
+#[test]
+fn test_failable_cast() {
+    let reference_to_i = reference(
+        Authorization::Unauthorized,
+        StaticType::intersection(&["I"]),
+    );
+    let program = typed_cast_program(vec![
+        failable_cast_function("toInt", StaticType::AnyStruct, StaticType::Int),
+        failable_cast_function(
+            "toI",
+            StaticType::AnyStruct,
+            StaticType::intersection(&["I"]),
+        ),
+        failable_cast_function(
+            "toRI",
+            StaticType::AnyResource,
+            StaticType::intersection(&["RI"]),
+        ),
+        failable_cast_function("toS", StaticType::AnyResource, composite("S")),
+        failable_cast_function("toReferenceToI", StaticType::AnyStruct, reference_to_i),
+    ]);
+    let mut vm = VM::new(&program);
+
+    let int = Value::Int(IntValue { value: 42 });
+    let s = Value::Composite(CompositeValue::new("S", CompositeKind::Structure, vec![]));
+    let t = Value::Composite(CompositeValue::new("T", CompositeKind::Structure, vec![]));
+    let r = Value::Composite(CompositeValue::new("R", CompositeKind::Resource, vec![]));
+
+    let cases: Vec<(&str, Value, Value)> = vec![
+        ("toInt", int.clone(), Value::some(int)),
+        ("toInt", Value::String(StringValue::new("42")), Value::Nil),
+        ("toI", s.clone(), Value::some(s.clone())),
+        ("toI", t.clone(), Value::Nil),
+        ("toRI", r.clone(), Value::some(r.clone())),
+        ("toS", r, Value::Nil),
+        (
+            "toReferenceToI",
+            reference_to(&s),
+            Value::some(reference_to(&s)),
+        ),
+        ("toReferenceToI", reference_to(&t), Value::Nil),
+    ];
+
+    for (function, argument, expected) in cases {
+        let result = vm.invoke(function, std::slice::from_ref(&argument));
+        assert_eq!(result, Ok(expected), "{}({})", function, argument);
+    }
+}
+
+/// Returns a function which casts its parameter `x` with the given cast,
+/// and returns the register of `x` afterwards.
+fn source_after_cast_function(
+    name: &str,
+    parameter_type: StaticType,
+    cast: Box<dyn OpCode>,
+) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters: vec![Parameter::unlabeled("x", parameter_type.clone())],
+        local_count: registers::RegisterCounts {
+            ints: 0,
+            bools: 0,
+            funcs: 0,
+            values: 2,
+        },
+        return_type: parameter_type,
+        code: vec![cast, Box::new(ReturnValue { index: 0 })],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_casts_move_resources() {
+    let cast_functions = |parameter_type: StaticType| {
+        let name = |cast: &str| format!("{}{}", cast, parameter_type);
+        vec![
+            source_after_cast_function(
+                &name("force"),
+                parameter_type.clone(),
+                Box::new(ForceCast {
+                    value: 0,
+                    target_type: parameter_type.clone(),
+                    result: 1,
+                }),
+            ),
+            source_after_cast_function(
+                &name("failable"),
+                parameter_type.clone(),
+                Box::new(FailableCast {
+                    value: 0,
+                    target_type: parameter_type.clone(),
+                    result: 1,
+                }),
+            ),
+            source_after_cast_function(
+                &name("failableToS"),
+                parameter_type.clone(),
+                Box::new(FailableCast {
+                    value: 0,
+                    target_type: composite("S"),
+                    result: 1,
+                }),
+            ),
+            source_after_cast_function(
+                &name("upcast"),
+                parameter_type.clone(),
+                Box::new(Upcast {
+                    typ: RegisterType::Value,
+                    value: 0,
+                    result: 1,
+                }),
+            ),
+        ]
+    };
+    let program = typed_cast_program(
+        cast_functions(StaticType::AnyResource)
+            .into_iter()
+            .chain(cast_functions(StaticType::AnyStruct))
+            .collect(),
+    );
+    let mut vm = VM::new(&program);
+
+    let r = Value::Composite(CompositeValue::new("R", CompositeKind::Resource, vec![]));
+    let t = Value::Composite(CompositeValue::new("T", CompositeKind::Structure, vec![]));
+
+    // Resources are moved out of the source register by successful casts,
+    // structs are copied.
+    let cases: Vec<(&str, Value, Value)> = vec![
+        ("forceAnyResource", r.clone(), Value::Void),
+        ("failableAnyResource", r.clone(), Value::Void),
+        ("failableToSAnyResource", r.clone(), r.clone()),
+        ("upcastAnyResource", r.clone(), Value::Void),
+        ("forceAnyStruct", t.clone(), t.clone()),
+        ("failableAnyStruct", t.clone(), t.clone()),
+        ("failableToSAnyStruct", t.clone(), t.clone()),
+        ("upcastAnyStruct", t.clone(), t),
+    ];
+
+    for (function, argument, expected) in cases {
+        let result = vm.invoke(function, std::slice::from_ref(&argument));
+        assert_eq!(result, Ok(expected), "{}({})", function, argument);
+    }
+}
+
+#[test]
+fn test_cast_to_optional() {
+    let optional_int = StaticType::optional(StaticType::Int);
+    let program = typed_cast_program(vec![
+        force_cast_function(
+            "forceToOptional",
+            StaticType::AnyStruct,
+            optional_int.clone(),
+        ),
+        failable_cast_function("failableToOptional", StaticType::AnyStruct, optional_int),
+    ]);
+    let mut vm = VM::new(&program);
+
+    let int = Value::Int(IntValue { value: 1 });
+
+    // `1 as! Int?` is `Some(1)`, and optionals are not wrapped again.
+    let result = vm.invoke("forceToOptional", std::slice::from_ref(&int));
+    assert_eq!(result, Ok(Value::some(int.clone())));
+    let result = vm.invoke("forceToOptional", &[Value::some(int.clone())]);
+    assert_eq!(result, Ok(Value::some(int.clone())));
+    let result = vm.invoke("forceToOptional", &[Value::Nil]);
+    assert_eq!(result, Ok(Value::Nil));
+
+    // `1 as? Int?` is `Some(Some(1))`.
+    let result = vm.invoke("failableToOptional", std::slice::from_ref(&int));
+    assert_eq!(result, Ok(Value::some(Value::some(int.clone()))));
+    let result = vm.invoke("failableToOptional", &[Value::Nil]);
+    assert_eq!(result, Ok(Value::some(Value::Nil)));
+    let result = vm.invoke(
+        "failableToOptional",
+        &[Value::Bool(BoolValue { value: true })],
+    );
+    assert_eq!(result, Ok(Value::Nil));
+}
+
+#[test]
+fn test_static_type_display() {
+    assert_eq!(
+        StaticType::reference(
+            Authorization::conjunction(&["E", "F"]),
+            StaticType::intersection(&["I", "J"]),
+        )
+        .to_string(),
+        "auth(E, F) &{I, J}"
+    );
+    assert_eq!(
+        StaticType::optional(StaticType::reference(
+            Authorization::disjunction(&["E", "F"]),
+            StaticType::Composite("R".to_string()),
+        ))
+        .to_string(),
+        "auth(E | F) &R?"
+    );
+}
@@ -0,0 +1,403 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::opcodes::{
+    Argument, Call, CaptureLoad, GlobalFuncLoad, IntAdd, IntConstantLoad, IntMove, NewClosure,
+    NewUpvalue, OpCode, ReturnValue, UpvalueLoad, UpvalueStore,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::types::StaticType;
+use cadence_vm::runtime::values::{IntValue, Value};
+use cadence_vm::runtime::vm::VM;
+
+const MAKE_COUNTER: usize = 0;
+const COUNTER: usize = 1;
+const APPLY: usize = 3;
+const ADD_K: usize = 4;
+
+fn function(
+    name: &str,
+    parameters: Vec<Parameter>,
+    ints: usize,
+    funcs: usize,
+    values: usize,
+    return_type: StaticType,
+    code: Vec<Box<dyn OpCode>>,
+) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters,
+        local_count: RegisterCounts {
+            ints,
+            bools: 0,
+            funcs,
+            values,
+        },
+        return_type,
+        code,
+        ..Default::default()
+    }
+}
+
+fn test_program() -> Program {
+    Program {
+        functions: vec![
+            // fun makeCounter(): fun(): Int {
+            //     var count = 0
+            //     return fun(): Int {
+            //         count = count + 1
+            //         return count
+            //     }
+            // }
+            function(
+                "makeCounter",
+                vec![],
+                1,
+                1,
+                1,
+                StaticType::Function,
+                vec![
+                    Box::new(IntConstantLoad {
+                        index: 0,
+                        target: 0,
+                    }),
+                    Box::new(NewUpvalue {
+                        typ: RegisterType::Int,
+                        value: 0,
+                        result: 0,
+                    }),
+                    Box::new(NewClosure {
+                        function_index: COUNTER,
+                        captures: vec![Argument {
+                            typ: RegisterType::Value,
+                            index: 0,
+                        }],
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // The counter closure
+            function(
+                "counter",
+                vec![],
+                2,
+                0,
+                1,
+                StaticType::Int,
+                vec![
+                    Box::new(CaptureLoad {
+                        index: 0,
+                        typ: RegisterType::Value,
+                        result: 0,
+                    }),
+                    Box::new(UpvalueLoad {
+                        upvalue: 0,
+                        typ: RegisterType::Int,
+                        result: 0,
+                    }),
+                    Box::new(IntConstantLoad {
+                        index: 1,
+                        target: 1,
+                    }),
+                    Box::new(IntAdd {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 0,
+                    }),
+                    Box::new(UpvalueStore {
+                        upvalue: 0,
+                        typ: RegisterType::Int,
+                        value: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // let c1 = makeCounter()
+            // c1()
+            // c1()
+            // let c2 = makeCounter()
+            // return c1() + c2()
+            function(
+                "testCounters",
+                vec![],
+                4,
+                3,
+                0,
+                StaticType::Int,
+                vec![
+                    Box::new(GlobalFuncLoad {
+                        index: MAKE_COUNTER,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![],
+                        result: 1,
+                    }),
+                    Box::new(Call {
+                        func_index: 1,
+                        arguments: vec![],
+                        result: 1,
+                    }),
+                    Box::new(Call {
+                        func_index: 1,
+                        arguments: vec![],
+                        result: 1,
+                    }),
+                    Box::new(Call {
+                        func_index: 1,
+                        arguments: vec![],
+                        result: 1,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![],
+                        result: 2,
+                    }),
+                    Box::new(Call {
+                        func_index: 2,
+                        arguments: vec![],
+                        result: 2,
+                    }),
+                    Box::new(IntAdd {
+                        left_operand: 1,
+                        right_operand: 2,
+                        result: 3,
+                    }),
+                    Box::new(ReturnValue { index: 3 }),
+                ],
+            ),
+            // fun apply(_ f: fun(Int): Int, _ x: Int): Int {
+            //     return f(x)
+            // }
+            function(
+                "apply",
+                vec![
+                    Parameter::unlabeled("f", StaticType::Function),
+                    Parameter::unlabeled("x", StaticType::Int),
+                ],
+                2,
+                1,
+                0,
+                StaticType::Int,
+                vec![
+                    Box::new(IntMove { from: 0, to: 1 }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Int],
+                        result: 1,
+                    }),
+                    Box::new(ReturnValue { index: 1 }),
+                ],
+            ),
+            // The closure `fun (x: Int): Int { return x + k }`
+            function(
+                "addK",
+                vec![Parameter::unlabeled("x", StaticType::Int)],
+                3,
+                0,
+                0,
+                StaticType::Int,
+                vec![
+                    Box::new(CaptureLoad {
+                        index: 0,
+                        typ: RegisterType::Int,
+                        result: 1,
+                    }),
+                    Box::new(IntAdd {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 2,
+                    }),
+                    Box::new(ReturnValue { index: 2 }),
+                ],
+            ),
+            // fun test(n: Int): Int {
+            //     var k = 10
+            //     let addK = fun (x: Int): Int { return x + k }
+            //     k = 20
+            //     return apply(addK, n)
+            // }
+            function(
+                "test",
+                vec![Parameter::unlabeled("n", StaticType::Int)],
+                3,
+                2,
+                0,
+                StaticType::Int,
+                vec![
+                    Box::new(IntConstantLoad {
+                        index: 2,
+                        target: 1,
+                    }),
+                    Box::new(NewClosure {
+                        function_index: ADD_K,
+                        captures: vec![Argument {
+                            typ: RegisterType::Int,
+                            index: 1,
+                        }],
+                        result: 1,
+                    }),
+                    Box::new(IntConstantLoad {
+                        index: 3,
+                        target: 1,
+                    }),
+                    Box::new(GlobalFuncLoad {
+                        index: APPLY,
+                        result: 0,
+                    }),
+                    Box::new(IntMove { from: 0, to: 2 }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Func, RegisterType::Int],
+                        result: 2,
+                    }),
+                    Box::new(ReturnValue { index: 2 }),
+                ],
+            ),
+            // var count = 0
+            // let inc = fun(): Int { ... }
+            // inc()
+            // inc()
+            // return count
+            function(
+                "testUpvalue",
+                vec![],
+                3,
+                1,
+                1,
+                StaticType::Int,
+                vec![
+                    Box::new(IntConstantLoad {
+                        index: 0,
+                        target: 0,
+                    }),
+                    Box::new(NewUpvalue {
+                        typ: RegisterType::Int,
+                        value: 0,
+                        result: 0,
+                    }),
+                    Box::new(NewClosure {
+                        function_index: COUNTER,
+                        captures: vec![Argument {
+                            typ: RegisterType::Value,
+                            index: 0,
+                        }],
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![],
+                        result: 1,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![],
+                        result: 1,
+                    }),
+                    Box::new(UpvalueLoad {
+                        upvalue: 0,
+                        typ: RegisterType::Int,
+                        result: 2,
+                    }),
+                    Box::new(ReturnValue { index: 2 }),
+                ],
+            ),
+        ],
+        constants: vec![
+            Constant::int(0),
+            Constant::int(1),
+            Constant::int(10),
+            Constant::int(20),
+        ],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_closure_returned_from_function() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm.invoke("testCounters", &[]).unwrap();
+
+    // Each counter has its own captured variable
+    assert_eq!(result, Value::Int(IntValue { value: 3 + 1 }));
+}
+
+#[test]
+fn test_closure_passed_as_argument() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm
+        .invoke("test", &[Value::Int(IntValue { value: 5 })])
+        .unwrap();
+
+    // `k` is captured by value, so the later assignment is not observed
+    assert_eq!(result, Value::Int(IntValue { value: 15 }));
+}
+
+#[test]
+fn test_upvalue_shared_with_enclosing_function() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm.invoke("testUpvalue", &[]).unwrap();
+
+    assert_eq!(result, Value::Int(IntValue { value: 2 }));
+}
+
+#[test]
+fn test_upvalue_load_of_non_upvalue() {
+    // Loads the parameter `x` as if it were an upvalue.
+    let program = Program {
+        functions: vec![function(
+            "load",
+            vec![Parameter::unlabeled("x", StaticType::AnyStruct)],
+            1,
+            0,
+            1,
+            StaticType::Int,
+            vec![
+                Box::new(UpvalueLoad {
+                    upvalue: 0,
+                    typ: RegisterType::Int,
+                    result: 0,
+                }),
+                Box::new(ReturnValue { index: 0 }),
+            ],
+        )],
+        ..Default::default()
+    };
+    let mut vm = VM::new(&program);
+
+    let result = vm.invoke("load", &[Value::Int(IntValue { value: 1 })]);
+
+    assert_eq!(
+        result,
+        Err(VMError::OperandTypeMismatch {
+            instruction: "UpvalueLoad",
+            actual_type: StaticType::Int,
+        })
+    );
+}
@@ -0,0 +1,165 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+mod common;
+
+use cadence_vm::runtime::commitment::{Hash, StateCommitment};
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::storage::{InMemoryStorage, JournaledStorage, Storage, StorageKey};
+use cadence_vm::runtime::values::{AccountValue, AddressValue, StringValue, Value};
+use common::{int, key, ADDRESS, OTHER_ADDRESS};
+
+fn registers<'a>(count: usize) -> Vec<(AddressValue, StorageKey, Value<'a>)> {
+    (0..count)
+        .map(|i| {
+            let address = if i % 3 == 0 { OTHER_ADDRESS } else { ADDRESS };
+            (address, key(&format!("r{}", i)), int(i as isize))
+        })
+        .collect()
+}
+
+#[test]
+fn test_empty_commitment() {
+    let mut commitment = StateCommitment::new();
+    assert_eq!(commitment.root(), Hash::EMPTY);
+
+    commitment
+        .update(ADDRESS, &key("a"), Some(&int(1)))
+        .unwrap();
+    assert_ne!(commitment.root(), Hash::EMPTY);
+
+    commitment.update(ADDRESS, &key("a"), None).unwrap();
+    assert_eq!(commitment.root(), Hash::EMPTY);
+    // Removing a register that is not stored has no effect.
+    commitment.update(ADDRESS, &key("b"), None).unwrap();
+    assert_eq!(commitment.root(), Hash::EMPTY);
+}
+
+#[test]
+fn test_root_only_depends_on_registers() {
+    let registers = registers(1000);
+
+    let mut storage = InMemoryStorage::new();
+    for (address, key, value) in &registers {
+        storage.write(*address, key, Some(value.clone()));
+    }
+    let expected = StateCommitment::from_entries(storage.entries())
+        .unwrap()
+        .root();
+
+    // Written in reverse order, with overwritten and removed registers in between.
+    let mut commitment = StateCommitment::new();
+    for (i, (address, key, value)) in registers.iter().enumerate().rev() {
+        commitment.update(*address, key, Some(&int(-1))).unwrap();
+        commitment
+            .update(
+                ADDRESS,
+                &StorageKey::CapabilityController(i as u64),
+                Some(value),
+            )
+            .unwrap();
+        commitment.update(*address, key, Some(value)).unwrap();
+    }
+    assert_ne!(commitment.root(), expected);
+    for i in 0..registers.len() {
+        let key = StorageKey::CapabilityController(i as u64);
+        commitment.update(ADDRESS, &key, None).unwrap();
+    }
+    assert_eq!(commitment.root(), expected);
+
+    // Any change of a value changes the root.
+    let (address, key, _) = &registers[500];
+    commitment.update(*address, key, Some(&int(0))).unwrap();
+    assert_ne!(commitment.root(), expected);
+    commitment.update(*address, key, Some(&int(500))).unwrap();
+    assert_eq!(commitment.root(), expected);
+}
+
+#[test]
+fn test_inclusion_proofs() {
+    let registers = registers(500);
+    let mut commitment = StateCommitment::new();
+    for (address, key, value) in &registers {
+        commitment.update(*address, key, Some(value)).unwrap();
+    }
+    let root = commitment.root();
+
+    for (address, key, value) in &registers {
+        let proof = commitment.prove(*address, key).unwrap();
+        assert!(proof.verify(&root, *address, key, value));
+        assert!(!proof.verify(&root, *address, key, &int(-1)));
+        assert!(!proof.verify(&root, *address, &self::key("other"), value));
+    }
+
+    assert_eq!(commitment.prove(ADDRESS, &key("missing")), None);
+    assert_eq!(commitment.prove(OTHER_ADDRESS, &key("r1")), None);
+
+    // Proofs are only valid for the root they were created for.
+    let (address, key, value) = &registers[0];
+    let proof = commitment.prove(*address, key).unwrap();
+    commitment
+        .update(ADDRESS, &self::key("new"), Some(&int(1)))
+        .unwrap();
+    assert!(!proof.verify(&commitment.root(), *address, key, value));
+    let proof = commitment.prove(*address, key).unwrap();
+    assert!(proof.verify(&commitment.root(), *address, key, value));
+}
+
+#[test]
+fn test_commitment_is_updated_on_commit() {
+    let mut storage = JournaledStorage::new(Box::new(InMemoryStorage::new()));
+    storage.set_commitment(StateCommitment::new());
+    let root = |storage: &JournaledStorage| storage.commitment().unwrap().root();
+
+    let value = Value::String(StringValue::new("v"));
+    let mut expected = StateCommitment::new();
+    expected.update(ADDRESS, &key("a"), Some(&value)).unwrap();
+
+    // Pending writes are not committed yet.
+    storage.checkpoint();
+    storage.write(ADDRESS, &key("a"), Some(value.clone()));
+    storage.checkpoint();
+    storage.write(ADDRESS, &key("b"), Some(int(2)));
+    storage.rollback();
+    assert_eq!(root(&storage), Hash::EMPTY);
+
+    storage.commit();
+    assert_eq!(root(&storage), expected.root());
+    assert_eq!(storage.flush(), Ok(()));
+
+    // Writes outside of a checkpoint are committed directly.
+    storage.write(ADDRESS, &key("a"), None);
+    assert_eq!(root(&storage), Hash::EMPTY);
+}
+
+#[test]
+fn test_unencodable_value() {
+    let mut storage = JournaledStorage::new(Box::new(InMemoryStorage::new()));
+    storage.set_commitment(StateCommitment::new());
+
+    let account = Value::Account(AccountValue { address: ADDRESS });
+    storage.write(ADDRESS, &key("account"), Some(account));
+    assert_eq!(storage.commitment().unwrap().root(), Hash::EMPTY);
+    assert_eq!(
+        storage.flush(),
+        Err(VMError::StorageFailure {
+            message: "cannot encode value of type `Account`".to_string()
+        })
+    );
+    assert_eq!(storage.flush(), Ok(()));
+}
@@ -0,0 +1,289 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::metering::{ComputationMeter, ComputationWeights};
+use cadence_vm::runtime::opcodes::{
+    Argument, Call, GlobalFuncLoad, IntAdd, IntConstantLoad, IterHasNext, IterNew, IterNext, Jump,
+    JumpIfFalse, NewArray, OpCode, ReturnValue, Switch, SwitchTable,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::types::StaticType;
+use cadence_vm::runtime::values::{ArrayValue, IntValue, Value};
+use cadence_vm::runtime::vm::VM;
+
+// The global of the native function, following the program's functions.
+const DOUBLE: usize = 5;
+
+fn function(
+    name: &str,
+    parameters: Vec<Parameter>,
+    return_type: StaticType,
+    code: Vec<Box<dyn OpCode>>,
+) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters,
+        return_type,
+        local_count: RegisterCounts {
+            ints: 2,
+            bools: 1,
+            funcs: 1,
+            values: 2,
+        },
+        code,
+        ..Default::default()
+    }
+}
+
+fn test_program() -> Program {
+    Program {
+        functions: vec![
+            // while true {}
+            function(
+                "loop",
+                vec![],
+                StaticType::Void,
+                vec![Box::new(Jump { target: 0 })],
+            ),
+            // while true { switch 0 { case 0: continue } }
+            function(
+                "switchLoop",
+                vec![],
+                StaticType::Void,
+                vec![
+                    Box::new(IntConstantLoad {
+                        index: 0,
+                        target: 0,
+                    }),
+                    Box::new(Switch {
+                        value: 0,
+                        typ: RegisterType::Int,
+                        table: SwitchTable::new(&[(0, 0)]),
+                        default: 0,
+                    }),
+                ],
+            ),
+            // var sum = 0; for x in xs { sum = sum + x }; return sum
+            function(
+                "sum",
+                vec![Parameter::unlabeled(
+                    "xs",
+                    StaticType::array(StaticType::Int),
+                )],
+                StaticType::Int,
+                vec![
+                    Box::new(IntConstantLoad {
+                        index: 0,
+                        target: 0,
+                    }),
+                    Box::new(IterNew {
+                        iterable: 0,
+                        result: 1,
+                    }),
+                    Box::new(IterHasNext {
+                        iterator: 1,
+                        result: 0,
+                    }),
+                    Box::new(JumpIfFalse {
+                        condition: 0,
+                        target: 7,
+                    }),
+                    Box::new(IterNext {
+                        iterator: 1,
+                        typ: RegisterType::Int,
+                        result: 1,
+                    }),
+                    Box::new(IntAdd {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 0,
+                    }),
+                    Box::new(Jump { target: 2 }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // return double(n)
+            Function {
+                // The argument of `double` is `n` itself, in the only int register.
+                local_count: RegisterCounts {
+                    ints: 1,
+                    bools: 0,
+                    funcs: 1,
+                    values: 0,
+                },
+                ..function(
+                    "callDouble",
+                    vec![Parameter::unlabeled("n", StaticType::Int)],
+                    StaticType::Int,
+                    vec![
+                        Box::new(GlobalFuncLoad {
+                            index: DOUBLE,
+                            result: 0,
+                        }),
+                        Box::new(Call {
+                            func_index: 0,
+                            arguments: vec![RegisterType::Int],
+                            result: 0,
+                        }),
+                        Box::new(ReturnValue { index: 0 }),
+                    ],
+                )
+            },
+            // return [n]
+            function(
+                "singleton",
+                vec![Parameter::unlabeled("n", StaticType::Int)],
+                StaticType::array(StaticType::Int),
+                vec![
+                    Box::new(NewArray {
+                        element_type: StaticType::Int,
+                        elements: vec![Argument {
+                            typ: RegisterType::Int,
+                            index: 0,
+                        }],
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+        ],
+        native_functions: vec!["double".to_string()],
+        constants: vec![Constant::int(0)],
+        ..Default::default()
+    }
+}
+
+fn int<'a>(value: isize) -> Value<'a> {
+    Value::Int(IntValue { value })
+}
+
+fn ints<'a>(values: &[isize]) -> Value<'a> {
+    Value::Array(ArrayValue::new(
+        StaticType::Int,
+        values.iter().map(|value| int(*value)).collect(),
+    ))
+}
+
+fn new_vm(program: &Program) -> VM<'_> {
+    let mut vm = VM::new(program);
+    vm.register_native_function(
+        "double",
+        RegisterType::Int,
+        |_, arguments| match &arguments[0] {
+            Value::Int(n) => Ok(int(n.value * 2)),
+            _ => unreachable!(),
+        },
+    );
+    vm
+}
+
+#[test]
+fn test_infinite_loop_exceeds_limit() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+    vm.set_computation_meter(ComputationMeter::new(ComputationWeights::default(), 1000));
+
+    let result = vm.invoke("loop", &[]);
+    assert_eq!(
+        result,
+        Err(VMError::ComputationLimitExceeded { limit: 1000 })
+    );
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "computation limit exceeded: limit 1000"
+    );
+    assert_eq!(vm.computation_used(), 1001);
+
+    // The VM can be used again, and each invocation is metered separately.
+    assert_eq!(vm.invoke("sum", &[ints(&[1, 2])]), Ok(int(3)));
+    assert_eq!(vm.computation_used(), 15);
+}
+
+#[test]
+fn test_computation_used() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    // By default, each instruction is charged 1, and there is no limit:
+    // 2 instructions before the loop, 5 per iteration, and 3 after it.
+    assert_eq!(vm.invoke("sum", &[ints(&[1, 2, 3])]), Ok(int(6)));
+    assert_eq!(vm.computation_used(), 20);
+    assert_eq!(vm.invoke("sum", &[ints(&[])]), Ok(int(0)));
+    assert_eq!(vm.computation_used(), 5);
+}
+
+#[test]
+fn test_computation_weights() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let weights = ComputationWeights {
+        instruction: 1,
+        opcodes: HashMap::from([("IntAdd", 10)]),
+        loop_iteration: 100,
+        function_call: 1000,
+        allocation: 10_000,
+        native_call: 100_000,
+    };
+    vm.set_computation_meter(ComputationMeter::new(weights.clone(), u64::MAX));
+
+    // Each iteration runs 4 instructions, an addition, and jumps back.
+    assert_eq!(vm.invoke("sum", &[ints(&[1, 2, 3])]), Ok(int(6)));
+    assert_eq!(vm.computation_used(), 5 + 3 * (4 + 10 + 100) + 1000);
+
+    assert_eq!(vm.invoke("callDouble", &[int(21)]), Ok(int(42)));
+    assert_eq!(vm.computation_used(), 3 + 1000 + 100_000);
+
+    assert_eq!(vm.invoke("singleton", &[int(1)]), Ok(ints(&[1])));
+    assert_eq!(vm.computation_used(), 2 + 1000 + 10_000);
+
+    // The limit is inclusive.
+    vm.set_computation_meter(ComputationMeter::new(weights.clone(), 11_002));
+    assert_eq!(vm.invoke("singleton", &[int(1)]), Ok(ints(&[1])));
+
+    vm.set_computation_meter(ComputationMeter::new(weights, 11_001));
+    assert_eq!(
+        vm.invoke("singleton", &[int(1)]),
+        Err(VMError::ComputationLimitExceeded { limit: 11_001 })
+    );
+}
+
+#[test]
+fn test_switch_loop_iterations() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let weights = ComputationWeights {
+        loop_iteration: 100,
+        ..ComputationWeights::default()
+    };
+    vm.set_computation_meter(ComputationMeter::new(weights, 1000));
+
+    // Each iteration runs 2 instructions, and the switch jumps back.
+    let result = vm.invoke("switchLoop", &[]);
+    assert_eq!(
+        result,
+        Err(VMError::ComputationLimitExceeded { limit: 1000 })
+    );
+    assert_eq!(vm.computation_used(), 10 * (2 + 100));
+}
@@ -0,0 +1,229 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::{Constant, ConstantKind};
+use cadence_vm::runtime::encoding::DecodeError;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::opcodes::{ConstantLoad, JumpIfFalse, ReturnValue};
+use cadence_vm::runtime::registers::RegisterCounts;
+use cadence_vm::runtime::types::{Authorization, StaticType};
+use cadence_vm::runtime::values::{
+    AddressValue, BoolValue, Fix64Value, FixedSizeIntValue, IntValue, StringValue, TypeValue,
+    UFix64Value, Value,
+};
+use cadence_vm::runtime::vm::VM;
+
+#[test]
+fn test_constant_round_trip() {
+    let values = vec![
+        Value::Int(IntValue { value: 0 }),
+        Value::Int(IntValue { value: -1 }),
+        Value::Int(IntValue { value: 128 }),
+        Value::Int(IntValue { value: isize::MIN }),
+        Value::FixedSizeInt(FixedSizeIntValue::Int8(i8::MIN)),
+        Value::FixedSizeInt(FixedSizeIntValue::Int64(-300)),
+        Value::FixedSizeInt(FixedSizeIntValue::Int128(i128::MAX)),
+        Value::FixedSizeInt(FixedSizeIntValue::UInt8(u8::MAX)),
+        Value::FixedSizeInt(FixedSizeIntValue::UInt128(u128::MAX)),
+        Value::FixedSizeInt(FixedSizeIntValue::Word16(0)),
+        Value::Fix64(Fix64Value { value: -150000000 }),
+        Value::UFix64(UFix64Value { value: u64::MAX }),
+        Value::String(StringValue::new("héllo")),
+        Value::Address(AddressValue {
+            value: 0xf8d6e0586b0a20c7,
+        }),
+        Value::Type(TypeValue {
+            typ: StaticType::optional(StaticType::reference(
+                Authorization::disjunction(&["E", "F"]),
+                StaticType::intersection(&["I"]),
+            )),
+        }),
+        Value::Type(TypeValue {
+            typ: StaticType::dictionary(
+                StaticType::String,
+                StaticType::array(StaticType::Character),
+            ),
+        }),
+    ];
+
+    for value in values {
+        let constant = match &value {
+            Value::Int(value) => Constant::int(value.value as i128),
+            Value::FixedSizeInt(value) => Constant::fixed_size_int(*value),
+            Value::Fix64(value) => Constant::fix64(*value),
+            Value::UFix64(value) => Constant::ufix64(*value),
+            Value::String(value) => Constant::string(&value.value),
+            Value::Address(value) => Constant::address(*value),
+            Value::Type(value) => Constant::type_literal(&value.typ),
+            _ => unreachable!(),
+        };
+
+        assert_eq!(constant.decode().unwrap(), value);
+    }
+}
+
+#[test]
+fn test_constant_encoding_is_minimal() {
+    assert_eq!(Constant::int(0).data, vec![0x00]);
+    assert_eq!(Constant::int(127).data, vec![0x7f]);
+    assert_eq!(Constant::int(128).data, vec![0x00, 0x80]);
+    assert_eq!(Constant::int(-128).data, vec![0x80]);
+    assert_eq!(Constant::int(-129).data, vec![0xff, 0x7f]);
+    assert_eq!(
+        Constant::fixed_size_int(FixedSizeIntValue::UInt16(255)).data,
+        vec![0xff]
+    );
+}
+
+#[test]
+fn test_constant_out_of_range() {
+    let decode = |kind, data: &[u8]| {
+        Constant {
+            kind,
+            data: data.to_vec(),
+        }
+        .decode()
+    };
+
+    // 128 does not fit `Int8`
+    assert_eq!(
+        decode(ConstantKind::Int8, &[0x00, 0x80]),
+        Err(DecodeError::OutOfRange)
+    );
+    // Negative numbers are encoded as signed
+    assert_eq!(
+        decode(ConstantKind::Int8, &[0xff, 0x7f]),
+        Err(DecodeError::OutOfRange)
+    );
+    // 2^127 does not fit `Int128`, even though it is sign-padded
+    let mut data = vec![0x00, 0x80];
+    data.extend([0; 15]);
+    assert_eq!(
+        decode(ConstantKind::Int128, &data),
+        Err(DecodeError::OutOfRange)
+    );
+    // `Int` constants wider than the platform's integers
+    assert_eq!(
+        decode(ConstantKind::Int, &[0x01; 17]),
+        Err(DecodeError::OutOfRange)
+    );
+    assert_eq!(
+        decode(ConstantKind::UInt64, &[]),
+        Err(DecodeError::UnexpectedEnd)
+    );
+    assert_eq!(
+        decode(ConstantKind::String, &[0xff]),
+        Err(DecodeError::InvalidUtf8)
+    );
+    assert_eq!(
+        decode(ConstantKind::Type, &[0xff]),
+        Err(DecodeError::InvalidTag(0xff))
+    );
+}
+
+#[test]
+fn test_fix64_display() {
+    assert_eq!(Fix64Value { value: -150000000 }.to_string(), "-1.50000000");
+    assert_eq!(UFix64Value { value: 1 }.to_string(), "0.00000001");
+}
+
+/// `fun f(_ b: Bool): AnyStruct { return b ? "yes" : <constant 1> }`
+fn constant_program(second: Constant) -> Program {
+    Program {
+        functions: vec![Function {
+            name: "f".to_string(),
+            parameters: vec![Parameter::unlabeled("b", StaticType::Bool)],
+            return_type: StaticType::AnyStruct,
+            local_count: RegisterCounts {
+                ints: 0,
+                bools: 1,
+                funcs: 0,
+                values: 1,
+            },
+            code: vec![
+                Box::new(JumpIfFalse {
+                    condition: 0,
+                    target: 3,
+                }),
+                Box::new(ConstantLoad {
+                    index: 0,
+                    result: 0,
+                }),
+                Box::new(ReturnValue { index: 0 }),
+                Box::new(ConstantLoad {
+                    index: 1,
+                    result: 0,
+                }),
+                Box::new(ReturnValue { index: 0 }),
+            ],
+            ..Default::default()
+        }],
+        constants: vec![Constant::string("yes"), second],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_constants_are_decoded_lazily() {
+    let program = constant_program(Constant {
+        kind: ConstantKind::Address,
+        data: vec![],
+    });
+    let mut vm = VM::new(&program);
+
+    // The invalid constant is never loaded
+    for _ in 0..2 {
+        let result = vm.invoke("f", &[Value::Bool(BoolValue { value: true })]);
+        assert_eq!(result, Ok(Value::String(StringValue::new("yes"))));
+    }
+
+    let err = vm
+        .invoke("f", &[Value::Bool(BoolValue { value: false })])
+        .unwrap_err();
+
+    assert_eq!(
+        err,
+        VMError::InvalidConstant {
+            index: 1,
+            error: DecodeError::UnexpectedEnd,
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "invalid constant 1: unexpected end of data"
+    );
+}
+
+#[test]
+fn test_load_typed_constant() {
+    let program = constant_program(Constant::type_literal(&StaticType::Int));
+    let mut vm = VM::new(&program);
+
+    let result = vm
+        .invoke("f", &[Value::Bool(BoolValue { value: false })])
+        .unwrap();
+
+    assert_eq!(
+        result,
+        Value::Type(TypeValue {
+            typ: StaticType::Int
+        })
+    );
+    assert_eq!(result.to_string(), "Type<Int>()");
+}
@@ -0,0 +1,323 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::opcodes::{
+    EnumLookup, EnumRawValue, Equal, IntConstantLoad, IntEqual, JumpIfFalse, NewEnumCase, NotEqual,
+    OpCode, ReturnValue,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::types::{CompositeKind, CompositeType, EnumInfo, StaticType};
+use cadence_vm::runtime::values::{BoolValue, CompositeValue, FixedSizeIntValue, IntValue, Value};
+use cadence_vm::runtime::vm::VM;
+
+const COLOR: usize = 0;
+const RED: usize = 0;
+const GREEN: usize = 1;
+
+fn color() -> StaticType {
+    StaticType::Composite("Color".to_string())
+}
+
+fn function(
+    name: &str,
+    parameters: Vec<Parameter>,
+    return_type: StaticType,
+    code: Vec<Box<dyn OpCode>>,
+) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters,
+        return_type,
+        local_count: RegisterCounts {
+            ints: 2,
+            bools: 1,
+            funcs: 0,
+            values: 2,
+        },
+        code,
+        ..Default::default()
+    }
+}
+
+/// Returns `switch <value> { case <case>: return 1, case <case>: return 2, default: return 0 }`,
+/// with each case compared by the given comparison.
+fn lowered_switch(
+    load_case: impl Fn(usize) -> Box<dyn OpCode>,
+    compare: impl Fn() -> Box<dyn OpCode>,
+) -> Vec<Box<dyn OpCode>> {
+    vec![
+        load_case(0),
+        compare(),
+        Box::new(JumpIfFalse {
+            condition: 0,
+            target: 5,
+        }),
+        Box::new(IntConstantLoad {
+            index: 1,
+            target: 1,
+        }),
+        Box::new(ReturnValue { index: 1 }),
+        load_case(1),
+        compare(),
+        Box::new(JumpIfFalse {
+            condition: 0,
+            target: 10,
+        }),
+        Box::new(IntConstantLoad {
+            index: 2,
+            target: 1,
+        }),
+        Box::new(ReturnValue { index: 1 }),
+        // default
+        Box::new(IntConstantLoad {
+            index: 0,
+            target: 1,
+        }),
+        Box::new(ReturnValue { index: 1 }),
+    ]
+}
+
+fn test_program() -> Program {
+    Program {
+        functions: vec![
+            // return Color.green
+            function(
+                "green",
+                vec![],
+                color(),
+                vec![
+                    Box::new(NewEnumCase {
+                        type_index: COLOR,
+                        case: GREEN,
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // return c.rawValue
+            function(
+                "rawValue",
+                vec![Parameter::unlabeled("c", color())],
+                StaticType::UInt8,
+                vec![
+                    Box::new(EnumRawValue {
+                        value: 0,
+                        result: 1,
+                    }),
+                    Box::new(ReturnValue { index: 1 }),
+                ],
+            ),
+            // return Color(rawValue: r)
+            function(
+                "lookup",
+                vec![Parameter::unlabeled("r", StaticType::UInt8)],
+                StaticType::optional(color()),
+                vec![
+                    Box::new(EnumLookup {
+                        type_index: COLOR,
+                        raw_value: 0,
+                        typ: RegisterType::Value,
+                        result: 1,
+                    }),
+                    Box::new(ReturnValue { index: 1 }),
+                ],
+            ),
+            // return a != b
+            function(
+                "notEqual",
+                vec![
+                    Parameter::unlabeled("a", color()),
+                    Parameter::unlabeled("b", color()),
+                ],
+                StaticType::Bool,
+                vec![
+                    Box::new(NotEqual {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // switch c { case Color.red: ... case Color.green: ... default: ... }
+            function(
+                "switchCase",
+                vec![Parameter::unlabeled("c", color())],
+                StaticType::Int,
+                lowered_switch(
+                    |case| {
+                        Box::new(NewEnumCase {
+                            type_index: COLOR,
+                            case: [RED, GREEN][case],
+                            result: 1,
+                        })
+                    },
+                    || {
+                        Box::new(Equal {
+                            left_operand: 0,
+                            right_operand: 1,
+                            result: 0,
+                        })
+                    },
+                ),
+            ),
+            // switch n { case 1: ... case 2: ... default: ... }
+            function(
+                "switchInt",
+                vec![Parameter::unlabeled("n", StaticType::Int)],
+                StaticType::Int,
+                lowered_switch(
+                    |case| {
+                        Box::new(IntConstantLoad {
+                            index: case + 1,
+                            target: 1,
+                        })
+                    },
+                    || {
+                        Box::new(IntEqual {
+                            left_operand: 0,
+                            right_operand: 1,
+                            result: 0,
+                        })
+                    },
+                ),
+            ),
+        ],
+        constants: vec![Constant::int(0), Constant::int(1), Constant::int(2)],
+        // enum Color: UInt8 { case red; case green; case blue }
+        composite_types: vec![CompositeType {
+            identifier: "Color".to_string(),
+            kind: CompositeKind::Enum,
+            conformances: vec![],
+            methods: vec![],
+            enum_info: Some(EnumInfo {
+                raw_type: StaticType::UInt8,
+                cases: vec!["red".to_string(), "green".to_string(), "blue".to_string()],
+            }),
+        }],
+        ..Default::default()
+    }
+}
+
+fn uint8<'a>(value: u8) -> Value<'a> {
+    Value::FixedSizeInt(FixedSizeIntValue::UInt8(value))
+}
+
+fn case<'a>(raw_value: u8) -> Value<'a> {
+    Value::Composite(CompositeValue::new(
+        "Color",
+        CompositeKind::Enum,
+        vec![("rawValue", uint8(raw_value))],
+    ))
+}
+
+#[test]
+fn test_enum_case() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let green = vm.invoke("green", &[]).unwrap();
+    assert_eq!(green, case(1));
+    assert_eq!(green.static_type(), color());
+    assert_eq!(green.to_string(), "Color(rawValue: 1)");
+
+    let raw_value = vm.invoke("rawValue", &[green]).unwrap();
+    assert_eq!(raw_value, uint8(1));
+}
+
+#[test]
+fn test_enum_lookup() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm.invoke("lookup", &[uint8(2)]).unwrap();
+    assert_eq!(result, Value::some(case(2)));
+
+    let result = vm.invoke("lookup", &[uint8(3)]).unwrap();
+    assert_eq!(result, Value::Nil);
+}
+
+#[test]
+fn test_enum_comparison() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm.invoke("notEqual", &[case(0), case(0)]).unwrap();
+    assert_eq!(result, Value::Bool(BoolValue { value: false }));
+
+    let result = vm.invoke("notEqual", &[case(0), case(2)]).unwrap();
+    assert_eq!(result, Value::Bool(BoolValue { value: true }));
+}
+
+#[test]
+fn test_lowered_enum_switch() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    for (raw_value, expected) in [(0, 1), (1, 2), (2, 0)] {
+        let result = vm.invoke("switchCase", &[case(raw_value)]).unwrap();
+        assert_eq!(result, Value::Int(IntValue { value: expected }));
+    }
+}
+
+#[test]
+fn test_lowered_int_switch() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    for (n, expected) in [(1, 1), (2, 2), (3, 0)] {
+        let result = vm
+            .invoke("switchInt", &[Value::Int(IntValue { value: n })])
+            .unwrap();
+        assert_eq!(result, Value::Int(IntValue { value: expected }));
+    }
+}
+
+#[test]
+fn test_raw_value_of_non_enum() {
+    // return (v as Color).rawValue, for any value `v`
+    let program = Program {
+        functions: vec![function(
+            "rawValue",
+            vec![Parameter::unlabeled("v", StaticType::AnyStruct)],
+            StaticType::UInt8,
+            vec![
+                Box::new(EnumRawValue {
+                    value: 0,
+                    result: 1,
+                }),
+                Box::new(ReturnValue { index: 1 }),
+            ],
+        )],
+        ..Default::default()
+    };
+    let mut vm = VM::new(&program);
+
+    let result = vm.invoke("rawValue", &[Value::Int(IntValue { value: 1 })]);
+
+    assert_eq!(
+        result,
+        Err(VMError::OperandTypeMismatch {
+            instruction: "EnumRawValue",
+            actual_type: StaticType::Int,
+        })
+    );
+}
@@ -0,0 +1,394 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use cadence_vm::runtime::bbq::Program;
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::file_storage::{self, FileStorage, FileStorageError};
+use cadence_vm::runtime::slabs;
+use cadence_vm::runtime::stdlib;
+use cadence_vm::runtime::storage::{JournaledStorage, Storage, StorageKey};
+use cadence_vm::runtime::types::{CompositeKind, CompositeType, StaticType};
+use cadence_vm::runtime::values::{
+    ArrayValue, CompositeValue, DictionaryValue, FixedSizeIntValue, IntValue, PathDomain,
+    StringValue, Value,
+};
+use cadence_vm::runtime::vm::VM;
+use common::{account, forward, key, parameter, path, storage_path, ADDRESS, OTHER_ADDRESS};
+
+// Natives, following the functions
+const NATIVE_SAVE: usize = 2;
+const NATIVE_COPY: usize = 3;
+
+// Constants
+const S_TYPE: usize = 0;
+
+fn s_type() -> StaticType {
+    StaticType::Composite("S".to_string())
+}
+
+fn test_program() -> Program {
+    let account = || parameter("account", StaticType::Account);
+    let path = || parameter("path", StaticType::StoragePath);
+
+    Program {
+        functions: vec![
+            // account.storage.save(s, to: path)
+            forward(
+                "save",
+                vec![account(), parameter("s", s_type()), path()],
+                StaticType::Void,
+                NATIVE_SAVE,
+                None,
+            ),
+            // return account.storage.copy<S>(from: path)
+            forward(
+                "copy",
+                vec![account(), path()],
+                StaticType::optional(s_type()),
+                NATIVE_COPY,
+                Some(S_TYPE),
+            ),
+        ],
+        constants: vec![Constant::type_literal(&s_type())],
+        composite_types: vec![CompositeType {
+            identifier: "S".to_string(),
+            kind: CompositeKind::Structure,
+            conformances: vec![],
+            methods: vec![],
+            enum_info: None,
+        }],
+        native_functions: vec![
+            "Account.Storage.save".to_string(),
+            "Account.Storage.copy".to_string(),
+        ],
+        ..Default::default()
+    }
+}
+
+/// Returns a path in the temporary directory, removing any file left by a previous run.
+fn temporary_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "cadence_vm_{}_{}.storage",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// Returns a struct with nested containers, e.g. `S(name: "s", scores: [1, 2], tags: {"a": 1})`.
+fn nested_structure<'a>(name: &str) -> Value<'a> {
+    let scores = ArrayValue::new(
+        StaticType::Int,
+        vec![
+            Value::Int(IntValue { value: 1 }),
+            Value::Int(IntValue { value: 2 }),
+        ],
+    );
+    let tags = DictionaryValue::new(
+        StaticType::String,
+        StaticType::UInt8,
+        vec![(
+            Value::String(StringValue::new("a")),
+            Value::FixedSizeInt(FixedSizeIntValue::UInt8(1)),
+        )],
+    );
+    Value::Composite(CompositeValue::new(
+        "S",
+        CompositeKind::Structure,
+        vec![
+            ("name", Value::String(StringValue::new(name))),
+            ("scores", Value::Array(scores)),
+            ("tags", Value::Dictionary(tags)),
+        ],
+    ))
+}
+
+#[test]
+fn test_reopen() {
+    let file = temporary_file("reopen");
+
+    let mut storage = FileStorage::open(&file).unwrap();
+    storage.write(ADDRESS, &key("a"), Some(nested_structure("a")));
+    storage.write(ADDRESS, &key("b"), Some(nested_structure("b")));
+    storage.write(ADDRESS, &key("a"), None);
+    storage.write(OTHER_ADDRESS, &key("c"), Some(storage_path("b")));
+    storage.write(ADDRESS, &StorageKey::LastCapabilityId, Some(Value::Nil));
+    storage.flush().unwrap();
+    drop(storage);
+
+    let storage = FileStorage::open(&file).unwrap();
+    assert_eq!(storage.read(ADDRESS, &key("a")), None);
+    assert_eq!(
+        storage.read(ADDRESS, &key("b")),
+        Some(nested_structure("b"))
+    );
+    assert_eq!(
+        storage.read(OTHER_ADDRESS, &key("c")),
+        Some(storage_path("b"))
+    );
+    assert_eq!(
+        storage.read(ADDRESS, &StorageKey::LastCapabilityId),
+        Some(Value::Nil)
+    );
+    assert_eq!(storage.paths(ADDRESS, PathDomain::Storage), vec![path("b")]);
+
+    fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn test_reopen_after_mutations_in_place() {
+    let file = temporary_file("mutations");
+    let int = |value| Value::Int(IntValue { value });
+
+    let mut storage = FileStorage::open(&file).unwrap();
+    storage.write(ADDRESS, &key("s"), Some(nested_structure("s")));
+    let nested = Value::Array(ArrayValue::new(
+        StaticType::array(StaticType::Int),
+        vec![Value::Array(ArrayValue::new(StaticType::Int, vec![int(1)]))],
+    ));
+    let array = slabs::store(&mut storage, ADDRESS, nested).unwrap();
+    storage.write(ADDRESS, &key("array"), Some(array));
+    storage.flush().unwrap();
+    drop(storage);
+
+    let mut storage = FileStorage::open(&file).unwrap();
+
+    // Mutate the struct as through a reference, and the array stored inline in a slab.
+    let scores = match storage.read(ADDRESS, &key("s")) {
+        Some(Value::Composite(composite)) => composite.get_field("scores"),
+        value => panic!("expected struct, got {:?}", value),
+    };
+    match scores {
+        Some(Value::Array(scores)) => scores.append(int(3)),
+        value => panic!("expected array, got {:?}", value),
+    }
+    let array = match storage.read(ADDRESS, &key("array")) {
+        Some(Value::StoredArray(array)) => array,
+        value => panic!("expected stored array, got {:?}", value),
+    };
+    match array.get(&storage, 0).unwrap() {
+        Some(Value::Array(inner)) => inner.append(int(2)),
+        value => panic!("expected array, got {:?}", value),
+    }
+    storage.flush().unwrap();
+    drop(storage);
+
+    let storage = FileStorage::open(&file).unwrap();
+    match storage.read(ADDRESS, &key("s")) {
+        Some(Value::Composite(composite)) => assert_eq!(
+            composite.get_field("scores"),
+            Some(Value::Array(ArrayValue::new(
+                StaticType::Int,
+                vec![int(1), int(2), int(3)]
+            )))
+        ),
+        value => panic!("expected struct, got {:?}", value),
+    }
+    assert_eq!(
+        array.get(&storage, 0).unwrap(),
+        Some(Value::Array(ArrayValue::new(
+            StaticType::Int,
+            vec![int(1), int(2)]
+        )))
+    );
+
+    fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn test_compact() {
+    let file = temporary_file("compact");
+
+    let mut storage = FileStorage::open(&file).unwrap();
+    for _ in 0..10 {
+        storage.write(ADDRESS, &key("a"), Some(nested_structure("a")));
+    }
+    storage.write(ADDRESS, &key("b"), Some(nested_structure("b")));
+    storage.write(ADDRESS, &key("b"), None);
+    storage.flush().unwrap();
+    let size = fs::metadata(&file).unwrap().len();
+
+    storage.compact().unwrap();
+    assert!(fs::metadata(&file).unwrap().len() < size / 5);
+
+    // Writes after compacting are appended to the compacted file.
+    storage.write(ADDRESS, &key("c"), Some(nested_structure("c")));
+    storage.flush().unwrap();
+    drop(storage);
+
+    let storage = FileStorage::open(&file).unwrap();
+    assert_eq!(
+        storage.paths(ADDRESS, PathDomain::Storage),
+        vec![path("a"), path("c")]
+    );
+    assert_eq!(
+        storage.read(ADDRESS, &key("a")),
+        Some(nested_structure("a"))
+    );
+
+    fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn test_vm_sessions() {
+    let file = temporary_file("sessions");
+    let program = test_program();
+
+    let mut vm = VM::new(&program);
+    stdlib::register_account_functions(&mut vm);
+    vm.storage = JournaledStorage::new(Box::new(FileStorage::open(&file).unwrap()));
+    let result = vm.invoke(
+        "save",
+        &[account(), nested_structure("s"), storage_path("s")],
+    );
+    assert_eq!(result, Ok(Value::Void));
+    // The writes are persisted when the invocation completes.
+    drop(vm);
+
+    let mut vm = VM::new(&program);
+    stdlib::register_account_functions(&mut vm);
+    vm.storage = JournaledStorage::new(Box::new(FileStorage::open(&file).unwrap()));
+    let result = vm.invoke("copy", &[account(), storage_path("s")]);
+    assert_eq!(result, Ok(Value::some(nested_structure("s"))));
+
+    fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn test_unstorable_value() {
+    let file = temporary_file("unstorable");
+
+    let mut storage = FileStorage::open(&file).unwrap();
+    storage.write(ADDRESS, &key("a"), Some(account()));
+    assert_eq!(
+        storage.flush(),
+        Err(VMError::StorageFailure {
+            message: "cannot encode value of type `Account`".to_string()
+        })
+    );
+
+    fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn test_invalid_file() {
+    let file = temporary_file("invalid");
+
+    fs::write(&file, b"not a storage file").unwrap();
+    assert!(matches!(
+        FileStorage::open(&file),
+        Err(FileStorageError::InvalidHeader)
+    ));
+
+    // A record that is complete but cannot be decoded fails to open.
+    let mut data = b"CDCSTORE\x01".to_vec();
+    data.extend_from_slice(&[2, 1, 0xff]);
+    fs::write(&file, &data).unwrap();
+    assert!(matches!(
+        FileStorage::open(&file),
+        Err(FileStorageError::Decode(_))
+    ));
+
+    fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn test_incomplete_final_record() {
+    let file = temporary_file("incomplete");
+
+    let mut storage = FileStorage::open(&file).unwrap();
+    storage.write(ADDRESS, &key("a"), Some(nested_structure("a")));
+    storage.flush().unwrap();
+    let complete = fs::metadata(&file).unwrap().len();
+    storage.write(ADDRESS, &key("b"), Some(nested_structure("b")));
+    storage.flush().unwrap();
+    drop(storage);
+
+    // Cut the file in the middle of the second record, as if the process was killed.
+    let data = fs::read(&file).unwrap();
+    let torn = complete as usize + 5;
+    assert!(torn < data.len());
+    fs::write(&file, &data[..torn]).unwrap();
+
+    // The first record is replayed, and the incomplete record is truncated.
+    let mut storage = FileStorage::open(&file).unwrap();
+    assert_eq!(
+        storage.read(ADDRESS, &key("a")),
+        Some(nested_structure("a"))
+    );
+    assert_eq!(storage.read(ADDRESS, &key("b")), None);
+    assert_eq!(fs::metadata(&file).unwrap().len(), complete);
+
+    // Later records are appended after the last complete record.
+    storage.write(ADDRESS, &key("c"), Some(nested_structure("c")));
+    storage.flush().unwrap();
+    drop(storage);
+
+    let storage = FileStorage::open(&file).unwrap();
+    assert_eq!(
+        storage.paths(ADDRESS, PathDomain::Storage),
+        vec![path("a"), path("c")]
+    );
+
+    fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn test_dump() {
+    let file = temporary_file("dump");
+
+    let mut storage = FileStorage::open(&file).unwrap();
+    storage.write(ADDRESS, &key("s"), Some(nested_structure("s")));
+    storage.write(
+        ADDRESS,
+        &StorageKey::LastCapabilityId,
+        Some(Value::FixedSizeInt(FixedSizeIntValue::UInt64(3))),
+    );
+    storage.write(
+        OTHER_ADDRESS,
+        &key("t"),
+        Some(Value::String(StringValue::new("t"))),
+    );
+
+    let mut out = vec![];
+    file_storage::dump(&storage, None, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "0x0000000000000001:\n\
+         \x20 /storage/s: S = S(name: \"s\", scores: [1, 2], tags: {\"a\": 1})\n\
+         \x20 lastCapabilityID: UInt64 = 3\n\
+         0x0000000000000002:\n\
+         \x20 /storage/t: String = \"t\"\n"
+    );
+
+    let mut out = vec![];
+    file_storage::dump(&storage, Some(OTHER_ADDRESS), &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "0x0000000000000002:\n  /storage/t: String = \"t\"\n"
+    );
+
+    fs::remove_file(&file).unwrap();
+}
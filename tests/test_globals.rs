@@ -0,0 +1,353 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program, Variable};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::opcodes::{
+    ConstantLoad, GlobalLoad, GlobalStore, IntAdd, IntConstantLoad, Return, ReturnValue,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::types::{CompositeKind, StaticType};
+use cadence_vm::runtime::values::{CompositeValue, IntValue, StringValue, Value};
+use cadence_vm::runtime::vm::VM;
+
+// Globals of the variables, following the program's functions.
+const COUNT: usize = 6;
+const GREETER: usize = 7;
+const VAULT: usize = 8;
+
+// The global of the function `increment`.
+const INCREMENT: usize = 1;
+
+fn vault_type() -> StaticType {
+    StaticType::Composite("Vault".to_string())
+}
+
+fn test_program() -> Program {
+    Program {
+        functions: vec![
+            // var count = 10
+            Function {
+                name: "init".to_string(),
+                local_count: RegisterCounts {
+                    ints: 1,
+                    bools: 0,
+                    funcs: 0,
+                    values: 0,
+                },
+                code: vec![
+                    Box::new(IntConstantLoad {
+                        index: 0,
+                        target: 0,
+                    }),
+                    Box::new(GlobalStore {
+                        index: COUNT,
+                        typ: RegisterType::Int,
+                        value: 0,
+                    }),
+                    Box::new(Return {}),
+                ],
+                ..Default::default()
+            },
+            // fun increment(): Int {
+            //     count = count + 1
+            //     return count
+            // }
+            Function {
+                name: "increment".to_string(),
+                return_type: StaticType::Int,
+                local_count: RegisterCounts {
+                    ints: 2,
+                    bools: 0,
+                    funcs: 0,
+                    values: 0,
+                },
+                code: vec![
+                    Box::new(GlobalLoad {
+                        index: COUNT,
+                        typ: RegisterType::Int,
+                        result: 0,
+                    }),
+                    Box::new(IntConstantLoad {
+                        index: 1,
+                        target: 1,
+                    }),
+                    Box::new(IntAdd {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 0,
+                    }),
+                    Box::new(GlobalStore {
+                        index: COUNT,
+                        typ: RegisterType::Int,
+                        value: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+                ..Default::default()
+            },
+            // fun getGreeter(): Greeter {
+            //     return Greeter
+            // }
+            Function {
+                name: "getGreeter".to_string(),
+                return_type: StaticType::Composite("Greeter".to_string()),
+                local_count: RegisterCounts {
+                    ints: 0,
+                    bools: 0,
+                    funcs: 0,
+                    values: 1,
+                },
+                code: vec![
+                    Box::new(GlobalLoad {
+                        index: GREETER,
+                        typ: RegisterType::Value,
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+                ..Default::default()
+            },
+            // increment = 1
+            Function {
+                name: "assignFunction".to_string(),
+                local_count: RegisterCounts {
+                    ints: 1,
+                    bools: 0,
+                    funcs: 0,
+                    values: 0,
+                },
+                code: vec![
+                    Box::new(IntConstantLoad {
+                        index: 1,
+                        target: 0,
+                    }),
+                    Box::new(GlobalStore {
+                        index: INCREMENT,
+                        typ: RegisterType::Int,
+                        value: 0,
+                    }),
+                ],
+                ..Default::default()
+            },
+            // Greeter = "hello"
+            Function {
+                name: "assignWrongType".to_string(),
+                local_count: RegisterCounts {
+                    ints: 0,
+                    bools: 0,
+                    funcs: 0,
+                    values: 1,
+                },
+                code: vec![
+                    Box::new(ConstantLoad {
+                        index: 2,
+                        result: 0,
+                    }),
+                    Box::new(GlobalStore {
+                        index: GREETER,
+                        typ: RegisterType::Value,
+                        value: 0,
+                    }),
+                ],
+                ..Default::default()
+            },
+            // fun storeVault(_ v: @Vault) { vault <- v },
+            // returning the register of `v` after the move
+            Function {
+                name: "storeVault".to_string(),
+                parameters: vec![Parameter::unlabeled("v", vault_type())],
+                return_type: StaticType::optional(vault_type()),
+                local_count: RegisterCounts {
+                    ints: 0,
+                    bools: 0,
+                    funcs: 0,
+                    values: 1,
+                },
+                code: vec![
+                    Box::new(GlobalStore {
+                        index: VAULT,
+                        typ: RegisterType::Value,
+                        value: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+                ..Default::default()
+            },
+        ],
+        constants: vec![
+            Constant::int(10),
+            Constant::int(1),
+            Constant::string("hello"),
+        ],
+        variables: vec![
+            Variable {
+                name: "count".to_string(),
+                typ: StaticType::Int,
+            },
+            // import Greeter from 0x1
+            Variable {
+                name: "Greeter".to_string(),
+                typ: StaticType::Composite("Greeter".to_string()),
+            },
+            Variable {
+                name: "vault".to_string(),
+                typ: vault_type(),
+            },
+            Variable {
+                name: "limit".to_string(),
+                typ: StaticType::optional(StaticType::Int),
+            },
+        ],
+        initializer: Some(0),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_global_variable() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    assert_eq!(vm.global("count"), None);
+
+    // The initializer runs once, before the first invocation
+    for expected in [11, 12] {
+        let result = vm.invoke("increment", &[]).unwrap();
+        assert_eq!(result, Value::Int(IntValue { value: expected }));
+    }
+
+    assert_eq!(vm.global("count"), Some(Value::Int(IntValue { value: 12 })));
+}
+
+#[test]
+fn test_imported_contract() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let err = vm.invoke("getGreeter", &[]).unwrap_err();
+    assert_eq!(
+        err,
+        VMError::UninitializedVariable {
+            name: "Greeter".to_string()
+        }
+    );
+
+    let greeter = Value::Composite(CompositeValue::new(
+        "Greeter",
+        CompositeKind::Contract,
+        vec![("greeting", Value::String(StringValue::new("hello")))],
+    ));
+    vm.set_global("Greeter", greeter.clone()).unwrap();
+
+    let result = vm.invoke("getGreeter", &[]).unwrap();
+    assert_eq!(result, greeter);
+}
+
+#[test]
+fn test_set_global_errors() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    assert_eq!(
+        vm.set_global("count", Value::String(StringValue::new("ten"))),
+        Err(VMError::VariableTypeMismatch {
+            name: "count".to_string(),
+            expected_type: StaticType::Int,
+            actual_type: StaticType::String,
+        })
+    );
+    assert_eq!(
+        vm.set_global("missing", Value::Int(IntValue { value: 1 })),
+        Err(VMError::UndefinedVariable {
+            name: "missing".to_string()
+        })
+    );
+    // Functions are not variables
+    assert_eq!(
+        vm.set_global("increment", Value::Int(IntValue { value: 1 })),
+        Err(VMError::UndefinedVariable {
+            name: "increment".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_set_optional_global() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    // Values assigned to variables of optional types are wrapped.
+    let five = Value::Int(IntValue { value: 5 });
+    vm.set_global("limit", five.clone()).unwrap();
+    assert_eq!(vm.global("limit"), Some(Value::some(five.clone())));
+
+    vm.set_global("limit", Value::some(five.clone())).unwrap();
+    assert_eq!(vm.global("limit"), Some(Value::some(five)));
+
+    vm.set_global("limit", Value::Nil).unwrap();
+    assert_eq!(vm.global("limit"), Some(Value::Nil));
+}
+
+#[test]
+fn test_global_store_errors() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let error = VMError::NotAVariable {
+        name: "increment".to_string(),
+    };
+    assert_eq!(vm.invoke("assignFunction", &[]), Err(error.clone()));
+    assert_eq!(
+        error.to_string(),
+        "cannot assign to `increment`: not a variable"
+    );
+    // The function is not overwritten.
+    assert_eq!(
+        vm.invoke("increment", &[]),
+        Ok(Value::Int(IntValue { value: 11 }))
+    );
+
+    assert_eq!(
+        vm.invoke("assignWrongType", &[]),
+        Err(VMError::VariableTypeMismatch {
+            name: "Greeter".to_string(),
+            expected_type: StaticType::Composite("Greeter".to_string()),
+            actual_type: StaticType::String,
+        })
+    );
+    assert_eq!(vm.global("Greeter"), None);
+}
+
+#[test]
+fn test_global_store_moves_resources() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let vault = Value::Composite(CompositeValue::new(
+        "Vault",
+        CompositeKind::Resource,
+        vec![("balance", Value::Int(IntValue { value: 1 }))],
+    ));
+
+    // The resource is moved into the variable, out of its register.
+    let result = vm.invoke("storeVault", std::slice::from_ref(&vault));
+    assert_eq!(result, Ok(Value::Void));
+    assert_eq!(vm.global("vault"), Some(vault));
+}
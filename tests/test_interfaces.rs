@@ -0,0 +1,292 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::opcodes::{
+    IntAdd, IntConstantLoad, IntLess, IntMove, InvokeMethod, Jump, JumpIfFalse, NewComposite,
+    OpCode, ReturnValue, Upcast,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::types::{CompositeKind, CompositeType, InterfaceType, Method, StaticType};
+use cadence_vm::runtime::values::{IntValue, Value};
+use cadence_vm::runtime::vm::VM;
+
+const GET_VALUE: usize = 0;
+const ADD: usize = 1;
+const MISSING: usize = 2;
+
+fn method(name: &str, function: usize) -> Method {
+    Method {
+        name: name.to_string(),
+        function,
+    }
+}
+
+/// Creates a value of type `S` if `n < 1`, and a value of type `T` otherwise,
+/// then invokes the given method through the interface `I`.
+fn dispatch_function(
+    name: &str,
+    method_name_index: usize,
+    arguments: Vec<RegisterType>,
+) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters: vec![Parameter::unlabeled("n", StaticType::Int)],
+        local_count: RegisterCounts {
+            ints: 3,
+            bools: 1,
+            funcs: 0,
+            values: 1,
+        },
+        return_type: StaticType::Int,
+        code: vec![
+            // let x: {I} = n < 1 ? S() : T()
+            Box::new(IntConstantLoad {
+                index: 0,
+                target: 1,
+            }),
+            Box::new(IntLess {
+                left_operand: 0,
+                right_operand: 1,
+                result: 0,
+            }),
+            Box::new(JumpIfFalse {
+                condition: 0,
+                target: 5,
+            }),
+            Box::new(NewComposite {
+                type_index: 0,
+                result: 0,
+            }),
+            Box::new(Jump { target: 6 }),
+            Box::new(NewComposite {
+                type_index: 1,
+                result: 0,
+            }),
+            // return x.method(n), passing n in the top int register, if at all
+            Box::new(IntMove { from: 0, to: 2 }),
+            Box::new(InvokeMethod::new(method_name_index, arguments, 2)),
+            Box::new(ReturnValue { index: 2 }),
+        ],
+        ..Default::default()
+    }
+}
+
+fn method_function(name: &str, parameters: Vec<Parameter>, code: Vec<Box<dyn OpCode>>) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters,
+        local_count: RegisterCounts {
+            ints: 2,
+            bools: 0,
+            funcs: 0,
+            values: 1,
+        },
+        return_type: StaticType::Int,
+        code,
+        ..Default::default()
+    }
+}
+
+fn test_program() -> Program {
+    Program {
+        functions: vec![
+            dispatch_function("getValue", GET_VALUE, vec![]),
+            dispatch_function("add", ADD, vec![RegisterType::Int]),
+            dispatch_function("missing", MISSING, vec![]),
+            // S.getValue(): Int { return 1 }
+            method_function(
+                "S.getValue",
+                vec![],
+                vec![
+                    Box::new(IntConstantLoad {
+                        index: 0,
+                        target: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // S.add(_ x: Int): Int { return x + x }
+            method_function(
+                "S.add",
+                vec![Parameter::unlabeled("x", StaticType::Int)],
+                vec![
+                    Box::new(IntAdd {
+                        left_operand: 0,
+                        right_operand: 0,
+                        result: 1,
+                    }),
+                    Box::new(ReturnValue { index: 1 }),
+                ],
+            ),
+            // I.getValue(): Int { return 2 }
+            method_function(
+                "I.getValue",
+                vec![],
+                vec![
+                    Box::new(IntConstantLoad {
+                        index: 1,
+                        target: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // J.add(_ x: Int): Int { return x + 2 }
+            method_function(
+                "J.add",
+                vec![Parameter::unlabeled("x", StaticType::Int)],
+                vec![
+                    Box::new(IntConstantLoad {
+                        index: 1,
+                        target: 1,
+                    }),
+                    Box::new(IntAdd {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 1,
+                    }),
+                    Box::new(ReturnValue { index: 1 }),
+                ],
+            ),
+            // let x: AnyStruct = n; return x.getValue()
+            Function {
+                name: "invokeOnInt".to_string(),
+                parameters: vec![Parameter::unlabeled("n", StaticType::Int)],
+                local_count: RegisterCounts {
+                    ints: 2,
+                    bools: 0,
+                    funcs: 0,
+                    values: 1,
+                },
+                return_type: StaticType::Int,
+                code: vec![
+                    Box::new(Upcast {
+                        typ: RegisterType::Int,
+                        value: 0,
+                        result: 0,
+                    }),
+                    Box::new(InvokeMethod::new(GET_VALUE, vec![], 1)),
+                    Box::new(ReturnValue { index: 1 }),
+                ],
+                ..Default::default()
+            },
+        ],
+        constants: vec![Constant::int(1), Constant::int(2)],
+        composite_types: vec![
+            CompositeType {
+                identifier: "S".to_string(),
+                kind: CompositeKind::Structure,
+                conformances: vec!["I".to_string()],
+                methods: vec![method("getValue", 3), method("add", 4)],
+                enum_info: None,
+            },
+            CompositeType {
+                identifier: "T".to_string(),
+                kind: CompositeKind::Structure,
+                conformances: vec!["I".to_string()],
+                methods: vec![],
+                enum_info: None,
+            },
+        ],
+        interface_types: vec![
+            InterfaceType {
+                identifier: "I".to_string(),
+                kind: CompositeKind::Structure,
+                conformances: vec!["J".to_string()],
+                methods: vec![method("getValue", 5)],
+            },
+            InterfaceType {
+                identifier: "J".to_string(),
+                kind: CompositeKind::Structure,
+                conformances: vec![],
+                methods: vec![method("add", 6)],
+            },
+        ],
+        method_names: vec![
+            "getValue".to_string(),
+            "add".to_string(),
+            "missing".to_string(),
+        ],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_interface_method_dispatch() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let mut invoke = |n| {
+        vm.invoke("getValue", &[Value::Int(IntValue { value: n })])
+            .unwrap()
+    };
+
+    // The same call site dispatches to the implementation of each concrete type:
+    // S implements the method, T falls back to the default implementation of I.
+    assert_eq!(invoke(0), Value::Int(IntValue { value: 1 }));
+    assert_eq!(invoke(1), Value::Int(IntValue { value: 2 }));
+    assert_eq!(invoke(0), Value::Int(IntValue { value: 1 }));
+}
+
+#[test]
+fn test_inherited_default_method_with_arguments() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let mut invoke = |n| {
+        vm.invoke("add", &[Value::Int(IntValue { value: n })])
+            .unwrap()
+    };
+
+    // S.add
+    assert_eq!(invoke(-5), Value::Int(IntValue { value: -10 }));
+    // J.add, inherited by T through I
+    assert_eq!(invoke(5), Value::Int(IntValue { value: 7 }));
+}
+
+#[test]
+fn test_method_not_found() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let err = vm
+        .invoke("missing", &[Value::Int(IntValue { value: 0 })])
+        .unwrap_err();
+
+    assert_eq!(
+        err,
+        VMError::MethodNotFound {
+            type_identifier: "S".to_string(),
+            method_name: "missing".to_string(),
+        }
+    );
+
+    // Methods cannot be invoked on values which are not composites.
+    let err = vm
+        .invoke("invokeOnInt", &[Value::Int(IntValue { value: 0 })])
+        .unwrap_err();
+    assert_eq!(
+        err,
+        VMError::MethodNotFound {
+            type_identifier: "Int".to_string(),
+            method_name: "getValue".to_string(),
+        }
+    );
+}
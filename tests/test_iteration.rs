@@ -0,0 +1,322 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::opcodes::{
+    Argument, ArrayAppend, IntAdd, IntConstantLoad, IterHasNext, IterNew, IterNext, Jump,
+    JumpIfFalse, NewArray, OpCode, ReturnValue,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::storage::InMemoryStorage;
+use cadence_vm::runtime::types::StaticType;
+use cadence_vm::runtime::values::{
+    ArrayValue, CharacterValue, DictionaryValue, IntValue, IteratorValue, StringValue, Value,
+};
+use cadence_vm::runtime::vm::VM;
+
+fn function(
+    name: &str,
+    parameter_type: StaticType,
+    return_type: StaticType,
+    code: Vec<Box<dyn OpCode>>,
+) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters: vec![Parameter::unlabeled("xs", parameter_type)],
+        return_type,
+        local_count: RegisterCounts {
+            ints: 2,
+            bools: 1,
+            funcs: 0,
+            values: 4,
+        },
+        code,
+        ..Default::default()
+    }
+}
+
+/// Returns the code of `let ys: [T] = []; for x in xs { ys.append(x) }; return ys`.
+/// If `append_to` is the register of `xs`, the loop appends to the iterated array instead.
+fn collect(element_type: StaticType, append_to: usize) -> Vec<Box<dyn OpCode>> {
+    vec![
+        Box::new(NewArray {
+            element_type,
+            elements: vec![],
+            result: 2,
+        }),
+        Box::new(IterNew {
+            iterable: 0,
+            result: 1,
+        }),
+        Box::new(IterHasNext {
+            iterator: 1,
+            result: 0,
+        }),
+        Box::new(JumpIfFalse {
+            condition: 0,
+            target: 7,
+        }),
+        Box::new(IterNext {
+            iterator: 1,
+            typ: RegisterType::Value,
+            result: 3,
+        }),
+        Box::new(ArrayAppend {
+            array: append_to,
+            value: Argument {
+                typ: RegisterType::Value,
+                index: 3,
+            },
+        }),
+        Box::new(Jump { target: 2 }),
+        Box::new(ReturnValue { index: 2 }),
+    ]
+}
+
+fn test_program() -> Program {
+    Program {
+        functions: vec![
+            // var sum = 0; for x in xs { sum = sum + x }; return sum
+            function(
+                "sum",
+                StaticType::array(StaticType::Int),
+                StaticType::Int,
+                vec![
+                    Box::new(IntConstantLoad {
+                        index: 0,
+                        target: 0,
+                    }),
+                    Box::new(IterNew {
+                        iterable: 0,
+                        result: 1,
+                    }),
+                    Box::new(IterHasNext {
+                        iterator: 1,
+                        result: 0,
+                    }),
+                    Box::new(JumpIfFalse {
+                        condition: 0,
+                        target: 7,
+                    }),
+                    Box::new(IterNext {
+                        iterator: 1,
+                        typ: RegisterType::Int,
+                        result: 1,
+                    }),
+                    Box::new(IntAdd {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 0,
+                    }),
+                    Box::new(Jump { target: 2 }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            function(
+                "keys",
+                StaticType::dictionary(StaticType::String, StaticType::Int),
+                StaticType::array(StaticType::String),
+                collect(StaticType::String, 2),
+            ),
+            function(
+                "characters",
+                StaticType::String,
+                StaticType::array(StaticType::Character),
+                collect(StaticType::Character, 2),
+            ),
+            // for x in xs { xs.append(x) }
+            function(
+                "appendDuringIteration",
+                StaticType::array(StaticType::String),
+                StaticType::array(StaticType::String),
+                collect(StaticType::String, 0),
+            ),
+        ],
+        constants: vec![Constant::int(0)],
+        ..Default::default()
+    }
+}
+
+fn int<'a>(value: isize) -> Value<'a> {
+    Value::Int(IntValue { value })
+}
+
+fn string<'a>(value: &str) -> Value<'a> {
+    Value::String(StringValue::new(value))
+}
+
+fn character<'a>(value: &str) -> Value<'a> {
+    Value::Character(CharacterValue::new(value))
+}
+
+fn strings<'a>(values: &[&str]) -> Value<'a> {
+    Value::Array(ArrayValue::new(
+        StaticType::String,
+        values.iter().map(|value| string(value)).collect(),
+    ))
+}
+
+#[test]
+fn test_iterate_array() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let xs = ArrayValue::new(StaticType::Int, vec![int(1), int(2), int(3), int(4)]);
+    let result = vm.invoke("sum", &[Value::Array(xs)]);
+    assert_eq!(result, Ok(int(10)));
+
+    let empty = ArrayValue::new(StaticType::Int, vec![]);
+    let result = vm.invoke("sum", &[Value::Array(empty)]);
+    assert_eq!(result, Ok(int(0)));
+}
+
+#[test]
+fn test_iterate_dictionary_keys() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let dictionary = DictionaryValue::new(
+        StaticType::String,
+        StaticType::Int,
+        vec![(string("b"), int(2)), (string("a"), int(1))],
+    );
+    let result = vm.invoke("keys", &[Value::Dictionary(dictionary)]);
+    assert_eq!(result, Ok(strings(&["b", "a"])));
+}
+
+#[test]
+fn test_iterate_string_characters() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    // "e" followed by a combining acute accent is a single character.
+    let result = vm.invoke("characters", &[string("he\u{301}y")]);
+    let expected = ArrayValue::new(
+        StaticType::Character,
+        vec![character("h"), character("e\u{301}"), character("y")],
+    );
+    assert_eq!(result, Ok(Value::Array(expected)));
+}
+
+#[test]
+fn test_mutation_during_iteration() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm.invoke("appendDuringIteration", &[strings(&["a"])]);
+    assert_eq!(result, Err(VMError::ContainerMutatedDuringIteration));
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "resource container modified during iteration"
+    );
+
+    // The loop body, and so the mutation, is never executed for an empty array.
+    let empty = strings(&[]);
+    let result = vm.invoke("appendDuringIteration", &[empty]);
+    assert_eq!(result, Ok(strings(&[])));
+}
+
+#[test]
+fn test_dictionary_mutation_during_iteration() {
+    let dictionary = DictionaryValue::new(
+        StaticType::String,
+        StaticType::Int,
+        vec![(string("a"), int(1)), (string("b"), int(2))],
+    );
+    let storage = InMemoryStorage::new();
+    let mut iterator = IteratorValue::new(&Value::Dictionary(dictionary.clone()), &storage)
+        .unwrap()
+        .unwrap();
+    assert_eq!(iterator.next_element(&storage), Ok(Some(string("a"))));
+
+    dictionary.remove(&string("b"));
+    assert_eq!(
+        iterator.has_next(&storage),
+        Err(VMError::ContainerMutatedDuringIteration)
+    );
+}
+
+#[test]
+fn test_dictionary_operations() {
+    let dictionary = DictionaryValue::new(
+        StaticType::String,
+        StaticType::Int,
+        vec![
+            (string("a"), int(1)),
+            (string("b"), int(2)),
+            (string("c"), int(3)),
+        ],
+    );
+
+    assert_eq!(dictionary.insert(string("b"), int(20)), Some(int(2)));
+    assert_eq!(dictionary.remove(&string("a")), Some(int(1)));
+    assert_eq!(dictionary.remove(&string("a")), None);
+    assert_eq!(dictionary.get(&string("c")), Some(int(3)));
+    assert_eq!(dictionary.get(&string("b")), Some(int(20)));
+    assert_eq!(dictionary.len(), 2);
+
+    // Dictionaries with the same entries are equal, regardless of order.
+    let other = DictionaryValue::new(
+        StaticType::String,
+        StaticType::Int,
+        vec![(string("b"), int(20)), (string("c"), int(3))],
+    );
+    assert_eq!(dictionary, other);
+    assert_eq!(
+        Value::Dictionary(dictionary).to_string(),
+        r#"{"c": 3, "b": 20}"#
+    );
+}
+
+#[test]
+fn test_operations_on_non_containers() {
+    // for x in xs { ... }, where xs is not iterable
+    let iterate: Vec<Box<dyn OpCode>> = vec![Box::new(IterNew {
+        iterable: 0,
+        result: 1,
+    })];
+    // xs.append(xs), where xs is not an array
+    let append: Vec<Box<dyn OpCode>> = vec![Box::new(ArrayAppend {
+        array: 0,
+        value: Argument {
+            typ: RegisterType::Value,
+            index: 0,
+        },
+    })];
+    let program = Program {
+        functions: vec![
+            function("iterate", StaticType::AnyStruct, StaticType::Void, iterate),
+            function("append", StaticType::AnyStruct, StaticType::Void, append),
+        ],
+        ..Default::default()
+    };
+    let mut vm = VM::new(&program);
+
+    for (name, instruction) in [("iterate", "IterNew"), ("append", "ArrayAppend")] {
+        let result = vm.invoke(name, &[Value::Int(IntValue { value: 1 })]);
+        assert_eq!(
+            result,
+            Err(VMError::OperandTypeMismatch {
+                instruction,
+                actual_type: StaticType::Int,
+            })
+        );
+    }
+}
@@ -0,0 +1,631 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+mod common;
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::opcodes::{Call, ConstantLoad, GlobalFuncLoad, Move, OpCode};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::slabs;
+use cadence_vm::runtime::stdlib;
+use cadence_vm::runtime::storage::{InMemoryStorage, JournaledStorage, Storage};
+use cadence_vm::runtime::types::{Authorization, CompositeKind, CompositeType, StaticType};
+use cadence_vm::runtime::values::{
+    ArrayValue, CompositeValue, IntValue, PathDomain, ReferenceValue, Value,
+};
+use cadence_vm::runtime::vm::VM;
+use common::{account, int, key, path, storage_path, structure, ADDRESS};
+
+const ARGUMENTS: [RegisterType; 3] = [RegisterType::Value; 3];
+
+// Program functions
+const SAVE_AND_PANIC: usize = 1;
+const FUNCTION_COUNT: usize = 5;
+
+// Natives, following the functions
+const NATIVE_SAVE: usize = FUNCTION_COUNT;
+const NATIVE_PANIC: usize = FUNCTION_COUNT + 1;
+const NATIVE_TRY_INVOKE: usize = FUNCTION_COUNT + 2;
+const NATIVE_BORROW: usize = FUNCTION_COUNT + 3;
+const NATIVE_INCREMENT: usize = FUNCTION_COUNT + 4;
+
+// Constants
+const MESSAGE: usize = 0;
+const KEPT_PATH: usize = 1;
+const S_REFERENCE_TYPE: usize = 2;
+
+fn s_type() -> StaticType {
+    StaticType::Composite("S".to_string())
+}
+
+fn function(name: &str, values: usize, code: Vec<Box<dyn OpCode>>) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters: vec![
+            Parameter::unlabeled("account", StaticType::Account),
+            Parameter::unlabeled("s", s_type()),
+            Parameter::unlabeled("path", StaticType::StoragePath),
+        ],
+        return_type: StaticType::Void,
+        local_count: RegisterCounts {
+            ints: 0,
+            bools: 0,
+            funcs: 2,
+            values,
+        },
+        code,
+        ..Default::default()
+    }
+}
+
+/// Calls `save` with the top three value registers.
+fn call_save() -> Vec<Box<dyn OpCode>> {
+    vec![
+        Box::new(GlobalFuncLoad {
+            index: NATIVE_SAVE,
+            result: 0,
+        }),
+        Box::new(Call {
+            func_index: 0,
+            arguments: ARGUMENTS.to_vec(),
+            result: 0,
+        }),
+    ]
+}
+
+/// Copies the parameters to the value registers above them, e.g. to pass them to a call.
+fn copy_parameters() -> Vec<Box<dyn OpCode>> {
+    (0..3)
+        .map(|index| -> Box<dyn OpCode> {
+            Box::new(Move {
+                typ: RegisterType::Value,
+                from: index,
+                to: index + 3,
+            })
+        })
+        .collect()
+}
+
+fn test_program() -> Program {
+    let mut save_and_panic = call_save();
+    save_and_panic.extend::<[Box<dyn OpCode>; 3]>([
+        Box::new(GlobalFuncLoad {
+            index: NATIVE_PANIC,
+            result: 0,
+        }),
+        Box::new(ConstantLoad {
+            index: MESSAGE,
+            result: 2,
+        }),
+        Box::new(Call {
+            func_index: 0,
+            arguments: vec![RegisterType::Value],
+            result: 0,
+        }),
+    ]);
+
+    let mut save_after_failure = copy_parameters();
+    save_after_failure.extend::<[Box<dyn OpCode>; 3]>([
+        Box::new(GlobalFuncLoad {
+            index: SAVE_AND_PANIC,
+            result: 1,
+        }),
+        Box::new(GlobalFuncLoad {
+            index: NATIVE_TRY_INVOKE,
+            result: 0,
+        }),
+        Box::new(Call {
+            func_index: 0,
+            arguments: vec![
+                RegisterType::Func,
+                RegisterType::Value,
+                RegisterType::Value,
+                RegisterType::Value,
+            ],
+            result: 0,
+        }),
+    ]);
+    save_after_failure.extend(copy_parameters());
+    save_after_failure.push(Box::new(ConstantLoad {
+        index: KEPT_PATH,
+        result: 5,
+    }));
+    save_after_failure.extend(call_save());
+
+    let increment_and_panic: Vec<Box<dyn OpCode>> = vec![
+        Box::new(GlobalFuncLoad {
+            index: NATIVE_BORROW,
+            result: 0,
+        }),
+        Box::new(ConstantLoad {
+            index: S_REFERENCE_TYPE,
+            result: 2,
+        }),
+        Box::new(Call {
+            func_index: 0,
+            arguments: ARGUMENTS.to_vec(),
+            result: 0,
+        }),
+        Box::new(Move {
+            typ: RegisterType::Value,
+            from: 0,
+            to: 2,
+        }),
+        Box::new(GlobalFuncLoad {
+            index: NATIVE_INCREMENT,
+            result: 0,
+        }),
+        Box::new(Call {
+            func_index: 0,
+            arguments: vec![RegisterType::Value],
+            result: 0,
+        }),
+        Box::new(GlobalFuncLoad {
+            index: NATIVE_PANIC,
+            result: 0,
+        }),
+        Box::new(ConstantLoad {
+            index: MESSAGE,
+            result: 2,
+        }),
+        Box::new(Call {
+            func_index: 0,
+            arguments: vec![RegisterType::Value],
+            result: 0,
+        }),
+    ];
+
+    let mut increment_reference_and_panic: Vec<Box<dyn OpCode>> = vec![];
+    for (from, to) in [(0, 3), (1, 4)] {
+        increment_reference_and_panic.push(Box::new(Move {
+            typ: RegisterType::Value,
+            from,
+            to,
+        }));
+    }
+    increment_reference_and_panic.extend::<[Box<dyn OpCode>; 9]>([
+        Box::new(ConstantLoad {
+            index: S_REFERENCE_TYPE,
+            result: 5,
+        }),
+        Box::new(GlobalFuncLoad {
+            index: NATIVE_BORROW,
+            result: 0,
+        }),
+        Box::new(Call {
+            func_index: 0,
+            arguments: ARGUMENTS.to_vec(),
+            result: 3,
+        }),
+        Box::new(Move {
+            typ: RegisterType::Value,
+            from: 2,
+            to: 5,
+        }),
+        Box::new(GlobalFuncLoad {
+            index: NATIVE_INCREMENT,
+            result: 0,
+        }),
+        Box::new(Call {
+            func_index: 0,
+            arguments: vec![RegisterType::Value],
+            result: 3,
+        }),
+        Box::new(GlobalFuncLoad {
+            index: NATIVE_PANIC,
+            result: 0,
+        }),
+        Box::new(ConstantLoad {
+            index: MESSAGE,
+            result: 5,
+        }),
+        Box::new(Call {
+            func_index: 0,
+            arguments: vec![RegisterType::Value],
+            result: 3,
+        }),
+    ]);
+
+    Program {
+        functions: vec![
+            // account.storage.save(s, to: path)
+            function("save", 3, call_save()),
+            // account.storage.save(s, to: path); panic("failed")
+            function("saveAndPanic", 3, save_and_panic),
+            // tryInvoke(saveAndPanic, account, s, path); account.storage.save(s, to: /storage/kept)
+            function("saveAfterFailure", 6, save_after_failure),
+            // increment(account.storage.borrow<&S>(from: path)!); panic("failed")
+            Function {
+                parameters: vec![
+                    Parameter::unlabeled("account", StaticType::Account),
+                    Parameter::unlabeled("path", StaticType::StoragePath),
+                ],
+                ..function("incrementAndPanic", 3, increment_and_panic)
+            },
+            // account.storage.borrow<&S>(from: path); increment(r); panic("failed")
+            Function {
+                parameters: vec![
+                    Parameter::unlabeled("account", StaticType::Account),
+                    Parameter::unlabeled("path", StaticType::StoragePath),
+                    Parameter::unlabeled(
+                        "r",
+                        StaticType::optional(StaticType::reference(
+                            Authorization::Unauthorized,
+                            s_type(),
+                        )),
+                    ),
+                ],
+                ..function(
+                    "incrementReferenceAndPanic",
+                    6,
+                    increment_reference_and_panic,
+                )
+            },
+        ],
+        constants: vec![
+            Constant::string("failed"),
+            Constant::path(&path("kept")),
+            Constant::type_literal(&StaticType::reference(
+                Authorization::Unauthorized,
+                s_type(),
+            )),
+        ],
+        composite_types: vec![CompositeType {
+            identifier: "S".to_string(),
+            kind: CompositeKind::Structure,
+            conformances: vec![],
+            methods: vec![],
+            enum_info: None,
+        }],
+        native_functions: vec![
+            "Account.Storage.save".to_string(),
+            "panic".to_string(),
+            "tryInvoke".to_string(),
+            "Account.Storage.borrow".to_string(),
+            "increment".to_string(),
+        ],
+        ..Default::default()
+    }
+}
+
+fn new_vm(program: &Program) -> VM<'_> {
+    let mut vm = VM::new(program);
+    stdlib::register_standard_functions(&mut vm);
+    stdlib::register_account_functions(&mut vm);
+    // Invokes the function with the remaining arguments, ignoring failures.
+    vm.register_native_function("tryInvoke", RegisterType::Value, |context, arguments| {
+        match &arguments[0] {
+            Value::Function(function) => {
+                let _ = context.invoke_function(function, &arguments[1..]);
+                Ok(Value::Void)
+            }
+            value => panic!("expected function, got {:?}", value),
+        }
+    });
+    // Increments the count of the optional reference to an `S`.
+    vm.register_native_function("increment", RegisterType::Value, |context, arguments| {
+        match &arguments[0] {
+            Value::Some(value) => match &**value {
+                reference @ Value::Reference(_) => increment(&context.dereference(reference)?),
+                value => panic!("expected reference, got {:?}", value),
+            },
+            value => panic!("expected optional, got {:?}", value),
+        }
+        Ok(Value::Void)
+    });
+    vm
+}
+
+fn increment(value: &Value) {
+    match value {
+        Value::Composite(composite) => match composite.get_field("count") {
+            Some(Value::Int(count)) => composite.set_field(
+                "count",
+                Value::Int(IntValue {
+                    value: count.value + 1,
+                }),
+            ),
+            field => panic!("expected count, got {:?}", field),
+        },
+        value => panic!("expected composite, got {:?}", value),
+    }
+}
+
+#[test]
+fn test_checkpoints() {
+    let mut storage = JournaledStorage::new(Box::new(InMemoryStorage::new()));
+
+    // Writes outside of a checkpoint are not journaled.
+    storage.write(ADDRESS, &key("a"), Some(Value::Composite(structure(1))));
+    assert_eq!(
+        storage.storage().read(ADDRESS, &key("a")),
+        Some(Value::Composite(structure(1)))
+    );
+
+    storage.checkpoint();
+    storage.write(ADDRESS, &key("b"), Some(Value::Composite(structure(2))));
+
+    storage.checkpoint();
+    storage.write(ADDRESS, &key("a"), None);
+    storage.write(ADDRESS, &key("c"), Some(Value::Composite(structure(3))));
+    assert_eq!(storage.depth(), 2);
+    assert_eq!(storage.read(ADDRESS, &key("a")), None);
+    assert_eq!(
+        storage.paths(ADDRESS, PathDomain::Storage),
+        vec![path("b"), path("c")]
+    );
+
+    // Rolling back the inner checkpoint reverts its writes only.
+    storage.rollback();
+    assert_eq!(
+        storage.read(ADDRESS, &key("a")),
+        Some(Value::Composite(structure(1)))
+    );
+    assert_eq!(storage.read(ADDRESS, &key("c")), None);
+    assert_eq!(
+        storage.paths(ADDRESS, PathDomain::Storage),
+        vec![path("a"), path("b")]
+    );
+
+    // Committing an inner checkpoint keeps the writes pending.
+    storage.checkpoint();
+    storage.write(ADDRESS, &key("c"), Some(Value::Composite(structure(3))));
+    storage.commit();
+    assert_eq!(
+        storage.read(ADDRESS, &key("c")),
+        Some(Value::Composite(structure(3)))
+    );
+    assert_eq!(storage.storage().read(ADDRESS, &key("c")), None);
+
+    // Committing the outermost checkpoint writes to the host storage.
+    storage.commit();
+    assert_eq!(storage.depth(), 0);
+    assert_eq!(
+        storage.storage().paths(ADDRESS, PathDomain::Storage),
+        vec![path("a"), path("b"), path("c")]
+    );
+}
+
+#[test]
+fn test_rollback_reverts_committed_nested_checkpoints() {
+    let mut storage = JournaledStorage::new(Box::new(InMemoryStorage::new()));
+
+    storage.checkpoint();
+    storage.write(ADDRESS, &key("a"), Some(Value::Composite(structure(1))));
+    storage.checkpoint();
+    storage.write(ADDRESS, &key("a"), Some(Value::Composite(structure(2))));
+    storage.commit();
+    storage.rollback();
+
+    assert_eq!(storage.read(ADDRESS, &key("a")), None);
+    assert_eq!(storage.storage().read(ADDRESS, &key("a")), None);
+}
+
+#[test]
+fn test_successful_invocation_is_committed() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let result = vm.invoke(
+        "save",
+        &[account(), Value::Composite(structure(1)), storage_path("s")],
+    );
+    assert_eq!(result, Ok(Value::Void));
+    assert_eq!(vm.storage.depth(), 0);
+    assert_eq!(
+        vm.storage.storage().read(ADDRESS, &key("s")),
+        Some(Value::Composite(structure(1)))
+    );
+}
+
+#[test]
+fn test_failed_invocation_is_rolled_back() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let result = vm.invoke(
+        "saveAndPanic",
+        &[account(), Value::Composite(structure(1)), storage_path("s")],
+    );
+    assert_eq!(
+        result,
+        Err(VMError::Panic {
+            message: "failed".to_string()
+        })
+    );
+    assert_eq!(vm.storage.depth(), 0);
+    assert_eq!(vm.storage.read(ADDRESS, &key("s")), None);
+    assert_eq!(vm.storage.storage().read(ADDRESS, &key("s")), None);
+}
+
+#[test]
+fn test_failed_nested_invocation_is_rolled_back() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let result = vm.invoke(
+        "saveAfterFailure",
+        &[account(), Value::Composite(structure(1)), storage_path("s")],
+    );
+    assert_eq!(result, Ok(Value::Void));
+
+    // Only the write of the failed nested invocation is reverted.
+    let storage = vm.storage.storage();
+    assert_eq!(storage.read(ADDRESS, &key("s")), None);
+    assert_eq!(
+        storage.read(ADDRESS, &key("kept")),
+        Some(Value::Composite(structure(1)))
+    );
+}
+
+#[test]
+fn test_rollback_reverts_mutations_in_place() {
+    let mut storage = JournaledStorage::new(Box::new(InMemoryStorage::new()));
+    storage.write(ADDRESS, &key("a"), Some(Value::Composite(structure(1))));
+
+    storage.checkpoint();
+    let value = storage.read(ADDRESS, &key("a")).unwrap();
+    increment(&value);
+
+    storage.checkpoint();
+    increment(&storage.read(ADDRESS, &key("a")).unwrap());
+    assert_eq!(
+        storage.read(ADDRESS, &key("a")),
+        Some(Value::Composite(structure(3)))
+    );
+
+    // Rolling back the inner checkpoint reverts its mutation only,
+    // also through the values read before.
+    storage.rollback();
+    assert_eq!(value, Value::Composite(structure(2)));
+    assert_eq!(
+        storage.read(ADDRESS, &key("a")),
+        Some(Value::Composite(structure(2)))
+    );
+
+    storage.rollback();
+    assert_eq!(value, Value::Composite(structure(1)));
+    assert_eq!(
+        storage.storage().read(ADDRESS, &key("a")),
+        Some(Value::Composite(structure(1)))
+    );
+
+    // Mutations in committed checkpoints are written to the host storage.
+    storage.checkpoint();
+    increment(&storage.read(ADDRESS, &key("a")).unwrap());
+    storage.commit();
+    assert_eq!(
+        storage.storage().read(ADDRESS, &key("a")),
+        Some(Value::Composite(structure(2)))
+    );
+}
+
+#[test]
+fn test_rollback_reverts_mutations_of_nested_collections() {
+    let mut storage = JournaledStorage::new(Box::new(InMemoryStorage::new()));
+
+    let int_array = |values: &[isize]| {
+        Value::Array(ArrayValue::new(
+            StaticType::Int,
+            values
+                .iter()
+                .map(|value| Value::Int(IntValue { value: *value }))
+                .collect(),
+        ))
+    };
+    let nested = Value::Array(ArrayValue::new(
+        StaticType::array(StaticType::Int),
+        vec![int_array(&[1])],
+    ));
+    let array = match slabs::store(&mut storage, ADDRESS, nested).unwrap() {
+        Value::StoredArray(array) => array,
+        value => panic!("expected stored array, got {:?}", value),
+    };
+
+    // The inner array is stored inline in the slab of the outer array.
+    storage.checkpoint();
+    match array.get(&storage, 0).unwrap() {
+        Some(Value::Array(inner)) => inner.append(Value::Int(IntValue { value: 2 })),
+        value => panic!("expected array, got {:?}", value),
+    }
+    assert_eq!(array.get(&storage, 0).unwrap(), Some(int_array(&[1, 2])));
+
+    storage.rollback();
+    assert_eq!(array.get(&storage, 0).unwrap(), Some(int_array(&[1])));
+}
+
+#[test]
+fn test_failed_invocation_reverts_mutations_through_references() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    vm.invoke(
+        "save",
+        &[account(), Value::Composite(structure(1)), storage_path("s")],
+    )
+    .unwrap();
+
+    let result = vm.invoke("incrementAndPanic", &[account(), storage_path("s")]);
+    assert_eq!(
+        result,
+        Err(VMError::Panic {
+            message: "failed".to_string()
+        })
+    );
+    assert_eq!(
+        vm.storage.read(ADDRESS, &key("s")),
+        Some(Value::Composite(structure(1)))
+    );
+}
+
+#[test]
+fn test_failed_invocation_restores_nested_values_in_place() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let outer = CompositeValue::new(
+        "S",
+        CompositeKind::Structure,
+        vec![("count", int(1)), ("inner", Value::Composite(structure(1)))],
+    );
+    vm.invoke(
+        "save",
+        &[account(), Value::Composite(outer), storage_path("s")],
+    )
+    .unwrap();
+
+    // Keep a reference to the struct nested in the stored struct.
+    let inner = match vm.storage.read(ADDRESS, &key("s")) {
+        Some(Value::Composite(outer)) => outer.get_field("inner").unwrap(),
+        value => panic!("expected composite, got {:?}", value),
+    };
+    let reference = Value::Reference(ReferenceValue::new(
+        Authorization::Unauthorized,
+        inner.clone(),
+    ));
+
+    // The failed invocation reads the stored struct, and mutates the nested one through the reference.
+    let result = vm.invoke(
+        "incrementReferenceAndPanic",
+        &[account(), storage_path("s"), Value::some(reference.clone())],
+    );
+    assert_eq!(
+        result,
+        Err(VMError::Panic {
+            message: "failed".to_string()
+        })
+    );
+
+    // The nested struct is restored in place, so the reference still refers to the stored one.
+    assert_eq!(
+        vm.dereference(&reference),
+        Ok(Value::Composite(structure(1)))
+    );
+    increment(&inner);
+    match vm.storage.read(ADDRESS, &key("s")) {
+        Some(Value::Composite(outer)) => {
+            assert_eq!(
+                outer.get_field("inner"),
+                Some(Value::Composite(structure(2)))
+            )
+        }
+        value => panic!("expected composite, got {:?}", value),
+    };
+}
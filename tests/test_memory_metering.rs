@@ -0,0 +1,232 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use cadence_vm::runtime::bbq::{Function, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::metering::{MemoryKind, MemoryMeter, MemoryWeights};
+use cadence_vm::runtime::opcodes::{
+    Argument, ArrayAppend, ConstantLoad, DictionaryInsert, Jump, NewArray, NewComposite,
+    NewDictionary, OpCode, ReturnValue,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::types::{CompositeKind, CompositeType, StaticType};
+use cadence_vm::runtime::values::{FixedSizeIntValue, StringValue, Value};
+use cadence_vm::runtime::vm::VM;
+
+const INT: Argument = Argument {
+    typ: RegisterType::Int,
+    index: 0,
+};
+
+const KEY: Argument = Argument {
+    typ: RegisterType::Value,
+    index: 2,
+};
+
+fn function(name: &str, return_type: StaticType, code: Vec<Box<dyn OpCode>>) -> Function {
+    Function {
+        name: name.to_string(),
+        return_type,
+        // 5 registers.
+        local_count: RegisterCounts {
+            ints: 1,
+            bools: 1,
+            funcs: 0,
+            values: 3,
+        },
+        code,
+        ..Default::default()
+    }
+}
+
+fn test_program() -> Program {
+    Program {
+        functions: vec![
+            // let s = "hello"; let n: Int128 = 1; return s
+            function(
+                "constants",
+                StaticType::String,
+                vec![
+                    Box::new(ConstantLoad {
+                        index: 0,
+                        result: 0,
+                    }),
+                    Box::new(ConstantLoad {
+                        index: 1,
+                        result: 1,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // let xs = [0]; xs.append(0)
+            // let d: {String: Int} = {}; d["hello"] = 0; d["hello"] = 0
+            // let s = S()
+            // return xs
+            function(
+                "containers",
+                StaticType::array(StaticType::Int),
+                vec![
+                    Box::new(NewArray {
+                        element_type: StaticType::Int,
+                        elements: vec![INT],
+                        result: 0,
+                    }),
+                    Box::new(ArrayAppend {
+                        array: 0,
+                        value: INT,
+                    }),
+                    Box::new(NewDictionary {
+                        key_type: StaticType::String,
+                        value_type: StaticType::Int,
+                        entries: vec![],
+                        result: 1,
+                    }),
+                    Box::new(ConstantLoad {
+                        index: 0,
+                        result: 2,
+                    }),
+                    Box::new(DictionaryInsert {
+                        dictionary: 1,
+                        key: KEY,
+                        value: INT,
+                        result: 2,
+                    }),
+                    Box::new(ConstantLoad {
+                        index: 0,
+                        result: 2,
+                    }),
+                    Box::new(DictionaryInsert {
+                        dictionary: 1,
+                        key: KEY,
+                        value: INT,
+                        result: 2,
+                    }),
+                    Box::new(NewComposite {
+                        type_index: 0,
+                        result: 2,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // let xs: [Int] = []; while true { xs.append(0) }
+            function(
+                "grow",
+                StaticType::Void,
+                vec![
+                    Box::new(NewArray {
+                        element_type: StaticType::Int,
+                        elements: vec![],
+                        result: 0,
+                    }),
+                    Box::new(ArrayAppend {
+                        array: 0,
+                        value: INT,
+                    }),
+                    Box::new(Jump { target: 1 }),
+                ],
+            ),
+        ],
+        composite_types: vec![CompositeType {
+            identifier: "S".to_string(),
+            kind: CompositeKind::Structure,
+            conformances: vec![],
+            methods: vec![],
+            enum_info: None,
+        }],
+        constants: vec![
+            Constant::string("hello"),
+            Constant::fixed_size_int(FixedSizeIntValue::Int128(1)),
+        ],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_memory_usage() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm.invoke("constants", &[]);
+    assert_eq!(result, Ok(Value::String(StringValue::new("hello"))));
+
+    let usage = vm.memory_usage();
+    assert_eq!(usage.get(MemoryKind::Registers), 5);
+    assert_eq!(usage.get(MemoryKind::String), 5);
+    assert_eq!(usage.get(MemoryKind::BigInteger), 1);
+    assert_eq!(usage.total(), 11);
+
+    // The array has two elements, and the dictionary one entry.
+    assert!(vm.invoke("containers", &[]).is_ok());
+    let usage = vm.memory_usage();
+    assert_eq!(
+        usage.to_string(),
+        "registers: 5, strings: 0, arrays: 3, dictionaries: 2, composites: 1, \
+         big integers: 0, total: 11"
+    );
+}
+
+#[test]
+fn test_memory_weights() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let weights = MemoryWeights {
+        registers: 8,
+        string: 1,
+        array: 16,
+        dictionary: 32,
+        composite: 64,
+        big_integer: 128,
+    };
+    vm.set_memory_meter(MemoryMeter::new(weights, u64::MAX));
+
+    assert!(vm.invoke("containers", &[]).is_ok());
+    let usage = vm.memory_usage();
+    assert_eq!(usage.get(MemoryKind::Registers), 5 * 8);
+    assert_eq!(usage.get(MemoryKind::String), 5);
+    assert_eq!(usage.get(MemoryKind::Array), 3 * 16);
+    assert_eq!(usage.get(MemoryKind::Dictionary), 2 * 32);
+    assert_eq!(usage.get(MemoryKind::Composite), 64);
+    assert_eq!(usage.get(MemoryKind::BigInteger), 0);
+}
+
+#[test]
+fn test_memory_limit_exceeded() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+    vm.set_memory_meter(MemoryMeter::new(MemoryWeights::default(), 100));
+
+    let result = vm.invoke("grow", &[]);
+    assert_eq!(result, Err(VMError::MemoryLimitExceeded { limit: 100 }));
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "memory limit exceeded: limit 100"
+    );
+
+    // The registers, the array, and the elements appended until the limit was exceeded.
+    let usage = vm.memory_usage();
+    assert_eq!(usage.get(MemoryKind::Registers), 5);
+    assert_eq!(usage.get(MemoryKind::Array), 96);
+    assert_eq!(usage.total(), 101);
+
+    // Each invocation is metered separately.
+    // Constants are decoded on first use, so this invocation also allocates the string.
+    assert!(vm.invoke("containers", &[]).is_ok());
+    assert_eq!(vm.memory_usage().total(), 16);
+}
@@ -0,0 +1,306 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::opcodes::{
+    Call, GlobalFuncLoad, IntConstantLoad, IntLess, IntMove, OpCode, ReturnValue, Upcast,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::stdlib;
+use cadence_vm::runtime::types::StaticType;
+use cadence_vm::runtime::values::{IntValue, StringValue, Value};
+use cadence_vm::runtime::vm::VM;
+
+// Globals of the native functions, following the program's functions.
+const LOG: usize = 6;
+const ASSERT: usize = 7;
+const DOUBLE: usize = 8;
+const GET_CURRENT_BLOCK: usize = 9;
+const GREETING: usize = 10;
+const PANIC: usize = 11;
+
+fn function(name: &str, parameters: Vec<Parameter>, code: Vec<Box<dyn OpCode>>) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters,
+        local_count: RegisterCounts {
+            ints: 2,
+            bools: 1,
+            funcs: 2,
+            values: 2,
+        },
+        return_type: StaticType::Int,
+        code,
+        ..Default::default()
+    }
+}
+
+fn test_program() -> Program {
+    Program {
+        functions: vec![
+            // log(n)
+            // return n
+            function(
+                "logAndReturn",
+                vec![Parameter::unlabeled("n", StaticType::Int)],
+                vec![
+                    Box::new(Upcast {
+                        typ: RegisterType::Int,
+                        value: 0,
+                        result: 1,
+                    }),
+                    Box::new(GlobalFuncLoad {
+                        index: LOG,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Value],
+                        result: 1,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // assert(n < 10)
+            // return n
+            function(
+                "assertLessThanTen",
+                vec![Parameter::unlabeled("n", StaticType::Int)],
+                vec![
+                    Box::new(IntConstantLoad {
+                        index: 0,
+                        target: 1,
+                    }),
+                    Box::new(IntLess {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 0,
+                    }),
+                    Box::new(GlobalFuncLoad {
+                        index: ASSERT,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Bool],
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // fun apply(_ f: fun(Int): Int, _ x: Int): Int {
+            //     return f(x)
+            // }
+            function(
+                "apply",
+                vec![
+                    Parameter::unlabeled("f", StaticType::Function),
+                    Parameter::unlabeled("x", StaticType::Int),
+                ],
+                vec![
+                    Box::new(IntMove { from: 0, to: 1 }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Int],
+                        result: 1,
+                    }),
+                    Box::new(ReturnValue { index: 1 }),
+                ],
+            ),
+            // return apply(double, n)
+            function(
+                "applyDouble",
+                vec![Parameter::unlabeled("n", StaticType::Int)],
+                vec![
+                    Box::new(GlobalFuncLoad {
+                        index: 2,
+                        result: 0,
+                    }),
+                    Box::new(GlobalFuncLoad {
+                        index: DOUBLE,
+                        result: 1,
+                    }),
+                    Box::new(IntMove { from: 0, to: 1 }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Func, RegisterType::Int],
+                        result: 1,
+                    }),
+                    Box::new(ReturnValue { index: 1 }),
+                ],
+            ),
+            // getCurrentBlock()
+            // return n
+            function(
+                "callUnregistered",
+                vec![],
+                vec![
+                    Box::new(GlobalFuncLoad {
+                        index: GET_CURRENT_BLOCK,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![],
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            // panic(greeting())
+            function(
+                "panicWithGreeting",
+                vec![],
+                vec![
+                    Box::new(GlobalFuncLoad {
+                        index: GREETING,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![],
+                        result: 1,
+                    }),
+                    Box::new(GlobalFuncLoad {
+                        index: PANIC,
+                        result: 1,
+                    }),
+                    Box::new(Call {
+                        func_index: 1,
+                        arguments: vec![RegisterType::Value],
+                        result: 1,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+        ],
+        constants: vec![Constant::int(10)],
+        native_functions: vec![
+            "log".to_string(),
+            "assert".to_string(),
+            "double".to_string(),
+            "getCurrentBlock".to_string(),
+            "greeting".to_string(),
+            "panic".to_string(),
+        ],
+        ..Default::default()
+    }
+}
+
+fn new_vm(program: &Program) -> VM<'_> {
+    let mut vm = VM::new(program);
+
+    stdlib::register_standard_functions(&mut vm);
+
+    vm.register_native_function(
+        "double",
+        RegisterType::Int,
+        |_, arguments| match arguments[0] {
+            Value::Int(n) => Ok(Value::Int(IntValue { value: n.value * 2 })),
+            _ => unreachable!(),
+        },
+    );
+
+    vm.register_native_function("greeting", RegisterType::Value, |_, _| {
+        Ok(Value::String(StringValue::new("hello")))
+    });
+
+    vm
+}
+
+#[test]
+fn test_native_function_with_host_state() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let logs = Rc::new(RefCell::new(Vec::new()));
+    let log_messages = logs.clone();
+    stdlib::register_log_function(&mut vm, move |message| {
+        log_messages.borrow_mut().push(message.to_string());
+    });
+
+    let result = vm
+        .invoke("logAndReturn", &[Value::Int(IntValue { value: 42 })])
+        .unwrap();
+
+    assert_eq!(result, Value::Int(IntValue { value: 42 }));
+    assert_eq!(*logs.borrow(), vec!["42".to_string()]);
+}
+
+#[test]
+fn test_assert() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let result = vm
+        .invoke("assertLessThanTen", &[Value::Int(IntValue { value: 5 })])
+        .unwrap();
+    assert_eq!(result, Value::Int(IntValue { value: 5 }));
+
+    let err = vm
+        .invoke("assertLessThanTen", &[Value::Int(IntValue { value: 15 })])
+        .unwrap_err();
+    assert_eq!(
+        err,
+        VMError::AssertionFailure {
+            message: String::new()
+        }
+    );
+}
+
+#[test]
+fn test_native_function_as_argument() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let result = vm
+        .invoke("applyDouble", &[Value::Int(IntValue { value: 21 })])
+        .unwrap();
+
+    assert_eq!(result, Value::Int(IntValue { value: 42 }));
+}
+
+#[test]
+fn test_unregistered_native_function() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let err = vm.invoke("callUnregistered", &[]).unwrap_err();
+
+    assert_eq!(
+        err,
+        VMError::UndefinedFunction {
+            name: "getCurrentBlock".to_string()
+        }
+    );
+}
+
+#[test]
+fn test_panic() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let err = vm.invoke("panicWithGreeting", &[]).unwrap_err();
+
+    assert_eq!(err.to_string(), "panic: hello");
+}
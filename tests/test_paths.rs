@@ -0,0 +1,176 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::{Constant, ConstantKind};
+use cadence_vm::runtime::encoding::DecodeError;
+use cadence_vm::runtime::opcodes::{Call, ConstantLoad, GlobalFuncLoad, ReturnValue};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::stdlib;
+use cadence_vm::runtime::types::{StaticType, TypeRegistry};
+use cadence_vm::runtime::values::{PathDomain, PathValue, StringValue, Value};
+use cadence_vm::runtime::vm::VM;
+
+fn path(domain: PathDomain, identifier: &str) -> PathValue {
+    PathValue::new(domain, identifier).unwrap()
+}
+
+#[test]
+fn test_path_display_and_type() {
+    let storage = path(PathDomain::Storage, "flowTokenVault");
+    assert_eq!(storage.to_string(), "/storage/flowTokenVault");
+    assert_eq!(storage.static_type(), StaticType::StoragePath);
+
+    let public = path(PathDomain::Public, "receiver_1");
+    assert_eq!(public.to_string(), "/public/receiver_1");
+    assert_eq!(public.static_type(), StaticType::PublicPath);
+
+    assert_eq!(
+        PathDomain::from_identifier("private"),
+        Some(PathDomain::Private)
+    );
+    assert_eq!(PathDomain::from_identifier("account"), None);
+}
+
+#[test]
+fn test_invalid_path_identifiers() {
+    for identifier in ["", "1vault", "my vault", "vault/x", "é"] {
+        assert_eq!(PathValue::new(PathDomain::Storage, identifier), None);
+    }
+    assert!(PathValue::new(PathDomain::Storage, "_vault2").is_some());
+}
+
+#[test]
+fn test_path_subtyping() {
+    let types = TypeRegistry::new();
+
+    assert!(types.is_subtype(&StaticType::StoragePath, &StaticType::Path));
+    assert!(types.is_subtype(&StaticType::PublicPath, &StaticType::CapabilityPath));
+    assert!(types.is_subtype(&StaticType::PrivatePath, &StaticType::Path));
+    assert!(types.is_subtype(&StaticType::PublicPath, &StaticType::AnyStruct));
+    assert!(!types.is_subtype(&StaticType::StoragePath, &StaticType::CapabilityPath));
+    assert!(!types.is_subtype(&StaticType::Path, &StaticType::StoragePath));
+}
+
+#[test]
+fn test_path_constant() {
+    let value = path(PathDomain::Public, "vault");
+    let constant = Constant::path(&value);
+    assert_eq!(constant.decode(), Ok(Value::Path(value)));
+
+    let invalid_domain = Constant {
+        kind: ConstantKind::Path,
+        data: vec![0, 1, b'a'],
+    };
+    assert_eq!(invalid_domain.decode(), Err(DecodeError::InvalidTag(0)));
+
+    let invalid_identifier = Constant {
+        kind: ConstantKind::Path,
+        data: vec![1, 1, b'1'],
+    };
+    assert_eq!(
+        invalid_identifier.decode(),
+        Err(DecodeError::InvalidIdentifier)
+    );
+}
+
+const STORAGE_PATH: usize = 2;
+
+fn test_program() -> Program {
+    let local_count = || RegisterCounts {
+        ints: 0,
+        bools: 0,
+        funcs: 1,
+        values: 2,
+    };
+
+    Program {
+        functions: vec![
+            // return /storage/vault
+            Function {
+                name: "vaultPath".to_string(),
+                return_type: StaticType::StoragePath,
+                local_count: local_count(),
+                code: vec![
+                    Box::new(ConstantLoad {
+                        index: 0,
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+                ..Default::default()
+            },
+            // return StoragePath(identifier: id)
+            Function {
+                name: "toStoragePath".to_string(),
+                parameters: vec![Parameter {
+                    label: Some("identifier".to_string()),
+                    identifier: "id".to_string(),
+                    typ: StaticType::String,
+                }],
+                return_type: StaticType::optional(StaticType::StoragePath),
+                local_count: RegisterCounts {
+                    values: 1,
+                    ..local_count()
+                },
+                code: vec![
+                    Box::new(GlobalFuncLoad {
+                        index: STORAGE_PATH,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Value],
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+                ..Default::default()
+            },
+        ],
+        constants: vec![Constant::path(&path(PathDomain::Storage, "vault"))],
+        native_functions: vec!["StoragePath".to_string()],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_load_path_constant() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm.invoke("vaultPath", &[]);
+    assert_eq!(result, Ok(Value::Path(path(PathDomain::Storage, "vault"))));
+}
+
+#[test]
+fn test_storage_path_conversion() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+    stdlib::register_standard_functions(&mut vm);
+
+    let result = vm.invoke("toStoragePath", &[Value::String(StringValue::new("vault"))]);
+    let expected = path(PathDomain::Storage, "vault");
+    assert_eq!(result, Ok(Value::some(Value::Path(expected))));
+
+    let result = vm.invoke(
+        "toStoragePath",
+        &[Value::String(StringValue::new("not a path"))],
+    );
+    assert_eq!(result, Ok(Value::Nil));
+}
@@ -0,0 +1,372 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::opcodes::{
+    Argument, InclusiveRangeContains, InclusiveRangeMember, InclusiveRangeMemberLoad, IntAdd,
+    IntConstantLoad, IterHasNext, IterNew, IterNext, Jump, JumpIfFalse, NewInclusiveRange, OpCode,
+    ReturnValue,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::storage::InMemoryStorage;
+use cadence_vm::runtime::types::StaticType;
+use cadence_vm::runtime::values::{
+    BoolValue, FixedSizeIntValue, InclusiveRangeValue, IntValue, IteratorValue, Value,
+};
+use cadence_vm::runtime::vm::VM;
+
+fn int<'a>(value: isize) -> Value<'a> {
+    Value::Int(IntValue { value })
+}
+
+fn fixed<'a>(value: FixedSizeIntValue) -> Value<'a> {
+    Value::FixedSizeInt(value)
+}
+
+fn range<'a>(
+    start: Value<'a>,
+    end: Value<'a>,
+    step: Option<Value<'a>>,
+) -> Result<InclusiveRangeValue, VMError> {
+    InclusiveRangeValue::new(&start, &end, step.as_ref())
+}
+
+fn elements<'a>(range: &InclusiveRangeValue) -> Vec<Value<'a>> {
+    let storage = InMemoryStorage::new();
+    let mut iterator = IteratorValue::new(&Value::InclusiveRange(range.clone()), &storage)
+        .unwrap()
+        .unwrap();
+    let mut elements = vec![];
+    while let Some(element) = iterator.next_element(&storage).unwrap() {
+        elements.push(element);
+    }
+    elements
+}
+
+#[test]
+fn test_range_default_step() {
+    let up = range(int(1), int(3), None).unwrap();
+    assert_eq!(up.step(), int(1));
+    assert_eq!(elements(&up), vec![int(1), int(2), int(3)]);
+
+    let down = range(int(3), int(1), None).unwrap();
+    assert_eq!(down.step(), int(-1));
+    assert_eq!(elements(&down), vec![int(3), int(2), int(1)]);
+
+    let single = range(int(5), int(5), None).unwrap();
+    assert_eq!(elements(&single), vec![int(5)]);
+}
+
+#[test]
+fn test_range_step() {
+    let up = range(int(0), int(10), Some(int(4))).unwrap();
+    assert_eq!(elements(&up), vec![int(0), int(4), int(8)]);
+    assert_eq!(up.start(), int(0));
+    assert_eq!(up.end(), int(10));
+    assert_eq!(up.step(), int(4));
+
+    let down = range(int(10), int(-5), Some(int(-5))).unwrap();
+    assert_eq!(elements(&down), vec![int(10), int(5), int(0), int(-5)]);
+}
+
+#[test]
+fn test_range_at_type_bounds() {
+    use FixedSizeIntValue::*;
+
+    // Stepping past the end of the type's range ends the iteration.
+    let up = range(fixed(UInt8(250)), fixed(UInt8(255)), Some(fixed(UInt8(5)))).unwrap();
+    assert_eq!(elements(&up), vec![fixed(UInt8(250)), fixed(UInt8(255))]);
+
+    let down = range(
+        fixed(Int8(-120)),
+        fixed(Int8(-128)),
+        Some(fixed(Int8(-128))),
+    )
+    .unwrap();
+    assert_eq!(down.step(), fixed(Int8(-128)));
+    assert_eq!(elements(&down), vec![fixed(Int8(-120))]);
+
+    let full = range(fixed(Int8(-128)), fixed(Int8(127)), Some(fixed(Int8(127)))).unwrap();
+    assert_eq!(
+        elements(&full),
+        vec![fixed(Int8(-128)), fixed(Int8(-1)), fixed(Int8(126))]
+    );
+
+    let top = range(
+        fixed(UInt128(u128::MAX - 1)),
+        fixed(UInt128(u128::MAX)),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        elements(&top),
+        vec![fixed(UInt128(u128::MAX - 1)), fixed(UInt128(u128::MAX))]
+    );
+    assert!(top.contains(&fixed(UInt128(u128::MAX))));
+}
+
+#[test]
+fn test_range_contains() {
+    let up = range(int(1), int(10), Some(int(3))).unwrap();
+    let contained: Vec<isize> = (-2..13).filter(|n| up.contains(&int(*n))).collect();
+    assert_eq!(contained, vec![1, 4, 7, 10]);
+
+    let down = range(int(5), int(-5), Some(int(-5))).unwrap();
+    let contained: Vec<isize> = (-7..8).filter(|n| down.contains(&int(*n))).collect();
+    assert_eq!(contained, vec![-5, 0, 5]);
+
+    // Values of other types are not contained.
+    assert!(!up.contains(&fixed(FixedSizeIntValue::Int64(4))));
+}
+
+#[test]
+fn test_range_construction_errors() {
+    let error = range(int(1), int(10), Some(int(0))).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "InclusiveRange construction failed: step value cannot be zero"
+    );
+
+    let error = range(int(1), int(10), Some(int(-1))).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "InclusiveRange construction failed: sequence is non-terminating as the difference \
+         between the end (10) and start (1) does not have the same sign as the step (-1)"
+    );
+
+    // Unsigned ranges cannot count down.
+    use FixedSizeIntValue::UInt8;
+    let error = range(fixed(UInt8(10)), fixed(UInt8(1)), None).unwrap_err();
+    assert!(matches!(error, VMError::InclusiveRangeConstruction { .. }));
+
+    let error = range(int(1), fixed(FixedSizeIntValue::Int8(2)), None).unwrap_err();
+    assert!(matches!(error, VMError::InclusiveRangeConstruction { .. }));
+}
+
+fn test_program() -> Program {
+    let local_count = || RegisterCounts {
+        ints: 4,
+        bools: 1,
+        funcs: 0,
+        values: 2,
+    };
+    let int_argument = |index| Argument {
+        typ: RegisterType::Int,
+        index,
+    };
+
+    Program {
+        functions: vec![
+            // var sum = 0; for i in InclusiveRange(a, b, step: s) { sum = sum + i }; return sum
+            Function {
+                name: "sum".to_string(),
+                parameters: vec![
+                    Parameter::unlabeled("a", StaticType::Int),
+                    Parameter::unlabeled("b", StaticType::Int),
+                    Parameter::unlabeled("s", StaticType::Int),
+                ],
+                return_type: StaticType::Int,
+                local_count: local_count(),
+                code: vec![
+                    Box::new(NewInclusiveRange {
+                        start: int_argument(0),
+                        end: int_argument(1),
+                        step: Some(int_argument(2)),
+                        result: 0,
+                    }),
+                    Box::new(IntConstantLoad {
+                        index: 0,
+                        target: 3,
+                    }),
+                    Box::new(IterNew {
+                        iterable: 0,
+                        result: 1,
+                    }),
+                    Box::new(IterHasNext {
+                        iterator: 1,
+                        result: 0,
+                    }),
+                    Box::new(JumpIfFalse {
+                        condition: 0,
+                        target: 8,
+                    }),
+                    Box::new(IterNext {
+                        iterator: 1,
+                        typ: RegisterType::Int,
+                        result: 0,
+                    }),
+                    Box::new(IntAdd {
+                        left_operand: 3,
+                        right_operand: 0,
+                        result: 3,
+                    }),
+                    Box::new(Jump { target: 3 }),
+                    Box::new(ReturnValue { index: 3 }),
+                ],
+                ..Default::default()
+            },
+            // return InclusiveRange(a, b).contains(c)
+            Function {
+                name: "contains".to_string(),
+                parameters: vec![
+                    Parameter::unlabeled("a", StaticType::Int),
+                    Parameter::unlabeled("b", StaticType::Int),
+                    Parameter::unlabeled("c", StaticType::Int),
+                ],
+                return_type: StaticType::Bool,
+                local_count: local_count(),
+                code: vec![
+                    Box::new(NewInclusiveRange {
+                        start: int_argument(0),
+                        end: int_argument(1),
+                        step: None,
+                        result: 0,
+                    }),
+                    Box::new(InclusiveRangeContains {
+                        range: 0,
+                        value: int_argument(2),
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+                ..Default::default()
+            },
+            // return InclusiveRange(a, b).step
+            Function {
+                name: "step".to_string(),
+                parameters: vec![
+                    Parameter::unlabeled("a", StaticType::Int),
+                    Parameter::unlabeled("b", StaticType::Int),
+                ],
+                return_type: StaticType::Int,
+                local_count: local_count(),
+                code: vec![
+                    Box::new(NewInclusiveRange {
+                        start: int_argument(0),
+                        end: int_argument(1),
+                        step: None,
+                        result: 0,
+                    }),
+                    Box::new(InclusiveRangeMemberLoad {
+                        range: 0,
+                        member: InclusiveRangeMember::Step,
+                        typ: RegisterType::Int,
+                        result: 2,
+                    }),
+                    Box::new(ReturnValue { index: 2 }),
+                ],
+                ..Default::default()
+            },
+        ],
+        constants: vec![Constant::int(0)],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_iterate_range() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm.invoke("sum", &[int(1), int(100), int(1)]);
+    assert_eq!(result, Ok(int(5050)));
+
+    let result = vm.invoke("sum", &[int(10), int(0), int(-3)]);
+    assert_eq!(result, Ok(int(10 + 7 + 4 + 1)));
+
+    let result = vm.invoke("sum", &[int(1), int(100), int(0)]);
+    assert!(matches!(
+        result,
+        Err(VMError::InclusiveRangeConstruction { .. })
+    ));
+}
+
+#[test]
+fn test_range_members() {
+    let program = test_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm.invoke("contains", &[int(1), int(5), int(5)]);
+    assert_eq!(result, Ok(Value::Bool(BoolValue { value: true })));
+
+    let result = vm.invoke("contains", &[int(1), int(5), int(6)]);
+    assert_eq!(result, Ok(Value::Bool(BoolValue { value: false })));
+
+    let result = vm.invoke("step", &[int(5), int(1)]);
+    assert_eq!(result, Ok(int(-1)));
+}
+
+#[test]
+fn test_range_members_of_non_range() {
+    // return r.start, or r.contains(r), where r is not a range
+    let function = |name: &str, code: Vec<Box<dyn OpCode>>| Function {
+        name: name.to_string(),
+        parameters: vec![Parameter::unlabeled("r", StaticType::AnyStruct)],
+        return_type: StaticType::Int,
+        local_count: RegisterCounts {
+            ints: 1,
+            bools: 1,
+            funcs: 0,
+            values: 1,
+        },
+        code,
+        ..Default::default()
+    };
+    let program = Program {
+        functions: vec![
+            function(
+                "start",
+                vec![Box::new(InclusiveRangeMemberLoad {
+                    range: 0,
+                    member: InclusiveRangeMember::Start,
+                    typ: RegisterType::Int,
+                    result: 0,
+                })],
+            ),
+            function(
+                "contains",
+                vec![Box::new(InclusiveRangeContains {
+                    range: 0,
+                    value: Argument {
+                        typ: RegisterType::Value,
+                        index: 0,
+                    },
+                    result: 0,
+                })],
+            ),
+        ],
+        ..Default::default()
+    };
+    let mut vm = VM::new(&program);
+
+    for (name, instruction) in [
+        ("start", "InclusiveRangeMemberLoad"),
+        ("contains", "InclusiveRangeContains"),
+    ] {
+        let result = vm.invoke(name, &[int(1)]);
+        assert_eq!(
+            result,
+            Err(VMError::OperandTypeMismatch {
+                instruction,
+                actual_type: StaticType::Int,
+            })
+        );
+    }
+}
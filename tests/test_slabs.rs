@@ -0,0 +1,688 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+mod common;
+
+use std::cell::Cell;
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::file_storage::FileStorage;
+use cadence_vm::runtime::metering::{MemoryKind, MemoryMeter, MemoryWeights};
+use cadence_vm::runtime::opcodes::{
+    Argument, ArrayAppend, Call, ConstantLoad, DictionaryInsert, GlobalFuncLoad, IntAdd,
+    IntConstantLoad, IterHasNext, IterNew, IterNext, Jump, JumpIfFalse, NewArray, OpCode,
+    ReturnValue,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::slabs::{StoredArrayValue, StoredDictionaryValue};
+use cadence_vm::runtime::stdlib;
+use cadence_vm::runtime::storage::{InMemoryStorage, Storage, StorageKey};
+use cadence_vm::runtime::types::{Authorization, StaticType};
+use cadence_vm::runtime::values::{
+    AddressValue, ArrayValue, DictionaryValue, IteratorValue, PathDomain, PathValue, StringValue,
+    Value,
+};
+use cadence_vm::runtime::vm::VM;
+use common::{account, int, parameter, storage_path, ADDRESS};
+
+fn string<'a>(value: &str) -> Value<'a> {
+    Value::String(StringValue::new(value))
+}
+
+/// Storage that counts the reads and writes of slabs.
+#[derive(Default)]
+struct CountingStorage<'a> {
+    storage: InMemoryStorage<'a>,
+    reads: Cell<usize>,
+    writes: usize,
+}
+
+impl<'a> CountingStorage<'a> {
+    fn reset(&mut self) {
+        self.reads.set(0);
+        self.writes = 0;
+    }
+
+    fn slab_count(&self) -> usize {
+        self.storage
+            .entries()
+            .filter(|(_, key, _)| matches!(key, StorageKey::Slab(_)))
+            .count()
+    }
+}
+
+impl<'a> Storage<'a> for CountingStorage<'a> {
+    fn read(&self, address: AddressValue, key: &StorageKey) -> Option<Value<'a>> {
+        if matches!(key, StorageKey::Slab(_)) {
+            self.reads.set(self.reads.get() + 1);
+        }
+        self.storage.read(address, key)
+    }
+
+    fn write(&mut self, address: AddressValue, key: &StorageKey, value: Option<Value<'a>>) {
+        if matches!(key, StorageKey::Slab(_)) {
+            self.writes += 1;
+        }
+        self.storage.write(address, key, value);
+    }
+
+    fn paths(&self, address: AddressValue, domain: PathDomain) -> Vec<PathValue> {
+        self.storage.paths(address, domain)
+    }
+}
+
+#[test]
+fn test_large_stored_array() {
+    const COUNT: usize = 300_000;
+
+    let mut storage = CountingStorage::default();
+    let array = StoredArrayValue::new(&mut storage, ADDRESS, StaticType::Int, vec![]).unwrap();
+    for i in 0..COUNT {
+        array.append(&mut storage, int(i as isize)).unwrap();
+    }
+    assert_eq!(array.len(&storage).unwrap(), COUNT);
+    for i in [0, 1, 63, 64, 4095, 4096, 150_000, COUNT - 1] {
+        assert_eq!(array.get(&storage, i).unwrap(), Some(int(i as isize)));
+    }
+    assert_eq!(array.get(&storage, COUNT).unwrap(), None);
+
+    // The elements are split into slabs, of which an append only touches the path to the end.
+    assert!(storage.slab_count() > COUNT / 64);
+    storage.reset();
+    array.append(&mut storage, int(COUNT as isize)).unwrap();
+    assert!(storage.reads.get() <= 8, "{} reads", storage.reads.get());
+    assert!(storage.writes <= 6, "{} writes", storage.writes);
+
+    storage.reset();
+    assert_eq!(array.get(&storage, 123_456).unwrap(), Some(int(123_456)));
+    assert!(storage.reads.get() <= 4, "{} reads", storage.reads.get());
+
+    storage.reset();
+    assert_eq!(array.set(&mut storage, 7, int(-7)).unwrap(), Some(int(7)));
+    array.insert(&mut storage, 0, int(-1)).unwrap();
+    assert_eq!(
+        array.remove(&mut storage, 200_001).unwrap(),
+        Some(int(200_000))
+    );
+    assert!(storage.writes <= 20, "{} writes", storage.writes);
+
+    let mut expected: Vec<Value> = (0..=COUNT as isize).map(int).collect();
+    expected[7] = int(-7);
+    expected.insert(0, int(-1));
+    expected.remove(200_001);
+    assert_eq!(array.elements(&storage).unwrap(), expected);
+
+    array.delete(&mut storage).unwrap();
+    assert_eq!(storage.slab_count(), 0);
+
+    // Removing all elements removes all slabs but the root.
+    let elements = (0..5000).map(int).collect();
+    let array = StoredArrayValue::new(&mut storage, ADDRESS, StaticType::Int, elements).unwrap();
+    while !array.is_empty(&storage).unwrap() {
+        let index = array.len(&storage).unwrap() / 2;
+        array.remove(&mut storage, index).unwrap();
+    }
+    assert_eq!(storage.slab_count(), 1);
+}
+
+#[test]
+fn test_large_stored_dictionary() {
+    const COUNT: isize = 200_000;
+
+    let mut storage = CountingStorage::default();
+    let dictionary = StoredDictionaryValue::new(
+        &mut storage,
+        ADDRESS,
+        StaticType::Int,
+        StaticType::Int,
+        vec![],
+    )
+    .unwrap();
+    for i in 0..COUNT {
+        assert_eq!(
+            dictionary.insert(&mut storage, int(i), int(i * 2)).unwrap(),
+            None
+        );
+    }
+    assert_eq!(dictionary.len(&storage).unwrap(), COUNT as usize);
+    for i in [0, 1, 64, 99_999, COUNT - 1] {
+        assert_eq!(dictionary.get(&storage, &int(i)).unwrap(), Some(int(i * 2)));
+    }
+    assert_eq!(dictionary.get(&storage, &int(COUNT)).unwrap(), None);
+
+    // An insertion only touches the path to the key's leaf.
+    assert!(storage.slab_count() > COUNT as usize / 64);
+    storage.reset();
+    assert_eq!(
+        dictionary.insert(&mut storage, int(5), int(-5)).unwrap(),
+        Some(int(10))
+    );
+    assert!(storage.reads.get() <= 6, "{} reads", storage.reads.get());
+    assert!(storage.writes <= 6, "{} writes", storage.writes);
+
+    for i in (0..COUNT).step_by(2) {
+        assert!(dictionary.remove(&mut storage, &int(i)).unwrap().is_some());
+    }
+    assert_eq!(dictionary.remove(&mut storage, &int(0)).unwrap(), None);
+    assert_eq!(dictionary.len(&storage).unwrap(), COUNT as usize / 2);
+    assert_eq!(dictionary.get(&storage, &int(5)).unwrap(), Some(int(-5)));
+    assert_eq!(dictionary.get(&storage, &int(6)).unwrap(), None);
+
+    // Iteration visits each key once, reading the slabs as it reaches them.
+    let value = Value::StoredDictionary(dictionary.clone());
+    let mut iterator = IteratorValue::new(&value, &storage).unwrap().unwrap();
+    let mut keys = vec![];
+    while iterator.has_next(&storage).unwrap() {
+        let key = iterator.next_element(&storage).unwrap().unwrap();
+        keys.push(key.to_i128().unwrap() as isize);
+    }
+    keys.sort();
+    assert_eq!(keys, (1..COUNT).step_by(2).collect::<Vec<_>>());
+
+    let entries = dictionary.entries(&storage).unwrap();
+    assert_eq!(entries.len(), COUNT as usize / 2);
+    dictionary.delete(&mut storage).unwrap();
+    assert_eq!(storage.slab_count(), 0);
+}
+
+#[test]
+fn test_stored_dictionary_mutation_during_iteration() {
+    let mut storage = InMemoryStorage::new();
+    let entries = (0..100).map(|i| (int(i), int(i))).collect();
+    let dictionary = StoredDictionaryValue::new(
+        &mut storage,
+        ADDRESS,
+        StaticType::Int,
+        StaticType::Int,
+        entries,
+    )
+    .unwrap();
+
+    let value = Value::StoredDictionary(dictionary.clone());
+    let mut iterator = IteratorValue::new(&value, &storage).unwrap().unwrap();
+    assert!(iterator.next_element(&storage).unwrap().is_some());
+
+    dictionary.insert(&mut storage, int(100), int(100)).unwrap();
+    assert_eq!(
+        iterator.has_next(&storage),
+        Err(VMError::ContainerMutatedDuringIteration)
+    );
+
+    // Removing the dictionary is a mutation too.
+    let mut iterator = IteratorValue::new(&value, &storage).unwrap().unwrap();
+    dictionary.delete(&mut storage).unwrap();
+    assert_eq!(
+        iterator.next_element(&storage),
+        Err(VMError::ContainerMutatedDuringIteration)
+    );
+}
+
+#[test]
+fn test_corrupted_slabs() {
+    let mut storage = InMemoryStorage::new();
+    let elements = (0..100).map(int).collect();
+    let array = StoredArrayValue::new(&mut storage, ADDRESS, StaticType::Int, elements).unwrap();
+
+    // A missing child slab fails the reads that reach it.
+    storage.write(ADDRESS, &StorageKey::Slab(array.root + 1), None);
+    assert_eq!(
+        array.elements(&storage),
+        Err(VMError::CorruptedStorage {
+            address: ADDRESS,
+            message: format!("missing slab {}", array.root + 1),
+        })
+    );
+
+    // So does a value that is not a slab.
+    storage.write(ADDRESS, &StorageKey::Slab(array.root), Some(int(1)));
+    assert_eq!(
+        array.append(&mut storage, int(100)),
+        Err(VMError::CorruptedStorage {
+            address: ADDRESS,
+            message: format!("expected slab {}, got `1`", array.root),
+        })
+    );
+
+    // And a slab of the wrong kind.
+    let dictionary = StoredDictionaryValue::new(
+        &mut storage,
+        ADDRESS,
+        StaticType::Int,
+        StaticType::Int,
+        vec![],
+    )
+    .unwrap();
+    let handle = StoredArrayValue {
+        root: dictionary.root,
+        ..array
+    };
+    assert!(matches!(
+        handle.len(&storage),
+        Err(VMError::CorruptedStorage { .. })
+    ));
+}
+
+/*
+*  Stored collections in programs
+*/
+
+const fn argument(typ: RegisterType, index: usize) -> Argument {
+    Argument { typ, index }
+}
+
+// Program functions
+const FUNCTION_COUNT: usize = 8;
+
+// Natives, following the functions
+const NATIVE_SAVE: usize = FUNCTION_COUNT;
+const NATIVE_BORROW: usize = FUNCTION_COUNT + 1;
+const NATIVE_LOAD: usize = FUNCTION_COUNT + 2;
+const NATIVE_PANIC: usize = FUNCTION_COUNT + 3;
+
+// Constants
+const ZERO: usize = 0;
+const ARRAY_REFERENCE_TYPE: usize = 1;
+const ARRAY_TYPE: usize = 2;
+const DICTIONARY_REFERENCE_TYPE: usize = 3;
+const FAILED: usize = 4;
+
+fn array_type() -> StaticType {
+    StaticType::array(StaticType::Int)
+}
+
+fn dictionary_type() -> StaticType {
+    StaticType::dictionary(StaticType::String, StaticType::Int)
+}
+
+fn reference_type(typ: StaticType) -> StaticType {
+    StaticType::reference(Authorization::Unauthorized, typ)
+}
+
+fn function(
+    name: &str,
+    parameters: Vec<Parameter>,
+    return_type: StaticType,
+    code: Vec<Box<dyn OpCode>>,
+) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters,
+        return_type,
+        local_count: RegisterCounts {
+            ints: 2,
+            bools: 1,
+            funcs: 1,
+            values: 4,
+        },
+        code,
+        ..Default::default()
+    }
+}
+
+/// Returns a function that calls the account storage function with the account and path,
+/// followed by the value to save or the type constant, and returns the result.
+/// The arguments are all the value registers, and the result replaces the first.
+fn storage_function(name: &str, native: usize, typ: Result<StaticType, usize>) -> Function {
+    let account = parameter("account", StaticType::Account);
+    let path = parameter("path", StaticType::StoragePath);
+    let (parameters, mut code): (_, Vec<Box<dyn OpCode>>) = match typ {
+        Ok(typ) => (vec![account, parameter("value", typ), path], vec![]),
+        Err(type_constant) => (
+            vec![account, path],
+            vec![Box::new(ConstantLoad {
+                index: type_constant,
+                result: 2,
+            })],
+        ),
+    };
+    code.push(Box::new(GlobalFuncLoad {
+        index: native,
+        result: 0,
+    }));
+    code.push(Box::new(Call {
+        func_index: 0,
+        arguments: vec![RegisterType::Value; 3],
+        result: 0,
+    }));
+    code.push(Box::new(ReturnValue { index: 0 }));
+    let function = function(name, parameters, StaticType::AnyStruct, code);
+    Function {
+        local_count: RegisterCounts {
+            values: 3,
+            ..function.local_count
+        },
+        ..function
+    }
+}
+
+/// Returns the code, followed by a loop over the collection in register 0
+/// with the body, which gets the element in the given register, followed by the end.
+fn with_loop(
+    mut code: Vec<Box<dyn OpCode>>,
+    element: Argument,
+    body: Vec<Box<dyn OpCode>>,
+    end: Vec<Box<dyn OpCode>>,
+) -> Vec<Box<dyn OpCode>> {
+    let start = code.len() + 1;
+    let exit = start + 4 + body.len();
+    code.push(Box::new(IterNew {
+        iterable: 0,
+        result: 1,
+    }));
+    code.push(Box::new(IterHasNext {
+        iterator: 1,
+        result: 0,
+    }));
+    code.push(Box::new(JumpIfFalse {
+        condition: 0,
+        target: exit,
+    }));
+    code.push(Box::new(IterNext {
+        iterator: 1,
+        typ: element.typ,
+        result: element.index,
+    }));
+    code.extend(body);
+    code.push(Box::new(Jump { target: start }));
+    code.extend(end);
+    code
+}
+
+fn test_program() -> Program {
+    let array_reference = || parameter("xs", reference_type(array_type()));
+    let dictionary_reference = || parameter("d", reference_type(dictionary_type()));
+
+    Program {
+        functions: vec![
+            storage_function("save", NATIVE_SAVE, Ok(StaticType::AnyStruct)),
+            storage_function("borrowArray", NATIVE_BORROW, Err(ARRAY_REFERENCE_TYPE)),
+            storage_function(
+                "borrowDictionary",
+                NATIVE_BORROW,
+                Err(DICTIONARY_REFERENCE_TYPE),
+            ),
+            storage_function("load", NATIVE_LOAD, Err(ARRAY_TYPE)),
+            // xs.append(x)
+            function(
+                "append",
+                vec![array_reference(), parameter("x", StaticType::Int)],
+                StaticType::Void,
+                vec![Box::new(ArrayAppend {
+                    array: 0,
+                    value: argument(RegisterType::Int, 0),
+                })],
+            ),
+            // xs.append(x); panic("failed")
+            function(
+                "appendAndFail",
+                vec![array_reference(), parameter("x", StaticType::Int)],
+                StaticType::Void,
+                vec![
+                    Box::new(ArrayAppend {
+                        array: 0,
+                        value: argument(RegisterType::Int, 0),
+                    }),
+                    Box::new(GlobalFuncLoad {
+                        index: NATIVE_PANIC,
+                        result: 0,
+                    }),
+                    Box::new(ConstantLoad {
+                        index: FAILED,
+                        result: 3,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Value],
+                        result: 3,
+                    }),
+                ],
+            ),
+            // var sum = 0; for x in xs { sum = sum + x }; return sum
+            function(
+                "sum",
+                vec![array_reference()],
+                StaticType::Int,
+                with_loop(
+                    vec![Box::new(IntConstantLoad {
+                        index: ZERO,
+                        target: 0,
+                    })],
+                    argument(RegisterType::Int, 1),
+                    vec![Box::new(IntAdd {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 0,
+                    })],
+                    vec![Box::new(ReturnValue { index: 0 })],
+                ),
+            ),
+            // d.insert(key: k, v); var keys: [String] = []; for k in d { keys.append(k) }; return keys
+            function(
+                "insertAndCollectKeys",
+                vec![
+                    dictionary_reference(),
+                    parameter("k", StaticType::String),
+                    parameter("v", StaticType::Int),
+                ],
+                StaticType::array(StaticType::String),
+                with_loop(
+                    vec![
+                        Box::new(DictionaryInsert {
+                            dictionary: 0,
+                            key: argument(RegisterType::Value, 1),
+                            value: argument(RegisterType::Int, 0),
+                            result: 3,
+                        }),
+                        Box::new(NewArray {
+                            element_type: StaticType::String,
+                            elements: vec![],
+                            result: 2,
+                        }),
+                    ],
+                    argument(RegisterType::Value, 3),
+                    vec![Box::new(ArrayAppend {
+                        array: 2,
+                        value: argument(RegisterType::Value, 3),
+                    })],
+                    vec![Box::new(ReturnValue { index: 2 })],
+                ),
+            ),
+        ],
+        constants: vec![
+            Constant::int(0),
+            Constant::type_literal(&reference_type(array_type())),
+            Constant::type_literal(&array_type()),
+            Constant::type_literal(&reference_type(dictionary_type())),
+            Constant::string("failed"),
+        ],
+        native_functions: vec![
+            "Account.Storage.save".to_string(),
+            "Account.Storage.borrow".to_string(),
+            "Account.Storage.load".to_string(),
+            "panic".to_string(),
+        ],
+        ..Default::default()
+    }
+}
+
+fn new_vm(program: &Program) -> VM<'_> {
+    let mut vm = VM::new(program);
+    stdlib::register_standard_functions(&mut vm);
+    stdlib::register_account_functions(&mut vm);
+    vm
+}
+
+fn borrowed<'a>(vm: &mut VM<'a>, function: &str, identifier: &str) -> Value<'a> {
+    match vm.invoke(function, &[account(), storage_path(identifier)]) {
+        Ok(Value::Some(reference)) => *reference,
+        result => panic!("expected reference, got {:?}", result),
+    }
+}
+
+fn slab_count(vm: &VM) -> usize {
+    let storage = vm.storage.storage();
+    (1..=1000)
+        .filter(|id| storage.read(ADDRESS, &StorageKey::Slab(*id)).is_some())
+        .count()
+}
+
+#[test]
+fn test_stored_array_in_program() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let elements = (0..1000).map(int).collect();
+    let array = Value::Array(ArrayValue::new(StaticType::Int, elements));
+    assert_eq!(
+        vm.invoke("save", &[account(), array, storage_path("xs")]),
+        Ok(Value::Void)
+    );
+    assert!(slab_count(&vm) > 1);
+
+    // The reference reads and writes the slabs of the stored array.
+    let xs = borrowed(&mut vm, "borrowArray", "xs");
+    assert!(matches!(vm.dereference(&xs), Ok(Value::StoredArray(_))));
+    assert_eq!(
+        vm.invoke("append", &[xs.clone(), int(1000)]),
+        Ok(Value::Void)
+    );
+    assert_eq!(
+        vm.invoke("sum", std::slice::from_ref(&xs)),
+        Ok(int(1001 * 500))
+    );
+
+    // The slab writes of a failed invocation are rolled back.
+    assert_eq!(
+        vm.invoke("appendAndFail", &[xs.clone(), int(1)]),
+        Err(VMError::Panic {
+            message: "failed".to_string()
+        })
+    );
+    assert_eq!(vm.invoke("sum", &[xs]), Ok(int(1001 * 500)));
+
+    // Loading reads the elements into memory, and removes the slabs.
+    let elements = (0..=1000).map(int).collect();
+    assert_eq!(
+        vm.invoke("load", &[account(), storage_path("xs")]),
+        Ok(Value::some(Value::Array(ArrayValue::new(
+            StaticType::Int,
+            elements
+        ))))
+    );
+    assert_eq!(slab_count(&vm), 0);
+}
+
+#[test]
+fn test_stored_array_append_memory_limit() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let array = Value::Array(ArrayValue::new(StaticType::Int, vec![int(1)]));
+    assert_eq!(
+        vm.invoke("save", &[account(), array, storage_path("xs")]),
+        Ok(Value::Void)
+    );
+    let xs = borrowed(&mut vm, "borrowArray", "xs");
+
+    // Appending to the stored array is charged like appending to an array in memory.
+    vm.set_memory_meter(MemoryMeter::new(MemoryWeights::default(), u64::MAX));
+    assert_eq!(vm.invoke("append", &[xs.clone(), int(2)]), Ok(Value::Void));
+    let usage = vm.memory_usage();
+    assert_eq!(usage.get(MemoryKind::Array), 1);
+
+    // Under a limit that leaves no room for the element, the append fails and is rolled back.
+    let limit = usage.total() - 1;
+    vm.set_memory_meter(MemoryMeter::new(MemoryWeights::default(), limit));
+    assert_eq!(
+        vm.invoke("append", &[xs.clone(), int(3)]),
+        Err(VMError::MemoryLimitExceeded { limit })
+    );
+    assert_eq!(vm.invoke("sum", &[xs]), Ok(int(3)));
+}
+
+#[test]
+fn test_stored_dictionary_in_program() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let entries = vec![(string("a"), int(1))];
+    let dictionary = DictionaryValue::new(StaticType::String, StaticType::Int, entries);
+    assert_eq!(
+        vm.invoke(
+            "save",
+            &[account(), Value::Dictionary(dictionary), storage_path("d")]
+        ),
+        Ok(Value::Void)
+    );
+
+    let d = borrowed(&mut vm, "borrowDictionary", "d");
+    let result = vm.invoke("insertAndCollectKeys", &[d.clone(), string("b"), int(2)]);
+    let keys = match result {
+        Ok(Value::Array(keys)) => keys.elements(),
+        result => panic!("expected array, got {:?}", result),
+    };
+    let mut keys: Vec<String> = keys.iter().map(Value::to_string).collect();
+    keys.sort();
+    assert_eq!(keys, ["\"a\"", "\"b\""]);
+
+    let stored = match vm.dereference(&d) {
+        Ok(Value::StoredDictionary(dictionary)) => dictionary,
+        value => panic!("expected stored dictionary, got {:?}", value),
+    };
+    assert_eq!(stored.get(&vm.storage, &string("b")).unwrap(), Some(int(2)));
+}
+
+#[test]
+fn test_stored_array_in_file_storage() {
+    const COUNT: isize = 100_000;
+
+    let path = std::env::temp_dir().join(format!("cadence-slabs-{}.store", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let array = {
+        let mut storage = FileStorage::open(&path).unwrap();
+        let elements = (0..COUNT).map(int).collect();
+        let array =
+            StoredArrayValue::new(&mut storage, ADDRESS, StaticType::Int, elements).unwrap();
+        storage.flush_log().unwrap();
+        array
+    };
+
+    // Appending to the reopened array only logs the touched slabs.
+    let size = std::fs::metadata(&path).unwrap().len();
+    {
+        let mut storage = FileStorage::open(&path).unwrap();
+        assert_eq!(array.len(&storage).unwrap(), COUNT as usize);
+        array.append(&mut storage, int(COUNT)).unwrap();
+        storage.flush_log().unwrap();
+    }
+    let appended = std::fs::metadata(&path).unwrap().len() - size;
+    assert!(appended < 4096, "{} bytes appended", appended);
+
+    let storage = FileStorage::open(&path).unwrap();
+    assert_eq!(array.len(&storage).unwrap(), COUNT as usize + 1);
+    assert_eq!(
+        array.get(&storage, COUNT as usize).unwrap(),
+        Some(int(COUNT))
+    );
+    assert_eq!(array.get(&storage, 54_321).unwrap(), Some(int(54_321)));
+
+    std::fs::remove_file(&path).unwrap();
+}
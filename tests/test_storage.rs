@@ -0,0 +1,716 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program, Variable};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::{StackFrame, VMError};
+use cadence_vm::runtime::opcodes::{
+    Call, GlobalFuncLoad, GlobalLoad, GlobalStore, IntAdd, IntConstantLoad, Move, OpCode,
+    ReturnValue, True,
+};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::stdlib;
+use cadence_vm::runtime::storage::{Storage, StorageKey};
+use cadence_vm::runtime::types::{Authorization, CompositeKind, CompositeType, StaticType};
+use cadence_vm::runtime::values::{
+    BoolValue, CompositeValue, FunctionValue, IntValue, NativeFunctionValue, PathDomain, PathValue,
+    TypeValue, Value,
+};
+use cadence_vm::runtime::vm::VM;
+use common::{account, forward, parameter, path, storage_path, structure, ADDRESS};
+
+// Program functions
+const COUNT: usize = 9;
+const FUNCTION_COUNT: usize = 12;
+
+// Natives, following the functions
+const NATIVE_SAVE: usize = FUNCTION_COUNT;
+const NATIVE_LOAD: usize = FUNCTION_COUNT + 1;
+const NATIVE_COPY: usize = FUNCTION_COUNT + 2;
+const NATIVE_BORROW: usize = FUNCTION_COUNT + 3;
+const NATIVE_TYPE: usize = FUNCTION_COUNT + 4;
+const NATIVE_CHECK: usize = FUNCTION_COUNT + 5;
+const NATIVE_FOR_EACH_STORED: usize = FUNCTION_COUNT + 6;
+
+// Variables, following the natives
+const STORED_COUNT: usize = FUNCTION_COUNT + 7;
+
+// Constants
+const ONE: usize = 0;
+const R_TYPE: usize = 1;
+const R_REFERENCE_TYPE: usize = 2;
+const S_TYPE: usize = 3;
+
+fn r_type() -> StaticType {
+    StaticType::Composite("R".to_string())
+}
+
+fn s_type() -> StaticType {
+    StaticType::Composite("S".to_string())
+}
+
+fn function(
+    name: &str,
+    parameters: Vec<Parameter>,
+    return_type: StaticType,
+    values: usize,
+    code: Vec<Box<dyn OpCode>>,
+) -> Function {
+    Function {
+        name: name.to_string(),
+        parameters,
+        return_type,
+        local_count: RegisterCounts {
+            ints: 2,
+            bools: 1,
+            funcs: 2,
+            values,
+        },
+        code,
+        ..Default::default()
+    }
+}
+
+fn test_program() -> Program {
+    let account = || parameter("account", StaticType::Account);
+    let path = || parameter("path", StaticType::StoragePath);
+
+    Program {
+        functions: vec![
+            // account.storage.save(<-r, to: path); return <-r
+            function(
+                "saveResource",
+                vec![account(), parameter("r", r_type()), path()],
+                StaticType::AnyResource,
+                3,
+                vec![
+                    Box::new(GlobalFuncLoad {
+                        index: NATIVE_SAVE,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Value; 3],
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 1 }),
+                ],
+            ),
+            forward(
+                "saveStruct",
+                vec![account(), parameter("s", StaticType::AnyStruct), path()],
+                StaticType::Void,
+                NATIVE_SAVE,
+                None,
+            ),
+            forward(
+                "load",
+                vec![account(), path()],
+                StaticType::optional(r_type()),
+                NATIVE_LOAD,
+                Some(R_TYPE),
+            ),
+            forward(
+                "copy",
+                vec![account(), path()],
+                StaticType::optional(s_type()),
+                NATIVE_COPY,
+                Some(S_TYPE),
+            ),
+            forward(
+                "borrow",
+                vec![account(), path()],
+                StaticType::optional(StaticType::reference(Authorization::Unauthorized, r_type())),
+                NATIVE_BORROW,
+                Some(R_REFERENCE_TYPE),
+            ),
+            forward(
+                "type",
+                vec![account(), path()],
+                StaticType::optional(StaticType::MetaType),
+                NATIVE_TYPE,
+                None,
+            ),
+            forward(
+                "check",
+                vec![account(), path()],
+                StaticType::Bool,
+                NATIVE_CHECK,
+                Some(R_TYPE),
+            ),
+            // account.storage.forEachStored(f)
+            function(
+                "forEachStored",
+                vec![account(), parameter("f", StaticType::Function)],
+                StaticType::Void,
+                1,
+                vec![
+                    Box::new(Move {
+                        typ: RegisterType::Func,
+                        from: 0,
+                        to: 1,
+                    }),
+                    Box::new(GlobalFuncLoad {
+                        index: NATIVE_FOR_EACH_STORED,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Value, RegisterType::Func],
+                        result: 0,
+                    }),
+                ],
+            ),
+            // account.storage.forEachStored(count)
+            function(
+                "countStored",
+                vec![account()],
+                StaticType::Void,
+                1,
+                vec![
+                    Box::new(GlobalFuncLoad {
+                        index: COUNT,
+                        result: 1,
+                    }),
+                    Box::new(GlobalFuncLoad {
+                        index: NATIVE_FOR_EACH_STORED,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![RegisterType::Value, RegisterType::Func],
+                        result: 0,
+                    }),
+                ],
+            ),
+            // fun count(path: StoragePath, type: Type): Bool { storedCount = storedCount + 1; return true }
+            function(
+                "count",
+                vec![path(), parameter("type", StaticType::MetaType)],
+                StaticType::Bool,
+                2,
+                vec![
+                    Box::new(GlobalLoad {
+                        index: STORED_COUNT,
+                        typ: RegisterType::Int,
+                        result: 0,
+                    }),
+                    Box::new(IntConstantLoad {
+                        index: ONE,
+                        target: 1,
+                    }),
+                    Box::new(IntAdd {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 0,
+                    }),
+                    Box::new(GlobalStore {
+                        index: STORED_COUNT,
+                        typ: RegisterType::Int,
+                        value: 0,
+                    }),
+                    Box::new(True { index: 0 }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+            ),
+            forward(
+                "saveToPath",
+                vec![
+                    account(),
+                    parameter("s", StaticType::AnyStruct),
+                    parameter("path", StaticType::Path),
+                ],
+                StaticType::Void,
+                NATIVE_SAVE,
+                None,
+            ),
+            forward(
+                "borrowAsResource",
+                vec![account(), path()],
+                StaticType::optional(r_type()),
+                NATIVE_BORROW,
+                Some(R_TYPE),
+            ),
+        ],
+        constants: vec![
+            Constant::int(1),
+            Constant::type_literal(&r_type()),
+            Constant::type_literal(&StaticType::reference(
+                Authorization::Unauthorized,
+                r_type(),
+            )),
+            Constant::type_literal(&s_type()),
+        ],
+        composite_types: vec![
+            CompositeType {
+                identifier: "R".to_string(),
+                kind: CompositeKind::Resource,
+                conformances: vec![],
+                methods: vec![],
+                enum_info: None,
+            },
+            CompositeType {
+                identifier: "S".to_string(),
+                kind: CompositeKind::Structure,
+                conformances: vec![],
+                methods: vec![],
+                enum_info: None,
+            },
+        ],
+        native_functions: [
+            "save",
+            "load",
+            "copy",
+            "borrow",
+            "type",
+            "check",
+            "forEachStored",
+        ]
+        .iter()
+        .map(|name| format!("Account.Storage.{}", name))
+        .collect(),
+        variables: vec![Variable {
+            name: "storedCount".to_string(),
+            typ: StaticType::Int,
+        }],
+        ..Default::default()
+    }
+}
+
+fn new_vm(program: &Program) -> VM<'_> {
+    let mut vm = VM::new(program);
+    stdlib::register_account_functions(&mut vm);
+    vm
+}
+
+fn resource<'a>(id: isize) -> CompositeValue<'a> {
+    CompositeValue::new(
+        "R",
+        CompositeKind::Resource,
+        vec![("id", Value::Int(IntValue { value: id }))],
+    )
+}
+
+fn bool<'a>(value: bool) -> Value<'a> {
+    Value::Bool(BoolValue { value })
+}
+
+fn type_value<'a>(typ: StaticType) -> Value<'a> {
+    Value::Type(TypeValue { typ })
+}
+
+#[test]
+fn test_save_and_load_resource() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let r = resource(1);
+    let result = vm.invoke(
+        "saveResource",
+        &[account(), Value::Composite(r.clone()), storage_path("r")],
+    );
+    // The resource was moved out of its register.
+    assert_eq!(result, Ok(Value::Void));
+
+    let result = vm.invoke("type", &[account(), storage_path("r")]);
+    assert_eq!(result, Ok(Value::some(type_value(r_type()))));
+    assert_eq!(
+        vm.invoke("check", &[account(), storage_path("r")]),
+        Ok(bool(true))
+    );
+    assert_eq!(
+        vm.invoke("check", &[account(), storage_path("s")]),
+        Ok(bool(false))
+    );
+
+    let result = vm.invoke("load", &[account(), storage_path("r")]);
+    assert_eq!(result, Ok(Value::some(Value::Composite(r))));
+
+    // The resource was moved out of storage.
+    assert_eq!(
+        vm.invoke("load", &[account(), storage_path("r")]),
+        Ok(Value::Nil)
+    );
+    assert_eq!(
+        vm.invoke("type", &[account(), storage_path("r")]),
+        Ok(Value::Nil)
+    );
+}
+
+#[test]
+fn test_save_to_occupied_path() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let result = vm.invoke(
+        "saveStruct",
+        &[account(), Value::Composite(structure(1)), storage_path("s")],
+    );
+    assert_eq!(result, Ok(Value::Void));
+
+    let err = vm
+        .invoke(
+            "saveStruct",
+            &[account(), Value::Composite(structure(2)), storage_path("s")],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "failed to save object: path /storage/s in account 0x0000000000000001 \
+         already stores an object"
+    );
+}
+
+#[test]
+fn test_load_with_wrong_type() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let s = structure(1);
+    vm.invoke(
+        "saveStruct",
+        &[account(), Value::Composite(s), storage_path("s")],
+    )
+    .unwrap();
+
+    let result = vm.invoke("load", &[account(), storage_path("s")]);
+    assert_eq!(
+        result,
+        Err(VMError::ForceCastTypeMismatch {
+            expected_type: r_type(),
+            actual_type: s_type(),
+        })
+    );
+
+    // The value remains stored.
+    let result = vm.invoke("type", &[account(), storage_path("s")]);
+    assert_eq!(result, Ok(Value::some(type_value(s_type()))));
+}
+
+#[test]
+fn test_structs_are_copied() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let s = structure(1);
+    vm.invoke(
+        "saveStruct",
+        &[account(), Value::Composite(s.clone()), storage_path("s")],
+    )
+    .unwrap();
+
+    // Mutating the saved struct does not affect the stored copy.
+    s.set_field("count", Value::Int(IntValue { value: 2 }));
+
+    let copy = match vm.invoke("copy", &[account(), storage_path("s")]).unwrap() {
+        Value::Some(value) => match *value {
+            Value::Composite(copy) => copy,
+            value => panic!("unexpected {:?}", value),
+        },
+        value => panic!("unexpected {:?}", value),
+    };
+    assert_eq!(copy, structure(1));
+
+    // Mutating the copy does not affect the stored value either.
+    copy.set_field("count", Value::Int(IntValue { value: 3 }));
+    let result = vm.invoke("copy", &[account(), storage_path("s")]);
+    assert_eq!(result, Ok(Value::some(Value::Composite(structure(1)))));
+}
+
+#[test]
+fn test_copy_resource() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    vm.invoke(
+        "saveResource",
+        &[account(), Value::Composite(resource(1)), storage_path("r")],
+    )
+    .unwrap();
+
+    // Copying would duplicate the resource.
+    let result = vm.invoke("copy", &[account(), storage_path("r")]);
+    let error = VMError::StoredResourceCopy {
+        address: ADDRESS,
+        path: PathValue::new(PathDomain::Storage, "r").unwrap(),
+    };
+    assert_eq!(result, Err(error.clone()));
+    assert_eq!(
+        error.to_string(),
+        "cannot copy resource: path /storage/r in account 0x0000000000000001 stores a resource"
+    );
+
+    // The resource is still stored.
+    let result = vm.invoke("load", &[account(), storage_path("r")]);
+    assert_eq!(result, Ok(Value::some(Value::Composite(resource(1)))));
+}
+
+fn borrow_reference<'a>(vm: &mut VM<'a>, identifier: &str) -> Option<Value<'a>> {
+    match vm
+        .invoke("borrow", &[account(), storage_path(identifier)])
+        .unwrap()
+    {
+        Value::Some(value) => match *value {
+            reference @ Value::Reference(_) => Some(reference),
+            value => panic!("unexpected {:?}", value),
+        },
+        Value::Nil => None,
+        value => panic!("unexpected {:?}", value),
+    }
+}
+
+fn borrow<'a>(vm: &mut VM<'a>, identifier: &str) -> Option<CompositeValue<'a>> {
+    let reference = borrow_reference(vm, identifier)?;
+    match vm.dereference(&reference) {
+        Ok(Value::Composite(composite)) => Some(composite),
+        value => panic!("unexpected {:?}", value),
+    }
+}
+
+#[test]
+fn test_borrow_observes_mutations() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    vm.invoke(
+        "saveResource",
+        &[account(), Value::Composite(resource(1)), storage_path("r")],
+    )
+    .unwrap();
+    vm.invoke(
+        "saveStruct",
+        &[account(), Value::Composite(structure(1)), storage_path("s")],
+    )
+    .unwrap();
+
+    let first = borrow(&mut vm, "r").unwrap();
+    let second = borrow(&mut vm, "r").unwrap();
+
+    second.set_field("id", Value::Int(IntValue { value: 2 }));
+    assert_eq!(
+        first.get_field("id"),
+        Some(Value::Int(IntValue { value: 2 }))
+    );
+
+    // The mutation is stored.
+    let result = vm.invoke("load", &[account(), storage_path("r")]);
+    assert_eq!(result, Ok(Value::some(Value::Composite(resource(2)))));
+
+    // Borrowing a value of another type, or an empty path, results in nil.
+    assert_eq!(borrow(&mut vm, "s"), None);
+    assert_eq!(borrow(&mut vm, "r"), None);
+}
+
+#[test]
+fn test_borrow_reads_path() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    vm.invoke(
+        "saveResource",
+        &[account(), Value::Composite(resource(1)), storage_path("r")],
+    )
+    .unwrap();
+    let reference = borrow_reference(&mut vm, "r").unwrap();
+
+    // Using the reference after the value is moved out of the path fails.
+    let result = vm.invoke("load", &[account(), storage_path("r")]);
+    assert_eq!(result, Ok(Value::some(Value::Composite(resource(1)))));
+    let failure = Err(VMError::DereferenceFailure {
+        address: ADDRESS,
+        path: path("r"),
+    });
+    assert_eq!(vm.dereference(&reference), failure);
+
+    // The reference observes the value saved to the path later.
+    vm.invoke(
+        "saveResource",
+        &[account(), Value::Composite(resource(2)), storage_path("r")],
+    )
+    .unwrap();
+    assert_eq!(
+        vm.dereference(&reference),
+        Ok(Value::Composite(resource(2)))
+    );
+
+    // Unless it does not have the referenced type.
+    vm.invoke("load", &[account(), storage_path("r")]).unwrap();
+    vm.invoke(
+        "saveStruct",
+        &[account(), Value::Composite(structure(1)), storage_path("r")],
+    )
+    .unwrap();
+    assert_eq!(vm.dereference(&reference), failure);
+}
+
+#[test]
+fn test_save_to_public_path() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    let public_path = Value::Path(PathValue::new(PathDomain::Public, "s").unwrap());
+    let result = vm.invoke(
+        "saveToPath",
+        &[account(), Value::Composite(structure(1)), public_path],
+    );
+    assert_eq!(
+        result,
+        Err(VMError::ArgumentTypeMismatch {
+            parameter: "path".to_string(),
+            expected_type: StaticType::StoragePath,
+            actual_type: StaticType::PublicPath,
+            call_site: Some(Box::new(StackFrame {
+                function: "saveToPath".to_string(),
+                instruction: 1,
+                position: None,
+            })),
+        })
+    );
+    assert!(vm.storage.paths(ADDRESS, PathDomain::Public).is_empty());
+}
+
+#[test]
+fn test_borrow_as_non_reference_type() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+
+    vm.invoke(
+        "saveResource",
+        &[account(), Value::Composite(resource(1)), storage_path("r")],
+    )
+    .unwrap();
+    let result = vm.invoke("borrowAsResource", &[account(), storage_path("r")]);
+    assert_eq!(
+        result,
+        Err(VMError::NonReferenceBorrowType {
+            borrow_type: r_type()
+        })
+    );
+}
+
+/// Returns a native function that records the paths and types it is called with,
+/// and returns whether to continue.
+fn recorder<'a>(
+    records: Rc<RefCell<Vec<String>>>,
+    continue_iteration: impl Fn(&mut VM<'a>) -> bool + 'a,
+) -> Value<'a> {
+    Value::Function(FunctionValue::Native(NativeFunctionValue {
+        name: Rc::from("record"),
+        return_type: RegisterType::Bool,
+        function: Rc::new(move |context, arguments| {
+            records
+                .borrow_mut()
+                .push(format!("{}: {}", arguments[0], arguments[1]));
+            Ok(bool(continue_iteration(context)))
+        }),
+    }))
+}
+
+fn save_three(vm: &mut VM<'_>) {
+    for identifier in ["b", "a"] {
+        vm.invoke(
+            "saveStruct",
+            &[
+                account(),
+                Value::Composite(structure(1)),
+                storage_path(identifier),
+            ],
+        )
+        .unwrap();
+    }
+    vm.invoke(
+        "saveResource",
+        &[account(), Value::Composite(resource(1)), storage_path("c")],
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_for_each_stored() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+    save_three(&mut vm);
+
+    let records = Rc::new(RefCell::new(vec![]));
+    let f = recorder(records.clone(), |_| true);
+    vm.invoke("forEachStored", &[account(), f]).unwrap();
+    assert_eq!(
+        *records.borrow(),
+        vec![
+            "/storage/a: Type<S>()",
+            "/storage/b: Type<S>()",
+            "/storage/c: Type<R>()",
+        ]
+    );
+
+    // The iteration stops when the function returns false.
+    let records = Rc::new(RefCell::new(vec![]));
+    let f = recorder(records.clone(), |_| false);
+    vm.invoke("forEachStored", &[account(), f]).unwrap();
+    assert_eq!(records.borrow().len(), 1);
+}
+
+#[test]
+fn test_for_each_stored_with_program_function() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+    save_three(&mut vm);
+
+    vm.set_global("storedCount", Value::Int(IntValue { value: 0 }))
+        .unwrap();
+    vm.invoke("countStored", &[account()]).unwrap();
+    assert_eq!(
+        vm.global("storedCount"),
+        Some(Value::Int(IntValue { value: 3 }))
+    );
+}
+
+#[test]
+fn test_for_each_stored_mutation() {
+    let program = test_program();
+    let mut vm = new_vm(&program);
+    save_three(&mut vm);
+
+    let remove_c = |context: &mut VM| {
+        let c = PathValue::new(PathDomain::Storage, "c").unwrap();
+        context.write_storage(ADDRESS, &StorageKey::Path(c), None);
+    };
+
+    // Stopping the iteration after mutating the storage is fine.
+    let records = Rc::new(RefCell::new(vec![]));
+    let f = recorder(records.clone(), move |context| {
+        remove_c(context);
+        false
+    });
+    assert_eq!(vm.invoke("forEachStored", &[account(), f]), Ok(Value::Void));
+
+    // Continuing the iteration is not.
+    let records = Rc::new(RefCell::new(vec![]));
+    let f = recorder(records.clone(), move |context| {
+        remove_c(context);
+        true
+    });
+    let err = vm.invoke("forEachStored", &[account(), f]).unwrap_err();
+    assert_eq!(err, VMError::StorageMutatedDuringIteration);
+    assert_eq!(records.borrow().len(), 1);
+}
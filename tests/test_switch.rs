@@ -0,0 +1,236 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use cadence_vm::runtime::bbq::{Function, Parameter, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::VMError;
+use cadence_vm::runtime::opcodes::{IntConstantLoad, OpCode, ReturnValue, Switch, SwitchTable};
+use cadence_vm::runtime::registers::{RegisterCounts, RegisterType};
+use cadence_vm::runtime::types::{CompositeKind, CompositeType, EnumInfo, StaticType};
+use cadence_vm::runtime::values::{
+    CompositeValue, FixedSizeIntValue, IntValue, StringValue, Value,
+};
+use cadence_vm::runtime::vm::VM;
+
+#[test]
+fn test_dense_switch_table() {
+    let table = SwitchTable::new(&[(3, 30), (1, 10), (4, 40), (1, 11)]);
+
+    assert_eq!(
+        table,
+        SwitchTable::Dense {
+            first: 1,
+            targets: vec![Some(10), None, Some(30), Some(40)],
+        }
+    );
+    assert_eq!(table.target(1), Some(10));
+    assert_eq!(table.target(2), None);
+    assert_eq!(table.target(4), Some(40));
+    assert_eq!(table.target(0), None);
+    assert_eq!(table.target(5), None);
+    assert_eq!(table.target(isize::MIN), None);
+}
+
+#[test]
+fn test_sparse_switch_table() {
+    let table = SwitchTable::new(&[(isize::MAX, 3), (-1000, 1), (isize::MIN, 0), (7, 2)]);
+
+    assert_eq!(
+        table,
+        SwitchTable::Sparse(vec![(isize::MIN, 0), (-1000, 1), (7, 2), (isize::MAX, 3)])
+    );
+    assert_eq!(table.target(isize::MIN), Some(0));
+    assert_eq!(table.target(7), Some(2));
+    assert_eq!(table.target(isize::MAX), Some(3));
+    assert_eq!(table.target(8), None);
+
+    assert_eq!(SwitchTable::new(&[]).target(0), None);
+}
+
+/// ```cadence
+/// fun f(_ n: Int): Int {
+///     switch n {
+///         case <value>: return <value> * 10
+///         ...
+///         default: return 0
+///     }
+/// }
+/// ```
+fn switch_program(values: &[isize]) -> Program {
+    let mut code: Vec<Box<dyn OpCode>> = vec![Box::new(Switch {
+        value: 0,
+        typ: RegisterType::Int,
+        table: SwitchTable::new(
+            &values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| (*value, 3 + 2 * i))
+                .collect::<Vec<_>>(),
+        ),
+        default: 1,
+    })];
+
+    // default
+    code.push(Box::new(IntConstantLoad {
+        index: 0,
+        target: 1,
+    }));
+    code.push(Box::new(ReturnValue { index: 1 }));
+
+    for i in 0..values.len() {
+        code.push(Box::new(IntConstantLoad {
+            index: i + 1,
+            target: 1,
+        }));
+        code.push(Box::new(ReturnValue { index: 1 }));
+    }
+
+    Program {
+        functions: vec![Function {
+            name: "f".to_string(),
+            parameters: vec![Parameter::unlabeled("n", StaticType::Int)],
+            return_type: StaticType::Int,
+            local_count: RegisterCounts {
+                ints: 2,
+                bools: 0,
+                funcs: 0,
+                values: 0,
+            },
+            code,
+            ..Default::default()
+        }],
+        constants: std::iter::once(0)
+            .chain(values.iter().copied())
+            .map(|value| Constant::int(value as i128 * 10))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_switch() {
+    for values in [vec![-1, 0, 1, 2], vec![-100, 3, 1_000_000]] {
+        let program = switch_program(&values);
+        let mut vm = VM::new(&program);
+
+        for n in [-100, -1, 0, 1, 2, 3, 4, 1_000_000] {
+            let expected = if values.contains(&n) { n * 10 } else { 0 };
+
+            let result = vm.invoke("f", &[Value::Int(IntValue { value: n })]);
+            assert_eq!(result, Ok(Value::Int(IntValue { value: expected })));
+        }
+    }
+}
+
+/// ```cadence
+/// fun f(_ x: <parameter type>): Int {
+///     switch x {
+///         case Color.red: return 1
+///         case Color.blue: return 3
+///         default: return 0
+///     }
+/// }
+/// ```
+fn value_switch_function(name: &str, parameter_type: StaticType) -> Function {
+    let mut code: Vec<Box<dyn OpCode>> = vec![Box::new(Switch {
+        value: 0,
+        typ: RegisterType::Value,
+        table: SwitchTable::new(&[(0, 3), (2, 5)]),
+        default: 1,
+    })];
+    for index in 0..3 {
+        code.push(Box::new(IntConstantLoad { index, target: 0 }));
+        code.push(Box::new(ReturnValue { index: 0 }));
+    }
+
+    Function {
+        name: name.to_string(),
+        parameters: vec![Parameter::unlabeled("x", parameter_type)],
+        return_type: StaticType::Int,
+        local_count: RegisterCounts {
+            ints: 1,
+            bools: 0,
+            funcs: 0,
+            values: 1,
+        },
+        code,
+        ..Default::default()
+    }
+}
+
+fn enum_switch_program() -> Program {
+    Program {
+        functions: vec![
+            value_switch_function("switchColor", StaticType::Composite("Color".to_string())),
+            value_switch_function("switchAny", StaticType::AnyStruct),
+        ],
+        constants: vec![Constant::int(0), Constant::int(1), Constant::int(3)],
+        // enum Color: UInt8 { case red; case green; case blue }
+        composite_types: vec![CompositeType {
+            identifier: "Color".to_string(),
+            kind: CompositeKind::Enum,
+            conformances: vec![],
+            methods: vec![],
+            enum_info: Some(EnumInfo {
+                raw_type: StaticType::UInt8,
+                cases: vec!["red".to_string(), "green".to_string(), "blue".to_string()],
+            }),
+        }],
+        ..Default::default()
+    }
+}
+
+fn uint8<'a>(value: u8) -> Value<'a> {
+    Value::FixedSizeInt(FixedSizeIntValue::UInt8(value))
+}
+
+#[test]
+fn test_enum_switch() {
+    let program = enum_switch_program();
+    let mut vm = VM::new(&program);
+
+    for (raw_value, expected) in [(0, 1), (1, 0), (2, 3)] {
+        let case = Value::Composite(CompositeValue::new(
+            "Color",
+            CompositeKind::Enum,
+            vec![("rawValue", uint8(raw_value))],
+        ));
+        let result = vm.invoke("switchColor", &[case]);
+        assert_eq!(result, Ok(Value::Int(IntValue { value: expected })));
+    }
+}
+
+#[test]
+fn test_fixed_size_int_switch() {
+    let program = enum_switch_program();
+    let mut vm = VM::new(&program);
+
+    for (value, expected) in [(0, 1), (2, 3), (255, 0)] {
+        let result = vm.invoke("switchAny", &[uint8(value)]);
+        assert_eq!(result, Ok(Value::Int(IntValue { value: expected })));
+    }
+
+    let result = vm.invoke("switchAny", &[Value::String(StringValue::new("red"))]);
+    assert_eq!(
+        result,
+        Err(VMError::OperandTypeMismatch {
+            instruction: "Switch",
+            actual_type: StaticType::String,
+        })
+    );
+}
@@ -0,0 +1,537 @@
+/*
+ * Cadence - The resource-oriented smart contract programming language
+ *
+ * Copyright 2019-2022 Dapper Labs, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use proptest::prelude::*;
+
+use cadence_vm::runtime::encoding::{
+    decode_value, encode_value, DecodeError, EncodeError, VALUE_FORMAT_VERSION,
+};
+use cadence_vm::runtime::slabs::{
+    ArrayChild, Slab, SlabValue, StoredArrayValue, StoredDictionaryValue, DICTIONARY_BRANCH_WIDTH,
+};
+use cadence_vm::runtime::types::{Authorization, CompositeKind, StaticType};
+use cadence_vm::runtime::values::{
+    AccountValue, AddressValue, ArrayValue, BoolValue, CapabilityControllerValue, CapabilityValue,
+    CharacterValue, CompositeValue, DictionaryValue, Fix64Value, FixedSizeIntValue,
+    InclusiveRangeValue, IntValue, PathDomain, PathValue, StringValue, TypeValue, UFix64Value,
+    Value,
+};
+
+/// The golden encodings of `samples()`, one `<name> <hex>` line per sample.
+/// Run the tests with `UPDATE_FIXTURES=1` to rewrite the file after an intended format change.
+const FIXTURES: &str = "tests/fixtures/value_encoding.hex";
+
+fn int<'a>(value: isize) -> Value<'a> {
+    Value::Int(IntValue { value })
+}
+
+fn string<'a>(value: &str) -> Value<'a> {
+    Value::String(StringValue::new(value))
+}
+
+fn address(value: u64) -> AddressValue {
+    AddressValue { value }
+}
+
+fn path(domain: PathDomain, identifier: &str) -> PathValue {
+    PathValue::new(domain, identifier).unwrap()
+}
+
+fn r_reference_type() -> StaticType {
+    StaticType::reference(
+        Authorization::Conjunction(BTreeSet::from(["Withdraw".to_string()])),
+        StaticType::Composite("R".to_string()),
+    )
+}
+
+/// Returns a value of each kind, and of each number type.
+fn samples<'a>() -> Vec<(&'static str, Value<'a>)> {
+    let fixed = |value| Value::FixedSizeInt(value);
+    let range = |start: i8, end: i8, step: i8| {
+        let int8 = |value| Value::FixedSizeInt(FixedSizeIntValue::Int8(value));
+        Value::InclusiveRange(
+            InclusiveRangeValue::new(&int8(start), &int8(end), Some(&int8(step))).unwrap(),
+        )
+    };
+
+    vec![
+        ("void", Value::Void),
+        ("nil", Value::Nil),
+        ("some", Value::some(Value::some(int(1)))),
+        ("false", Value::Bool(BoolValue { value: false })),
+        ("true", Value::Bool(BoolValue { value: true })),
+        ("int_zero", int(0)),
+        ("int_negative", int(-129)),
+        ("int_max", int(isize::MAX)),
+        ("int8", fixed(FixedSizeIntValue::Int8(i8::MIN))),
+        ("int16", fixed(FixedSizeIntValue::Int16(-300))),
+        ("int32", fixed(FixedSizeIntValue::Int32(70_000))),
+        ("int64", fixed(FixedSizeIntValue::Int64(i64::MIN))),
+        ("int128", fixed(FixedSizeIntValue::Int128(i128::MAX))),
+        ("uint8", fixed(FixedSizeIntValue::UInt8(u8::MAX))),
+        ("uint16", fixed(FixedSizeIntValue::UInt16(256))),
+        ("uint32", fixed(FixedSizeIntValue::UInt32(u32::MAX))),
+        ("uint64", fixed(FixedSizeIntValue::UInt64(u64::MAX))),
+        ("uint128", fixed(FixedSizeIntValue::UInt128(u128::MAX))),
+        ("word8", fixed(FixedSizeIntValue::Word8(0))),
+        ("word16", fixed(FixedSizeIntValue::Word16(1))),
+        ("word32", fixed(FixedSizeIntValue::Word32(2))),
+        ("word64", fixed(FixedSizeIntValue::Word64(3))),
+        (
+            "fix64",
+            Value::Fix64(Fix64Value {
+                value: -150_000_000,
+            }),
+        ),
+        ("ufix64", Value::UFix64(UFix64Value { value: 150_000_000 })),
+        ("string", string("Flow 🌊")),
+        ("empty_string", string("")),
+        ("character", Value::Character(CharacterValue::new("🇨🇭"))),
+        ("address", Value::Address(address(0xf8d6e0586b0a20c7))),
+        (
+            "storage_path",
+            Value::Path(path(PathDomain::Storage, "flowTokenVault")),
+        ),
+        (
+            "public_path",
+            Value::Path(path(PathDomain::Public, "receiver")),
+        ),
+        (
+            "private_path",
+            Value::Path(path(PathDomain::Private, "_p1")),
+        ),
+        (
+            "capability",
+            Value::Capability(CapabilityValue {
+                address: address(1),
+                id: 300,
+                borrow_type: r_reference_type(),
+            }),
+        ),
+        (
+            "capability_controller",
+            Value::CapabilityController(CapabilityControllerValue {
+                address: address(1),
+                id: 1,
+                borrow_type: r_reference_type(),
+                target: path(PathDomain::Storage, "r"),
+            }),
+        ),
+        (
+            "array",
+            Value::Array(ArrayValue::new(
+                StaticType::optional(StaticType::Int),
+                vec![Value::some(int(1)), Value::Nil],
+            )),
+        ),
+        (
+            "empty_array",
+            Value::Array(ArrayValue::new(StaticType::AnyStruct, vec![])),
+        ),
+        (
+            "dictionary",
+            Value::Dictionary(DictionaryValue::new(
+                StaticType::String,
+                StaticType::array(StaticType::Character),
+                vec![
+                    (
+                        string("b"),
+                        Value::Array(ArrayValue::new(StaticType::Character, vec![])),
+                    ),
+                    (
+                        string("a"),
+                        Value::Array(ArrayValue::new(
+                            StaticType::Character,
+                            vec![Value::Character(CharacterValue::new("a"))],
+                        )),
+                    ),
+                ],
+            )),
+        ),
+        ("ascending_range", range(-3, 9, 3)),
+        ("descending_range", range(10, -10, -5)),
+        (
+            "structure",
+            Value::Composite(CompositeValue::new(
+                "S",
+                CompositeKind::Structure,
+                vec![("name", string("s")), ("count", int(2))],
+            )),
+        ),
+        (
+            "resource",
+            Value::Composite(CompositeValue::new(
+                "A.0000000000000001.Vault",
+                CompositeKind::Resource,
+                vec![("balance", Value::UFix64(UFix64Value { value: 1_000_000 }))],
+            )),
+        ),
+        (
+            "enum_case",
+            Value::Composite(CompositeValue::new(
+                "Color",
+                CompositeKind::Enum,
+                vec![("rawValue", fixed(FixedSizeIntValue::UInt8(2)))],
+            )),
+        ),
+        (
+            "contract",
+            Value::Composite(CompositeValue::new("C", CompositeKind::Contract, vec![])),
+        ),
+        (
+            "type",
+            Value::Type(TypeValue {
+                typ: StaticType::dictionary(
+                    StaticType::PublicPath,
+                    StaticType::capability(r_reference_type()),
+                ),
+            }),
+        ),
+        (
+            "intersection_type",
+            Value::Type(TypeValue {
+                typ: StaticType::optional(StaticType::intersection(&["I1", "I2"])),
+            }),
+        ),
+        (
+            "range_type",
+            Value::Type(TypeValue {
+                typ: StaticType::inclusive_range(StaticType::Word64),
+            }),
+        ),
+        (
+            "stored_array",
+            Value::StoredArray(StoredArrayValue {
+                address: address(1),
+                root: 2,
+                element_type: StaticType::Int,
+            }),
+        ),
+        (
+            "stored_dictionary",
+            Value::StoredDictionary(StoredDictionaryValue {
+                address: address(1),
+                root: 300,
+                key_type: StaticType::String,
+                value_type: StaticType::array(StaticType::Int),
+            }),
+        ),
+        (
+            "array_leaf_slab",
+            slab(7, Slab::ArrayLeaf(vec![int(1), string("a")])),
+        ),
+        (
+            "array_branch_slab",
+            slab(
+                0,
+                Slab::ArrayBranch(vec![
+                    ArrayChild { id: 3, count: 32 },
+                    ArrayChild { id: 4, count: 33 },
+                ]),
+            ),
+        ),
+        (
+            "dictionary_leaf_slab",
+            slab(0, Slab::DictionaryLeaf(vec![(string("k"), int(-1))])),
+        ),
+        (
+            "dictionary_branch_slab",
+            slab(2, {
+                let mut children = vec![None; DICTIONARY_BRANCH_WIDTH];
+                children[0] = Some(5);
+                children[31] = Some(6);
+                Slab::DictionaryBranch {
+                    count: 70,
+                    children,
+                }
+            }),
+        ),
+    ]
+}
+
+fn slab(mutations: u64, slab: Slab) -> Value {
+    Value::Slab(SlabValue {
+        mutations,
+        slab: Rc::new(slab),
+    })
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{:02x}", byte).unwrap();
+        hex
+    })
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_golden_fixtures() {
+    let encodings: Vec<(&str, String)> = samples()
+        .iter()
+        .map(|(name, value)| (*name, to_hex(&encode_value(value).unwrap())))
+        .collect();
+
+    if std::env::var_os("UPDATE_FIXTURES").is_some() {
+        let contents: String = encodings
+            .iter()
+            .map(|(name, hex)| format!("{} {}\n", name, hex))
+            .collect();
+        fs::write(FIXTURES, contents).unwrap();
+    }
+
+    let fixtures = fs::read_to_string(Path::new(FIXTURES)).unwrap();
+    let fixtures: Vec<(&str, &str)> = fixtures
+        .lines()
+        .map(|line| line.split_once(' ').unwrap())
+        .collect();
+
+    let fixture_names: Vec<&str> = fixtures.iter().map(|(name, _)| *name).collect();
+    let sample_names: Vec<&str> = encodings.iter().map(|(name, _)| *name).collect();
+    assert_eq!(fixture_names, sample_names);
+
+    for ((name, value), (_, fixture)) in samples().into_iter().zip(fixtures) {
+        // Values must keep their encoding...
+        let encoding = to_hex(&encode_value(&value).unwrap());
+        assert_eq!(encoding, fixture, "encoding of {} changed", name);
+        // ...and encodings written by earlier versions must keep decoding to the same values.
+        assert_eq!(decode_value(&from_hex(fixture)), Ok(value), "{}", name);
+    }
+}
+
+#[test]
+fn test_version() {
+    let mut encoding = encode_value(&int(1)).unwrap();
+    assert_eq!(encoding[0], VALUE_FORMAT_VERSION);
+
+    encoding[0] = VALUE_FORMAT_VERSION + 1;
+    assert_eq!(
+        decode_value(&encoding),
+        Err(DecodeError::UnsupportedVersion(VALUE_FORMAT_VERSION + 1))
+    );
+}
+
+#[test]
+fn test_invalid_encodings() {
+    let decode = |bytes: &[u8]| decode_value(bytes);
+
+    assert_eq!(decode(&[]), Err(DecodeError::UnexpectedEnd));
+    assert_eq!(decode(&[1, 0, 0]), Err(DecodeError::TrailingData));
+    assert_eq!(decode(&[1, 0xff]), Err(DecodeError::InvalidTag(0xff)));
+    // A Bool other than 0 or 1.
+    assert_eq!(decode(&[1, 3, 2]), Err(DecodeError::InvalidTag(2)));
+    // An Int8 that does not fit.
+    assert_eq!(decode(&[1, 5, 12, 2, 1, 0]), Err(DecodeError::OutOfRange));
+    // A dictionary `{1: 1, 1: 2}`: Int keys and values, two entries with the same key.
+    assert_eq!(
+        decode(&[1, 15, 11, 11, 2, 4, 1, 1, 4, 1, 1, 4, 1, 1, 4, 1, 2]),
+        Err(DecodeError::NonCanonical)
+    );
+    // A dictionary leaf slab with two entries with the same key.
+    assert_eq!(
+        decode(&[1, 21, 0, 2, 2, 4, 1, 1, 4, 1, 1, 4, 1, 1, 4, 1, 2]),
+        Err(DecodeError::NonCanonical)
+    );
+    // A dictionary branch slab with children out of slot order, and with a slot out of range.
+    assert_eq!(
+        decode(&[1, 21, 0, 3, 2, 2, 5, 1, 4, 2]),
+        Err(DecodeError::NonCanonical)
+    );
+    assert_eq!(
+        decode(&[1, 21, 0, 3, 1, 1, 32, 1]),
+        Err(DecodeError::OutOfRange)
+    );
+}
+
+#[test]
+fn test_unstorable_values() {
+    let account = Value::Account(AccountValue {
+        address: address(1),
+    });
+    assert_eq!(
+        encode_value(&account),
+        Err(EncodeError::NotStorable(StaticType::Account))
+    );
+}
+
+/*
+*  Properties
+*/
+
+fn identifier() -> impl Strategy<Value = String> {
+    "[a-zA-Z_][a-zA-Z0-9_]{0,8}"
+}
+
+fn static_type() -> impl Strategy<Value = StaticType> {
+    let leaf = prop_oneof![
+        Just(StaticType::Int),
+        Just(StaticType::String),
+        Just(StaticType::AnyStruct),
+        Just(StaticType::StoragePath),
+        identifier().prop_map(StaticType::Composite),
+    ];
+    leaf.prop_recursive(3, 8, 2, |inner| {
+        prop_oneof![
+            inner.clone().prop_map(StaticType::optional),
+            inner.clone().prop_map(StaticType::array),
+            (inner.clone(), inner.clone()).prop_map(|(k, v)| StaticType::dictionary(k, v)),
+            inner.prop_map(|typ| StaticType::reference(Authorization::Unauthorized, typ)),
+        ]
+    })
+}
+
+fn fixed_size_int() -> impl Strategy<Value = FixedSizeIntValue> {
+    prop_oneof![
+        any::<i8>().prop_map(FixedSizeIntValue::Int8),
+        any::<i16>().prop_map(FixedSizeIntValue::Int16),
+        any::<i32>().prop_map(FixedSizeIntValue::Int32),
+        any::<i64>().prop_map(FixedSizeIntValue::Int64),
+        any::<i128>().prop_map(FixedSizeIntValue::Int128),
+        any::<u8>().prop_map(FixedSizeIntValue::UInt8),
+        any::<u16>().prop_map(FixedSizeIntValue::UInt16),
+        any::<u32>().prop_map(FixedSizeIntValue::UInt32),
+        any::<u64>().prop_map(FixedSizeIntValue::UInt64),
+        any::<u128>().prop_map(FixedSizeIntValue::UInt128),
+        any::<u8>().prop_map(FixedSizeIntValue::Word8),
+        any::<u16>().prop_map(FixedSizeIntValue::Word16),
+        any::<u32>().prop_map(FixedSizeIntValue::Word32),
+        any::<u64>().prop_map(FixedSizeIntValue::Word64),
+    ]
+}
+
+fn path_value() -> impl Strategy<Value = PathValue> {
+    let domain = prop_oneof![
+        Just(PathDomain::Storage),
+        Just(PathDomain::Private),
+        Just(PathDomain::Public),
+    ];
+    (domain, identifier()).prop_map(|(domain, identifier)| path(domain, &identifier))
+}
+
+fn range_value() -> impl Strategy<Value = InclusiveRangeValue> {
+    (any::<i64>(), any::<i64>(), 1..=i64::MAX).prop_map(|(start, end, step)| {
+        let int64 = |value| Value::FixedSizeInt(FixedSizeIntValue::Int64(value));
+        let step = if end < start { -step } else { step };
+        InclusiveRangeValue::new(&int64(start), &int64(end), Some(&int64(step))).unwrap()
+    })
+}
+
+fn storable_value() -> impl Strategy<Value = Value<'static>> {
+    let leaf = prop_oneof![
+        Just(Value::Void),
+        Just(Value::Nil),
+        any::<bool>().prop_map(|value| Value::Bool(BoolValue { value })),
+        any::<isize>().prop_map(int),
+        fixed_size_int().prop_map(Value::FixedSizeInt),
+        any::<i64>().prop_map(|value| Value::Fix64(Fix64Value { value })),
+        any::<u64>().prop_map(|value| Value::UFix64(UFix64Value { value })),
+        any::<String>().prop_map(|value| string(&value)),
+        any::<char>().prop_map(|c| Value::Character(CharacterValue::new(&c.to_string()))),
+        any::<u64>().prop_map(|value| Value::Address(address(value))),
+        path_value().prop_map(Value::Path),
+        (any::<u64>(), any::<u64>(), static_type()).prop_map(|(value, id, borrow_type)| {
+            Value::Capability(CapabilityValue {
+                address: address(value),
+                id,
+                borrow_type,
+            })
+        }),
+        (any::<u64>(), static_type(), path_value()).prop_map(|(id, borrow_type, target)| {
+            Value::CapabilityController(CapabilityControllerValue {
+                address: address(1),
+                id,
+                borrow_type,
+                target,
+            })
+        }),
+        range_value().prop_map(Value::InclusiveRange),
+        static_type().prop_map(|typ| Value::Type(TypeValue { typ })),
+    ];
+
+    leaf.prop_recursive(4, 64, 8, |inner| {
+        prop_oneof![
+            inner.clone().prop_map(Value::some),
+            prop::collection::vec(inner.clone(), 0..8).prop_map(|elements| {
+                Value::Array(ArrayValue::new(StaticType::AnyStruct, elements))
+            }),
+            prop::collection::vec((any::<String>(), inner.clone()), 0..8).prop_map(|entries| {
+                let entries = entries
+                    .into_iter()
+                    .map(|(key, value)| (string(&key), value))
+                    .collect();
+                Value::Dictionary(DictionaryValue::new(
+                    StaticType::String,
+                    StaticType::AnyStruct,
+                    entries,
+                ))
+            }),
+            (
+                identifier(),
+                prop::collection::btree_map(identifier(), inner, 0..8)
+            )
+                .prop_map(|(identifier, fields)| {
+                    let fields = fields
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), value.clone()))
+                        .collect();
+                    Value::Composite(CompositeValue::new(
+                        &identifier,
+                        CompositeKind::Structure,
+                        fields,
+                    ))
+                }),
+        ]
+    })
+}
+
+proptest! {
+    #[test]
+    fn test_round_trip(value in storable_value()) {
+        let encoding = encode_value(&value).unwrap();
+        let decoded = decode_value(&encoding).unwrap();
+        prop_assert_eq!(&decoded, &value);
+
+        // The encoding is deterministic.
+        prop_assert_eq!(encode_value(&decoded).unwrap(), encoding);
+    }
+
+    #[test]
+    fn test_decoding_arbitrary_data_does_not_panic(
+        data in prop::collection::vec(any::<u8>(), 0..64),
+    ) {
+        let _ = decode_value(&data);
+    }
+
+    #[test]
+    fn test_decoding_truncated_data_fails(value in storable_value(), cut in any::<prop::sample::Index>()) {
+        let encoding = encode_value(&value).unwrap();
+        let length = cut.index(encoding.len());
+        prop_assert!(decode_value(&encoding[..length]).is_err());
+    }
+}
@@ -16,23 +16,30 @@
  * limitations under the License.
  */
 
-use cadence_vm::runtime::bbq::Function;
+use cadence_vm::runtime::bbq::{Function, Parameter, Position, Program};
+use cadence_vm::runtime::constants::Constant;
+use cadence_vm::runtime::errors::{StackFrame, VMError};
 use cadence_vm::runtime::opcodes::{
-    Argument, Call, GlobalFuncLoad, IntAdd, IntConstantLoad, IntLess, IntMove, IntSubtract, Jump,
-    JumpIfFalse, ReturnValue,
+    Argument, Call, ConstantLoad, GlobalFuncLoad, IntAdd, IntConstantLoad, IntLess, IntMove,
+    IntSubtract, Jump, JumpIfFalse, Move, NewArray, Return, ReturnValue, True,
 };
 use cadence_vm::runtime::registers;
-use cadence_vm::runtime::values::{FunctionValue, IntValue};
+use cadence_vm::runtime::types::StaticType;
+use cadence_vm::runtime::values::{ArrayValue, BoolValue, IntValue, StringValue, Value};
 use cadence_vm::runtime::vm::VM;
 
 #[test]
 fn test_recursive_fib() {
     let func = Function {
+        name: "recursiveFib".to_string(),
+        parameters: vec![Parameter::unlabeled("n", StaticType::Int)],
         local_count: registers::RegisterCounts {
-            ints: 9,
+            ints: 10,
             bools: 1,
             funcs: 2,
+            values: 0,
         },
+        return_type: StaticType::Int,
         code: vec![
             // if n < 2
             Box::new(IntConstantLoad {
@@ -58,7 +65,7 @@ fn test_recursive_fib() {
             Box::new(IntSubtract {
                 left_operand: 0,
                 right_operand: 2,
-                result: 3,
+                result: 9,
             }),
             Box::new(GlobalFuncLoad {
                 index: 0,
@@ -66,10 +73,7 @@ fn test_recursive_fib() {
             }),
             Box::new(Call {
                 func_index: 0,
-                arguments: &[Argument {
-                    typ: registers::RegisterType::Int,
-                    index: 3,
-                }],
+                arguments: vec![registers::RegisterType::Int],
                 result: 4,
             }),
             // fib(n - 2)
@@ -80,7 +84,7 @@ fn test_recursive_fib() {
             Box::new(IntSubtract {
                 left_operand: 0,
                 right_operand: 5,
-                result: 6,
+                result: 9,
             }),
             Box::new(GlobalFuncLoad {
                 index: 0,
@@ -88,10 +92,7 @@ fn test_recursive_fib() {
             }),
             Box::new(Call {
                 func_index: 1,
-                arguments: &[Argument {
-                    typ: registers::RegisterType::Int,
-                    index: 6,
-                }],
+                arguments: vec![registers::RegisterType::Int],
                 result: 7,
             }),
             // return sum
@@ -102,33 +103,36 @@ fn test_recursive_fib() {
             }),
             Box::new(ReturnValue { index: 8 }),
         ],
+        ..Default::default()
     };
 
-    let mut vm = VM {
-        constants: vec![
-            IntValue { value: 2 },
-            IntValue { value: 1 },
-            IntValue { value: 2 },
-        ],
-        call_stack: vec![],
-        globals: vec![FunctionValue { function: &func }],
-        current_index: 0,
-        return_value: IntValue { value: 0 },
+    let program = Program {
+        functions: vec![func],
+        constants: vec![Constant::int(2), Constant::int(1), Constant::int(2)],
+        ..Default::default()
     };
 
-    let result = vm.invoke(&func, IntValue { value: 7 });
+    let mut vm = VM::new(&program);
 
-    assert_eq!(result.value, 13);
+    let result = vm
+        .invoke("recursiveFib", &[Value::Int(IntValue { value: 7 })])
+        .unwrap();
+
+    assert_eq!(result, Value::Int(IntValue { value: 13 }));
 }
 
 #[test]
 fn test_imperative_fib() {
     let func = Function {
+        name: "imperativeFib".to_string(),
+        parameters: vec![Parameter::unlabeled("n", StaticType::Int)],
         local_count: registers::RegisterCounts {
             ints: 11,
             bools: 1,
             funcs: 0,
+            values: 0,
         },
+        return_type: StaticType::Int,
         code: vec![
             // var fib1 = 1
             Box::new(IntConstantLoad {
@@ -187,22 +191,498 @@ fn test_imperative_fib() {
             // return fibonacci
             Box::new(ReturnValue { index: 5 }),
         ],
+        ..Default::default()
     };
 
-    let mut vm = VM {
+    let program = Program {
+        functions: vec![func],
         constants: vec![
-            IntValue { value: 1 },
-            IntValue { value: 1 },
-            IntValue { value: 2 },
-            IntValue { value: 1 },
+            Constant::int(1),
+            Constant::int(1),
+            Constant::int(2),
+            Constant::int(1),
+        ],
+        ..Default::default()
+    };
+
+    let mut vm = VM::new(&program);
+
+    let result = vm
+        .invoke("imperativeFib", &[Value::Int(IntValue { value: 7 })])
+        .unwrap();
+
+    assert_eq!(result, Value::Int(IntValue { value: 13 }));
+}
+
+fn typed_program() -> Program {
+    Program {
+        functions: vec![
+            // fun less(_ a: Int, _ b: Int): Bool {
+            //     return a < b
+            // }
+            Function {
+                name: "less".to_string(),
+                parameters: vec![
+                    Parameter::unlabeled("a", StaticType::Int),
+                    Parameter::unlabeled("b", StaticType::Int),
+                ],
+                local_count: registers::RegisterCounts {
+                    ints: 2,
+                    bools: 1,
+                    funcs: 0,
+                    values: 0,
+                },
+                return_type: StaticType::Bool,
+                code: vec![
+                    Box::new(IntLess {
+                        left_operand: 0,
+                        right_operand: 1,
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+                ..Default::default()
+            },
+            // fun select(_ condition: Bool, _ a: String, _ b: String): String {
+            //     if condition {
+            //         return a
+            //     }
+            //     return b
+            // }
+            Function {
+                name: "select".to_string(),
+                parameters: vec![
+                    Parameter::unlabeled("condition", StaticType::Bool),
+                    Parameter::unlabeled("a", StaticType::String),
+                    Parameter::unlabeled("b", StaticType::String),
+                ],
+                local_count: registers::RegisterCounts {
+                    ints: 0,
+                    bools: 1,
+                    funcs: 0,
+                    values: 2,
+                },
+                return_type: StaticType::String,
+                code: vec![
+                    Box::new(JumpIfFalse {
+                        condition: 0,
+                        target: 2,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                    Box::new(ReturnValue { index: 1 }),
+                ],
+                ..Default::default()
+            },
+            // fun ignore(_ n: Int) {
+            //     return
+            // }
+            Function {
+                name: "ignore".to_string(),
+                parameters: vec![Parameter::unlabeled("n", StaticType::Int)],
+                local_count: registers::RegisterCounts {
+                    ints: 1,
+                    bools: 0,
+                    funcs: 0,
+                    values: 0,
+                },
+                return_type: StaticType::Void,
+                code: vec![Box::new(Return {})],
+                ..Default::default()
+            },
+            // fun empty() {}
+            Function {
+                name: "empty".to_string(),
+                parameters: vec![],
+                local_count: registers::RegisterCounts::default(),
+                return_type: StaticType::Void,
+                code: vec![],
+                ..Default::default()
+            },
+            // fun callLessWithBools(_ b: Bool): Bool {
+            //     return less(b, b)
+            // }
+            Function {
+                name: "callLessWithBools".to_string(),
+                parameters: vec![Parameter::unlabeled("b", StaticType::Bool)],
+                local_count: registers::RegisterCounts {
+                    ints: 0,
+                    bools: 2,
+                    funcs: 1,
+                    values: 0,
+                },
+                return_type: StaticType::Bool,
+                code: vec![
+                    Box::new(GlobalFuncLoad {
+                        index: 0,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![
+                            registers::RegisterType::Bool,
+                            registers::RegisterType::Bool,
+                        ],
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+                positions: vec![
+                    Position {
+                        instruction: 0,
+                        line: 2,
+                        column: 11,
+                    },
+                    Position {
+                        instruction: 2,
+                        line: 2,
+                        column: 4,
+                    },
+                ],
+                ..Default::default()
+            },
+            // fun callSelect(_ x: AnyStruct): String {
+            //     return select(true, x, x)
+            // }
+            Function {
+                name: "callSelect".to_string(),
+                parameters: vec![Parameter::unlabeled("x", StaticType::AnyStruct)],
+                local_count: registers::RegisterCounts {
+                    ints: 0,
+                    bools: 1,
+                    funcs: 1,
+                    values: 3,
+                },
+                return_type: StaticType::String,
+                code: vec![
+                    Box::new(GlobalFuncLoad {
+                        index: 1,
+                        result: 0,
+                    }),
+                    Box::new(True { index: 0 }),
+                    Box::new(Move {
+                        typ: registers::RegisterType::Value,
+                        from: 0,
+                        to: 1,
+                    }),
+                    Box::new(Move {
+                        typ: registers::RegisterType::Value,
+                        from: 0,
+                        to: 2,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![
+                            registers::RegisterType::Bool,
+                            registers::RegisterType::Value,
+                            registers::RegisterType::Value,
+                        ],
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+                ..Default::default()
+            },
+            // fun optional(_ x: Int?): Int? {
+            //     return x
+            // }
+            Function {
+                name: "optional".to_string(),
+                parameters: vec![Parameter::unlabeled(
+                    "x",
+                    StaticType::optional(StaticType::Int),
+                )],
+                local_count: registers::RegisterCounts {
+                    ints: 0,
+                    bools: 0,
+                    funcs: 0,
+                    values: 1,
+                },
+                return_type: StaticType::optional(StaticType::Int),
+                code: vec![Box::new(ReturnValue { index: 0 })],
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_invoke_with_optional_parameter() {
+    let program = typed_program();
+    let mut vm = VM::new(&program);
+
+    // Arguments for optional parameters are wrapped.
+    let one = Value::Int(IntValue { value: 1 });
+    let result = vm.invoke("optional", std::slice::from_ref(&one));
+    assert_eq!(result, Ok(Value::some(one.clone())));
+
+    let result = vm.invoke("optional", &[Value::some(one.clone())]);
+    assert_eq!(result, Ok(Value::some(one)));
+
+    let result = vm.invoke("optional", &[Value::Nil]);
+    assert_eq!(result, Ok(Value::Nil));
+}
+
+#[test]
+fn test_invoke_with_multiple_arguments() {
+    let program = typed_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm
+        .invoke(
+            "less",
+            &[
+                Value::Int(IntValue { value: 1 }),
+                Value::Int(IntValue { value: 2 }),
+            ],
+        )
+        .unwrap();
+    assert_eq!(result, Value::Bool(BoolValue { value: true }));
+
+    let arguments = |condition| {
+        [
+            Value::Bool(BoolValue { value: condition }),
+            Value::String(StringValue::new("a")),
+            Value::String(StringValue::new("b")),
+        ]
+    };
+
+    let result = vm.invoke("select", &arguments(true)).unwrap();
+    assert_eq!(result, Value::String(StringValue::new("a")));
+
+    let result = vm.invoke("select", &arguments(false)).unwrap();
+    assert_eq!(result, Value::String(StringValue::new("b")));
+}
+
+#[test]
+fn test_invoke_void_function() {
+    let program = typed_program();
+    let mut vm = VM::new(&program);
+
+    let result = vm
+        .invoke("ignore", &[Value::Int(IntValue { value: 1 })])
+        .unwrap();
+    assert_eq!(result, Value::Void);
+
+    let result = vm.invoke("empty", &[]).unwrap();
+    assert_eq!(result, Value::Void);
+    assert!(vm.call_stack.is_empty());
+}
+
+#[test]
+fn test_invoke_undefined_function() {
+    let program = typed_program();
+    let mut vm = VM::new(&program);
+
+    let err = vm.invoke("missing", &[]).unwrap_err();
+
+    assert_eq!(
+        err,
+        VMError::UndefinedFunction {
+            name: "missing".to_string()
+        }
+    );
+}
+
+#[test]
+fn test_invoke_argument_count_mismatch() {
+    let program = typed_program();
+    let mut vm = VM::new(&program);
+
+    let err = vm
+        .invoke("less", &[Value::Int(IntValue { value: 1 })])
+        .unwrap_err();
+
+    assert_eq!(
+        err,
+        VMError::ArgumentCountMismatch {
+            function: "less".to_string(),
+            expected: 2,
+            actual: 1,
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "incorrect number of arguments for function `less`: expected 2, got 1"
+    );
+}
+
+#[test]
+fn test_invoke_argument_type_mismatch() {
+    let program = typed_program();
+    let mut vm = VM::new(&program);
+
+    let err = vm
+        .invoke(
+            "less",
+            &[
+                Value::Int(IntValue { value: 1 }),
+                Value::String(StringValue::new("2")),
+            ],
+        )
+        .unwrap_err();
+
+    assert_eq!(
+        err,
+        VMError::ArgumentTypeMismatch {
+            parameter: "b".to_string(),
+            expected_type: StaticType::Int,
+            actual_type: StaticType::String,
+            call_site: None,
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "mismatched types for parameter `b`: expected `Int`, got `String`"
+    );
+}
+
+#[test]
+fn test_call_argument_type_mismatch() {
+    let program = typed_program();
+    let mut vm = VM::new(&program);
+
+    let err = vm
+        .invoke(
+            "callLessWithBools",
+            &[Value::Bool(BoolValue { value: true })],
+        )
+        .unwrap_err();
+
+    assert_eq!(
+        err,
+        VMError::ArgumentTypeMismatch {
+            parameter: "a".to_string(),
+            expected_type: StaticType::Int,
+            actual_type: StaticType::Bool,
+            call_site: Some(Box::new(StackFrame {
+                function: "callLessWithBools".to_string(),
+                instruction: 1,
+                position: Some((2, 11)),
+            })),
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "mismatched types for parameter `a`: expected `Int`, got `Bool`, \
+         at callLessWithBools:1 (2:11)"
+    );
+
+    // Arguments in value registers are checked against the parameter types.
+    let err = vm
+        .invoke("callSelect", &[Value::Int(IntValue { value: 1 })])
+        .unwrap_err();
+    assert_eq!(
+        err,
+        VMError::ArgumentTypeMismatch {
+            parameter: "a".to_string(),
+            expected_type: StaticType::String,
+            actual_type: StaticType::Int,
+            call_site: Some(Box::new(StackFrame {
+                function: "callSelect".to_string(),
+                instruction: 4,
+                position: None,
+            })),
+        }
+    );
+
+    let result = vm.invoke("callSelect", &[Value::String(StringValue::new("a"))]);
+    assert_eq!(result, Ok(Value::String(StringValue::new("a"))));
+}
+
+#[test]
+fn test_function_positions() {
+    let program = typed_program();
+    let function = &program.functions[4];
+
+    assert_eq!(
+        function.position(1).map(|position| position.column),
+        Some(11)
+    );
+    assert_eq!(
+        function.position(2).map(|position| position.column),
+        Some(4)
+    );
+    assert_eq!(program.functions[0].position(0), None);
+}
+
+#[test]
+fn test_arguments_are_shared_with_parameters() {
+    let program = Program {
+        functions: vec![
+            // fun id(_ s: String): String { return s }
+            Function {
+                name: "id".to_string(),
+                parameters: vec![Parameter::unlabeled("s", StaticType::String)],
+                local_count: registers::RegisterCounts {
+                    ints: 0,
+                    bools: 0,
+                    funcs: 0,
+                    values: 1,
+                },
+                return_type: StaticType::String,
+                code: vec![Box::new(ReturnValue { index: 0 })],
+                ..Default::default()
+            },
+            // let s = "a"; let t = id(s); return [t, s]
+            Function {
+                name: "callId".to_string(),
+                local_count: registers::RegisterCounts {
+                    ints: 0,
+                    bools: 0,
+                    funcs: 1,
+                    values: 2,
+                },
+                return_type: StaticType::array(StaticType::AnyStruct),
+                code: vec![
+                    Box::new(ConstantLoad {
+                        index: 0,
+                        result: 1,
+                    }),
+                    Box::new(GlobalFuncLoad {
+                        index: 0,
+                        result: 0,
+                    }),
+                    Box::new(Call {
+                        func_index: 0,
+                        arguments: vec![registers::RegisterType::Value],
+                        result: 0,
+                    }),
+                    Box::new(NewArray {
+                        element_type: StaticType::AnyStruct,
+                        elements: vec![
+                            Argument {
+                                typ: registers::RegisterType::Value,
+                                index: 0,
+                            },
+                            Argument {
+                                typ: registers::RegisterType::Value,
+                                index: 1,
+                            },
+                        ],
+                        result: 0,
+                    }),
+                    Box::new(ReturnValue { index: 0 }),
+                ],
+                ..Default::default()
+            },
         ],
-        call_stack: vec![],
-        globals: vec![FunctionValue { function: &func }],
-        current_index: 0,
-        return_value: IntValue { value: 0 },
+        constants: vec![Constant::string("a")],
+        ..Default::default()
     };
 
-    let result = vm.invoke(&func, IntValue { value: 7 });
+    let mut vm = VM::new(&program);
+    let result = vm.invoke("callId", &[]);
 
-    assert_eq!(result.value, 13);
+    // `id` reads the argument from its parameter register without a `Move`,
+    // and the argument register of `callId` is cleared when the registers of `id` are popped,
+    // so the two are the same register rather than a copy.
+    let expected = vec![Value::String(StringValue::new("a")), Value::Void];
+    assert_eq!(
+        result,
+        Ok(Value::Array(ArrayValue::new(
+            StaticType::AnyStruct,
+            expected
+        )))
+    );
 }